@@ -0,0 +1,83 @@
+//! Dictionaries distributed as a zip of the mdx, mdd and companion files
+
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::mdx::Reader;
+use crate::Result;
+
+pub(crate) struct Archive {
+	path: PathBuf,
+	zip: ZipArchive<BufReader<File>>,
+}
+
+impl Archive {
+	pub(crate) fn open(path: &Path) -> Result<Self>
+	{
+		let reader = BufReader::new(File::open(path)?);
+		let zip = ZipArchive::new(reader)?;
+		Ok(Archive { path: path.to_path_buf(), zip })
+	}
+
+	/// Index of the member named `name`, ignoring directories and ascii case
+	pub(crate) fn find(&self, name: &str) -> Option<usize>
+	{
+		let name = name.trim_start_matches(['\\', '/']).replace('\\', "/");
+		(0..self.zip.len()).find(|idx| self.zip.name_for_index(*idx)
+			.is_some_and(|member| member.eq_ignore_ascii_case(&name)
+				|| member.rsplit('/').next().is_some_and(|file| file.eq_ignore_ascii_case(&name))))
+	}
+
+	#[inline]
+	pub(crate) fn contains(&self, name: &str) -> bool
+	{
+		self.find(name).is_some()
+	}
+
+	/// File name of the first `.mdx` member
+	pub(crate) fn mdx_name(&self) -> Option<String>
+	{
+		self.zip.file_names()
+			.map(|name| name.rsplit('/').next().unwrap_or(name))
+			.find(|name| name.to_ascii_lowercase().ends_with(".mdx"))
+			.map(str::to_owned)
+	}
+
+	/// Stored members are read in place, compressed ones are inflated
+	/// into memory
+	pub(crate) fn reader(&mut self, name: &str, capacity: usize) -> Result<Option<Reader>>
+	{
+		let idx = match self.find(name) {
+			Some(idx) => idx,
+			None => return Ok(None),
+		};
+		let mut member = self.zip.by_index(idx)?;
+		if member.compression() == CompressionMethod::Stored {
+			let start = member.data_start();
+			let len = member.size();
+			drop(member);
+			let mut reader = BufReader::with_capacity(capacity, File::open(&self.path)?);
+			reader.seek(SeekFrom::Start(start))?;
+			Ok(Some(Reader::Slice { reader, start, len, pos: 0 }))
+		} else {
+			let mut data = Vec::with_capacity(member.size() as usize);
+			member.read_to_end(&mut data)?;
+			Ok(Some(Reader::Memory(Cursor::new(data))))
+		}
+	}
+
+	/// Content of the member named `name`
+	pub(crate) fn read(&mut self, name: &str) -> Result<Option<Vec<u8>>>
+	{
+		let idx = match self.find(name) {
+			Some(idx) => idx,
+			None => return Ok(None),
+		};
+		let mut member = self.zip.by_index(idx)?;
+		let mut data = Vec::with_capacity(member.size() as usize);
+		member.read_to_end(&mut data)?;
+		Ok(Some(data))
+	}
+}
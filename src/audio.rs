@@ -0,0 +1,56 @@
+use std::borrow::Cow;
+
+use crate::lint::{references, resource_path};
+use crate::mdx::{KeyMaker, MDict};
+use crate::parser::find_entry;
+use crate::Result;
+
+// links followed to find the entry a redirect ends at
+const MAX_LINKS: usize = 4;
+
+/// A `sound://` reference of an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioReference {
+	/// the reference as written in the definition
+	pub reference: String,
+	/// the `\path\file` form, as given to [`MDict::resource_shared`]
+	pub path: String,
+	/// whether one of the mdd files has it
+	pub found: bool,
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// The `sound://` references of the entry of `word` in document order,
+	/// each once, with their mdd paths, so audio can be fetched ahead of
+	/// time. Redirects are followed to the entry they lead to
+	pub fn audio_references(&mut self, word: &str) -> Result<Vec<AudioReference>>
+	{
+		let mut word = word.to_owned();
+		let mut hops = 0;
+		let definition = loop {
+			let Some(record) = self.lookup_record(&word)? else {
+				return Ok(vec![]);
+			};
+			let definition = record.text()?.into_owned();
+			match definition.trim().strip_prefix("@@@LINK=") {
+				Some(_) if hops == MAX_LINKS => return Ok(vec![]),
+				Some(target) => word = target.trim_end_matches('\0').trim().to_owned(),
+				None => break definition,
+			}
+			hops += 1;
+		};
+		let mut audio: Vec<AudioReference> = vec![];
+		for reference in references(&definition) {
+			if !reference.starts_with("sound://") || audio.iter().any(|audio| audio.reference == reference) {
+				continue;
+			}
+			let Some(path) = resource_path(&reference) else {
+				continue;
+			};
+			let key = self.key_maker.make(&Cow::Borrowed(&path), true);
+			let found = self.resources.iter().any(|mdd| find_entry(mdd, &key).is_some());
+			audio.push(AudioReference { reference, path, found });
+		}
+		Ok(audio)
+	}
+}
@@ -0,0 +1,386 @@
+use std::ops::Bound;
+use std::process::ExitCode;
+
+use regex::Regex;
+
+use mdict::{inspect, Exporter, MDictBuilder, Sharding};
+use mdict::site::{Pages, SiteExporter};
+#[cfg(feature = "zip")]
+use mdict::epub::EpubExporter;
+use mdict::kindle::KindleExporter;
+#[cfg(any(feature = "repl", feature = "tui"))]
+use mdict::{KeyMaker, MDict};
+#[cfg(any(feature = "repl", feature = "tui"))]
+use mdict::record::to_markdown;
+
+#[cfg(feature = "repl")]
+mod repl;
+#[cfg(feature = "tui")]
+mod tui;
+
+// redirects followed for one lookup
+#[cfg(any(feature = "repl", feature = "tui"))]
+const MAX_REDIRECTS: usize = 4;
+
+const USAGE: &str = "usage: mdict <command> <file.mdx|file.mdd> [options]
+       mdict repl|tui <file.mdx>... [--rebuild-index]
+
+commands:
+  check [--json]    verify the checksum of every block
+  dump-structure    print header fields and block tables
+  dump --output <file> [--shards <n>] [--resume]
+                    write every entry as JSON lines, shards by key range to
+                    <file>.0 and on, --resume continues an interrupted dump
+       [--keys <file>] [--match <regex>] [--from <key>] [--to <key>]
+       [--max-size <bytes>]
+                    only the words listed one per line, the headwords
+                    matching, the keys from and before the ones given, the
+                    records of at most that size
+  repair --output <file>
+                    copy the file with the checksums not matching their data
+                    rewritten
+  subset --output <file> --words <file> [--top <n>]
+                    copy the entries of the first <n> words of a frequency
+                    list, one word per line most frequent first, and those
+                    their redirects lead to
+  invert --output <file> --targets <regex>
+                    write the reverse of a bilingual dictionary, the text of
+                    the first group of each match in a definition becomes a
+                    headword, experimental
+  hexdump --key <word>
+                    dump the record of <word> and its block header
+  site --output <dir> [--by-letter]
+                    write a static website with the resources referenced,
+                    one page per entry or per first letter
+  epub --output <file> [--dictionary] [--language <tag>]
+                    write an EPUB book, --dictionary marks it up for lookups
+                    from e-readers, needs the zip feature
+  kindle --output <dir> [--language <tag>]
+                    write the OPF and html source of a Kindle dictionary for
+                    kindlegen or Kindle Previewer
+  repl              look up words interactively, needs the repl feature
+  tui               browse the dictionaries, needs the tui feature
+
+hexdump, repl and tui keep the keys in a .idx file next to each dictionary,
+--rebuild-index writes it again";
+
+// options followed by a value
+const VALUE_OPTIONS: [&str; 12] = ["--from", "--key", "--keys", "--language", "--match",
+	"--max-size", "--output", "--shards", "--targets", "--to", "--top", "--words"];
+
+fn main() -> ExitCode
+{
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	let (command, args) = match args.split_first() {
+		Some((command, args)) => (command.as_str(), args),
+		None => return usage(),
+	};
+	let mut paths = vec![];
+	let mut options = vec![];
+	let mut args = args.iter();
+	while let Some(arg) = args.next() {
+		if VALUE_OPTIONS.contains(&arg.as_str()) {
+			match args.next() {
+				Some(value) => options.push((arg.as_str(), Some(value.as_str()))),
+				None => return usage(),
+			}
+		} else if arg.starts_with("--") {
+			options.push((arg.as_str(), None));
+		} else {
+			paths.push(arg.as_str());
+		}
+	}
+	let flag = |name: &str| options.iter().any(|(option, _)| *option == name);
+	let value = |name: &str| options.iter().find(|(option, _)| *option == name)
+		.and_then(|(_, value)| *value);
+	let rebuild = flag("--rebuild-index");
+	if command == "repl" && !paths.is_empty() {
+		return exit(repl(&paths, rebuild));
+	}
+	if command == "tui" && !paths.is_empty() {
+		return exit(tui(&paths, rebuild));
+	}
+	let path = match paths[..] {
+		[path] => path,
+		_ => return usage(),
+	};
+	let result = match command {
+		"check" => check(path, flag("--json")),
+		"dump-structure" => dump_structure(path),
+		"dump" => match (value("--output"), exporter(&value)) {
+			(Some(output), Some(exporter)) =>
+				dump(path, output, exporter.resume(flag("--resume")), value("--keys")),
+			_ => return usage(),
+		},
+		"site" => match value("--output") {
+			Some(output) => site(path, output, flag("--by-letter")),
+			None => return usage(),
+		},
+		"epub" => match value("--output") {
+			Some(output) => epub(path, output, flag("--dictionary"), value("--language")),
+			None => return usage(),
+		},
+		"kindle" => match value("--output") {
+			Some(output) => kindle(path, output, value("--language")),
+			None => return usage(),
+		},
+		"repair" => match value("--output") {
+			Some(output) => repair(path, output),
+			None => return usage(),
+		},
+		"subset" => match (value("--output"), value("--words"), value("--top").map(str::parse)) {
+			(Some(output), Some(words), None) => subset(path, output, words, usize::MAX),
+			(Some(output), Some(words), Some(Ok(top))) => subset(path, output, words, top),
+			_ => return usage(),
+		},
+		"invert" => match (value("--output"), value("--targets").map(Regex::new)) {
+			(Some(output), Some(Ok(targets))) => invert(path, output, &targets),
+			_ => return usage(),
+		},
+		"hexdump" => match value("--key") {
+			Some(key) => hexdump(path, key, rebuild),
+			None => return usage(),
+		},
+		_ => return usage(),
+	};
+	exit(result)
+}
+
+fn exit(result: mdict::Result<ExitCode>) -> ExitCode
+{
+	match result {
+		Ok(code) => code,
+		Err(err) => {
+			eprint!("mdict: {}", err);
+			let mut source = std::error::Error::source(&err);
+			while let Some(err) = source {
+				eprint!(": {}", err);
+				source = err.source();
+			}
+			eprintln!();
+			ExitCode::FAILURE
+		}
+	}
+}
+
+fn usage() -> ExitCode
+{
+	eprintln!("{}", USAGE);
+	ExitCode::from(2)
+}
+
+fn check(path: &str, json: bool) -> mdict::Result<ExitCode>
+{
+	let report = inspect::check_file(path)?;
+	if json {
+		println!("{}", report.to_json());
+	} else {
+		let failures: Vec<_> = report.failures().collect();
+		println!("{} blocks, {} failed", report.blocks.len(), failures.len());
+		for block in failures {
+			print!("{:?} block {} at {:#x}: ", block.kind, block.index, block.offset);
+			match &block.error {
+				Some(err) => println!("{}", err),
+				None => println!("checksum {:08x}, expected {:08x}",
+					block.actual_checksum.unwrap_or_default(),
+					block.expected_checksum.unwrap_or_default()),
+			}
+		}
+	}
+	Ok(if report.is_ok() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+fn dump_structure(path: &str) -> mdict::Result<ExitCode>
+{
+	print!("{}", inspect::read_structure(path)?);
+	Ok(ExitCode::SUCCESS)
+}
+
+/// Builder using the key index next to `path`
+fn builder(path: &str, rebuild: bool) -> MDictBuilder
+{
+	let builder = MDictBuilder::new(path).index_cache(true);
+	if rebuild { builder.rebuild_index() } else { builder }
+}
+
+/// The dump options but `--keys` and `--resume`, none when one is invalid
+fn exporter<'a>(value: &impl Fn(&str) -> Option<&'a str>) -> Option<Exporter>
+{
+	let shards = value("--shards").unwrap_or("1").parse().ok()?;
+	let mut exporter = Exporter::new().shards(shards, Sharding::KeyRange);
+	if let Some(pattern) = value("--match") {
+		exporter = exporter.matching(Regex::new(pattern).ok()?);
+	}
+	let start = value("--from").map_or(Bound::Unbounded, Bound::Included);
+	let end = value("--to").map_or(Bound::Unbounded, Bound::Excluded);
+	exporter = exporter.key_range((start, end));
+	if let Some(size) = value("--max-size") {
+		exporter = exporter.max_size(size.parse().ok()?);
+	}
+	Some(exporter)
+}
+
+fn dump(path: &str, output: &str, mut exporter: Exporter, keys: Option<&str>)
+	-> mdict::Result<ExitCode>
+{
+	let mut dict = MDictBuilder::new(path).build()?;
+	if let Some(keys) = keys {
+		exporter = exporter.key_file(keys)?;
+	}
+	exporter.json_lines(&mut dict, output)?;
+	Ok(ExitCode::SUCCESS)
+}
+
+fn site(path: &str, output: &str, by_letter: bool) -> mdict::Result<ExitCode>
+{
+	let mut dict = MDictBuilder::new(path).build()?;
+	let pages = if by_letter { Pages::PerLetter } else { Pages::PerEntry };
+	let summary = SiteExporter::new().pages(pages).write(&mut dict, output)?;
+	println!("{} entries on {} pages, {} resources", summary.entries, summary.pages,
+		summary.resources);
+	for path in &summary.missing {
+		eprintln!("missing resource: {}", path);
+	}
+	Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(feature = "zip")]
+fn epub(path: &str, output: &str, dictionary: bool, language: Option<&str>)
+	-> mdict::Result<ExitCode>
+{
+	let mut dict = MDictBuilder::new(path).build()?;
+	let exporter = EpubExporter::new()
+		.dictionary(dictionary)
+		.language(language.unwrap_or("und"));
+	let summary = exporter.write(&mut dict, output)?;
+	println!("{} entries in {} chapters, {} resources", summary.entries, summary.pages,
+		summary.resources);
+	for path in &summary.missing {
+		eprintln!("missing resource: {}", path);
+	}
+	Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(not(feature = "zip"))]
+fn epub(_path: &str, _output: &str, _dictionary: bool, _language: Option<&str>)
+	-> mdict::Result<ExitCode>
+{
+	eprintln!("mdict: built without the zip feature");
+	Ok(ExitCode::FAILURE)
+}
+
+fn kindle(path: &str, output: &str, language: Option<&str>) -> mdict::Result<ExitCode>
+{
+	let mut dict = MDictBuilder::new(path).build()?;
+	let mut exporter = KindleExporter::new();
+	if let Some(language) = language {
+		exporter = exporter.input_language(language).output_language(language);
+	}
+	let summary = exporter.write(&mut dict, output)?;
+	println!("{} entries on {} pages, {} resources", summary.entries, summary.pages,
+		summary.resources);
+	for path in &summary.missing {
+		eprintln!("missing resource: {}", path);
+	}
+	Ok(ExitCode::SUCCESS)
+}
+
+fn repair(path: &str, output: &str) -> mdict::Result<ExitCode>
+{
+	let repair = mdict::writer::repair_checksums(path, output)?;
+	for site in &repair.repaired {
+		println!("repaired {:?}", site);
+	}
+	for site in &repair.unchecked {
+		eprintln!("left unchecked: {:?}", site);
+	}
+	println!("{} checksums repaired", repair.repaired.len());
+	Ok(ExitCode::SUCCESS)
+}
+
+fn subset(path: &str, output: &str, words: &str, top: usize) -> mdict::Result<ExitCode>
+{
+	// frequency lists often carry counts after a tab
+	let words = std::fs::read_to_string(words)?;
+	let words = words.lines()
+		.filter_map(|line| line.split('\t').next())
+		.map(str::trim)
+		.filter(|word| !word.is_empty());
+	let count = mdict::writer::subset(path, words, top, output)?;
+	println!("{} entries written", count);
+	Ok(ExitCode::SUCCESS)
+}
+
+fn invert(path: &str, output: &str, targets: &Regex) -> mdict::Result<ExitCode>
+{
+	let count = mdict::writer::invert(path, targets, output)?;
+	println!("{} headwords written", count);
+	Ok(ExitCode::SUCCESS)
+}
+
+fn hexdump(path: &str, key: &str, rebuild: bool) -> mdict::Result<ExitCode>
+{
+	let mut dict = builder(path, rebuild).build()?;
+	match dict.dump_record(key)? {
+		Some(dump) => {
+			print!("{}", dump);
+			Ok(ExitCode::SUCCESS)
+		}
+		None => {
+			eprintln!("mdict: {} not found", key);
+			Ok(ExitCode::FAILURE)
+		}
+	}
+}
+
+/// Definition of `word` as markdown, following `@@@LINK=` redirects
+#[cfg(any(feature = "repl", feature = "tui"))]
+fn definition<M: KeyMaker>(dict: &mut MDict<M>, word: &str) -> mdict::Result<Option<String>>
+{
+	let mut word = word.to_owned();
+	for _ in 0..MAX_REDIRECTS {
+		let definition = match dict.lookup(&word)? {
+			Some(definition) => definition.definition,
+			None => return Ok(None),
+		};
+		match definition.trim().strip_prefix("@@@LINK=") {
+			Some(target) => word = target.trim_end_matches('\0').trim().to_owned(),
+			None => return Ok(Some(to_markdown(&definition))),
+		}
+	}
+	Ok(None)
+}
+
+#[cfg(feature = "repl")]
+fn repl(paths: &[&str], rebuild: bool) -> mdict::Result<ExitCode>
+{
+	let dicts = paths.iter()
+		.map(|path| builder(path, rebuild).build())
+		.collect::<mdict::Result<Vec<_>>>()?;
+	repl::run(dicts)?;
+	Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(not(feature = "repl"))]
+fn repl(_paths: &[&str], _rebuild: bool) -> mdict::Result<ExitCode>
+{
+	eprintln!("mdict: built without the repl feature");
+	Ok(ExitCode::FAILURE)
+}
+
+#[cfg(feature = "tui")]
+fn tui(paths: &[&str], rebuild: bool) -> mdict::Result<ExitCode>
+{
+	let dicts = paths.iter()
+		.map(|path| builder(path, rebuild).build())
+		.collect::<mdict::Result<Vec<_>>>()?;
+	tui::run(dicts)?;
+	Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(not(feature = "tui"))]
+fn tui(_paths: &[&str], _rebuild: bool) -> mdict::Result<ExitCode>
+{
+	eprintln!("mdict: built without the tui feature");
+	Ok(ExitCode::FAILURE)
+}
@@ -0,0 +1,114 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use mdict::{KeyMaker, MDict};
+
+use crate::definition;
+
+const MAX_SUGGESTIONS: usize = 20;
+
+/// Suggests keys starting with the typed text
+struct KeyHelper<M: KeyMaker> {
+	dicts: Rc<RefCell<Vec<MDict<M>>>>,
+}
+
+impl<M: KeyMaker> KeyHelper<M> {
+	fn suggestions(&self, prefix: &str) -> Vec<String>
+	{
+		let dicts = self.dicts.borrow();
+		let mut keys: Vec<String> = dicts.iter()
+			.flat_map(|dict| dict.search_prefix(prefix).take(MAX_SUGGESTIONS))
+			.map(str::to_owned)
+			.collect();
+		keys.sort_by(|a, b| a.chars().count().cmp(&b.chars().count()).then_with(|| a.cmp(b)));
+		keys.dedup();
+		keys.truncate(MAX_SUGGESTIONS);
+		keys
+	}
+}
+
+impl<M: KeyMaker> Completer for KeyHelper<M> {
+	type Candidate = String;
+
+	fn complete(&self, line: &str, _pos: usize, _ctx: &Context<'_>)
+		-> rustyline::Result<(usize, Vec<String>)>
+	{
+		Ok((0, self.suggestions(line)))
+	}
+}
+
+impl<M: KeyMaker> Hinter for KeyHelper<M> {
+	type Hint = String;
+
+	fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String>
+	{
+		if line.is_empty() || pos < line.len() {
+			return None;
+		}
+		self.suggestions(line)
+			.into_iter()
+			.find(|key| key.len() > line.len() && key.starts_with(line))
+			.map(|key| key[line.len()..].to_owned())
+	}
+}
+
+impl<M: KeyMaker> Highlighter for KeyHelper<M> {
+	fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str>
+	{
+		Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+	}
+}
+
+impl<M: KeyMaker> Validator for KeyHelper<M> {}
+
+impl<M: KeyMaker> Helper for KeyHelper<M> {}
+
+fn readline_error(err: ReadlineError) -> mdict::Error
+{
+	std::io::Error::other(err).into()
+}
+
+/// Look up each line in every dictionary until end of input
+pub fn run<M: KeyMaker>(dicts: Vec<MDict<M>>) -> mdict::Result<()>
+{
+	let dicts = Rc::new(RefCell::new(dicts));
+	let mut editor: Editor<KeyHelper<M>, DefaultHistory> = Editor::new().map_err(readline_error)?;
+	editor.set_helper(Some(KeyHelper { dicts: dicts.clone() }));
+	loop {
+		let line = match editor.readline("> ") {
+			Ok(line) => line,
+			Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Ok(()),
+			Err(err) => return Err(readline_error(err)),
+		};
+		let word = line.trim();
+		if word.is_empty() {
+			continue;
+		}
+		editor.add_history_entry(word).map_err(readline_error)?;
+		let mut found = false;
+		for dict in dicts.borrow_mut().iter_mut() {
+			if let Some(definition) = definition(dict, word)? {
+				println!("\x1b[1m{}\x1b[0m\n{}\n", dict.title(), definition);
+				found = true;
+			}
+		}
+		if !found {
+			let suggestions = editor.helper()
+				.map(|helper| helper.suggestions(word))
+				.unwrap_or_default();
+			if suggestions.is_empty() {
+				println!("{} not found", word);
+			} else {
+				println!("{} not found, did you mean: {}", word, suggestions.join(", "));
+			}
+		}
+	}
+}
@@ -0,0 +1,176 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, List, ListState, Paragraph, Tabs, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use mdict::{KeyMaker, MDict};
+
+use crate::definition;
+
+/// A dictionary tab, keys matching the query and the selected definition
+struct Pane<M: KeyMaker> {
+	dict: MDict<M>,
+	// the query `keys` were searched for
+	query: Option<String>,
+	keys: Vec<String>,
+	selected: usize,
+	offset: usize,
+	// key of the loaded definition
+	shown: Option<String>,
+	definition: String,
+	scroll: u16,
+}
+
+impl<M: KeyMaker> Pane<M> {
+	fn new(dict: MDict<M>) -> Self
+	{
+		Pane {
+			dict,
+			query: None,
+			keys: vec![],
+			selected: 0,
+			offset: 0,
+			shown: None,
+			definition: String::new(),
+			scroll: 0,
+		}
+	}
+
+	/// Search `query` if it changed and load the selected definition
+	fn update(&mut self, query: &str) -> mdict::Result<()>
+	{
+		if self.query.as_deref() != Some(query) {
+			self.keys = if query.is_empty() {
+				self.dict.keys().map(str::to_owned).collect()
+			} else {
+				self.dict.search_prefix(query).map(str::to_owned).collect()
+			};
+			self.query = Some(query.to_owned());
+			self.selected = 0;
+			self.offset = 0;
+		}
+		let key = self.keys.get(self.selected);
+		if self.shown.as_ref() != key {
+			self.definition = match key {
+				Some(key) => definition(&mut self.dict, key)?.unwrap_or_default(),
+				None => String::new(),
+			};
+			self.shown = key.cloned();
+			self.scroll = 0;
+		}
+		Ok(())
+	}
+
+	fn select(&mut self, delta: isize)
+	{
+		let last = self.keys.len().saturating_sub(1);
+		self.selected = self.selected.saturating_add_signed(delta).min(last);
+	}
+
+	/// Draw only the visible keys, the list may hold every key
+	fn draw(&mut self, frame: &mut Frame, keys_area: Rect, definition_area: Rect)
+	{
+		let height = usize::from(keys_area.height.saturating_sub(2)).max(1);
+		if self.selected < self.offset {
+			self.offset = self.selected;
+		} else if self.selected >= self.offset + height {
+			self.offset = self.selected + 1 - height;
+		}
+		let end = (self.offset + height).min(self.keys.len());
+		let list = List::new(self.keys[self.offset..end].iter().map(String::as_str))
+			.block(Block::default()
+				.borders(Borders::ALL)
+				.title(format!("{}/{}", self.keys.len().min(self.selected + 1), self.keys.len())))
+			.highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+		let mut state = ListState::default().with_selected(Some(self.selected - self.offset));
+		frame.render_stateful_widget(list, keys_area, &mut state);
+		let definition = Paragraph::new(self.definition.as_str())
+			.block(Block::default().borders(Borders::ALL).title(self.shown.as_deref().unwrap_or("")))
+			.wrap(Wrap { trim: false })
+			.scroll((self.scroll, 0));
+		frame.render_widget(definition, definition_area);
+	}
+}
+
+struct App<M: KeyMaker> {
+	panes: Vec<Pane<M>>,
+	tab: usize,
+	query: String,
+}
+
+impl<M: KeyMaker> App<M> {
+	fn draw(&mut self, frame: &mut Frame)
+	{
+		let [tabs_area, query_area, main_area] = Layout::vertical([
+			Constraint::Length(1),
+			Constraint::Length(1),
+			Constraint::Min(3),
+		]).areas(frame.area());
+		let [keys_area, definition_area] = Layout::horizontal([
+			Constraint::Percentage(30),
+			Constraint::Percentage(70),
+		]).areas(main_area);
+		let tabs = Tabs::new(self.panes.iter().map(|pane| pane.dict.title().to_owned()))
+			.select(self.tab)
+			.highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+		frame.render_widget(tabs, tabs_area);
+		frame.render_widget(Paragraph::new(format!("> {}", self.query)), query_area);
+		self.panes[self.tab].draw(frame, keys_area, definition_area);
+	}
+
+	/// Handle a key press, false to quit
+	fn key(&mut self, code: KeyCode, modifiers: KeyModifiers, page: isize) -> bool
+	{
+		let pane = &mut self.panes[self.tab];
+		let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+		match code {
+			KeyCode::Esc => return false,
+			KeyCode::Char('c') if ctrl => return false,
+			KeyCode::Char('d') if ctrl => pane.scroll = pane.scroll.saturating_add(page as u16),
+			KeyCode::Char('u') if ctrl => pane.scroll = pane.scroll.saturating_sub(page as u16),
+			KeyCode::Char(ch) if !ctrl => self.query.push(ch),
+			KeyCode::Backspace => {
+				self.query.pop();
+			}
+			KeyCode::Up => pane.select(-1),
+			KeyCode::Down => pane.select(1),
+			KeyCode::PageUp => pane.select(-page),
+			KeyCode::PageDown => pane.select(page),
+			KeyCode::Home => pane.selected = 0,
+			KeyCode::End => pane.select(isize::MAX),
+			KeyCode::Tab => self.tab = (self.tab + 1) % self.panes.len(),
+			KeyCode::BackTab => self.tab = (self.tab + self.panes.len() - 1) % self.panes.len(),
+			_ => {}
+		}
+		true
+	}
+
+	fn run(&mut self, terminal: &mut DefaultTerminal) -> mdict::Result<()>
+	{
+		loop {
+			self.panes[self.tab].update(&self.query)?;
+			terminal.draw(|frame| self.draw(frame))?;
+			let page = (terminal.size()?.height.saturating_sub(4) as isize).max(1);
+			if let Event::Key(key) = event::read()? {
+				if key.kind == KeyEventKind::Press && !self.key(key.code, key.modifiers, page) {
+					return Ok(());
+				}
+			}
+		}
+	}
+}
+
+/// Browse the dictionaries in tabs until Esc is pressed
+pub fn run<M: KeyMaker>(dicts: Vec<MDict<M>>) -> mdict::Result<()>
+{
+	let mut app = App {
+		panes: dicts.into_iter().map(Pane::new).collect(),
+		tab: 0,
+		query: String::new(),
+	};
+	let mut terminal = ratatui::init();
+	let result = app.run(&mut terminal);
+	ratatui::restore();
+	result
+}
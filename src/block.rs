@@ -0,0 +1,82 @@
+use crate::format::KeyEntry;
+use crate::mdx::{KeyMaker, MDict, Mdx};
+#[cfg(feature = "rayon")]
+use crate::parser::par_visit_records;
+use crate::parser::{find_entry, read_record_block, record_end};
+use crate::record::Record;
+use crate::Result;
+
+/// Where an entry's record is, without reading it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryInfo<'a> {
+	pub raw: &'a str,
+	pub key: &'a str,
+	/// decoded record length in bytes
+	pub size: usize,
+	/// the record block holding it, see `read_record_block()`
+	pub block: usize,
+}
+
+/// Decoded offset where each record block ends
+pub(crate) fn block_ends(mdx: &Mdx) -> Vec<usize>
+{
+	mdx.records_info.iter()
+		.scan(0usize, |end, info| {
+			*end = end.saturating_add(info.decompressed_size);
+			Some(*end)
+		})
+		.collect()
+}
+
+/// `ends` are the `block_ends()` of `mdx`
+pub(crate) fn entry_info<'a>(mdx: &Mdx, ends: &[usize], entry: &'a KeyEntry)
+	-> Option<EntryInfo<'a>>
+{
+	let block = ends.partition_point(|end| *end <= entry.offset);
+	let end = record_end(&mdx.record_offsets, entry.offset, *ends.get(block)?);
+	Some(EntryInfo { raw: entry.raw(), key: &entry.text, size: end - entry.offset, block })
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Record blocks of the mdx file
+	#[inline]
+	pub fn record_block_count(&self) -> usize
+	{
+		self.mdx.records_info.len()
+	}
+
+	/// The records of the entries stored in record block `index`, in file
+	/// order, none past the last block. Each block is decoded on its own,
+	/// pipelines can share them among workers each with its dictionary
+	#[inline]
+	pub fn read_record_block(&mut self, index: usize) -> Result<Vec<Record<'static>>>
+	{
+		read_record_block(&mut self.mdx, index)
+	}
+
+	/// Record size and block of the entry `word` finds
+	pub fn entry_info(&self, word: &str) -> Option<EntryInfo<'_>>
+	{
+		let key = self.resolve_key(word);
+		entry_info(&self.mdx, &block_ends(&self.mdx), find_entry(&self.mdx, &key)?)
+	}
+
+	/// Record size and block of every entry, in key order like
+	/// `headwords()`. Sorting them by block orders batch work so each
+	/// block is decoded once
+	pub fn entry_infos(&self) -> impl Iterator<Item=EntryInfo<'_>>
+	{
+		let ends = block_ends(&self.mdx);
+		self.mdx.key_entries.iter().filter_map(move |entry| entry_info(&self.mdx, &ends, entry))
+	}
+
+	/// Call `f` with the headword as stored and the record of every entry
+	/// of the mdx file, the record blocks decoded in parallel. Entries of
+	/// a block come in file order, blocks in no particular one
+	#[cfg(feature = "rayon")]
+	#[inline]
+	pub fn par_visit(&mut self, f: impl Fn(&str, &[u8]) + Sync) -> Result<()>
+	{
+		par_visit_records(&mut self.mdx, &f)
+	}
+}
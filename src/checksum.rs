@@ -0,0 +1,51 @@
+#[cfg(not(feature = "simd"))]
+use adler32::RollingAdler32;
+
+/// Checksum of the data stored in mdict files
+pub(crate) trait Checksum {
+	fn hash(data: &[u8]) -> u32;
+
+	#[inline]
+	fn verify(data: &[u8], checksum: u32) -> bool
+	{
+		Self::hash(data) == checksum
+	}
+}
+
+/// adler32 computed a byte at a time
+#[cfg(not(feature = "simd"))]
+pub(crate) struct Adler32;
+
+#[cfg(not(feature = "simd"))]
+impl Checksum for Adler32 {
+	#[inline]
+	fn hash(data: &[u8]) -> u32
+	{
+		RollingAdler32::from_buffer(data).hash()
+	}
+}
+
+/// adler32 with the vector instructions of the cpu
+#[cfg(feature = "simd")]
+pub(crate) struct SimdAdler32;
+
+#[cfg(feature = "simd")]
+impl Checksum for SimdAdler32 {
+	#[inline]
+	fn hash(data: &[u8]) -> u32
+	{
+		simd_adler32::adler32(&data)
+	}
+}
+
+#[cfg(not(feature = "simd"))]
+pub(crate) type Adler = Adler32;
+#[cfg(feature = "simd")]
+pub(crate) type Adler = SimdAdler32;
+
+#[cfg(feature = "std")]
+#[inline]
+pub(crate) fn adler32(data: &[u8]) -> u32
+{
+	Adler::hash(data)
+}
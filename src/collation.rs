@@ -0,0 +1,80 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+pub(crate) type Comparator = Arc<dyn Fn(&str, &str) -> Ordering + Send + Sync>;
+
+/// Order of keys, used both for sorting at load time and for lookup
+#[derive(Clone)]
+pub(crate) enum KeyOrder {
+	Bytes,
+	#[cfg(feature = "icu")]
+	Icu(Arc<icu_collator::Collator>),
+	Custom(Comparator),
+}
+
+impl KeyOrder {
+	#[cfg(feature = "icu")]
+	pub(crate) fn icu(locale: &str) -> crate::Result<Self>
+	{
+		use icu_collator::{Collator, CollatorOptions};
+		use icu_provider::DataLocale;
+
+		let data_locale: DataLocale = locale.parse()
+			.map_err(|_| crate::Error::InvalidLocale(locale.to_owned()))?;
+		let collator = Collator::try_new(&data_locale, CollatorOptions::new())
+			.map_err(|_| crate::Error::InvalidLocale(locale.to_owned()))?;
+		Ok(KeyOrder::Icu(Arc::new(collator)))
+	}
+
+	#[inline]
+	pub(crate) fn compare(&self, a: &str, b: &str) -> Ordering
+	{
+		match self {
+			KeyOrder::Bytes => a.cmp(b),
+			// keys the collator ranks equal, e.g. differing in ignorable
+			// characters only, are still told apart
+			#[cfg(feature = "icu")]
+			KeyOrder::Icu(collator) => collator.compare(a, b).then_with(|| a.cmp(b)),
+			KeyOrder::Custom(compare) => compare(a, b),
+		}
+	}
+}
+
+/// Byte-wise order with runs of ascii digits compared by value, e.g.
+/// `"2" < "10"`. Texts equal by value, e.g. `"02"` and `"2"`, fall back
+/// to byte-wise order, so only equal texts compare equal
+pub(crate) fn natural_cmp(a: &str, b: &str) -> Ordering
+{
+	let (mut a_rest, mut b_rest) = (a.as_bytes(), b.as_bytes());
+	loop {
+		match (a_rest.first(), b_rest.first()) {
+			(Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+				let (a_digits, a_tail) = split_digits(a_rest);
+				let (b_digits, b_tail) = split_digits(b_rest);
+				let a_value = trim_zeros(a_digits);
+				let b_value = trim_zeros(b_digits);
+				let order = a_value.len().cmp(&b_value.len()).then_with(|| a_value.cmp(b_value));
+				if order != Ordering::Equal {
+					return order;
+				}
+				(a_rest, b_rest) = (a_tail, b_tail);
+			}
+			(Some(x), Some(y)) if x == y => {
+				(a_rest, b_rest) = (&a_rest[1..], &b_rest[1..]);
+			}
+			(x, y) => return x.cmp(&y).then_with(|| a.cmp(b)),
+		}
+	}
+}
+
+#[inline]
+fn split_digits(text: &[u8]) -> (&[u8], &[u8])
+{
+	text.split_at(text.iter().position(|ch| !ch.is_ascii_digit()).unwrap_or(text.len()))
+}
+
+#[inline]
+fn trim_zeros(digits: &[u8]) -> &[u8]
+{
+	&digits[digits.iter().position(|ch| *ch != b'0').unwrap_or(digits.len())..]
+}
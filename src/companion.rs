@@ -0,0 +1,143 @@
+use std::fs;
+
+use crate::mdx::{KeyMaker, MDict};
+use crate::Result;
+
+/// Files shipped beside the mdx file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompanionKind {
+	Mdd,
+	Css,
+	Js,
+	Ini,
+	Icon,
+}
+
+impl CompanionKind {
+	/// Kinds looked up as `<name>.<extension>`, mdd files are found while
+	/// loading
+	pub(crate) const SIBLINGS: [(CompanionKind, &'static str); 9] = [
+		(CompanionKind::Css, "css"),
+		(CompanionKind::Js, "js"),
+		(CompanionKind::Ini, "ini"),
+		(CompanionKind::Icon, "png"),
+		(CompanionKind::Icon, "jpg"),
+		(CompanionKind::Icon, "gif"),
+		(CompanionKind::Icon, "bmp"),
+		(CompanionKind::Icon, "ico"),
+		(CompanionKind::Icon, "svg"),
+	];
+}
+
+#[derive(Debug, Clone)]
+pub struct Companion {
+	pub kind: CompanionKind,
+	/// file name, relative to the mdx directory or archive
+	pub name: String,
+}
+
+// logo paths conventionally stored in the mdd
+const MDD_ICONS: [&str; 4] = ["\\logo.png", "\\logo.jpg", "\\icon.png", "\\favicon.ico"];
+
+#[derive(Debug, Clone)]
+pub struct Icon {
+	pub data: Vec<u8>,
+	pub mime: &'static str,
+}
+
+/// MIME type of an image by its leading bytes
+pub(crate) fn image_mime(data: &[u8]) -> &'static str
+{
+	if data.starts_with(b"\x89PNG") {
+		"image/png"
+	} else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+		"image/jpeg"
+	} else if data.starts_with(b"GIF8") {
+		"image/gif"
+	} else if data.starts_with(b"BM") {
+		"image/bmp"
+	} else if data.starts_with(&[0, 0, 1, 0]) {
+		"image/x-icon"
+	} else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+		"image/webp"
+	} else if is_svg(data) {
+		"image/svg+xml"
+	} else {
+		"application/octet-stream"
+	}
+}
+
+/// Whether `data` starts with an `<svg` element, after an optional xml
+/// declaration, doctype and comments
+fn is_svg(data: &[u8]) -> bool
+{
+	let mut rest = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+	loop {
+		rest = rest.trim_ascii_start();
+		let end: &[u8] = if rest.starts_with(b"<?") {
+			b"?>"
+		} else if rest.starts_with(b"<!--") {
+			b"-->"
+		} else if rest.starts_with(b"<!") {
+			b">"
+		} else {
+			break;
+		};
+		match rest.windows(end.len()).position(|window| window == end) {
+			Some(idx) => rest = &rest[idx + end.len()..],
+			None => return false,
+		}
+	}
+	rest.starts_with(b"<svg")
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Companion files found when the dictionary was opened
+	#[inline]
+	pub fn companions(&self) -> &[Companion]
+	{
+		&self.companions
+	}
+
+	/// Content of the first companion file of `kind`
+	pub fn read_companion(&mut self, kind: CompanionKind) -> Result<Option<Vec<u8>>>
+	{
+		let name = match self.companions.iter().find(|companion| companion.kind == kind) {
+			Some(companion) => companion.name.clone(),
+			None => return Ok(None),
+		};
+		self.read_file(&name)
+	}
+
+	/// The dictionary logo from the mdd files, or the image file named
+	/// after the mdx file
+	pub fn icon(&mut self) -> Result<Option<Icon>>
+	{
+		let mut data = None;
+		for path in MDD_ICONS {
+			if let Some(record) = self.resource_record(path)? {
+				data = Some(record.into_data().into_owned());
+				break;
+			}
+		}
+		if data.is_none() {
+			data = self.read_companion(CompanionKind::Icon)?;
+		}
+		Ok(data.map(|data| Icon { mime: image_mime(&data), data }))
+	}
+
+	/// Content of the file `name` beside the mdx file or in its archive
+	pub(crate) fn read_file(&mut self, name: &str) -> Result<Option<Vec<u8>>>
+	{
+		#[cfg(feature = "zip")]
+		if let Some(archive) = &mut self.archive {
+			return archive.read(name);
+		}
+		let path = self.dir.join(name.trim_start_matches(['\\', '/']).replace('\\', "/"));
+		if path.is_file() {
+			Ok(Some(fs::read(path)?))
+		} else {
+			Ok(None)
+		}
+	}
+}
@@ -0,0 +1,190 @@
+//! Lookups from many threads: each thread reading the files with handles
+//! of its own, sharing one loaded key index
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+
+use crate::format::Salsa20Variant;
+use crate::mdx::{KeyMaker, MDict, Mdx, Reader};
+use crate::parser::{read_shared, Scratch};
+use crate::record::SharedRecord;
+use crate::{Error, Result};
+
+// what a lookup reads one mdx or mdd file with
+struct Handle {
+	reader: Reader,
+	scratch: Scratch,
+	salsa20: Salsa20Variant,
+}
+
+impl Handle {
+	fn reopen(mdx: &Mdx) -> Result<Self>
+	{
+		let source = mdx.source.as_deref().ok_or(Error::NoSource)?;
+		#[cfg(all(feature = "io-uring", target_os = "linux"))]
+		if let Reader::Uring(_) = mdx.reader {
+			let file = crate::uring::UringFile::open(source)?;
+			return Ok(Handle::new(mdx, Reader::Uring(Box::new(file))));
+		}
+		Ok(Handle::new(mdx, Reader::open(source)?))
+	}
+
+	#[inline]
+	fn new(mdx: &Mdx, reader: Reader) -> Self
+	{
+		Handle { reader, scratch: Scratch::default(), salsa20: mdx.salsa20 }
+	}
+}
+
+// handle sets by dictionary id, one handle per file, with whether the
+// dictionary is still around
+type HandleSets = HashMap<u64, (Weak<()>, Vec<Handle>)>;
+
+thread_local! {
+	static HANDLES: RefCell<HandleSets> = RefCell::new(HashMap::new());
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A dictionary shared by threads, each reading the mdx and mdd files with
+/// handles of its own, reopened on its first lookup, so lookups take no
+/// lock and don't wait on each other. The handles of a thread close when
+/// it exits or reopens files after the dictionary is dropped. The record
+/// cache of the dictionary isn't used
+pub struct ConcurrentMdx<M: KeyMaker> {
+	dict: MDict<M>,
+	id: u64,
+	alive: Arc<()>,
+}
+
+impl<M: KeyMaker> ConcurrentMdx<M> {
+	/// Share `dict` between threads. Fails with [`Error::NoSource`] for
+	/// dictionaries not read from files, e.g. archive members
+	pub fn new(dict: MDict<M>) -> Result<Self>
+	{
+		if std::iter::once(&dict.mdx).chain(&dict.resources).any(|mdx| mdx.source.is_none()) {
+			return Err(Error::NoSource);
+		}
+		let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+		Ok(ConcurrentMdx { dict, id, alive: Arc::new(()) })
+	}
+
+	/// The dictionary, for what doesn't read records: keys, searches
+	#[inline]
+	pub fn dict(&self) -> &MDict<M>
+	{
+		&self.dict
+	}
+
+	/// The definition of `word` with the transformer applied, see
+	/// [`MDict::lookup`]
+	pub fn lookup(&self, word: &str) -> Result<Option<String>>
+	{
+		let key = self.dict.resolve_key(word);
+		self.run(move |dict, handles| {
+			let Some(record) = read_record(&dict.mdx, &key, &mut handles[0])? else {
+				return Ok(None);
+			};
+			let definition = record.text()?;
+			Ok(Some(match &dict.transformer {
+				Some(transformer) => transformer.transform(&definition),
+				None => definition.into_owned(),
+			}))
+		})
+	}
+
+	/// See [`MDict::lookup_shared`]
+	pub fn lookup_shared(&self, word: &str) -> Result<Option<SharedRecord>>
+	{
+		let key = self.dict.resolve_key(word);
+		self.run(move |dict, handles| read_record(&dict.mdx, &key, &mut handles[0]))
+	}
+
+	/// See [`MDict::resource_shared`]
+	pub fn resource_shared(&self, path: &str) -> Result<Option<SharedRecord>>
+	{
+		let key = self.dict.key_maker.make(&Cow::Borrowed(path), true);
+		self.run(move |dict, handles| {
+			for (mdx, handle) in dict.resources.iter().zip(&mut handles[1..]) {
+				if let Some(record) = read_record(mdx, &key, handle)? {
+					return Ok(Some(record));
+				}
+			}
+			Ok(None)
+		})
+	}
+
+	/// Run `job` with the handle set of this thread, reopening the files
+	/// on its first lookup
+	fn run<T>(&self, job: impl FnOnce(&MDict<M>, &mut [Handle]) -> Result<T>) -> Result<T>
+	{
+		// taken out while in use, a nested lookup reopens the files
+		let handles = match HANDLES.with(|sets| sets.borrow_mut().remove(&self.id)) {
+			Some((_, handles)) => handles,
+			None => self.reopen()?,
+		};
+		let mut handles = CheckedOut { owner: self, handles };
+		job(&self.dict, &mut handles)
+	}
+
+	fn reopen(&self) -> Result<Vec<Handle>>
+	{
+		HANDLES.with(|sets| sets.borrow_mut().retain(|_, (alive, _)| alive.strong_count() > 0));
+		std::iter::once(&self.dict.mdx)
+			.chain(&self.dict.resources)
+			.map(Handle::reopen)
+			.collect()
+	}
+}
+
+impl<M: KeyMaker> Drop for ConcurrentMdx<M> {
+	fn drop(&mut self)
+	{
+		let _ = HANDLES.try_with(|sets| sets.borrow_mut().remove(&self.id));
+	}
+}
+
+// the handle set of a thread in use, back with the thread on drop, also
+// when the lookup panics
+struct CheckedOut<'a, M: KeyMaker> {
+	owner: &'a ConcurrentMdx<M>,
+	handles: Vec<Handle>,
+}
+
+impl<M: KeyMaker> Deref for CheckedOut<'_, M> {
+	type Target = [Handle];
+
+	#[inline]
+	fn deref(&self) -> &[Handle]
+	{
+		&self.handles
+	}
+}
+
+impl<M: KeyMaker> DerefMut for CheckedOut<'_, M> {
+	#[inline]
+	fn deref_mut(&mut self) -> &mut [Handle]
+	{
+		&mut self.handles
+	}
+}
+
+impl<M: KeyMaker> Drop for CheckedOut<'_, M> {
+	fn drop(&mut self)
+	{
+		let handles = std::mem::take(&mut self.handles);
+		let alive = Arc::downgrade(&self.owner.alive);
+		// a thread exiting closes them instead
+		let _ = HANDLES.try_with(|sets| sets.borrow_mut().insert(self.owner.id, (alive, handles)));
+	}
+}
+
+#[inline]
+fn read_record(mdx: &Mdx, key: &str, handle: &mut Handle) -> Result<Option<SharedRecord>>
+{
+	read_shared(mdx, key, &mut handle.reader, &mut handle.scratch, &mut handle.salsa20)
+}
@@ -0,0 +1,159 @@
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+
+use crate::format::decode_slice_string;
+use crate::lint::link_targets;
+use crate::mdx::{KeyMaker, MDict};
+use crate::parser::{for_each_record, for_each_record_where};
+use crate::Result;
+
+// links followed to find the entry a redirect ends at
+const MAX_LINKS: usize = 8;
+
+/// Headwords sharing one definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AliasGroup {
+	/// the headword keeping the definition, the first one stored
+	pub canonical: String,
+	/// headwords with an identical record or an `@@@LINK=` to another
+	/// headword of the group
+	pub aliases: Vec<String>,
+}
+
+// aliases by record offset and headword, homographs may differ in either
+pub(crate) struct Group {
+	pub(crate) canonical: String,
+	pub(crate) aliases: Vec<(usize, String)>,
+}
+
+// distinct records of one hash by their first offset, and the offsets
+// of the hash left to read
+struct Colliding {
+	records: Vec<(usize, Vec<u8>)>,
+	left: usize,
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Group headwords whose records are identical or redirect to each
+	/// other. Records are told apart by a hash, those with the same hash
+	/// at different offsets are read again to compare them
+	pub fn find_aliases(&mut self) -> Result<Vec<AliasGroup>>
+	{
+		let groups = self.alias_groups()?
+			.into_iter()
+			.map(|group| AliasGroup {
+				canonical: group.canonical,
+				aliases: group.aliases.into_iter().map(|(_, alias)| alias).collect(),
+			})
+			.collect();
+		Ok(groups)
+	}
+
+	pub(crate) fn alias_groups(&mut self) -> Result<Vec<Group>>
+	{
+		let encoding = self.mdx.encoding;
+		let key_maker = &self.key_maker;
+		let hasher = RandomState::new();
+		// entries in record order, and record offsets by hash
+		let mut entries: Vec<(usize, String, String)> = vec![];
+		let mut offsets: HashMap<u64, Vec<usize>> = HashMap::new();
+		let mut links = vec![];
+		for_each_record(&mut self.mdx, |entry, data| {
+			let definition = decode_slice_string(data, encoding)?.0;
+			if definition.starts_with("@@@LINK=") {
+				if let Some(target) = link_targets(&definition).pop() {
+					let target = key_maker.make(&Cow::Owned(target), false);
+					links.push((entry.text.clone(), (entry.offset, entry.raw().to_owned()), target));
+				}
+				return Ok(());
+			}
+			let hash = hasher.hash_one(data);
+			let same = offsets.entry(hash).or_default();
+			if same.last() != Some(&entry.offset) {
+				same.push(entry.offset);
+			}
+			entries.push((entry.offset, entry.raw().to_owned(), entry.text.clone()));
+			Ok(())
+		})?;
+
+		// the first offset of an identical record, for records whose hash
+		// another record has
+		let mut same_as: HashMap<usize, usize> = HashMap::new();
+		let compared: HashSet<usize> = offsets.values()
+			.filter(|same| same.len() > 1)
+			.flatten()
+			.copied()
+			.collect();
+		if !compared.is_empty() {
+			let mut colliding: HashMap<u64, Colliding> = offsets.iter()
+				.filter(|(_, same)| same.len() > 1)
+				.map(|(hash, same)| (*hash, Colliding { records: vec![], left: same.len() }))
+				.collect();
+			for_each_record_where(&mut self.mdx, |entry| compared.contains(&entry.offset),
+				|entry, data| {
+					if same_as.contains_key(&entry.offset) {
+						return Ok(());
+					}
+					let hash = hasher.hash_one(data);
+					let Some(same) = colliding.get_mut(&hash) else {
+						return Ok(());
+					};
+					let first = match same.records.iter().find(|(_, record)| record == data) {
+						Some((first, _)) => *first,
+						None => {
+							same.records.push((entry.offset, data.to_vec()));
+							entry.offset
+						}
+					};
+					same_as.insert(entry.offset, first);
+					same.left -= 1;
+					if same.left == 0 {
+						colliding.remove(&hash);
+					}
+					Ok(())
+				})?;
+		}
+
+		let mut groups: Vec<Group> = vec![];
+		let mut group_of: HashMap<String, usize> = HashMap::new();
+		let mut group_at: HashMap<usize, usize> = HashMap::new();
+		for (offset, raw, text) in entries {
+			let first = same_as.get(&offset).copied().unwrap_or(offset);
+			let group = match group_at.get(&first) {
+				Some(group) => {
+					groups[*group].aliases.push((offset, raw));
+					*group
+				}
+				None => {
+					let group = groups.len();
+					group_at.insert(first, group);
+					groups.push(Group { canonical: raw, aliases: vec![] });
+					group
+				}
+			};
+			group_of.entry(text).or_insert(group);
+		}
+
+		let redirects: HashMap<&str, &str> = links.iter()
+			.map(|(key, _, target)| (key.as_str(), target.as_str()))
+			.collect();
+		for (_, alias, target) in &links {
+			let mut target = target.as_str();
+			for _ in 0..MAX_LINKS {
+				if let Some(group) = group_of.get(target) {
+					groups[*group].aliases.push(alias.clone());
+					break;
+				}
+				match redirects.get(target) {
+					Some(next) => target = next,
+					None => break,
+				}
+			}
+		}
+		groups.retain(|group| !group.aliases.is_empty());
+		groups.sort_by(|a, b| a.canonical.cmp(&b.canonical));
+		Ok(groups)
+	}
+}
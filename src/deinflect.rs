@@ -0,0 +1,216 @@
+//! Japanese verb and adjective deinflection, e.g. 食べました => 食べる
+//!
+//! Rules carry the word types they accept and produce, like yomichan's
+//! deinflector, so only grammatical chains are followed.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+use crate::mdx::{KeyMaker, MDict, WordDefinition};
+use crate::parser::find_entry;
+use crate::Result;
+
+const V1: u8 = 1;
+const V5: u8 = 1 << 1;
+const VS: u8 = 1 << 2;
+const VK: u8 = 1 << 3;
+const ADJ_I: u8 = 1 << 4;
+const MASU: u8 = 1 << 5;
+const TE: u8 = 1 << 6;
+// types of forms found as dictionary headwords
+const DICTIONARY_FORM: u8 = V1 | V5 | VS | VK | ADJ_I;
+const MAX_DEPTH: usize = 5;
+
+struct Rule {
+	from: String,
+	to: &'static str,
+	reason: &'static str,
+	// 0 means the rule only applies to the word as given
+	types_in: u8,
+	types_out: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deinflection {
+	/// candidate dictionary form
+	pub form: String,
+	/// applied rules, outermost first
+	pub reasons: Vec<&'static str>,
+}
+
+// (dictionary ending, i-stem, a-stem, e-stem, o-stem, te-form)
+const GODAN: [(&str, &str, &str, &str, &str, &str); 9] = [
+	("う", "い", "わ", "え", "お", "って"),
+	("く", "き", "か", "け", "こ", "いて"),
+	("ぐ", "ぎ", "が", "げ", "ご", "いで"),
+	("す", "し", "さ", "せ", "そ", "して"),
+	("つ", "ち", "た", "て", "と", "って"),
+	("ぬ", "に", "な", "ね", "の", "んで"),
+	("ぶ", "び", "ば", "べ", "ぼ", "んで"),
+	("む", "み", "ま", "め", "も", "んで"),
+	("る", "り", "ら", "れ", "ろ", "って"),
+];
+
+fn rules() -> &'static [Rule]
+{
+	static RULES: OnceLock<Vec<Rule>> = OnceLock::new();
+	RULES.get_or_init(|| {
+		let mut rules = vec![];
+		let mut add = |from: String, to: &'static str, reason: &'static str, types_in: u8, types_out: u8|
+			rules.push(Rule { from, to, reason, types_in, types_out });
+
+		for (u, i, a, e, o, te) in GODAN {
+			let ta = te.replace('て', "た").replace('で', "だ");
+			add(format!("{}ます", i), u, "polite", MASU, V5);
+			add(format!("{}たい", i), u, "desire", ADJ_I, V5);
+			add(format!("{}ない", a), u, "negative", ADJ_I, V5);
+			add(format!("{}れる", a), u, "passive", V1, V5);
+			add(format!("{}せる", a), u, "causative", V1, V5);
+			add(format!("{}る", e), u, "potential", V1, V5);
+			add(format!("{}ば", e), u, "conditional", 0, V5);
+			add(e.to_owned(), u, "imperative", 0, V5);
+			add(format!("{}う", o), u, "volitional", 0, V5);
+			add(te.to_owned(), u, "te", TE, V5);
+			add(ta, u, "past", 0, V5);
+		}
+		// 行く is irregular in te and past
+		add("行って".to_owned(), "行く", "te", TE, V5);
+		add("行った".to_owned(), "行く", "past", 0, V5);
+		add("いって".to_owned(), "いく", "te", TE, V5);
+		add("いった".to_owned(), "いく", "past", 0, V5);
+
+		for (from, reason, types_in) in [
+			("ます", "polite", MASU),
+			("たい", "desire", ADJ_I),
+			("ない", "negative", ADJ_I),
+			("られる", "passive", V1),
+			("させる", "causative", V1),
+			("れば", "conditional", 0),
+			("ろ", "imperative", 0),
+			("よう", "volitional", 0),
+			("て", "te", TE),
+			("た", "past", 0),
+		] {
+			add(from.to_owned(), "る", reason, types_in, V1);
+		}
+
+		for (from, reason, types_in) in [
+			("します", "polite", MASU),
+			("したい", "desire", ADJ_I),
+			("しない", "negative", ADJ_I),
+			("される", "passive", V1),
+			("させる", "causative", V1),
+			("すれば", "conditional", 0),
+			("しろ", "imperative", 0),
+			("しよう", "volitional", 0),
+			("して", "te", TE),
+			("した", "past", 0),
+		] {
+			add(from.to_owned(), "する", reason, types_in, VS);
+		}
+
+		for (from, reason, types_in) in [
+			("きます", "polite", MASU),
+			("きたい", "desire", ADJ_I),
+			("こない", "negative", ADJ_I),
+			("こられる", "passive", V1),
+			("こさせる", "causative", V1),
+			("くれば", "conditional", 0),
+			("こい", "imperative", 0),
+			("こよう", "volitional", 0),
+			("きて", "te", TE),
+			("きた", "past", 0),
+		] {
+			add(from.to_owned(), "くる", reason, types_in, VK);
+		}
+
+		for (from, reason, types_in) in [
+			("かった", "past", 0),
+			("くない", "negative", ADJ_I),
+			("くて", "te", 0),
+			("ければ", "conditional", 0),
+			("く", "adverb", 0),
+			("さ", "noun", 0),
+			("そう", "appearance", 0),
+		] {
+			add(from.to_owned(), "い", reason, types_in, ADJ_I);
+		}
+
+		for (from, reason) in [
+			("ました", "past"),
+			("ません", "negative"),
+			("ませんでした", "negative past"),
+			("ましょう", "volitional"),
+		] {
+			add(from.to_owned(), "ます", reason, 0, MASU);
+		}
+		for (from, to) in [("ている", "て"), ("てる", "て"), ("でいる", "で"), ("でる", "で")] {
+			add(from.to_owned(), to, "progressive", V1, TE);
+		}
+		rules
+	})
+}
+
+/// All candidate dictionary forms of `word`, `word` itself first, then by
+/// number of applied rules
+pub fn deinflect(word: &str) -> Vec<Deinflection>
+{
+	let mut results = vec![Deinflection { form: word.to_owned(), reasons: vec![] }];
+	let mut types = vec![0u8];
+	let mut seen = HashSet::new();
+	let mut start = 0;
+	for _ in 0..MAX_DEPTH {
+		let end = results.len();
+		for idx in start..end {
+			let current_types = types[idx];
+			for rule in rules() {
+				if current_types != 0 && current_types & rule.types_in == 0 {
+					continue;
+				}
+				let form = &results[idx].form;
+				if let Some(stem) = form.strip_suffix(rule.from.as_str()) {
+					if stem.is_empty() {
+						continue;
+					}
+					let form = format!("{}{}", stem, rule.to);
+					if !seen.insert((form.clone(), rule.types_out)) {
+						continue;
+					}
+					let mut reasons = vec![rule.reason];
+					reasons.extend_from_slice(&results[idx].reasons);
+					results.push(Deinflection { form, reasons });
+					types.push(rule.types_out);
+				}
+			}
+		}
+		if results.len() == end {
+			break;
+		}
+		start = end;
+	}
+	results.into_iter()
+		.zip(types)
+		.filter(|(_, types)| *types == 0 || types & DICTIONARY_FORM != 0)
+		.map(|(deinflection, _)| deinflection)
+		.collect()
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Lookup the first dictionary form of `word` that is a key
+	pub fn lookup_deinflected<'a>(&mut self, word: &'a str)
+		-> Result<Option<(WordDefinition<'a>, Deinflection)>>
+	{
+		for deinflection in deinflect(word) {
+			let key = self.key_maker.make(&Cow::Borrowed(&deinflection.form), false);
+			if find_entry(&self.mdx, &key).is_none() {
+				continue;
+			}
+			if let Some(found) = self.lookup(&deinflection.form)? {
+				let definition = WordDefinition { key: word, definition: found.definition };
+				return Ok(Some((definition, deinflection)));
+			}
+		}
+		Ok(None)
+	}
+}
@@ -0,0 +1,299 @@
+//! EPUB 3 export, the entries in a chapter per first letter, optionally
+//! marked up as an EPUB dictionary that readers look words up in
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Seek, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::companion::CompanionKind;
+use crate::mdx::{KeyMaker, MDict};
+use crate::site::{escape_text, forms, media_type, resource_file, resource_url, rewrite, slug,
+	to_xhtml, unslug, Articles, SiteSummary};
+use crate::template::escape_attr;
+use crate::Result;
+
+const CONTAINER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+	<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+	<rootfiles>\n\
+	<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\n\
+	</rootfiles>\n\
+	</container>\n";
+
+/// Writes a dictionary as an EPUB 3 book, the resources referenced
+/// included
+pub struct EpubExporter {
+	language: String,
+	dictionary: bool,
+}
+
+impl Default for EpubExporter {
+	#[inline]
+	fn default() -> Self
+	{
+		EpubExporter { language: "und".to_owned(), dictionary: false }
+	}
+}
+
+impl EpubExporter {
+	#[inline]
+	pub fn new() -> Self
+	{
+		Self::default()
+	}
+
+	/// BCP 47 tag of the content, undetermined by default
+	#[inline]
+	pub fn language(mut self, language: impl Into<String>) -> Self
+	{
+		self.language = language.into();
+		self
+	}
+
+	/// Mark entries up as an EPUB dictionary with a search key map,
+	/// the headwords as stored and the aliases set on the dictionary
+	/// listed as inflections of their key
+	#[inline]
+	pub fn dictionary(mut self, dictionary: bool) -> Self
+	{
+		self.dictionary = dictionary;
+		self
+	}
+
+	pub fn write<M: KeyMaker>(&self, dict: &mut MDict<M>, path: impl AsRef<Path>)
+		-> Result<SiteSummary>
+	{
+		let mut file = BufWriter::new(File::create(path)?);
+		let summary = self.write_to(dict, &mut file)?;
+		file.flush()?;
+		Ok(summary)
+	}
+
+	pub fn write_to<M: KeyMaker, W: Write + Seek>(&self, dict: &mut MDict<M>, writer: W)
+		-> Result<SiteSummary>
+	{
+		let mut zip = ZipWriter::new(writer);
+		let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+		let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+		// must come first and uncompressed
+		zip.start_file("mimetype", stored)?;
+		zip.write_all(b"application/epub+zip")?;
+		zip.start_file("META-INF/container.xml", deflated)?;
+		zip.write_all(CONTAINER.as_bytes())?;
+
+		let css = dict.read_companion(CompanionKind::Css)?;
+		if let Some(css) = &css {
+			zip.start_file("OEBPS/style.css", deflated)?;
+			zip.write_all(css)?;
+		}
+		let title = escape_text(dict.title());
+		let language = escape_attr(&self.language);
+
+		let stylesheet = if css.is_some() {
+			"<link rel=\"stylesheet\" type=\"text/css\" href=\"../style.css\"/>\n"
+		} else {
+			""
+		};
+
+		// keys of a chapter are adjacent in key order, so chapters are
+		// written one at a time
+		let mut chapters: Vec<String> = vec![];
+		let mut entries = 0;
+		let mut articles = Articles::new(dict);
+		let mut resources = BTreeSet::new();
+		let resource_href = |path: &str| format!("../res/{}", resource_url(path));
+		while let Some((key, articles)) = articles.next(dict)? {
+			let name = chapter(&key);
+			if chapters.last() != Some(&name) {
+				if !chapters.is_empty() {
+					zip.write_all(b"</body>\n</html>\n")?;
+				}
+				zip.start_file(format!("OEBPS/entries/{}", name), deflated)?;
+				write!(zip, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n\
+					<html xmlns=\"http://www.w3.org/1999/xhtml\" \
+					xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{}\">\n\
+					<head>\n<title>{}</title>\n{}</head>\n<body>\n",
+					language, title, stylesheet)?;
+				chapters.push(name);
+			}
+			let entry_href = |word: &str| {
+				let key = dict.find_key(word)?;
+				Some(format!("{}#{}", chapter(key), slug(key)))
+			};
+			if self.dictionary {
+				writeln!(zip, "<article epub:type=\"dictentry\" id=\"{}\">",
+					escape_attr(&slug(&key)))?;
+			} else {
+				writeln!(zip, "<section id=\"{}\">", escape_attr(&slug(&key)))?;
+			}
+			for article in &articles {
+				let html = rewrite(&article.html, &entry_href, &resource_href, &mut resources);
+				let headword = escape_text(&article.headword);
+				if self.dictionary {
+					writeln!(zip, "<h2><dfn>{}</dfn></h2>", headword)?;
+				} else {
+					writeln!(zip, "<h2>{}</h2>", headword)?;
+				}
+				writeln!(zip, "<div>{}</div>", to_xhtml(&html))?;
+			}
+			zip.write_all(if self.dictionary { b"</article>\n" } else { b"</section>\n" })?;
+			entries += articles.len();
+		}
+		if !chapters.is_empty() {
+			zip.write_all(b"</body>\n</html>\n")?;
+		}
+
+		let mut manifest = String::new();
+		let mut included = 0;
+		let mut missing = vec![];
+		for (idx, path) in resources.iter().enumerate() {
+			let Some(data) = dict.get_resource(path)? else {
+				missing.push(path.clone());
+				continue;
+			};
+			let url = resource_url(path);
+			let file: Vec<_> = resource_file(path).iter()
+				.map(|part| part.to_string_lossy().into_owned())
+				.collect();
+			zip.start_file(format!("OEBPS/res/{}", file.join("/")), deflated)?;
+			zip.write_all(&data)?;
+			let _ = writeln!(manifest, "<item id=\"r{}\" href=\"res/{}\" media-type=\"{}\"/>",
+				idx, escape_attr(&url), media_type(path));
+			included += 1;
+		}
+		if css.is_some() {
+			manifest.push_str("<item id=\"css\" href=\"style.css\" media-type=\"text/css\"/>\n");
+		}
+
+		let mut spine = String::new();
+		let mut toc = String::new();
+		let mut points = String::new();
+		for (idx, name) in chapters.iter().enumerate() {
+			let href = format!("entries/{}", escape_attr(name));
+			let label = escape_text(&letter(name));
+			let _ = writeln!(manifest, "<item id=\"c{}\" href=\"{}\" \
+				media-type=\"application/xhtml+xml\"/>", idx, href);
+			let _ = writeln!(spine, "<itemref idref=\"c{}\"/>", idx);
+			let _ = writeln!(toc, "<li><a href=\"{}\">{}</a></li>", href, label);
+			let _ = writeln!(points, "<navPoint id=\"p{}\" playOrder=\"{}\">\
+				<navLabel><text>{}</text></navLabel><content src=\"{}\"/></navPoint>",
+				idx, idx + 1, label, href);
+		}
+		zip.start_file("OEBPS/nav.xhtml", deflated)?;
+		write!(zip, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n\
+			<html xmlns=\"http://www.w3.org/1999/xhtml\" \
+			xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{}\">\n\
+			<head>\n<title>{}</title>\n</head>\n<body>\n<nav epub:type=\"toc\" id=\"toc\">\n\
+			<h1>{}</h1>\n<ol>\n{}</ol>\n</nav>\n</body>\n</html>\n",
+			language, title, title, toc)?;
+		zip.start_file("OEBPS/toc.ncx", deflated)?;
+		write!(zip, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+			<ncx xmlns=\"http://www.daisy.org/z3986/2005/ncx/\" version=\"2005-1\">\n\
+			<head><meta name=\"dtb:uid\" content=\"{}\"/></head>\n\
+			<docTitle><text>{}</text></docTitle>\n<navMap>\n{}</navMap>\n</ncx>\n",
+			identifier(dict.title()), title, points)?;
+
+		let mut metadata = String::new();
+		let mut collection = String::new();
+		if self.dictionary {
+			zip.start_file("OEBPS/search.xml", deflated)?;
+			zip.write_all(self.search_key_map(dict).as_bytes())?;
+			manifest.push_str("<item id=\"search\" href=\"search.xml\" \
+				media-type=\"application/vnd.epub.search-key-map+xml\" \
+				properties=\"search-key-map\"/>\n");
+			let _ = write!(metadata, "<dc:type>dictionary</dc:type>\n\
+				<meta property=\"source-language\">{}</meta>\n\
+				<meta property=\"target-language\">{}</meta>\n", language, language);
+			collection.push_str("<collection role=\"dictionary\">\n<link href=\"search.xml\"/>\n");
+			for name in &chapters {
+				let _ = writeln!(collection, "<link href=\"entries/{}\"/>", escape_attr(name));
+			}
+			collection.push_str("</collection>\n");
+		}
+		zip.start_file("OEBPS/content.opf", deflated)?;
+		write!(zip, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+			<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" \
+			unique-identifier=\"uid\">\n\
+			<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+			<dc:identifier id=\"uid\">{}</dc:identifier>\n<dc:title>{}</dc:title>\n\
+			<dc:language>{}</dc:language>\n{}\
+			<meta property=\"dcterms:modified\">{}</meta>\n</metadata>\n\
+			<manifest>\n<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" \
+			properties=\"nav\"/>\n\
+			<item id=\"ncx\" href=\"toc.ncx\" media-type=\"application/x-dtbncx+xml\"/>\n\
+			{}</manifest>\n<spine toc=\"ncx\">\n{}</spine>\n{}</package>\n",
+			identifier(dict.title()), title, language, metadata, timestamp(SystemTime::now()),
+			manifest, spine, collection)?;
+		zip.finish()?;
+
+		Ok(SiteSummary {
+			pages: chapters.len(),
+			entries,
+			resources: included,
+			missing,
+		})
+	}
+
+	/// Every key with the other forms leading to it
+	fn search_key_map<M: KeyMaker>(&self, dict: &MDict<M>) -> String
+	{
+		let mut map = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+			<search-key-map xmlns=\"http://www.idpf.org/2007/ops\" xml:lang=\"{}\">\n",
+			escape_attr(&self.language));
+		for (key, forms) in forms(dict) {
+			let _ = writeln!(map, "<search-key-group href=\"entries/{}#{}\">",
+				escape_attr(&chapter(&key)), escape_attr(&slug(&key)));
+			let _ = writeln!(map, "<match value=\"{}\">", escape_attr(&key));
+			for form in forms.iter().filter(|form| **form != key) {
+				let _ = writeln!(map, "<value value=\"{}\"/>", escape_attr(form));
+			}
+			map.push_str("</match>\n</search-key-group>\n");
+		}
+		map.push_str("</search-key-map>\n");
+		map
+	}
+}
+
+/// File of the chapter holding `key`, relative to `entries/`
+fn chapter(key: &str) -> String
+{
+	let letter: String = key.chars().next().into_iter().collect();
+	format!("{}.xhtml", slug(&letter))
+}
+
+/// The letter of a chapter file
+#[inline]
+fn letter(chapter: &str) -> String
+{
+	unslug(chapter.trim_end_matches(".xhtml"))
+}
+
+#[inline]
+fn identifier(title: &str) -> String
+{
+	format!("urn:mdict:{}", slug(title))
+}
+
+/// `CCYY-MM-DDThh:mm:ssZ` in UTC
+fn timestamp(time: SystemTime) -> String
+{
+	let secs = time.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+	let (days, secs) = (secs / 86400, secs % 86400);
+	// civil from days, see http://howardhinnant.github.io/date_algorithms.html
+	let z = days as i64 + 719468;
+	let era = z / 146097;
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + i64::from(month <= 2);
+	format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, secs / 3600,
+		secs / 60 % 60, secs % 60)
+}
@@ -1,47 +1,104 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+#[cfg(feature = "std")]
 use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum Error {
-	#[error("Invalid Path: {0}")]
+	#[cfg(feature = "std")]
+	#[error("Invalid path: {0}")]
 	InvalidPath(PathBuf),
 
-	#[error("Failed to reading: {0}")]
-	FailedReading(std::io::Error),
+	#[cfg(feature = "std")]
+	#[error("Failed to read the dictionary")]
+	FailedReading(#[from] std::io::Error),
 
-	#[error("Invalid mdx {0} checksum")]
+	#[error("Checksum mismatch in the {0}")]
 	InvalidCheckSum(&'static str),
 
-	#[error("No Version found in header")]
+	#[error("No GeneratedByEngineVersion found in the header")]
 	NoVersion,
 
-	#[error("Invalid version({0})")]
+	#[error("Invalid engine version: {0}")]
 	InvalidVersion(String),
 
-	#[error("No Title found in header")]
+	#[error("No Title found in the header")]
 	NoTitle,
 
-	#[error("Unsupported version({0})")]
+	#[error("Unsupported engine version: {0}")]
 	UnsupportedVersion(u8),
 
-	#[error("Invalid data")]
+	#[error("Malformed dictionary data")]
 	InvalidData,
 
 	#[error("Invalid encoding: {0}")]
 	InvalidEncoding(String),
 
-	#[error("Invalid encrypt method: {0}")]
+	#[error("Text is not valid {0}")]
+	InvalidText(&'static str),
+
+	#[error("Key at {offset:#x} of key block {block} is not valid text")]
+	InvalidKey {
+		block: usize,
+		offset: usize,
+	},
+
+	#[error("Unknown encryption method: {0}")]
 	InvalidEncryptMethod(u32),
 
-	#[error("Invalid compress method: {0}")]
+	#[error("Unknown compression method: {0}")]
 	InvalidCompressMethod(u32),
-}
 
-impl From<std::io::Error> for Error {
-	fn from(value: std::io::Error) -> Self
-	{
-		Error::FailedReading(value)
-	}
+	#[error("Failed to decompress {0} block")]
+	Decompress(&'static str, #[source] Box<dyn core::error::Error + Send + Sync>),
+
+	#[error("The dictionary needs the {0} feature, which is not enabled")]
+	DisabledCodec(&'static str),
+
+	#[error("Block decoded to unexpected size: {0} bytes")]
+	BlockSize(usize),
+
+	#[error("Size or offset {0} does not fit in memory on this platform")]
+	TooLargeForPlatform(u64),
+
+	#[error("Size, offset or key length {0} does not fit in the file version written")]
+	TooLargeForVersion(u64),
+
+	#[error("The file version written does not support {0}")]
+	UnsupportedByVersion(&'static str),
+
+	#[error("Untrusted input over the {0} limit")]
+	LimitExceeded(&'static str),
+
+	#[error("The dictionary was not read from a file and can't be reopened")]
+	NoSource,
+
+	#[error("Record too large: {0} bytes")]
+	RecordTooLarge(usize),
+
+	#[cfg(feature = "icu")]
+	#[error("Invalid locale: {0}")]
+	InvalidLocale(String),
+
+	#[cfg(feature = "scraper")]
+	#[error("Invalid CSS selector: {0}")]
+	InvalidSelector(String),
+
+	#[error("Invalid query option: {0}")]
+	InvalidQuery(String),
+
+	#[cfg(feature = "zip")]
+	#[error("Invalid archive")]
+	Archive(#[from] zip::result::ZipError),
+
+	#[cfg(feature = "parquet")]
+	#[error("Failed to write parquet")]
+	Parquet(#[from] parquet::errors::ParquetError),
+
+	#[cfg(feature = "std")]
+	#[error("No mdx file found in {0}")]
+	NoMdx(PathBuf),
 }
 
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
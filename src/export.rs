@@ -0,0 +1,483 @@
+//! Whole dictionary exports for bulk processing
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::ops::{Bound, ControlFlow, RangeBounds};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "parquet")]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use encoding_rs::Encoding;
+use regex::Regex;
+#[cfg(feature = "parquet")]
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+#[cfg(feature = "parquet")]
+use parquet::file::properties::WriterProperties;
+#[cfg(feature = "parquet")]
+use parquet::file::writer::SerializedFileWriter;
+#[cfg(feature = "parquet")]
+use parquet::schema::parser::parse_message_type;
+
+use crate::block::{block_ends, entry_info};
+use crate::format::{decode_slice_string, BlockEntryInfo, KeyEntry};
+use crate::html::text_content;
+use crate::identity::{fnv, FNV_OFFSET};
+use crate::inspect::json_string;
+use crate::mdx::{KeyMaker, MDict, Mdx, Reader};
+use crate::parser::{visit_records, Scratch};
+use crate::Result;
+
+#[cfg(feature = "parquet")]
+const PARQUET_SCHEMA: &str = "message entry {
+	required binary key (UTF8);
+	required binary text (UTF8);
+	required binary html (UTF8);
+	required int64 size;
+}";
+// rows buffered before a row group is written
+#[cfg(feature = "parquet")]
+const ROW_GROUP_SIZE: usize = 8192;
+
+/// How entries are split between shards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sharding {
+	/// consecutive key ranges of about the same number of entries, each
+	/// shard reads only its own record blocks when the keys are stored in
+	/// order
+	KeyRange,
+	/// by a hash of the key, the same on every platform and Rust version,
+	/// every shard may read every record block
+	Hash,
+}
+
+/// Export options
+#[derive(Debug, Clone)]
+pub struct Exporter {
+	shards: usize,
+	sharding: Sharding,
+	threads: usize,
+	resume: bool,
+	// entries written, all when no filter is set
+	keys: Option<Vec<String>>,
+	pattern: Option<Regex>,
+	range: (Bound<String>, Bound<String>),
+	max_size: Option<usize>,
+}
+
+// an output and the entries written to it, sorted by record offset
+type Job<'a, S> = (S, Vec<&'a KeyEntry>);
+
+impl Default for Exporter {
+	fn default() -> Self
+	{
+		Exporter {
+			shards: 1,
+			sharding: Sharding::KeyRange,
+			threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+			resume: false,
+			keys: None,
+			pattern: None,
+			range: (Bound::Unbounded, Bound::Unbounded),
+			max_size: None,
+		}
+	}
+}
+
+impl Exporter {
+	#[inline]
+	pub fn new() -> Self
+	{
+		Self::default()
+	}
+	/// Split the output into `count` files
+	#[inline]
+	pub fn shards(mut self, count: usize, sharding: Sharding) -> Self
+	{
+		self.shards = count.max(1);
+		self.sharding = sharding;
+		self
+	}
+	/// Shards written at the same time, the available parallelism by
+	/// default. Dictionaries read from an archive are exported one shard
+	/// at a time
+	#[inline]
+	pub fn threads(mut self, threads: usize) -> Self
+	{
+		self.threads = threads.max(1);
+		self
+	}
+	/// Continue file exports from their checkpoints instead of starting
+	/// over
+	#[inline]
+	pub fn resume(mut self, resume: bool) -> Self
+	{
+		self.resume = resume;
+		self
+	}
+	/// Write only the entries of these words, normalized like lookups.
+	/// The filters set are all applied
+	pub fn keys<S: Into<String>>(mut self, words: impl IntoIterator<Item = S>) -> Self
+	{
+		self.keys.get_or_insert_with(Vec::new).extend(words.into_iter().map(Into::into));
+		self
+	}
+	/// `keys()` read from a file, a word per line. Empty lines and lines
+	/// starting with `#` are skipped
+	pub fn key_file(self, path: impl AsRef<Path>) -> Result<Self>
+	{
+		let text = fs::read_to_string(path)?;
+		let words: Vec<&str> = text.lines()
+			.map(str::trim)
+			.filter(|line| !line.is_empty() && !line.starts_with('#'))
+			.collect();
+		Ok(self.keys(words))
+	}
+	/// Write only the entries whose stored headword `pattern` matches
+	#[inline]
+	pub fn matching(mut self, pattern: Regex) -> Self
+	{
+		self.pattern = Some(pattern);
+		self
+	}
+	/// Write only the entries with keys in `range`, see [`MDict::range`]
+	pub fn key_range<'b>(mut self, range: impl RangeBounds<&'b str>) -> Self
+	{
+		let owned = |bound: Bound<&&str>| bound.map(|key| (*key).to_owned());
+		self.range = (owned(range.start_bound()), owned(range.end_bound()));
+		self
+	}
+	/// Write only the entries with records of at most `bytes` decoded
+	#[inline]
+	pub fn max_size(mut self, bytes: usize) -> Self
+	{
+		self.max_size = Some(bytes);
+		self
+	}
+
+	/// Write the entries as parquet, see [`parquet`], to the writer
+	/// `create` returns for each shard index
+	#[cfg(feature = "parquet")]
+	pub fn parquet<M: KeyMaker, W: Write + Send>(&self, dict: &mut MDict<M>,
+		mut create: impl FnMut(usize) -> Result<W>) -> Result<()>
+	{
+		let selected = self.select(dict);
+		self.run(&mut dict.mdx, |key_entries, _| {
+			let mut jobs = vec![];
+			let shards = self.split(key_entries, selected.as_deref());
+			for (index, entries) in shards.into_iter().enumerate() {
+				jobs.push((ParquetSink::new(create(index)?)?, entries));
+			}
+			Ok(jobs)
+		})
+	}
+
+	/// Write the entries to `path` as JSON lines with the fields of the
+	/// parquet export, shard `n` of several to `path.n`. The record blocks
+	/// written are kept in `<output>.checkpoint` until the export completes,
+	/// for [`Exporter::resume`]
+	pub fn json_lines<M: KeyMaker>(&self, dict: &mut MDict<M>, path: impl AsRef<Path>)
+		-> Result<()>
+	{
+		let path = path.as_ref();
+		let selected = self.select(dict);
+		self.run(&mut dict.mdx, |key_entries, records_info| {
+			let mut jobs = vec![];
+			let shards = self.split(key_entries, selected.as_deref());
+			for (index, entries) in shards.into_iter().enumerate() {
+				let output = if self.shards == 1 {
+					path.to_path_buf()
+				} else {
+					with_suffix(path, &index.to_string())
+				};
+				let sink = JsonLines::open(output, self.resume)?;
+				// entries of the blocks written before are skipped
+				let start: usize = records_info.iter()
+					.take(sink.next_block)
+					.map(|info| info.decompressed_size)
+					.sum();
+				let entries = entries.into_iter()
+					.filter(|entry| entry.offset >= start)
+					.collect();
+				jobs.push((sink, entries));
+			}
+			Ok(jobs)
+		})
+	}
+
+	fn run<'a, S: Sink>(&self, mdx: &'a mut Mdx,
+		jobs: impl FnOnce(&'a [KeyEntry], &[BlockEntryInfo]) -> Result<Vec<Job<'a, S>>>)
+		-> Result<()>
+	{
+		let Mdx { encoding, key_entries, records_info, record_offsets, reader, source,
+			record_block_offset, scratch, salsa20, .. } = mdx;
+		let jobs = jobs(key_entries, records_info)?;
+		let encoding = *encoding;
+		let export = |reader: &mut Reader, scratch: &mut Scratch, mut salsa20,
+			(mut sink, entries): Job<S>| {
+			let mut current = None;
+			visit_records(reader, scratch, &mut salsa20, records_info, record_offsets,
+				*record_block_offset, &entries, |block, entry, data| {
+					if current.is_some_and(|current| current != block) {
+						sink.checkpoint(block)?;
+					}
+					current = Some(block);
+					sink.row(entry, data, encoding)?;
+					Ok(ControlFlow::Continue(()))
+				})?;
+			sink.finish()
+		};
+		let source = match source {
+			Some(source) if self.threads > 1 && jobs.len() > 1 => source,
+			_ => {
+				for job in jobs {
+					export(reader, scratch, *salsa20, job)?;
+				}
+				return Ok(());
+			}
+		};
+		let salsa20 = *salsa20;
+		let workers = self.threads.min(jobs.len());
+		let jobs = Mutex::new(jobs.into_iter());
+		thread::scope(|scope| {
+			let workers: Vec<_> = (0..workers)
+				.map(|_| scope.spawn(|| {
+					let mut reader = Reader::open(source)?;
+					let mut scratch = Scratch::default();
+					loop {
+						let job = jobs.lock().unwrap_or_else(|err| err.into_inner()).next();
+						match job {
+							Some(job) => export(&mut reader, &mut scratch, salsa20, job)?,
+							None => return Ok(()),
+						}
+					}
+				}))
+				.collect();
+			workers.into_iter().try_for_each(|worker| worker.join()
+				.unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+		})
+	}
+
+	/// Which key entries the filters keep, none when there are none
+	fn select<M: KeyMaker>(&self, dict: &MDict<M>) -> Option<Vec<bool>>
+	{
+		let unbounded = matches!(self.range, (Bound::Unbounded, Bound::Unbounded));
+		if self.keys.is_none() && self.pattern.is_none() && unbounded && self.max_size.is_none() {
+			return None;
+		}
+		let mdx = &dict.mdx;
+		let (start, end) = &self.range;
+		let range = dict.range_indices((start.as_ref().map(String::as_str),
+			end.as_ref().map(String::as_str)));
+		let keys: Option<HashSet<String>> = self.keys.as_ref().map(|words| words.iter()
+			.map(|word| dict.key_maker.make(&Cow::Borrowed(word), false))
+			.collect());
+		let ends = block_ends(mdx);
+		let selected = mdx.key_entries.iter()
+			.enumerate()
+			.map(|(index, entry)| range.contains(&index)
+				&& keys.as_ref().is_none_or(|keys| keys.contains(&entry.text))
+				&& self.pattern.as_ref().is_none_or(|pattern| pattern.is_match(entry.raw()))
+				&& self.max_size.is_none_or(|max| entry_info(mdx, &ends, entry)
+					.is_some_and(|info| info.size <= max)))
+			.collect();
+		Some(selected)
+	}
+
+	/// Entries of each shard sorted by record offset, of those `selected`
+	fn split<'a>(&self, key_entries: &'a [KeyEntry], selected: Option<&[bool]>)
+		-> Vec<Vec<&'a KeyEntry>>
+	{
+		let entries: Vec<&KeyEntry> = match selected {
+			Some(selected) => key_entries.iter()
+				.zip(selected)
+				.filter_map(|(entry, selected)| selected.then_some(entry))
+				.collect(),
+			None => key_entries.iter().collect(),
+		};
+		let mut shards = vec![vec![]; self.shards];
+		for (index, entry) in entries.iter().copied().enumerate() {
+			let shard = match self.sharding {
+				Sharding::KeyRange => index * self.shards / entries.len(),
+				Sharding::Hash => (fnv(FNV_OFFSET, entry.text.as_bytes()) % self.shards as u64) as usize,
+			};
+			shards[shard].push(entry);
+		}
+		for shard in &mut shards {
+			shard.sort_by_key(|entry| entry.offset);
+		}
+		shards
+	}
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf
+{
+	let mut name = OsString::from(path.as_os_str());
+	name.push(".");
+	name.push(suffix);
+	PathBuf::from(name)
+}
+
+/// Receives the records of one output file
+trait Sink: Send {
+	fn row(&mut self, entry: &KeyEntry, data: &[u8], encoding: &'static Encoding) -> Result<()>;
+	/// Every row of the record blocks before `block` was given
+	fn checkpoint(&mut self, _block: usize) -> Result<()>
+	{
+		Ok(())
+	}
+	fn finish(self) -> Result<()>;
+}
+
+/// One JSON object per line, resumable from the checkpoint file
+struct JsonLines {
+	writer: BufWriter<File>,
+	checkpoint: PathBuf,
+	// first record block not written yet
+	next_block: usize,
+}
+
+impl JsonLines {
+	fn open(path: PathBuf, resume: bool) -> Result<Self>
+	{
+		let checkpoint = with_suffix(&path, "checkpoint");
+		let saved = if resume { read_checkpoint(&checkpoint) } else { None };
+		let (file, next_block) = match saved {
+			Some((next_block, len)) => {
+				let mut file = OpenOptions::new().write(true).open(&path)?;
+				// drop rows written after the checkpoint
+				file.set_len(len)?;
+				file.seek(SeekFrom::End(0))?;
+				(file, next_block)
+			}
+			None => (File::create(&path)?, 0),
+		};
+		Ok(JsonLines { writer: BufWriter::new(file), checkpoint, next_block })
+	}
+}
+
+/// The next record block and the output length saved in `path`
+fn read_checkpoint(path: &Path) -> Option<(usize, u64)>
+{
+	let text = fs::read_to_string(path).ok()?;
+	let (block, len) = text.trim().split_once(' ')?;
+	Some((block.parse().ok()?, len.parse().ok()?))
+}
+
+impl Sink for JsonLines {
+	fn row(&mut self, entry: &KeyEntry, data: &[u8], encoding: &'static Encoding) -> Result<()>
+	{
+		let html = decode_slice_string(data, encoding)?.0;
+		writeln!(self.writer, "{{\"key\":{},\"text\":{},\"html\":{},\"size\":{}}}",
+			json_string(entry.raw()), json_string(&text_content(&html)), json_string(&html),
+			data.len())?;
+		Ok(())
+	}
+
+	fn checkpoint(&mut self, block: usize) -> Result<()>
+	{
+		self.writer.flush()?;
+		let len = self.writer.get_mut().stream_position()?;
+		let temp = with_suffix(&self.checkpoint, "tmp");
+		fs::write(&temp, format!("{} {}\n", block, len))?;
+		fs::rename(&temp, &self.checkpoint)?;
+		Ok(())
+	}
+
+	fn finish(mut self) -> Result<()>
+	{
+		self.writer.flush()?;
+		match fs::remove_file(&self.checkpoint) {
+			Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+			_ => Ok(()),
+		}
+	}
+}
+
+#[cfg(feature = "parquet")]
+#[derive(Default)]
+struct Columns {
+	keys: Vec<ByteArray>,
+	texts: Vec<ByteArray>,
+	htmls: Vec<ByteArray>,
+	sizes: Vec<i64>,
+}
+
+#[cfg(feature = "parquet")]
+impl Columns {
+	fn write<W: Write + Send>(&mut self, writer: &mut SerializedFileWriter<W>) -> Result<()>
+	{
+		let mut row_group = writer.next_row_group()?;
+		let mut index = 0;
+		while let Some(mut column) = row_group.next_column()? {
+			match index {
+				0 => column.typed::<ByteArrayType>().write_batch(&self.keys, None, None)?,
+				1 => column.typed::<ByteArrayType>().write_batch(&self.texts, None, None)?,
+				2 => column.typed::<ByteArrayType>().write_batch(&self.htmls, None, None)?,
+				_ => column.typed::<Int64Type>().write_batch(&self.sizes, None, None)?,
+			};
+			column.close()?;
+			index += 1;
+		}
+		row_group.close()?;
+		*self = Columns::default();
+		Ok(())
+	}
+}
+
+#[cfg(feature = "parquet")]
+struct ParquetSink<W: Write + Send> {
+	writer: SerializedFileWriter<W>,
+	columns: Columns,
+}
+
+#[cfg(feature = "parquet")]
+impl<W: Write + Send> ParquetSink<W> {
+	fn new(writer: W) -> Result<Self>
+	{
+		let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+		let properties = Arc::new(WriterProperties::builder().build());
+		Ok(ParquetSink {
+			writer: SerializedFileWriter::new(writer, schema, properties)?,
+			columns: Columns::default(),
+		})
+	}
+}
+
+#[cfg(feature = "parquet")]
+impl<W: Write + Send> Sink for ParquetSink<W> {
+	fn row(&mut self, entry: &KeyEntry, data: &[u8], encoding: &'static Encoding) -> Result<()>
+	{
+		let html = decode_slice_string(data, encoding)?.0;
+		let columns = &mut self.columns;
+		columns.keys.push(entry.raw().into());
+		columns.texts.push(text_content(&html).into_bytes().into());
+		columns.sizes.push(data.len() as i64);
+		columns.htmls.push(html.into_owned().into_bytes().into());
+		if columns.keys.len() == ROW_GROUP_SIZE {
+			columns.write(&mut self.writer)?;
+		}
+		Ok(())
+	}
+
+	fn finish(mut self) -> Result<()>
+	{
+		if !self.columns.keys.is_empty() {
+			self.columns.write(&mut self.writer)?;
+		}
+		self.writer.close()?;
+		Ok(())
+	}
+}
+
+/// Write every entry as a parquet row of the headword, the definition as
+/// plain text and as HTML, and the size of the stored record in bytes
+#[cfg(feature = "parquet")]
+pub fn parquet<M: KeyMaker, W: Write + Send>(dict: &mut MDict<M>, writer: W) -> Result<()>
+{
+	let mut writer = Some(writer);
+	Exporter::new().parquet(dict, |_| Ok(writer.take().expect("one shard")))
+}
@@ -0,0 +1,793 @@
+//! Parsing and decoding of the file format from byte slices, without
+//! the file layer, so it builds without `std` on `alloc`
+
+// parts only the file layer uses are unused by `raw` alone
+#![cfg_attr(not(feature = "std"), allow(dead_code))]
+
+use alloc::borrow::{Cow, ToOwned};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use byteorder::{BE, ByteOrder, LE};
+#[cfg(all(feature = "compress", not(feature = "flate2")))]
+use compress::zlib;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE};
+#[cfg(feature = "encryption")]
+use ripemd::{Digest, Ripemd128};
+
+use crate::checksum::{Adler, Checksum};
+use crate::warning::Warning;
+use crate::{Error, Result};
+
+/// Bytes decompressed from a zlib block per read by default
+pub(crate) const DECOMPRESS_CHUNK: usize = 32 * 1024;
+
+/// What becomes of bytes invalid in the dictionary encoding, which
+/// many old files in legacy encodings have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Decoding {
+	/// replace them with U+FFFD
+	#[default]
+	Lossy,
+	/// fail with `Error::InvalidText`
+	Strict,
+}
+
+/// Salsa20 parameters of blocks encrypted with method 2, some MDD builders
+/// deviate from the standard one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Salsa20Variant {
+	/// try the variants below on the first encrypted block until its
+	/// checksum matches
+	#[default]
+	Detect,
+	/// 20 rounds with a zero nonce
+	Standard,
+	/// Salsa20/8 with a zero nonce
+	Rounds8,
+	/// 20 rounds with the block index as nonce
+	IndexNonce,
+	/// Salsa20/8 with the block index as nonce
+	Rounds8IndexNonce,
+}
+
+impl Salsa20Variant {
+	pub(crate) const CANDIDATES: [Salsa20Variant; 4] = [
+		Salsa20Variant::Standard,
+		Salsa20Variant::Rounds8,
+		Salsa20Variant::IndexNonce,
+		Salsa20Variant::Rounds8IndexNonce,
+	];
+
+	/// Rounds and nonce for the block at `index` of its section
+	#[cfg(feature = "encryption")]
+	pub(crate) fn params(self, index: usize) -> (usize, u64)
+	{
+		match self {
+			Salsa20Variant::Detect | Salsa20Variant::Standard => (20, 0),
+			Salsa20Variant::Rounds8 => (8, 0),
+			Salsa20Variant::IndexNonce => (20, index as u64),
+			Salsa20Variant::Rounds8IndexNonce => (8, index as u64),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub(crate) struct KeyEntry {
+	pub(crate) offset: usize,
+	pub(crate) text: String,
+	// the headword as stored, when the `KeyMaker` changed it
+	pub(crate) raw: Option<Box<str>>,
+}
+
+impl KeyEntry {
+	#[inline]
+	pub(crate) fn raw(&self) -> &str
+	{
+		self.raw.as_deref().unwrap_or(&self.text)
+	}
+}
+
+#[derive(Debug)]
+pub(crate) struct BlockEntryInfo {
+	pub(crate) compressed_size: usize,
+	pub(crate) decompressed_size: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct KeyBlockHeader {
+	pub(crate) block_num: u64,
+	pub(crate) entry_num: u64,
+	pub(crate) decompressed_size: u64,
+	pub(crate) block_info_size: u64,
+	pub(crate) key_block_size: u64,
+}
+
+#[derive(Debug)]
+pub(crate) enum Version {
+	V1,
+	V2,
+}
+
+impl Version {
+	#[inline]
+	fn number_width(&self) -> usize
+	{
+		match self {
+			Version::V1 => 4,
+			Version::V2 => 8,
+		}
+	}
+	#[inline]
+	pub(crate) fn byte_number(&self, data: &[u8]) -> Result<(usize, usize)>
+	{
+		if data.len() < self.number_width() {
+			return Err(Error::InvalidData);
+		}
+		match self {
+			Version::V1 => Ok((to_usize(u64::from(BE::read_u32(data)))?, 4)),
+			Version::V2 => Ok((to_usize(BE::read_u64(data))?, 8)),
+		}
+	}
+}
+
+/// Sizes and offsets are u64 in v2 files, which may not fit `usize` on
+/// 32-bit targets
+#[inline]
+pub(crate) fn to_usize(value: u64) -> Result<usize>
+{
+	usize::try_from(value).map_err(|_| Error::TooLargeForPlatform(value))
+}
+
+/// The `name="value"` attributes of the header text
+fn read_keys(s: &str) -> BTreeMap<String, String>
+{
+	let mut attrs = BTreeMap::new();
+	let mut rest = s;
+	while let Some(at) = rest.find("=\"") {
+		let name = &rest[rest[..at].trim_end_matches(|c: char| c.is_alphanumeric() || c == '_').len()..at];
+		let value = &rest[at + 2..];
+		let Some(end) = value.find('"') else {
+			break;
+		};
+		if !name.is_empty() {
+			attrs.insert(name.to_string(), value[..end].to_string());
+		}
+		rest = &value[end + 1..];
+	}
+	attrs
+}
+
+#[derive(Debug)]
+pub(crate) struct Header {
+	pub(crate) version: Version,
+	pub(crate) checksum: u32,
+	pub(crate) encrypted: u8,
+	pub(crate) encoding: &'static Encoding,
+	pub(crate) title: String,
+	pub(crate) attrs: BTreeMap<String, String>,
+}
+
+#[inline]
+pub(crate) fn check_adler32(data: &[u8], checksum: u32) -> Result<()>
+{
+	if !Adler::verify(data, checksum) {
+		return Err(Error::InvalidCheckSum("header"));
+	}
+	Ok(())
+}
+
+/// Header attributes written by MDict itself
+const KNOWN_ATTRIBUTES: [&str; 17] = [
+	"GeneratedByEngineVersion", "RequiredEngineVersion", "Format", "KeyCaseSensitive",
+	"StripKey", "Encrypted", "RegisterBy", "Description", "Title", "Encoding", "CreationDate",
+	"Compact", "Compat", "Left2Right", "DataSourceFormat", "StyleSheet", "RegCode",
+];
+
+/// The header from its UTF-16 text and the checksum after it
+pub(crate) fn parse_header(info_buf: &[u8], checksum: u32, default_encoding: &'static Encoding,
+	warnings: &mut Vec<Warning>) -> Result<Header>
+{
+	check_adler32(info_buf, checksum)?;
+
+	let info = UTF_16LE.decode(info_buf).0;
+	let attrs = read_keys(&info);
+
+	let version_str = attrs
+		.get("GeneratedByEngineVersion")
+		.ok_or(Error::NoVersion)?
+		.trim();
+	let version = version_str.get(0..1)
+		.and_then(|major| major.parse::<u8>().ok())
+		.ok_or_else(|| Error::InvalidVersion(version_str.to_owned()))?;
+
+
+	let title = attrs
+		.get("Title")
+		.ok_or(Error::NoTitle)?
+		.trim()
+		.to_owned();
+
+	let version = match version {
+		1 => Version::V1,
+		2 => Version::V2,
+		3 |
+		_ => return Err(Error::UnsupportedVersion(version)),
+	};
+
+	let encrypted = attrs
+		.get("Encrypted")
+		.and_then(|x| match x == "Yes" {
+			true => Some(1_u8),
+			false => x.as_str().parse().ok(),
+		})
+		.unwrap_or(0);
+
+	let encoding = match attrs.get("Encoding") {
+		Some(encoding) if !encoding.is_empty() => Encoding::for_label(encoding.as_bytes())
+			.ok_or(Error::InvalidEncoding(encoding.clone()))?,
+		_ => {
+			warnings.push(Warning::EncodingFallback(default_encoding.name()));
+			default_encoding
+		}
+	};
+	let mut unknown: Vec<&String> = attrs.keys()
+		.filter(|name| !KNOWN_ATTRIBUTES.contains(&name.as_str()))
+		.collect();
+	unknown.sort();
+	warnings.extend(unknown.into_iter().map(|name| Warning::UnknownAttribute(name.clone())));
+	Ok(Header {
+		version,
+		checksum,
+		encrypted,
+		encoding,
+		title,
+		attrs,
+	})
+}
+
+#[inline]
+pub(crate) fn parse_key_block_header(buf: &[u8], version: &Version) -> Result<KeyBlockHeader>
+{
+	match version {
+		Version::V1 => parse_key_block_header_v1(buf),
+		Version::V2 => parse_key_block_header_v2(buf),
+	}
+}
+
+impl KeyBlockHeader {
+	/// Bytes of the header in the file, with its checksum
+	#[inline]
+	pub(crate) fn size(version: &Version) -> usize
+	{
+		match version {
+			Version::V1 => 16,
+			Version::V2 => 44,
+		}
+	}
+}
+
+fn parse_key_block_header_v1(buf: &[u8]) -> Result<KeyBlockHeader>
+{
+	if buf.len() < 16 {
+		return Err(Error::InvalidData);
+	}
+	let block_num = BE::read_u32(&buf[0..4]);
+	let entry_num = BE::read_u32(&buf[4..8]);
+	let block_info_size = BE::read_u32(&buf[8..12]);
+	let key_block_size = BE::read_u32(&buf[12..16]);
+
+	Ok(KeyBlockHeader {
+		block_num: u64::from(block_num),
+		entry_num: u64::from(entry_num),
+		decompressed_size: u64::from(block_info_size),
+		block_info_size: u64::from(block_info_size),
+		key_block_size: u64::from(key_block_size),
+	})
+}
+
+fn parse_key_block_header_v2(buf: &[u8]) -> Result<KeyBlockHeader>
+{
+	if buf.len() < 44 {
+		return Err(Error::InvalidData);
+	}
+	let checksum = BE::read_u32(&buf[40..44]);
+	check_adler32(&buf[..40], checksum)?;
+
+	let block_num = BE::read_u64(&buf[0..8]);
+	let entry_num = BE::read_u64(&buf[8..16]);
+	let decompressed_size = BE::read_u64(&buf[16..24]);
+	let block_info_size = BE::read_u64(&buf[24..32]);
+	let key_block_size = BE::read_u64(&buf[32..40]);
+
+	Ok(KeyBlockHeader {
+		block_num,
+		entry_num,
+		decompressed_size,
+		block_info_size,
+		key_block_size,
+	})
+}
+
+#[cfg(feature = "encryption")]
+fn fast_decrypt(buf: &mut [u8], key: &[u8])
+{
+	let mut prev = 0x36;
+	for i in 0..buf.len() {
+		let mut t = buf[i] >> 4 | buf[i] << 4;
+		t = t ^ prev ^ (i as u8) ^ key[i % key.len()];
+		prev = buf[i];
+		buf[i] = t;
+	}
+}
+
+#[cfg(feature = "encryption")]
+fn decrypt_key_block_info(buf: &[u8]) -> Result<Vec<u8>>
+{
+	let mut v = Vec::from(&buf[4..8]);
+	let value: u32 = 0x3695;
+	v.extend_from_slice(&value.to_le_bytes());
+	let mut md = Ripemd128::default();
+	md.update(v);
+	let key = md.finalize();
+	let mut info = Vec::from(&buf[8..]);
+	fast_decrypt(&mut info, key.as_slice());
+	Ok(info)
+}
+
+#[cfg(not(feature = "encryption"))]
+#[inline]
+fn decrypt_key_block_info(_buf: &[u8]) -> Result<Vec<u8>>
+{
+	Err(Error::DisabledCodec("encryption"))
+}
+
+/// Sizes of the key blocks from the stored key block index, decompressed
+/// to at most `limit` bytes
+pub(crate) fn parse_key_block_infos(buf: Vec<u8>, header: &Header, limit: u64,
+	warnings: &mut Vec<Warning>) -> Result<Vec<BlockEntryInfo>>
+{
+	//decrypt
+	let key_block_info = match header.version {
+		Version::V1 => buf,
+		Version::V2 => {
+			if buf.len() < 8 || buf[0..4] != [2, 0, 0, 0] {
+				return Err(Error::InvalidData);
+			}
+			let checksum = BE::read_u32(&buf[4..8]);
+			let mut info = vec![];
+			let limit = Some(usize::try_from(limit).unwrap_or(usize::MAX));
+			if header.encrypted == 2 {
+				let decrypted = decrypt_key_block_info(&buf)?;
+				inflate(&decrypted, limit, DECOMPRESS_CHUNK, &mut info)?;
+			} else {
+				inflate(&buf[8..], limit, DECOMPRESS_CHUNK, &mut info)?;
+			}
+			check_adler32(&info, checksum)?;
+			info
+		}
+	};
+	let key_blocks = decode_key_blocks(&key_block_info, header, warnings)?;
+	Ok(key_blocks)
+}
+
+fn decode_key_blocks(data: &[u8], header: &Header, warnings: &mut Vec<Warning>)
+	-> Result<Vec<BlockEntryInfo>>
+{
+	#[inline]
+	fn read_num_bytes(data: &[u8], header: &Header) -> Result<(usize, usize)>
+	{
+		match header.version {
+			Version::V1 if !data.is_empty() => Ok((data[0] as usize, 1)),
+			Version::V2 if data.len() >= 2 => Ok((BE::read_u16(&data[0..2]) as usize, 2)),
+			_ => Err(Error::InvalidData),
+		}
+	}
+	#[inline]
+	fn skip(data: &[u8], len: usize) -> Result<&[u8]>
+	{
+		data.get(len..).ok_or(Error::InvalidData)
+	}
+	#[inline]
+	fn text_bytes(header: &Header, bytes: usize) -> usize
+	{
+		let text_size = match header.version {
+			Version::V1 => bytes,
+			Version::V2 => bytes + 1,
+		};
+		if is_utf16(header.encoding) {
+			text_size * 2
+		} else {
+			text_size
+		}
+	}
+	#[inline]
+	#[allow(unused)]
+	fn extract_text(data: &[u8], header: &Header, bytes: usize) -> (String, usize)
+	{
+		let text_size = match header.version {
+			Version::V1 => bytes,
+			Version::V2 => bytes + 1,
+		};
+		let bytes = if is_utf16(header.encoding) {
+			text_size * 2
+		} else {
+			text_size
+		};
+		let text = header.encoding
+			.decode(&data[..text_size])
+			.0
+			.trim_matches(char::from(0))
+			.to_string();
+		(text, bytes)
+	}
+
+	let mut key_block_info_list = vec![];
+	let mut slice = data;
+	while !slice.is_empty() {
+		if slice.iter().all(|byte| *byte == 0) {
+			warnings.push(Warning::Padding { section: "key block infos", len: slice.len() });
+			break;
+		}
+		let (_num_entries, delta) = header.version.byte_number(slice)?;
+		slice = skip(slice, delta)?;
+		let (bytes, delta) = read_num_bytes(slice, header)?;
+		slice = skip(slice, delta + text_bytes(header, bytes))?;
+		let (bytes, delta) = read_num_bytes(slice, header)?;
+		slice = skip(slice, delta + text_bytes(header, bytes))?;
+		let (compressed_size, delta) = header.version.byte_number(slice)?;
+		slice = skip(slice, delta)?;
+		let (decompressed_size, delta) = header.version.byte_number(slice)?;
+		slice = skip(slice, delta)?;
+		key_block_info_list.push(BlockEntryInfo {
+			compressed_size,
+			decompressed_size,
+		});
+	}
+	Ok(key_block_info_list)
+}
+
+/// Flags and checksum leading every key and record block
+pub(crate) struct BlockHeader {
+	pub(crate) encryption: u32,
+	pub(crate) compression: u32,
+	pub(crate) checksum: u32,
+}
+
+#[inline]
+pub(crate) fn block_header(slice: &[u8]) -> Result<BlockHeader>
+{
+	if slice.len() < 8 {
+		return Err(Error::InvalidData);
+	}
+	let enc = LE::read_u32(&slice[0..4]);
+	Ok(BlockHeader {
+		encryption: (enc >> 4) & 0xf,
+		// encryption_size: (enc >> 8) & 0xff,
+		compression: enc & 0xf,
+		checksum: BE::read_u32(&slice[4..8]),
+	})
+}
+
+/// Decode at most `limit` bytes of the block into `out`, the checksum can
+/// only be verified when the whole block is decoded
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn decode_block_into(slice: &[u8], compressed_size: usize, decompressed_size: usize,
+	limit: Option<usize>, salsa: &mut Salsa20Variant, index: usize, chunk: usize,
+	plain: &mut Vec<u8>, out: &mut Vec<u8>) -> Result<()>
+{
+	let limit = limit.filter(|limit| *limit < decompressed_size);
+	let verified = decompress_verified(slice, compressed_size, decompressed_size, limit,
+		salsa, index, chunk, plain, out)?;
+	if let Some(limit) = limit {
+		out.truncate(limit);
+	} else if !verified {
+		return Err(Error::InvalidCheckSum("block"));
+	}
+	Ok(())
+}
+
+/// Decode the block into `out`, failing when it decodes past
+/// `decompressed_size` or to data not matching its checksum, for files
+/// that can't be trusted
+pub(crate) fn decode_untrusted(slice: &[u8], decompressed_size: usize,
+	salsa: &mut Salsa20Variant, index: usize, out: &mut Vec<u8>) -> Result<()>
+{
+	let header = block_header(slice)?;
+	let given = [*salsa];
+	let variants = if *salsa == Salsa20Variant::Detect && header.encryption == 2 {
+		&Salsa20Variant::CANDIDATES[..]
+	} else {
+		&given[..]
+	};
+	let limit = Some(decompressed_size.saturating_add(1));
+	let chunk = DECOMPRESS_CHUNK;
+	let mut result = Err(Error::InvalidCheckSum("block"));
+	for &variant in variants {
+		result = decompress_block_into(slice, slice.len(), decompressed_size, limit, variant,
+			index, chunk, &mut vec![], out)
+			.and_then(|_| match out.len() == decompressed_size {
+				true if Adler::verify(out, header.checksum) => Ok(()),
+				true => Err(Error::InvalidCheckSum("block")),
+				false => Err(Error::BlockSize(out.len())),
+			});
+		if result.is_ok() {
+			*salsa = variant;
+			break;
+		}
+	}
+	result
+}
+
+/// Decompress the block and tell whether its checksum matches, detecting
+/// the Salsa20 variant of the first block encrypted with it
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn decompress_verified(slice: &[u8], compressed_size: usize, decompressed_size: usize,
+	limit: Option<usize>, salsa: &mut Salsa20Variant, index: usize, chunk: usize,
+	plain: &mut Vec<u8>, out: &mut Vec<u8>) -> Result<bool>
+{
+	let verify = |out: &[u8], header: &BlockHeader|
+		Adler::verify(out, header.checksum);
+	if *salsa == Salsa20Variant::Detect && block_header(slice)?.encryption == 2 {
+		for variant in Salsa20Variant::CANDIDATES {
+			let result = decompress_block_into(slice, compressed_size, decompressed_size, None,
+				variant, index, chunk, plain, out);
+			if matches!(result, Ok(header) if verify(out, &header)) {
+				*salsa = variant;
+				return Ok(true);
+			}
+		}
+	}
+	let header = decompress_block_into(slice, compressed_size, decompressed_size, limit,
+		*salsa, index, chunk, plain, out)?;
+	Ok(limit.is_none() && verify(out, &header))
+}
+
+/// Decrypt block data with the key made from its checksum bytes
+#[cfg(feature = "encryption")]
+fn decrypt_block(method: u32, checksum_bytes: &[u8], data: &mut [u8],
+	salsa: Salsa20Variant, index: usize) -> Result<()>
+{
+	/// RIPEMD-128 of the 4 checksum bytes in the block header
+	#[inline]
+	fn make_key(data: &[u8]) -> [u8; 16]
+	{
+		let mut md = Ripemd128::default();
+		md.update(data);
+		md.finalize().into()
+	}
+
+	match method {
+		1 => fast_decrypt(data, &make_key(checksum_bytes)),
+		2 => {
+			let (rounds, nonce) = salsa.params(index);
+			crate::salsa::apply_keystream(&make_key(checksum_bytes), nonce, rounds, data);
+		}
+		_ => return Err(Error::InvalidEncryptMethod(method)),
+	}
+	Ok(())
+}
+
+#[cfg(not(feature = "encryption"))]
+#[inline]
+fn decrypt_block(_method: u32, _checksum_bytes: &[u8], _data: &mut [u8],
+	_salsa: Salsa20Variant, _index: usize) -> Result<()>
+{
+	Err(Error::DisabledCodec("encryption"))
+}
+
+#[cfg(all(feature = "compress", not(feature = "flate2")))]
+#[inline]
+fn zlib_decoder(data: &[u8]) -> impl std::io::Read + '_
+{
+	zlib::Decoder::new(data)
+}
+
+#[cfg(feature = "flate2")]
+#[inline]
+fn zlib_decoder(data: &[u8]) -> impl std::io::Read + '_
+{
+	flate2::read::ZlibDecoder::new(data)
+}
+
+/// Inflate the zlib stream `data` into `out`, stopping after `limit` bytes
+#[cfg(any(feature = "compress", feature = "flate2"))]
+pub(crate) fn inflate(data: &[u8], limit: Option<usize>, chunk: usize, out: &mut Vec<u8>) -> Result<()>
+{
+	use std::io::Read;
+	let decoder = zlib_decoder(data);
+	match limit {
+		Some(limit) => read_chunked(decoder.take(limit as u64), chunk, out),
+		None => read_chunked(decoder, chunk, out),
+	}.map_err(|err| Error::Decompress("zlib", err.into()))
+}
+
+/// Inflate the zlib stream `data` into `out`, stopping after `limit` bytes
+#[cfg(not(any(feature = "compress", feature = "flate2")))]
+pub(crate) fn inflate(data: &[u8], limit: Option<usize>, _chunk: usize, out: &mut Vec<u8>) -> Result<()>
+{
+	use miniz_oxide::inflate::{decompress_to_vec_zlib_with_limit, TINFLStatus};
+	let inflated = match decompress_to_vec_zlib_with_limit(data, limit.unwrap_or(usize::MAX)) {
+		Ok(inflated) => inflated,
+		Err(err) if err.status == TINFLStatus::HasMoreOutput => err.output,
+		Err(err) => return Err(Error::Decompress("zlib", InflateError(err.status).into())),
+	};
+	out.extend_from_slice(&inflated);
+	Ok(())
+}
+
+/// Status of miniz_oxide failing to inflate, as its error type has no
+/// `Error` impl without its std feature
+#[cfg(not(any(feature = "compress", feature = "flate2")))]
+#[derive(Debug, thiserror::Error)]
+#[error("inflating stopped with {0:?}")]
+struct InflateError(miniz_oxide::inflate::TINFLStatus);
+
+#[cfg(feature = "lzo")]
+fn decompress_lzo(compressed: &[u8], decompressed_size: usize, out: &mut Vec<u8>) -> Result<()>
+{
+	out.resize(decompressed_size, 0);
+	let (result, err) = rust_lzo::LZOContext::decompress_to_slice(compressed, out);
+	let len = result.len();
+	if err != rust_lzo::LZOError::OK {
+		let err = std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", err));
+		return Err(Error::Decompress("lzo", err.into()));
+	}
+	out.truncate(len);
+	Ok(())
+}
+
+#[cfg(not(feature = "lzo"))]
+#[inline]
+fn decompress_lzo(_compressed: &[u8], _decompressed_size: usize, _out: &mut Vec<u8>) -> Result<()>
+{
+	Err(Error::DisabledCodec("lzo"))
+}
+
+/// Read all of `reader` into `out`, `chunk` bytes at a time
+#[cfg(any(feature = "compress", feature = "flate2"))]
+fn read_chunked(mut reader: impl std::io::Read, chunk: usize, out: &mut Vec<u8>) -> std::io::Result<()>
+{
+	loop {
+		let len = out.len();
+		out.resize(len + chunk, 0);
+		match reader.read(&mut out[len..]) {
+			Ok(0) => {
+				out.truncate(len);
+				return Ok(());
+			}
+			Ok(read) => out.truncate(len + read),
+			Err(err) if err.kind() == std::io::ErrorKind::Interrupted => out.truncate(len),
+			Err(err) => {
+				out.truncate(len);
+				return Err(err);
+			}
+		}
+	}
+}
+
+/// Decrypt and decompress the block into `out` without verifying its
+/// checksum, encrypted blocks are deciphered in `plain`
+#[allow(clippy::too_many_arguments)]
+fn decompress_block_into(slice: &[u8], compressed_size: usize, decompressed_size: usize,
+	limit: Option<usize>, salsa: Salsa20Variant, index: usize, chunk: usize,
+	plain: &mut Vec<u8>, out: &mut Vec<u8>) -> Result<BlockHeader>
+{
+	let header = block_header(slice)?;
+	if compressed_size < 8 || compressed_size > slice.len() {
+		return Err(Error::InvalidData);
+	}
+	let checksum_bytes = &slice[4..8];
+	let encrypted = &slice[8..compressed_size];
+	let compressed: &[u8] = match header.encryption {
+		0 => encrypted,
+		1 | 2 => {
+			plain.clear();
+			plain.extend_from_slice(encrypted);
+			decrypt_block(header.encryption, checksum_bytes, plain, salsa, index)?;
+			plain
+		}
+		method => return Err(Error::InvalidEncryptMethod(method)),
+	};
+
+	out.clear();
+	match header.compression {
+		0 => out.extend_from_slice(compressed),
+		1 => decompress_lzo(compressed, decompressed_size, out)?,
+		2 => {
+			out.reserve(limit.unwrap_or(decompressed_size));
+			inflate(compressed, limit, chunk, out)?;
+		}
+		method => return Err(Error::InvalidCompressMethod(method)),
+	}
+	Ok(header)
+}
+
+/// Append the entries of the decoded key block `block` to `entries`,
+/// `make` turns a stored headword into its lookup key
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn parse_key_entries(data: &[u8], header: &Header, make: &dyn Fn(&str) -> String,
+	block: usize, decoding: Decoding, entries: &mut Vec<KeyEntry>, warnings: &mut Vec<Warning>)
+	-> Result<()>
+{
+	let mut slice = data;
+	while !slice.is_empty() {
+		if slice.iter().all(|byte| *byte == 0) {
+			warnings.push(Warning::Padding { section: "key block", len: slice.len() });
+			break;
+		}
+		let (offset, delta) = header.version.byte_number(slice)?;
+		slice = &slice[delta..];
+		let (end, idx) = nul_terminated(slice, header.encoding)?;
+		let (raw, _, invalid) = header.encoding.decode(&slice[..end]);
+		if invalid {
+			let offset = data.len() - slice.len();
+			match decoding {
+				Decoding::Strict => return Err(Error::InvalidKey { block, offset }),
+				Decoding::Lossy =>
+					warnings.push(Warning::InvalidKey { block, offset, key: raw.to_string() }),
+			}
+		}
+		entries.push(key_entry(make, offset, &raw));
+		slice = &slice[idx..];
+	}
+	Ok(())
+}
+
+#[inline]
+pub(crate) fn key_entry(make: &dyn Fn(&str) -> String, offset: usize, raw: &str) -> KeyEntry
+{
+	let text = make(raw);
+	let raw = (text != raw).then(|| raw.into());
+	KeyEntry { offset, text, raw }
+}
+
+/// Whether characters take two bytes or more, and so the terminator
+#[inline]
+pub(crate) fn is_utf16(encoding: &'static Encoding) -> bool
+{
+	encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+#[inline]
+pub(crate) fn decode_slice_string<'a>(slice: &'a [u8],
+	encoding: &'static Encoding) -> Result<(Cow<'a, str>, usize)>
+{
+	decode_slice_string_with(slice, encoding, Decoding::Lossy)
+}
+
+pub(crate) fn decode_slice_string_with<'a>(slice: &'a [u8],
+	encoding: &'static Encoding, decoding: Decoding) -> Result<(Cow<'a, str>, usize)>
+{
+	let (idx, len) = nul_terminated(slice, encoding)?;
+	let text = match decoding {
+		Decoding::Lossy => encoding.decode(&slice[..idx]).0,
+		Decoding::Strict => encoding
+			.decode_without_bom_handling_and_without_replacement(&slice[..idx])
+			.ok_or(Error::InvalidText(encoding.name()))?,
+	};
+	Ok((text, len))
+}
+
+/// Bytes of the NUL terminated string at the start of `slice`, without
+/// and with its terminator
+pub(crate) fn nul_terminated(slice: &[u8], encoding: &'static Encoding) -> Result<(usize, usize)>
+{
+	if is_utf16(encoding) {
+		let idx = slice
+			.chunks_exact(2)
+			.position(|pair| pair == [0, 0])
+			.ok_or(Error::InvalidData)?;
+		Ok((idx * 2, idx * 2 + 2))
+	} else {
+		// UTF-8, single-byte encodings and the CJK multi-byte ones never
+		// use a NUL byte within a character
+		let idx = slice
+			.iter()
+			.position(|b| *b == 0)
+			.ok_or(Error::InvalidData)?;
+		Ok((idx, idx + 1))
+	}
+}
@@ -0,0 +1,118 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::format::decode_slice_string;
+use crate::lint::link_targets;
+use crate::mdx::{KeyMaker, MDict};
+use crate::parser::for_each_record;
+use crate::Result;
+
+/// Directed graph of `entry://` and `@@@LINK=` references, nodes are keys
+#[derive(Debug, Default)]
+pub struct LinkGraph {
+	edges: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl LinkGraph {
+	/// Keys referenced by `key`
+	#[inline]
+	pub fn targets(&self, key: &str) -> impl Iterator<Item=&str>
+	{
+		self.edges.get(key)
+			.into_iter()
+			.flat_map(|targets| targets.iter().map(String::as_str))
+	}
+
+	/// Adjacency list of every key
+	#[inline]
+	pub fn adjacency(&self) -> impl Iterator<Item=(&str, impl Iterator<Item=&str>)>
+	{
+		self.edges.iter()
+			.map(|(key, targets)| (key.as_str(), targets.iter().map(String::as_str)))
+	}
+
+	/// Keys no other entry links to
+	pub fn orphans(&self) -> Vec<&str>
+	{
+		let linked: BTreeSet<&str> = self.edges.iter()
+			.flat_map(|(key, targets)| targets.iter()
+				.filter(move |target| *target != key)
+				.map(String::as_str))
+			.collect();
+		self.edges.keys()
+			.map(String::as_str)
+			.filter(|key| !linked.contains(key))
+			.collect()
+	}
+
+	pub fn to_dot(&self) -> String
+	{
+		#[inline]
+		fn escape(s: &str) -> String
+		{
+			s.replace('\\', "\\\\").replace('"', "\\\"")
+		}
+		let mut dot = String::from("digraph mdict {\n");
+		for (key, targets) in &self.edges {
+			if targets.is_empty() {
+				let _ = writeln!(dot, "\t\"{}\";", escape(key));
+			}
+			for target in targets {
+				let _ = writeln!(dot, "\t\"{}\" -> \"{}\";", escape(key), escape(target));
+			}
+		}
+		dot.push_str("}\n");
+		dot
+	}
+
+	pub fn to_graphml(&self) -> String
+	{
+		#[inline]
+		fn escape(s: &str) -> String
+		{
+			s.replace('&', "&amp;")
+				.replace('<', "&lt;")
+				.replace('>', "&gt;")
+				.replace('"', "&quot;")
+		}
+		let mut xml = String::from(concat!(
+			"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+			"<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+			"<graph id=\"mdict\" edgedefault=\"directed\">\n"));
+		let mut nodes = BTreeSet::new();
+		for (key, targets) in &self.edges {
+			nodes.insert(key);
+			nodes.extend(targets);
+		}
+		for node in nodes {
+			let _ = writeln!(xml, "<node id=\"{}\"/>", escape(node));
+		}
+		for (key, targets) in &self.edges {
+			for target in targets {
+				let _ = writeln!(xml, "<edge source=\"{}\" target=\"{}\"/>",
+					escape(key), escape(target));
+			}
+		}
+		xml.push_str("</graph>\n</graphml>\n");
+		xml
+	}
+}
+
+impl<M: KeyMaker> MDict<M> {
+	pub fn link_graph(&mut self) -> Result<LinkGraph>
+	{
+		let encoding = self.mdx.encoding;
+		let key_maker = &self.key_maker;
+		let mut graph = LinkGraph::default();
+		for_each_record(&mut self.mdx, |entry, data| {
+			let definition = decode_slice_string(data, encoding)?.0;
+			let targets = graph.edges.entry(entry.text.clone()).or_default();
+			for target in link_targets(&definition) {
+				targets.insert(key_maker.make(&Cow::Borrowed(&target), false));
+			}
+			Ok(())
+		})?;
+		Ok(graph)
+	}
+}
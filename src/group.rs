@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::SystemTime;
+
+use crate::mdx::{KeyMaker, MDict};
+use crate::normalize::fold_case;
+use crate::query::Query;
+use crate::Result;
+
+type Loader<M> = Box<dyn Fn() -> Result<MDict<M>> + Send + Sync>;
+
+/// Several dictionaries searched together
+pub struct DictGroup<M: KeyMaker> {
+	members: Vec<Member<M>>,
+}
+
+struct Member<M: KeyMaker> {
+	dict: MDict<M>,
+	priority: i32,
+	enabled: bool,
+	// builds the dictionary again for `reload()`
+	loader: Option<Loader<M>>,
+	// modification time of the mdx file when loaded
+	modified: Option<SystemTime>,
+}
+
+impl<M: KeyMaker> Member<M> {
+	fn new(dict: MDict<M>, priority: i32, loader: Option<Loader<M>>) -> Self
+	{
+		let modified = modified(&dict);
+		Member { dict, priority, enabled: true, loader, modified }
+	}
+}
+
+/// The stored key and definition of `word`
+fn lookup_one<M: KeyMaker>(dict: &mut MDict<M>, word: &str) -> Result<Option<(String, String)>>
+{
+	let Some(key) = dict.find_key(word).map(str::to_owned) else {
+		return Ok(None);
+	};
+	Ok(dict.lookup(word)?.map(|definition| (key, definition.definition)))
+}
+
+/// Definitions found by priority grouped as `dedup` tells
+fn gather(mut found: Vec<(String, usize, String)>, dedup: Dedup) -> Vec<GroupDefinition>
+{
+	if dedup == Dedup::First {
+		found.truncate(1);
+	}
+	match dedup {
+		Dedup::KeepAll | Dedup::First => found.into_iter()
+			.map(|(key, idx, definition)| GroupDefinition {
+				key,
+				definitions: vec![(idx, definition)],
+			})
+			.collect(),
+		Dedup::Merge => match found.first() {
+			Some((key, _, _)) => vec![GroupDefinition {
+				key: key.clone(),
+				definitions: found.into_iter()
+					.map(|(_, idx, definition)| (idx, definition))
+					.collect(),
+			}],
+			None => vec![],
+		},
+	}
+}
+
+#[inline]
+fn modified<M: KeyMaker>(dict: &MDict<M>) -> Option<SystemTime>
+{
+	let source = dict.mdx.source.as_ref()?;
+	fs::metadata(source).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// How a key matched the query, better matches first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MatchKind {
+	Exact,
+	Prefix,
+	/// within the given number of edits
+	Fuzzy(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+	pub key: String,
+	pub kind: MatchKind,
+	/// indices of the dictionaries having the key, by priority
+	pub dicts: Vec<usize>,
+}
+
+/// What becomes of a headword found in several dictionaries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dedup {
+	/// a result for each dictionary
+	KeepAll,
+	/// one result from the dictionary of highest priority
+	First,
+	/// one result from every dictionary
+	#[default]
+	Merge,
+}
+
+/// Definitions of a headword from the dictionaries of a group
+#[derive(Debug, Clone)]
+pub struct GroupDefinition {
+	/// the stored key in the first dictionary
+	pub key: String,
+	/// dictionary index and definition, by priority
+	pub definitions: Vec<(usize, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+	pub prefix: bool,
+	/// maximum edit distance of fuzzy matches, 0 disables fuzzy search
+	pub fuzzy_distance: usize,
+	pub offset: usize,
+	pub limit: usize,
+	pub dedup: Dedup,
+	/// query dictionaries by priority and stop at the first one with a
+	/// hit
+	pub fallback: bool,
+}
+
+impl Default for SearchOptions {
+	fn default() -> Self
+	{
+		SearchOptions {
+			prefix: true,
+			fuzzy_distance: 0,
+			offset: 0,
+			limit: 50,
+			dedup: Dedup::Merge,
+			fallback: false,
+		}
+	}
+}
+
+impl<M: KeyMaker> Default for DictGroup<M> {
+	#[inline]
+	fn default() -> Self
+	{
+		DictGroup { members: vec![] }
+	}
+}
+
+impl<M: KeyMaker> DictGroup<M> {
+	#[inline]
+	pub fn new() -> Self
+	{
+		Self::default()
+	}
+
+	/// Add a dictionary, higher priority ranks first, returns its index
+	pub fn add(&mut self, dict: MDict<M>, priority: i32) -> usize
+	{
+		self.members.push(Member::new(dict, priority, None));
+		self.members.len() - 1
+	}
+
+	/// Add the dictionary `loader` builds, keeping `loader` to reload it
+	pub fn add_loaded(&mut self,
+		loader: impl Fn() -> Result<MDict<M>> + Send + Sync + 'static, priority: i32)
+		-> Result<usize>
+	{
+		let dict = loader()?;
+		self.members.push(Member::new(dict, priority, Some(Box::new(loader))));
+		Ok(self.members.len() - 1)
+	}
+
+	/// Leave the dictionary out of searches, or take it back in
+	#[inline]
+	pub fn set_enabled(&mut self, idx: usize, enabled: bool)
+	{
+		if let Some(member) = self.members.get_mut(idx) {
+			member.enabled = enabled;
+		}
+	}
+
+	#[inline]
+	pub fn is_enabled(&self, idx: usize) -> bool
+	{
+		self.members.get(idx).is_some_and(|member| member.enabled)
+	}
+
+	/// Build the dictionary again with its loader, false if it was added
+	/// without one. The loaded dictionary is kept when loading fails
+	pub fn reload(&mut self, idx: usize) -> Result<bool>
+	{
+		let Some(member) = self.members.get_mut(idx) else {
+			return Ok(false);
+		};
+		let Some(loader) = &member.loader else {
+			return Ok(false);
+		};
+		member.dict = loader()?;
+		member.modified = modified(&member.dict);
+		Ok(true)
+	}
+
+	/// Reload the dictionaries whose mdx file changed since loaded,
+	/// returns their indices
+	pub fn reload_changed(&mut self) -> Result<Vec<usize>>
+	{
+		let mut reloaded = vec![];
+		for idx in 0..self.members.len() {
+			let member = &self.members[idx];
+			if member.loader.is_some() && modified(&member.dict) != member.modified
+				&& self.reload(idx)? {
+				reloaded.push(idx);
+			}
+		}
+		Ok(reloaded)
+	}
+
+	#[inline]
+	pub fn len(&self) -> usize
+	{
+		self.members.len()
+	}
+
+	#[inline]
+	pub fn is_empty(&self) -> bool
+	{
+		self.members.is_empty()
+	}
+
+	/// Index of the member that is the same dictionary as `dict`, whatever
+	/// its file is named, see [`MDict::identity()`]
+	pub fn find_duplicate(&self, dict: &MDict<M>) -> Option<usize>
+	{
+		let identity = dict.identity();
+		self.members.iter().position(|member| member.dict.identity() == identity)
+	}
+
+	#[inline]
+	pub fn get(&self, idx: usize) -> Option<&MDict<M>>
+	{
+		self.members.get(idx).map(|member| &member.dict)
+	}
+
+	#[inline]
+	pub fn get_mut(&mut self, idx: usize) -> Option<&mut MDict<M>>
+	{
+		self.members.get_mut(idx).map(|member| &mut member.dict)
+	}
+
+	#[inline]
+	pub fn priority(&self, idx: usize) -> Option<i32>
+	{
+		self.members.get(idx).map(|member| member.priority)
+	}
+
+	/// Enabled dictionaries by priority
+	fn by_priority(&self) -> Vec<usize>
+	{
+		let mut order: Vec<usize> = (0..self.members.len())
+			.filter(|idx| self.members[*idx].enabled)
+			.collect();
+		order.sort_by_key(|idx| (-self.members[*idx].priority, *idx));
+		order
+	}
+
+	/// Definitions of `word` in the enabled dictionaries, grouped as
+	/// `dedup` tells
+	pub fn lookup_all(&mut self, word: &str, dedup: Dedup) -> Result<Vec<GroupDefinition>>
+	{
+		let mut found = vec![];
+		for idx in self.by_priority() {
+			if let Some((key, definition)) = lookup_one(&mut self.members[idx].dict, word)? {
+				found.push((key, idx, definition));
+				if dedup == Dedup::First {
+					break;
+				}
+			}
+		}
+		Ok(gather(found, dedup))
+	}
+
+	/// `lookup_all()` with the dictionaries shared among up to `threads`
+	/// threads, every enabled dictionary is queried even for `Dedup::First`
+	pub fn par_lookup_all(&mut self, word: &str, dedup: Dedup, threads: usize)
+		-> Result<Vec<GroupDefinition>>
+		where MDict<M>: Send
+	{
+		let jobs: Vec<_> = self.members.iter_mut()
+			.enumerate()
+			.filter(|(_, member)| member.enabled)
+			.map(|(idx, member)| (idx, &mut member.dict))
+			.collect();
+		let workers = threads.min(jobs.len());
+		if workers <= 1 {
+			return self.lookup_all(word, dedup);
+		}
+		let jobs = Mutex::new(jobs.into_iter());
+		let mut found = thread::scope(|scope| {
+			let workers: Vec<_> = (0..workers)
+				.map(|_| scope.spawn(|| {
+					let mut found = vec![];
+					loop {
+						let job = jobs.lock().unwrap_or_else(|err| err.into_inner()).next();
+						let Some((idx, dict)) = job else {
+							return Ok(found);
+						};
+						if let Some((key, definition)) = lookup_one(dict, word)? {
+							found.push((key, idx, definition));
+						}
+					}
+				}))
+				.collect();
+			workers.into_iter()
+				.map(|worker| worker.join()
+					.unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+				.collect::<Result<Vec<Vec<_>>>>()
+		})?
+			.concat();
+		found.sort_by_key(|(_, idx, _)| (-self.members[*idx].priority, *idx));
+		Ok(gather(found, dedup))
+	}
+
+	/// Definition of `word` in the first enabled dictionary by priority
+	/// having it, later dictionaries are not queried
+	#[inline]
+	pub fn lookup_first(&mut self, word: &str) -> Result<Option<GroupDefinition>>
+	{
+		Ok(self.lookup_all(word, Dedup::First)?.pop())
+	}
+
+	/// Search every enabled dictionary, ranked by match kind, dictionary
+	/// priority and key length, equal keys are kept as `options.dedup`
+	/// tells
+	#[inline]
+	pub fn search(&self, query: &str, options: &SearchOptions) -> Vec<SearchHit>
+	{
+		self.search_in(query, options, |_| true)
+	}
+
+	/// Search the enabled dictionaries `query` names as it tells, see
+	/// [`Query`] for the syntax
+	pub fn search_query(&self, query: &str) -> Result<Vec<SearchHit>>
+	{
+		let query = Query::parse(query)?;
+		let selected: Vec<usize> = (0..self.members.len())
+			.filter(|idx| query.dicts.is_empty()
+				|| query.dicts.iter().any(|name| self.is_named(*idx, name)))
+			.collect();
+		Ok(self.search_in(&query.text, &query.options, |idx| selected.contains(&idx)))
+	}
+
+	/// Whether `name` is the index of the dictionary or part of its title
+	/// or file name, ignoring case
+	fn is_named(&self, idx: usize, name: &str) -> bool
+	{
+		if name.parse() == Ok(idx) {
+			return true;
+		}
+		let dict = &self.members[idx].dict;
+		let name = fold_case(name);
+		let stem = dict.mdx.source.as_ref()
+			.and_then(|source| source.file_stem())
+			.map(|stem| stem.to_string_lossy());
+		fold_case(dict.title()).contains(&name)
+			|| stem.is_some_and(|stem| fold_case(&stem).contains(&name))
+	}
+
+	fn search_in(&self, query: &str, options: &SearchOptions, selected: impl Fn(usize) -> bool)
+		-> Vec<SearchHit>
+	{
+		let mut hits: HashMap<&str, (MatchKind, Vec<usize>)> = HashMap::new();
+		let mut add = |key, kind, idx| {
+			let (best, dicts) = hits.entry(key).or_insert((kind, vec![]));
+			if kind < *best {
+				*best = kind;
+			}
+			if !dicts.contains(&idx) {
+				dicts.push(idx);
+			}
+		};
+		for idx in self.by_priority().into_iter().filter(|idx| selected(*idx)) {
+			let dict = &self.members[idx].dict;
+			let mut found = false;
+			let mut add = |key, kind| {
+				found = true;
+				add(key, kind, idx);
+			};
+			if options.prefix {
+				for key in dict.search_prefix(query) {
+					add(key, MatchKind::Prefix);
+				}
+			}
+			if options.fuzzy_distance > 0 {
+				for (key, distance) in dict.search_fuzzy(query, options.fuzzy_distance) {
+					add(key, MatchKind::Fuzzy(distance));
+				}
+			}
+			if let Some(key) = dict.find_key(query) {
+				add(key, MatchKind::Exact)
+			}
+			if found && options.fallback {
+				break;
+			}
+		}
+		let mut hits: Vec<SearchHit> = hits.into_iter()
+			.flat_map(|(key, (kind, mut dicts))| {
+				dicts.sort_by_key(|idx| (-self.members[*idx].priority, *idx));
+				let dicts = match options.dedup {
+					Dedup::KeepAll => dicts.into_iter().map(|idx| vec![idx]).collect(),
+					Dedup::First => vec![vec![dicts[0]]],
+					Dedup::Merge => vec![dicts],
+				};
+				dicts.into_iter().map(move |dicts| SearchHit { key: key.to_owned(), kind, dicts })
+			})
+			.collect();
+		hits.sort_by(|a, b| a.kind.cmp(&b.kind)
+			.then_with(|| self.members[b.dicts[0]].priority
+				.cmp(&self.members[a.dicts[0]].priority))
+			.then_with(|| a.key.chars().count().cmp(&b.key.chars().count()))
+			.then_with(|| a.key.cmp(&b.key)));
+		hits.into_iter()
+			.skip(options.offset)
+			.take(options.limit)
+			.collect()
+	}
+}
@@ -0,0 +1,287 @@
+//! Lookup history and bookmarks, keyed by dictionary id and the key a
+//! headword normalizes to
+//!
+//! [`FileStore`] keeps them as an append-only text file, a line per
+//! change, fields separated by tabs:
+//!
+//! ```text
+//! L <seconds since the epoch> <dictionary id> <key>
+//! B <+ or -> <dictionary id> <key>
+//! C
+//! ```
+
+use std::collections::BTreeSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::mdx::{KeyMaker, MDict, WordDefinition};
+use crate::Result;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+	pub dict: String,
+	pub key: String,
+	pub time: SystemTime,
+}
+
+/// Where lookups and bookmarks are kept, `dict` is [`MDict::id()`]
+pub trait HistoryStore {
+	fn add_lookup(&mut self, dict: &str, key: &str, time: SystemTime) -> Result<()>;
+
+	/// Up to `limit` lookups, newest first, of `dict` or of all
+	/// dictionaries
+	fn history(&self, dict: Option<&str>, limit: usize) -> Result<Vec<HistoryEntry>>;
+
+	fn clear_history(&mut self) -> Result<()>;
+
+	fn set_bookmark(&mut self, dict: &str, key: &str, bookmarked: bool) -> Result<()>;
+
+	fn is_bookmarked(&self, dict: &str, key: &str) -> Result<bool>;
+
+	/// Dictionary id and key of the bookmarks of `dict` or of all
+	/// dictionaries, in order
+	fn bookmarks(&self, dict: Option<&str>) -> Result<Vec<(String, String)>>;
+}
+
+/// History and bookmarks held in memory only
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+	history: Vec<HistoryEntry>,
+	bookmarks: BTreeSet<(String, String)>,
+}
+
+impl MemoryStore {
+	#[inline]
+	pub fn new() -> Self
+	{
+		Self::default()
+	}
+}
+
+impl HistoryStore for MemoryStore {
+	fn add_lookup(&mut self, dict: &str, key: &str, time: SystemTime) -> Result<()>
+	{
+		self.history.push(HistoryEntry { dict: dict.to_owned(), key: key.to_owned(), time });
+		Ok(())
+	}
+
+	fn history(&self, dict: Option<&str>, limit: usize) -> Result<Vec<HistoryEntry>>
+	{
+		Ok(self.history.iter()
+			.rev()
+			.filter(|entry| dict.is_none_or(|dict| entry.dict == dict))
+			.take(limit)
+			.cloned()
+			.collect())
+	}
+
+	#[inline]
+	fn clear_history(&mut self) -> Result<()>
+	{
+		self.history.clear();
+		Ok(())
+	}
+
+	fn set_bookmark(&mut self, dict: &str, key: &str, bookmarked: bool) -> Result<()>
+	{
+		let bookmark = (dict.to_owned(), key.to_owned());
+		if bookmarked {
+			self.bookmarks.insert(bookmark);
+		} else {
+			self.bookmarks.remove(&bookmark);
+		}
+		Ok(())
+	}
+
+	#[inline]
+	fn is_bookmarked(&self, dict: &str, key: &str) -> Result<bool>
+	{
+		Ok(self.bookmarks.contains(&(dict.to_owned(), key.to_owned())))
+	}
+
+	fn bookmarks(&self, dict: Option<&str>) -> Result<Vec<(String, String)>>
+	{
+		Ok(self.bookmarks.iter()
+			.filter(|(id, _)| dict.is_none_or(|dict| id == dict))
+			.cloned()
+			.collect())
+	}
+}
+
+/// History and bookmarks kept in a file, every change is appended to it
+/// as it is made
+pub struct FileStore {
+	path: PathBuf,
+	memory: MemoryStore,
+	file: File,
+}
+
+impl FileStore {
+	/// Open the store at `path`, created when missing. Malformed lines
+	/// are skipped
+	pub fn open(path: impl AsRef<Path>) -> Result<Self>
+	{
+		let path = path.as_ref().to_path_buf();
+		let mut memory = MemoryStore::new();
+		if path.exists() {
+			for line in fs::read_to_string(&path)?.lines() {
+				let fields: Vec<String> = line.split('\t').map(unescape).collect();
+				match fields.as_slice() {
+					[kind, secs, dict, key] if kind == "L" => if let Ok(secs) = secs.parse() {
+						memory.add_lookup(dict, key, UNIX_EPOCH + Duration::from_secs(secs))?;
+					},
+					[kind, state, dict, key] if kind == "B" =>
+						memory.set_bookmark(dict, key, state == "+")?,
+					[kind] if kind == "C" => memory.clear_history()?,
+					_ => {}
+				}
+			}
+		}
+		let file = OpenOptions::new().create(true).append(true).open(&path)?;
+		Ok(FileStore { path, memory, file })
+	}
+
+	/// Rewrite the file with only what it holds now
+	pub fn compact(&mut self) -> Result<()>
+	{
+		let temporary = self.path.with_extension("tmp");
+		let mut writer = BufWriter::new(File::create(&temporary)?);
+		for entry in self.memory.history.iter() {
+			writeln!(writer, "L\t{}\t{}\t{}", secs(entry.time), escape(&entry.dict),
+				escape(&entry.key))?;
+		}
+		for (dict, key) in &self.memory.bookmarks {
+			writeln!(writer, "B\t+\t{}\t{}", escape(dict), escape(key))?;
+		}
+		writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+		fs::rename(&temporary, &self.path)?;
+		self.file = OpenOptions::new().append(true).open(&self.path)?;
+		Ok(())
+	}
+
+	#[inline]
+	fn append(&mut self, line: &str) -> Result<()>
+	{
+		writeln!(self.file, "{}", line)?;
+		Ok(())
+	}
+}
+
+impl HistoryStore for FileStore {
+	fn add_lookup(&mut self, dict: &str, key: &str, time: SystemTime) -> Result<()>
+	{
+		self.append(&format!("L\t{}\t{}\t{}", secs(time), escape(dict), escape(key)))?;
+		self.memory.add_lookup(dict, key, time)
+	}
+
+	#[inline]
+	fn history(&self, dict: Option<&str>, limit: usize) -> Result<Vec<HistoryEntry>>
+	{
+		self.memory.history(dict, limit)
+	}
+
+	fn clear_history(&mut self) -> Result<()>
+	{
+		self.append("C")?;
+		self.memory.clear_history()
+	}
+
+	fn set_bookmark(&mut self, dict: &str, key: &str, bookmarked: bool) -> Result<()>
+	{
+		let state = if bookmarked { '+' } else { '-' };
+		self.append(&format!("B\t{}\t{}\t{}", state, escape(dict), escape(key)))?;
+		self.memory.set_bookmark(dict, key, bookmarked)
+	}
+
+	#[inline]
+	fn is_bookmarked(&self, dict: &str, key: &str) -> Result<bool>
+	{
+		self.memory.is_bookmarked(dict, key)
+	}
+
+	#[inline]
+	fn bookmarks(&self, dict: Option<&str>) -> Result<Vec<(String, String)>>
+	{
+		self.memory.bookmarks(dict)
+	}
+}
+
+#[inline]
+fn secs(time: SystemTime) -> u64
+{
+	time.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn escape(text: &str) -> String
+{
+	text.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(text: &str) -> String
+{
+	let mut unescaped = String::with_capacity(text.len());
+	let mut chars = text.chars();
+	while let Some(ch) = chars.next() {
+		if ch != '\\' {
+			unescaped.push(ch);
+			continue;
+		}
+		match chars.next() {
+			Some('t') => unescaped.push('\t'),
+			Some('n') => unescaped.push('\n'),
+			Some('r') => unescaped.push('\r'),
+			Some(ch) => unescaped.push(ch),
+			None => unescaped.push('\\'),
+		}
+	}
+	unescaped
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Name of the dictionary in a [`HistoryStore`]: its mdx file name
+	/// without extension, its title when not read from a file
+	pub fn id(&self) -> String
+	{
+		self.mdx.source.as_ref()
+			.and_then(|source| source.file_stem())
+			.map_or_else(|| self.title().to_owned(), |stem| stem.to_string_lossy().into_owned())
+	}
+
+	/// `lookup()` adding the key found to the history in `store`
+	pub fn lookup_recorded<'a>(&mut self, word: &'a str, store: &mut dyn HistoryStore)
+		-> Result<Option<WordDefinition<'a>>>
+	{
+		let Some(key) = self.find_key(word).map(str::to_owned) else {
+			return Ok(None);
+		};
+		let definition = self.lookup(word)?;
+		if definition.is_some() {
+			store.add_lookup(&self.id(), &key, SystemTime::now())?;
+		}
+		Ok(definition)
+	}
+
+	/// Bookmark the key of `word`, or remove its bookmark, false when the
+	/// dictionary doesn't have it
+	pub fn set_bookmark(&self, word: &str, bookmarked: bool, store: &mut dyn HistoryStore)
+		-> Result<bool>
+	{
+		let Some(key) = self.find_key(word) else {
+			return Ok(false);
+		};
+		store.set_bookmark(&self.id(), key, bookmarked)?;
+		Ok(true)
+	}
+
+	/// Whether the key of `word` is bookmarked, any spelling normalizing
+	/// to it is
+	pub fn is_bookmarked(&self, word: &str, store: &dyn HistoryStore) -> Result<bool>
+	{
+		match self.find_key(word) {
+			Some(key) => store.is_bookmarked(&self.id(), key),
+			None => Ok(false),
+		}
+	}
+}
@@ -0,0 +1,352 @@
+use std::borrow::Cow;
+use std::fmt;
+
+pub(crate) enum Token<'a> {
+	Text(&'a str),
+	Tag(Tag<'a>),
+	// comments, doctype and processing instructions, kept verbatim
+	Other(&'a str),
+}
+
+pub struct Tag<'a> {
+	raw: &'a str,
+	name: String,
+	end: bool,
+	self_closing: bool,
+	attrs: Vec<(Cow<'a, str>, Option<Cow<'a, str>>)>,
+	modified: bool,
+}
+
+impl<'a> Tag<'a> {
+	#[inline]
+	pub fn name(&self) -> &str
+	{
+		&self.name
+	}
+
+	#[inline]
+	pub fn is_end(&self) -> bool
+	{
+		self.end
+	}
+
+	#[inline]
+	pub fn is_self_closing(&self) -> bool
+	{
+		self.self_closing
+	}
+
+	pub fn attr(&self, name: &str) -> Option<&str>
+	{
+		self.attrs.iter()
+			.find(|(n, _)| n.eq_ignore_ascii_case(name))
+			.map(|(_, v)| v.as_deref().unwrap_or(""))
+	}
+
+	pub fn attrs(&self) -> impl Iterator<Item=(&str, Option<&str>)>
+	{
+		self.attrs.iter().map(|(n, v)| (n.as_ref(), v.as_deref()))
+	}
+
+	pub fn set_attr(&mut self, name: &str, value: impl Into<String>)
+	{
+		let value = Cow::Owned(value.into());
+		if let Some((_, v)) = self.attrs.iter_mut()
+			.find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+			*v = Some(value);
+		} else {
+			self.attrs.push((Cow::Owned(name.to_owned()), Some(value)));
+		}
+		self.modified = true;
+	}
+
+	pub fn remove_attr(&mut self, name: &str)
+	{
+		let len = self.attrs.len();
+		self.attrs.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+		if self.attrs.len() != len {
+			self.modified = true;
+		}
+	}
+
+	pub fn retain_attrs(&mut self, f: impl Fn(&str) -> bool)
+	{
+		let len = self.attrs.len();
+		self.attrs.retain(|(n, _)| f(n));
+		if self.attrs.len() != len {
+			self.modified = true;
+		}
+	}
+}
+
+impl fmt::Display for Tag<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+	{
+		if !self.modified {
+			return f.write_str(self.raw);
+		}
+		f.write_str(if self.end { "</" } else { "<" })?;
+		f.write_str(&self.name)?;
+		for (name, value) in &self.attrs {
+			write!(f, " {}", name)?;
+			if let Some(value) = value {
+				write!(f, "=\"{}\"", value.replace('"', "&quot;"))?;
+			}
+		}
+		f.write_str(if self.self_closing { " />" } else { ">" })
+	}
+}
+
+pub(crate) struct Tokenizer<'a> {
+	html: &'a str,
+	pos: usize,
+	raw_text: Option<String>,
+}
+
+impl<'a> Tokenizer<'a> {
+	pub(crate) fn new(html: &'a str) -> Self
+	{
+		Tokenizer { html, pos: 0, raw_text: None }
+	}
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+	type Item = Token<'a>;
+
+	fn next(&mut self) -> Option<Self::Item>
+	{
+		// content of <script> and <style> is not markup
+		if let Some(name) = self.raw_text.take() {
+			let rest = &self.html[self.pos..];
+			let end = find_end_tag(rest, &name).unwrap_or(rest.len());
+			if end > 0 {
+				self.pos += end;
+				return Some(Token::Text(&rest[..end]));
+			}
+		}
+		let rest = &self.html[self.pos..];
+		if rest.is_empty() {
+			return None;
+		}
+		if rest.starts_with('<') {
+			let other_end = if rest.starts_with("<!--") {
+				Some(rest.find("-->").map_or(rest.len(), |idx| idx + 3))
+			} else if rest.starts_with("<!") || rest.starts_with("<?") {
+				Some(rest.find('>').map_or(rest.len(), |idx| idx + 1))
+			} else {
+				None
+			};
+			if let Some(end) = other_end {
+				self.pos += end;
+				return Some(Token::Other(&rest[..end]));
+			}
+			if let Some((tag, len)) = parse_tag(rest) {
+				self.pos += len;
+				if !tag.end && !tag.self_closing
+					&& (tag.name == "script" || tag.name == "style") {
+					self.raw_text = Some(tag.name.clone());
+				}
+				return Some(Token::Tag(tag));
+			}
+		}
+		// stray '<' is plain text
+		let from = usize::from(rest.starts_with('<'));
+		let end = rest[from..].find('<').map_or(rest.len(), |idx| idx + from);
+		self.pos += end;
+		Some(Token::Text(&rest[..end]))
+	}
+}
+
+fn find_end_tag(html: &str, name: &str) -> Option<usize>
+{
+	let bytes = html.as_bytes();
+	let mut from = 0;
+	while let Some(idx) = html[from..].find("</") {
+		let start = from + idx;
+		let name_end = start + 2 + name.len();
+		if name_end <= bytes.len()
+			&& bytes[start + 2..name_end].eq_ignore_ascii_case(name.as_bytes()) {
+			return Some(start);
+		}
+		from = start + 2;
+	}
+	None
+}
+
+fn parse_tag(html: &str) -> Option<(Tag<'_>, usize)>
+{
+	#[inline]
+	fn is_name_end(b: u8) -> bool
+	{
+		b.is_ascii_whitespace() || b == b'>' || b == b'/' || b == b'='
+	}
+
+	let bytes = html.as_bytes();
+	let mut i = 1;
+	let end = bytes.get(i) == Some(&b'/');
+	if end {
+		i += 1;
+	}
+	if !bytes.get(i)?.is_ascii_alphabetic() {
+		return None;
+	}
+	let name_start = i;
+	while i < bytes.len() && !is_name_end(bytes[i]) {
+		i += 1;
+	}
+	let name = html[name_start..i].to_ascii_lowercase();
+	let mut attrs = vec![];
+	let mut self_closing = false;
+	loop {
+		while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+			i += 1;
+		}
+		match bytes.get(i)? {
+			b'>' => {
+				i += 1;
+				break;
+			}
+			b'/' => {
+				i += 1;
+				if bytes.get(i) == Some(&b'>') {
+					self_closing = true;
+					i += 1;
+					break;
+				}
+				continue;
+			}
+			_ => {}
+		}
+		let attr_start = i;
+		while i < bytes.len() && !is_name_end(bytes[i]) {
+			i += 1;
+		}
+		if attr_start == i {
+			// lone '='
+			i += 1;
+			continue;
+		}
+		let attr_name = &html[attr_start..i];
+		while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+			i += 1;
+		}
+		let value = if bytes.get(i) == Some(&b'=') {
+			i += 1;
+			while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+				i += 1;
+			}
+			match bytes.get(i)? {
+				quote @ (b'"' | b'\'') => {
+					let value_start = i + 1;
+					let len = bytes[value_start..].iter().position(|b| b == quote)?;
+					i = value_start + len + 1;
+					Some(&html[value_start..value_start + len])
+				}
+				_ => {
+					let value_start = i;
+					while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+						i += 1;
+					}
+					Some(&html[value_start..i])
+				}
+			}
+		} else {
+			None
+		};
+		attrs.push((Cow::Borrowed(attr_name), value.map(Cow::Borrowed)));
+	}
+	Some((Tag {
+		raw: &html[..i],
+		name,
+		end,
+		self_closing,
+		attrs,
+		modified: false,
+	}, i))
+}
+
+const ENTITIES: [(&str, char); 16] = [
+	("amp", '&'), ("lt", '<'), ("gt", '>'), ("quot", '"'), ("apos", '\''),
+	("nbsp", '\u{a0}'), ("shy", '\u{ad}'), ("middot", '·'), ("hellip", '…'),
+	("mdash", '—'), ("ndash", '–'), ("laquo", '«'), ("raquo", '»'),
+	("copy", '©'), ("reg", '®'), ("deg", '°'),
+];
+
+/// Replace character references, unknown ones are kept as written
+pub(crate) fn decode_entities(text: &str) -> Cow<'_, str>
+{
+	if !text.contains('&') {
+		return Cow::Borrowed(text);
+	}
+	let mut decoded = String::with_capacity(text.len());
+	let mut rest = text;
+	while let Some(idx) = rest.find('&') {
+		decoded.push_str(&rest[..idx]);
+		rest = &rest[idx..];
+		let ch = rest[1..].find(';')
+			.filter(|end| *end <= 10)
+			.and_then(|end| {
+				let name = &rest[1..end + 1];
+				let ch = match name.strip_prefix('#') {
+					Some(number) => match number.strip_prefix(['x', 'X']) {
+						Some(hex) => u32::from_str_radix(hex, 16).ok(),
+						None => number.parse().ok(),
+					}.and_then(char::from_u32),
+					None => ENTITIES.iter()
+						.find(|(entity, _)| *entity == name)
+						.map(|(_, ch)| *ch),
+				};
+				ch.map(|ch| (ch, end + 2))
+			});
+		match ch {
+			Some((ch, len)) => {
+				decoded.push(ch);
+				rest = &rest[len..];
+			}
+			None => {
+				decoded.push('&');
+				rest = &rest[1..];
+			}
+		}
+	}
+	decoded.push_str(rest);
+	Cow::Owned(decoded)
+}
+
+/// Elements laid out apart from the text around them
+pub(crate) const BLOCK_ELEMENTS: [&str; 29] = [
+	"address", "article", "aside", "blockquote", "br", "dd", "div", "dl", "dt",
+	"figure", "footer", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr",
+	"li", "ol", "p", "pre", "section", "table", "td", "th", "tr", "ul",
+];
+
+/// Text of the document without markup, script and style content, with
+/// character references decoded. Block-level elements separate words,
+/// inline ones don't
+pub(crate) fn text_content(html: &str) -> String
+{
+	let mut text = String::with_capacity(html.len());
+	let mut skip = false;
+	// a block boundary since the last text
+	let mut separate = false;
+	for token in Tokenizer::new(html) {
+		match token {
+			Token::Text(content) => if !skip {
+				if separate && !text.is_empty() && !text.ends_with(char::is_whitespace) {
+					text.push(' ');
+				}
+				separate = false;
+				text.push_str(&decode_entities(content));
+			},
+			Token::Tag(tag) => {
+				if tag.name == "script" || tag.name == "style" {
+					skip = !tag.end && !tag.self_closing;
+				} else if BLOCK_ELEMENTS.contains(&tag.name.as_str()) {
+					separate = true;
+				}
+			}
+			Token::Other(_) => {}
+		}
+	}
+	text
+}
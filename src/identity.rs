@@ -0,0 +1,34 @@
+use crate::mdx::{KeyMaker, MDict};
+
+// FNV-1a, unlike the std hashers it is the same across Rust versions
+pub(crate) const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+#[inline]
+pub(crate) fn fnv(hash: u64, bytes: &[u8]) -> u64
+{
+	bytes.iter().fold(hash, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME))
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Hash of the header and the key index, the same for copies of a
+	/// dictionary under any file name and across runs, so groups and
+	/// libraries can tell they already hold it. Computed on first use
+	pub fn identity(&self) -> u64
+	{
+		*self.identity.get_or_init(|| {
+			let mut entries: Vec<(usize, &str)> = self.mdx.key_entries.iter()
+				.map(|entry| (entry.offset, entry.raw()))
+				.collect();
+			entries.sort_unstable();
+			let mut hash = fnv(FNV_OFFSET, &self.mdx.header_checksum.to_le_bytes());
+			hash = fnv(hash, &(entries.len() as u64).to_le_bytes());
+			for (offset, raw) in entries {
+				hash = fnv(hash, &(offset as u64).to_le_bytes());
+				hash = fnv(hash, raw.as_bytes());
+				hash = fnv(hash, &[0]);
+			}
+			hash
+		})
+	}
+}
@@ -0,0 +1,173 @@
+//! Key index persisted next to a dictionary file, so later loads skip
+//! decoding the key blocks
+//!
+//! The format is little endian throughout and holds the stored headwords
+//! in file order, keys are normalized and sorted again on load so the same
+//! index serves any `KeyMaker` and collation:
+//!
+//! ```text
+//! magic "MDICTIDX", format version u32
+//! fingerprint: file size u64, mtime seconds u64, mtime nanoseconds u32,
+//!     header checksum u32
+//! record blocks offset u64, record block count u64,
+//!     per block compressed size u64, decompressed size u64
+//! key count u64, per key record offset u64, headword length u32, utf-8 headword
+//! ```
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+
+use crate::Result;
+use crate::format::{to_usize, BlockEntryInfo, KeyEntry};
+
+const MAGIC: &[u8; 8] = b"MDICTIDX";
+const VERSION: u32 = 1;
+// longer headwords mean a corrupt index
+const MAX_HEADWORD: usize = 1 << 16;
+
+/// Whether to use the index next to the dictionary files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IndexMode {
+	Off,
+	Use,
+	/// ignore the existing index and write a new one
+	Rebuild,
+}
+
+/// What the index was built from, a stale index is ignored
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Fingerprint {
+	size: u64,
+	mtime_secs: u64,
+	mtime_nanos: u32,
+	header_checksum: u32,
+}
+
+impl Fingerprint {
+	pub(crate) fn of(path: &Path, header_checksum: u32) -> Result<Self>
+	{
+		let metadata = fs::metadata(path)?;
+		let mtime = metadata.modified()?
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default();
+		Ok(Fingerprint {
+			size: metadata.len(),
+			mtime_secs: mtime.as_secs(),
+			mtime_nanos: mtime.subsec_nanos(),
+			header_checksum,
+		})
+	}
+
+	fn read(reader: &mut impl Read) -> Result<Self>
+	{
+		Ok(Fingerprint {
+			size: reader.read_u64::<LE>()?,
+			mtime_secs: reader.read_u64::<LE>()?,
+			mtime_nanos: reader.read_u32::<LE>()?,
+			header_checksum: reader.read_u32::<LE>()?,
+		})
+	}
+
+	fn write(&self, writer: &mut impl Write) -> Result<()>
+	{
+		writer.write_u64::<LE>(self.size)?;
+		writer.write_u64::<LE>(self.mtime_secs)?;
+		writer.write_u32::<LE>(self.mtime_nanos)?;
+		writer.write_u32::<LE>(self.header_checksum)?;
+		Ok(())
+	}
+}
+
+/// The parts of a loaded file that take decoding the key blocks to find
+pub(crate) struct Index {
+	// record offset and stored headword
+	pub(crate) keys: Vec<(usize, String)>,
+	pub(crate) records_info: Vec<BlockEntryInfo>,
+	pub(crate) record_block_offset: u64,
+}
+
+/// `<file name>.idx` next to `path`
+pub(crate) fn index_path(path: &Path) -> PathBuf
+{
+	let mut name = OsString::from(path.as_os_str());
+	name.push(".idx");
+	PathBuf::from(name)
+}
+
+/// The index at `path` if it exists and matches `fingerprint`
+pub(crate) fn read(path: &Path, fingerprint: &Fingerprint) -> Option<Index>
+{
+	let mut reader = BufReader::new(File::open(path).ok()?);
+	read_index(&mut reader, fingerprint).ok().flatten()
+}
+
+fn read_index(reader: &mut impl Read, fingerprint: &Fingerprint) -> Result<Option<Index>>
+{
+	let mut magic = [0; 8];
+	reader.read_exact(&mut magic)?;
+	if &magic != MAGIC || reader.read_u32::<LE>()? != VERSION
+		|| Fingerprint::read(reader)? != *fingerprint {
+		return Ok(None);
+	}
+	let record_block_offset = reader.read_u64::<LE>()?;
+	let mut records_info = vec![];
+	for _ in 0..reader.read_u64::<LE>()? {
+		let compressed_size = to_usize(reader.read_u64::<LE>()?)?;
+		let decompressed_size = to_usize(reader.read_u64::<LE>()?)?;
+		records_info.push(BlockEntryInfo { compressed_size, decompressed_size });
+	}
+	let mut keys = vec![];
+	for _ in 0..reader.read_u64::<LE>()? {
+		let offset = to_usize(reader.read_u64::<LE>()?)?;
+		let len = reader.read_u32::<LE>()? as usize;
+		if len > MAX_HEADWORD {
+			return Ok(None);
+		}
+		let mut headword = vec![0; len];
+		reader.read_exact(&mut headword)?;
+		match String::from_utf8(headword) {
+			Ok(headword) => keys.push((offset, headword)),
+			Err(_) => return Ok(None),
+		}
+	}
+	Ok(Some(Index { keys, records_info, record_block_offset }))
+}
+
+/// Write the index to `path`, through a temporary file so readers never
+/// see it half written
+pub(crate) fn write(path: &Path, fingerprint: &Fingerprint, entries: &[KeyEntry],
+	records_info: &[BlockEntryInfo], record_block_offset: u64) -> Result<()>
+{
+	let mut keys: Vec<(usize, &str)> = entries.iter()
+		.map(|entry| (entry.offset, entry.raw()))
+		.collect();
+	// file order, whatever the collation
+	keys.sort_unstable();
+
+	let mut temp = OsString::from(path.as_os_str());
+	temp.push(".tmp");
+	let temp = PathBuf::from(temp);
+	let mut writer = BufWriter::new(File::create(&temp)?);
+	writer.write_all(MAGIC)?;
+	writer.write_u32::<LE>(VERSION)?;
+	fingerprint.write(&mut writer)?;
+	writer.write_u64::<LE>(record_block_offset)?;
+	writer.write_u64::<LE>(records_info.len() as u64)?;
+	for info in records_info {
+		writer.write_u64::<LE>(info.compressed_size as u64)?;
+		writer.write_u64::<LE>(info.decompressed_size as u64)?;
+	}
+	writer.write_u64::<LE>(keys.len() as u64)?;
+	for (offset, headword) in keys {
+		writer.write_u64::<LE>(offset as u64)?;
+		writer.write_u32::<LE>(headword.len() as u32)?;
+		writer.write_all(headword.as_bytes())?;
+	}
+	writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+	fs::rename(&temp, path)?;
+	Ok(())
+}
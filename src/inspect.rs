@@ -0,0 +1,384 @@
+//! Layout dumps and verification of dictionary files
+
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter, Write};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use encoding_rs::{Encoding, UTF_16LE};
+
+use crate::format::{block_header, BlockEntryInfo};
+use crate::mdx::{KeyMaker, MDict, Reader};
+use crate::parser::{check_block, find_entry, lookup_record, read_layout, record_offset};
+use crate::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+	Key,
+	Record,
+}
+
+impl BlockKind {
+	#[inline]
+	fn name(&self) -> &'static str
+	{
+		match self {
+			BlockKind::Key => "key",
+			BlockKind::Record => "record",
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockReport {
+	pub kind: BlockKind,
+	/// index among the blocks of its kind
+	pub index: usize,
+	/// position in the file
+	pub offset: u64,
+	pub compressed_size: usize,
+	pub decompressed_size: usize,
+	/// 0 none, 1 lzo, 2 zlib
+	pub compression: Option<u32>,
+	/// 0 none, 1 fast decrypt, 2 salsa20
+	pub encryption: Option<u32>,
+	/// adler32 stored in the block
+	pub expected_checksum: Option<u32>,
+	/// adler32 of the decoded block
+	pub actual_checksum: Option<u32>,
+	pub error: Option<String>,
+}
+
+impl BlockReport {
+	#[inline]
+	pub fn is_ok(&self) -> bool
+	{
+		self.error.is_none() && self.actual_checksum.is_some()
+			&& self.actual_checksum == self.expected_checksum
+	}
+}
+
+/// Block by block verification of one mdx or mdd file
+#[derive(Debug, Clone)]
+pub struct IntegrityReport {
+	pub blocks: Vec<BlockReport>,
+}
+
+#[inline]
+pub(crate) fn json_string(text: &str) -> String
+{
+	let mut json = String::with_capacity(text.len() + 2);
+	json.push('"');
+	for ch in text.chars() {
+		match ch {
+			'"' => json.push_str("\\\""),
+			'\\' => json.push_str("\\\\"),
+			'\n' => json.push_str("\\n"),
+			ch if (ch as u32) < 0x20 => {
+				let _ = write!(json, "\\u{:04x}", ch as u32);
+			}
+			ch => json.push(ch),
+		}
+	}
+	json.push('"');
+	json
+}
+
+#[inline]
+fn json_option(value: Option<impl ToString>) -> String
+{
+	value.map_or_else(|| "null".to_owned(), |value| value.to_string())
+}
+
+impl IntegrityReport {
+	#[inline]
+	pub fn is_ok(&self) -> bool
+	{
+		self.blocks.iter().all(BlockReport::is_ok)
+	}
+
+	#[inline]
+	pub fn failures(&self) -> impl Iterator<Item=&BlockReport>
+	{
+		self.blocks.iter().filter(|block| !block.is_ok())
+	}
+
+	/// The report as a JSON object
+	pub fn to_json(&self) -> String
+	{
+		let mut json = String::new();
+		let _ = write!(json, "{{\"ok\":{},\"blocks\":[", self.is_ok());
+		for (idx, block) in self.blocks.iter().enumerate() {
+			if idx > 0 {
+				json.push(',');
+			}
+			let _ = write!(json,
+				"{{\"kind\":\"{}\",\"index\":{},\"offset\":{},\"compressed_size\":{},\
+				\"decompressed_size\":{},\"compression\":{},\"encryption\":{},\
+				\"expected_checksum\":{},\"actual_checksum\":{},\"ok\":{},\"error\":{}}}",
+				block.kind.name(),
+				block.index,
+				block.offset,
+				block.compressed_size,
+				block.decompressed_size,
+				json_option(block.compression),
+				json_option(block.encryption),
+				json_option(block.expected_checksum),
+				json_option(block.actual_checksum),
+				block.is_ok(),
+				json_option(block.error.as_deref().map(json_string)));
+		}
+		json.push_str("]}");
+		json
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BlockSizes {
+	/// position in the file
+	pub offset: u64,
+	pub compressed_size: usize,
+	pub decompressed_size: usize,
+}
+
+/// Header fields and block tables of a file, read without decoding any
+/// key or record block
+#[derive(Debug, Clone)]
+pub struct Structure {
+	/// header attributes, by name
+	pub attributes: Vec<(String, String)>,
+	pub key_block_count: u64,
+	pub key_count: u64,
+	pub key_info_decompressed_size: u64,
+	pub key_info_size: u64,
+	pub key_blocks_size: u64,
+	pub key_blocks: Vec<BlockSizes>,
+	pub record_count: u64,
+	pub record_info_size: u64,
+	pub record_data_size: u64,
+	pub record_blocks: Vec<BlockSizes>,
+}
+
+fn block_sizes(mut offset: u64, infos: &[BlockEntryInfo]) -> Vec<BlockSizes>
+{
+	infos.iter()
+		.map(|info| {
+			let sizes = BlockSizes {
+				offset,
+				compressed_size: info.compressed_size,
+				decompressed_size: info.decompressed_size,
+			};
+			offset += info.compressed_size as u64;
+			sizes
+		})
+		.collect()
+}
+
+impl Display for Structure {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+	{
+		fn blocks(f: &mut Formatter<'_>, blocks: &[BlockSizes]) -> fmt::Result
+		{
+			writeln!(f, "  {:>6} {:>12} {:>12} {:>12}", "#", "offset", "compressed", "decompressed")?;
+			for (idx, block) in blocks.iter().enumerate() {
+				writeln!(f, "  {:>6} {:>#12x} {:>12} {:>12}",
+					idx, block.offset, block.compressed_size, block.decompressed_size)?;
+			}
+			Ok(())
+		}
+		writeln!(f, "header:")?;
+		for (name, value) in &self.attributes {
+			writeln!(f, "  {} = {:?}", name, value)?;
+		}
+		writeln!(f, "key blocks: {} blocks, {} keys", self.key_block_count, self.key_count)?;
+		writeln!(f, "  info size {} ({} decompressed), blocks size {}",
+			self.key_info_size, self.key_info_decompressed_size, self.key_blocks_size)?;
+		blocks(f, &self.key_blocks)?;
+		writeln!(f, "record blocks: {} blocks, {} records", self.record_blocks.len(), self.record_count)?;
+		writeln!(f, "  info size {}, data size {}", self.record_info_size, self.record_data_size)?;
+		blocks(f, &self.record_blocks)
+	}
+}
+
+fn structure(reader: &mut Reader, encoding: &'static Encoding) -> Result<Structure>
+{
+	let layout = read_layout(reader, encoding)?;
+	Ok(Structure {
+		attributes: layout.attributes,
+		key_block_count: layout.key_block_num,
+		key_count: layout.key_entry_num,
+		key_info_decompressed_size: layout.key_info_decompressed_size,
+		key_info_size: layout.key_info_size,
+		key_blocks_size: layout.key_blocks_size,
+		key_blocks: block_sizes(layout.key_blocks_offset, &layout.key_blocks),
+		record_count: layout.record_entry_num,
+		record_info_size: layout.record_info_size,
+		record_data_size: layout.record_data_size,
+		record_blocks: block_sizes(layout.record_blocks_offset, &layout.record_blocks),
+	})
+}
+
+fn check(reader: &mut Reader, encoding: &'static Encoding) -> Result<IntegrityReport>
+{
+	let layout = read_layout(reader, encoding)?;
+	let mut blocks = vec![];
+	let kinds = [
+		(BlockKind::Key, layout.key_blocks_offset, &layout.key_blocks),
+		(BlockKind::Record, layout.record_blocks_offset, &layout.record_blocks),
+	];
+	for (kind, mut offset, infos) in kinds {
+		for (index, info) in infos.iter().enumerate() {
+			let check = check_block(reader, offset, index, info);
+			blocks.push(BlockReport {
+				kind,
+				index,
+				offset,
+				compressed_size: info.compressed_size,
+				decompressed_size: info.decompressed_size,
+				compression: check.header.as_ref().map(|header| header.compression),
+				encryption: check.header.as_ref().map(|header| header.encryption),
+				expected_checksum: check.header.as_ref().map(|header| header.checksum),
+				actual_checksum: check.actual_checksum,
+				error: check.error.map(|err| err.to_string()),
+			});
+			offset += info.compressed_size as u64;
+		}
+	}
+	Ok(IntegrityReport { blocks })
+}
+
+/// The compressed block holding a record and the record bytes
+#[derive(Debug, Clone)]
+pub struct RecordDump {
+	pub key: String,
+	/// index among the record blocks
+	pub block_index: usize,
+	/// position of the block in the file
+	pub block_offset: u64,
+	/// leading flags and checksum of the block
+	pub block_header: [u8; 8],
+	pub compression: u32,
+	pub encryption: u32,
+	pub checksum: u32,
+	pub compressed_size: usize,
+	pub decompressed_size: usize,
+	/// position of the record in the decompressed block
+	pub record_offset: usize,
+	/// the record, as returned by lookups
+	pub data: Vec<u8>,
+}
+
+/// `xxd` style lines of offset, hex bytes and printable ascii
+pub fn hexdump(data: &[u8]) -> String
+{
+	let mut dump = String::with_capacity(data.len() * 4 + 16);
+	for (line, chunk) in data.chunks(16).enumerate() {
+		let _ = write!(dump, "{:08x}:", line * 16);
+		for (idx, byte) in chunk.iter().enumerate() {
+			if idx % 2 == 0 {
+				dump.push(' ');
+			}
+			let _ = write!(dump, "{:02x}", byte);
+		}
+		let missing = 16 - chunk.len();
+		dump.push_str(&" ".repeat(missing * 2 + missing / 2));
+		dump.push_str("  ");
+		dump.extend(chunk.iter().map(|byte| if byte.is_ascii_graphic() || *byte == b' ' {
+			char::from(*byte)
+		} else {
+			'.'
+		}));
+		dump.push('\n');
+	}
+	dump
+}
+
+impl Display for RecordDump {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+	{
+		writeln!(f, "key: {:?}", self.key)?;
+		writeln!(f, "record block {} at {:#x}, {} bytes ({} decompressed)",
+			self.block_index, self.block_offset, self.compressed_size, self.decompressed_size)?;
+		writeln!(f, "block header: {}, compression {}, encryption {}, checksum {:08x}",
+			hexdump(&self.block_header).trim_end(), self.compression, self.encryption, self.checksum)?;
+		writeln!(f, "record at {:#x} of the block, {} bytes:", self.record_offset, self.data.len())?;
+		f.write_str(&hexdump(&self.data))
+	}
+}
+
+/// Structure of the mdx or mdd file at `path`, also when its keys can't
+/// be loaded
+pub fn read_structure(path: impl AsRef<Path>) -> Result<Structure>
+{
+	structure(&mut Reader::open(path.as_ref())?, UTF_16LE)
+}
+
+/// Verify the blocks of the mdx or mdd file at `path`, also when its keys
+/// can't be loaded
+pub fn check_file(path: impl AsRef<Path>) -> Result<IntegrityReport>
+{
+	check(&mut Reader::open(path.as_ref())?, UTF_16LE)
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Decode every block and compare its checksum, reports of the mdx
+	/// file then of the mdd files in `companions()` order
+	pub fn check(&mut self) -> Result<Vec<IntegrityReport>>
+	{
+		let mut reports = vec![check(&mut self.mdx.reader, self.mdx.encoding)?];
+		for mdd in &mut self.resources {
+			reports.push(check(&mut mdd.reader, mdd.encoding)?);
+		}
+		Ok(reports)
+	}
+
+	/// Structure of the mdx file then of the mdd files
+	pub fn structure(&mut self) -> Result<Vec<Structure>>
+	{
+		let mut structures = vec![structure(&mut self.mdx.reader, self.mdx.encoding)?];
+		for mdd in &mut self.resources {
+			structures.push(structure(&mut mdd.reader, mdd.encoding)?);
+		}
+		Ok(structures)
+	}
+
+	/// Where the record of `word` is stored and its raw bytes
+	pub fn dump_record(&mut self, word: &str) -> Result<Option<RecordDump>>
+	{
+		let key = self.key_maker.make(&Cow::Borrowed(word), false);
+		let mdx = &mut self.mdx;
+		let offset = match find_entry(mdx, &key).and_then(|entry| record_offset(mdx, entry)) {
+			Some(offset) => offset,
+			None => return Ok(None),
+		};
+		let block_offset = mdx.record_block_offset + offset.buf_offset;
+		let mut block_start = 0;
+		let block_index = mdx.records_info.iter()
+			.position(|info| {
+				let found = block_start == offset.buf_offset;
+				block_start += info.compressed_size as u64;
+				found
+			})
+			.unwrap_or_default();
+		mdx.reader.seek(SeekFrom::Start(block_offset))?;
+		let mut header_bytes = [0; 8];
+		mdx.reader.read_exact(&mut header_bytes)?;
+		let header = block_header(&header_bytes)?;
+		let record = match lookup_record(mdx, &key)? {
+			Some(record) => record,
+			None => return Ok(None),
+		};
+		Ok(Some(RecordDump {
+			key: record.key().to_owned(),
+			block_index,
+			block_offset,
+			block_header: header_bytes,
+			compression: header.compression,
+			encryption: header.encryption,
+			checksum: header.checksum,
+			compressed_size: offset.record_size,
+			decompressed_size: offset.decomp_size,
+			record_offset: offset.block_offset,
+			data: record.into_data().into_owned(),
+		}))
+	}
+}
@@ -0,0 +1,186 @@
+//! Kindle dictionary source: an OPF package and html pages with `idx:`
+//! lookup markup, compiled to MOBI or KFX by kindlegen or Kindle Previewer
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::companion::CompanionKind;
+use crate::mdx::{KeyMaker, MDict};
+use crate::site::{escape_text, extract_resources, forms, media_type, resource_url, rewrite, slug,
+	to_xhtml, Articles, SiteSummary};
+use crate::template::escape_attr;
+use crate::Result;
+
+// namespace of the mbp: and idx: markup
+const NAMESPACE: &str = "https://kindlegen.s3.amazonaws.com/AmazonKindlePublishingGuidelines.pdf";
+
+/// Writes the source of a Kindle dictionary into a directory:
+/// `content.opf`, a page per first letter of the keys and the resources
+/// referenced in `res/`
+pub struct KindleExporter {
+	input_language: String,
+	output_language: String,
+}
+
+impl Default for KindleExporter {
+	#[inline]
+	fn default() -> Self
+	{
+		KindleExporter { input_language: "en".to_owned(), output_language: "en".to_owned() }
+	}
+}
+
+impl KindleExporter {
+	#[inline]
+	pub fn new() -> Self
+	{
+		Self::default()
+	}
+
+	/// Language of the headwords, the one lookups are made in
+	#[inline]
+	pub fn input_language(mut self, language: impl Into<String>) -> Self
+	{
+		self.input_language = language.into();
+		self
+	}
+
+	/// Language of the definitions
+	#[inline]
+	pub fn output_language(mut self, language: impl Into<String>) -> Self
+	{
+		self.output_language = language.into();
+		self
+	}
+
+	/// Every headword becomes an `idx:orth`, the other headwords stored
+	/// under its key and the aliases set on the dictionary its inflections
+	pub fn write<M: KeyMaker>(&self, dict: &mut MDict<M>, dir: impl AsRef<Path>)
+		-> Result<SiteSummary>
+	{
+		let dir = dir.as_ref();
+		fs::create_dir_all(dir)?;
+		let css = match dict.read_companion(CompanionKind::Css)? {
+			Some(css) => {
+				fs::write(dir.join("style.css"), css)?;
+				true
+			}
+			None => false,
+		};
+		let title = escape_text(dict.title());
+		let stylesheet = if css {
+			"<link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/>\n"
+		} else {
+			""
+		};
+
+		// keys of a page are adjacent in key order, so pages are written
+		// one at a time
+		let forms = forms(dict);
+		let mut pages = vec![];
+		let mut entries = 0;
+		let mut current: Option<(String, BufWriter<File>)> = None;
+		let mut articles = Articles::new(dict);
+		let mut resources = BTreeSet::new();
+		let resource_href = |path: &str| format!("res/{}", resource_url(path));
+		while let Some((key, articles)) = articles.next(dict)? {
+			let name = page(&key);
+			let (_, body) = match current.take() {
+				Some((open, body)) if open == name => current.insert((open, body)),
+				last => {
+					if let Some((_, body)) = last {
+						end_page(body)?;
+					}
+					let mut body = BufWriter::new(File::create(dir.join(&name))?);
+					write!(body, "<html xmlns:mbp=\"{ns}\" xmlns:idx=\"{ns}\">\n<head>\n\
+						<meta http-equiv=\"Content-Type\" content=\"text/html; charset=utf-8\"/>\n\
+						<title>{}</title>\n{}</head>\n<body>\n<mbp:frameset>\n",
+						title, stylesheet, ns = NAMESPACE)?;
+					pages.push(name.clone());
+					current.insert((name, body))
+				}
+			};
+			let entry_href = |word: &str| {
+				let key = dict.find_key(word)?;
+				Some(format!("{}#{}", page(key), slug(key)))
+			};
+			writeln!(body, "<a id=\"{}\"></a>", escape_attr(&slug(&key)))?;
+			for article in &articles {
+				let html = rewrite(&article.html, &entry_href, &resource_href, &mut resources);
+				write!(body, "<idx:entry name=\"default\" scriptable=\"yes\" spell=\"yes\">\n\
+					<idx:orth value=\"{}\"><b>{}</b>",
+					escape_attr(&article.headword), escape_text(&article.headword))?;
+				let inflections: Vec<_> = forms[&key].iter()
+					.filter(|form| **form != article.headword)
+					.collect();
+				if !inflections.is_empty() {
+					body.write_all(b"<idx:infl>")?;
+					for form in inflections {
+						write!(body, "<idx:iform value=\"{}\"/>", escape_attr(form))?;
+					}
+					body.write_all(b"</idx:infl>")?;
+				}
+				writeln!(body, "</idx:orth>\n<div>{}</div>\n</idx:entry>\n<hr/>",
+					to_xhtml(&html))?;
+			}
+			entries += articles.len();
+		}
+		if let Some((_, body)) = current {
+			end_page(body)?;
+		}
+
+		let missing = extract_resources(dict, &resources, &dir.join("res"))?;
+		let mut manifest = String::new();
+		let mut spine = String::new();
+		for (idx, name) in pages.iter().enumerate() {
+			let _ = writeln!(manifest, "<item id=\"p{}\" href=\"{}\" \
+				media-type=\"application/xhtml+xml\"/>", idx, escape_attr(name));
+			let _ = writeln!(spine, "<itemref idref=\"p{}\"/>", idx);
+		}
+		for (idx, path) in resources.iter().filter(|path| !missing.contains(path)).enumerate() {
+			let _ = writeln!(manifest, "<item id=\"r{}\" href=\"res/{}\" media-type=\"{}\"/>",
+				idx, escape_attr(&resource_url(path)), media_type(path));
+		}
+		if css {
+			manifest.push_str("<item id=\"css\" href=\"style.css\" media-type=\"text/css\"/>\n");
+		}
+		let opf = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+			<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"2.0\" \
+			unique-identifier=\"uid\">\n<metadata>\n\
+			<dc-metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+			<dc:Title>{}</dc:Title>\n<dc:Language>{}</dc:Language>\n\
+			<dc:Identifier id=\"uid\">urn:mdict:{}</dc:Identifier>\n</dc-metadata>\n\
+			<x-metadata>\n<DictionaryInLanguage>{}</DictionaryInLanguage>\n\
+			<DictionaryOutLanguage>{}</DictionaryOutLanguage>\n\
+			<DefaultLookupIndex>default</DefaultLookupIndex>\n</x-metadata>\n</metadata>\n\
+			<manifest>\n{}</manifest>\n<spine>\n{}</spine>\n</package>\n",
+			title, escape_text(&self.input_language), escape_text(&slug(dict.title())),
+			escape_text(&self.input_language), escape_text(&self.output_language), manifest, spine);
+		fs::write(dir.join("content.opf"), opf)?;
+
+		Ok(SiteSummary {
+			pages: pages.len(),
+			entries,
+			resources: resources.len() - missing.len(),
+			missing,
+		})
+	}
+}
+
+#[inline]
+fn end_page(mut body: BufWriter<File>) -> Result<()>
+{
+	body.write_all(b"</mbp:frameset>\n</body>\n</html>\n")?;
+	body.flush()?;
+	Ok(())
+}
+
+/// Page holding `key`
+fn page(key: &str) -> String
+{
+	let letter: String = key.chars().next().into_iter().collect();
+	format!("{}.html", slug(&letter))
+}
@@ -0,0 +1,43 @@
+use whatlang::{detect, Lang};
+
+use crate::html::text_content;
+use crate::mdx::{KeyMaker, MDict};
+use crate::parser::lookup_record;
+use crate::Result;
+
+/// Languages of the headwords and of the definitions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Languages {
+	pub source: Option<Lang>,
+	pub target: Option<Lang>,
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Detect the languages from up to `samples` entries spread over the
+	/// dictionary, `None` when no reliable guess is possible
+	pub fn detect_languages(&mut self, samples: usize) -> Result<Languages>
+	{
+		let entries = &self.mdx.key_entries;
+		let samples = samples.min(entries.len());
+		let keys: Vec<String> = (0..samples)
+			.map(|i| entries[i * entries.len() / samples].text.clone())
+			.collect();
+		let mut definitions = String::new();
+		for key in &keys {
+			if let Some(record) = lookup_record(&mut self.mdx, key)? {
+				let definition = record.text()?;
+				if !definition.starts_with("@@@LINK=") {
+					definitions.push_str(&text_content(&definition));
+					definitions.push('\n');
+				}
+			}
+		}
+		let guess = |text: &str| detect(text)
+			.filter(|info| info.is_reliable())
+			.map(|info| info.lang());
+		Ok(Languages {
+			source: guess(&keys.join("\n")),
+			target: guess(&definitions),
+		})
+	}
+}
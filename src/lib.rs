@@ -1,25 +1,227 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
 mod mdx;
 mod error;
+mod format;
+#[cfg(feature = "std")]
 mod parser;
+#[cfg(feature = "std")]
+mod collation;
+#[cfg(feature = "std")]
+mod html;
+#[cfg(feature = "std")]
+pub mod transform;
+#[cfg(feature = "std")]
+mod lint;
+#[cfg(feature = "std")]
+mod graph;
+#[cfg(feature = "std")]
+pub mod record;
+#[cfg(feature = "std")]
+mod stats;
+#[cfg(feature = "std")]
+mod pinyin;
+#[cfg(feature = "std")]
+pub mod normalize;
+#[cfg(feature = "std")]
+mod trigram;
+#[cfg(feature = "std")]
+mod phonetic;
+#[cfg(feature = "std")]
+mod audio;
+#[cfg(feature = "std")]
+mod search;
+#[cfg(feature = "std")]
+mod group;
+#[cfg(feature = "std")]
+mod query;
+#[cfg(feature = "std")]
+mod companion;
+#[cfg(feature = "std")]
+pub mod template;
+#[cfg(feature = "std")]
+pub mod inspect;
+mod warning;
+#[cfg(feature = "std")]
+mod index;
+mod checksum;
+pub mod raw;
+#[cfg(feature = "encryption")]
+mod salsa;
+#[cfg(feature = "zip")]
+mod archive;
+#[cfg(feature = "std")]
+pub mod export;
+#[cfg(feature = "std")]
+pub mod writer;
+#[cfg(feature = "std")]
+mod dedup;
+#[cfg(feature = "std")]
+mod synonym;
+#[cfg(feature = "std")]
+mod block;
+#[cfg(feature = "std")]
+mod identity;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+#[cfg(feature = "std")]
+mod range;
+#[cfg(feature = "std")]
+mod concurrent;
+#[cfg(feature = "std")]
+mod warm;
+#[cfg(feature = "std")]
+pub mod history;
+#[cfg(feature = "std")]
+pub mod site;
+#[cfg(feature = "zip")]
+pub mod epub;
+#[cfg(feature = "std")]
+pub mod kindle;
+#[cfg(feature = "language")]
+mod language;
+#[cfg(feature = "rand")]
+mod random;
+#[cfg(feature = "std")]
+mod untrusted;
+#[cfg(feature = "stemming")]
+pub mod stem;
+#[cfg(feature = "japanese")]
+pub mod deinflect;
 
+#[cfg(feature = "std")]
 pub use crate::mdx::MDict;
+#[cfg(feature = "std")]
 pub use crate::mdx::MDictBuilder;
+#[cfg(feature = "std")]
+pub use crate::mdx::LoadReport;
+#[cfg(feature = "std")]
+pub use crate::mdx::LoadTimings;
+#[cfg(feature = "std")]
+pub use crate::synonym::AliasTable;
+#[cfg(feature = "std")]
+pub use crate::mdx::BufferSizes;
+#[cfg(feature = "std")]
 pub use crate::mdx::KeyMaker;
+#[cfg(feature = "std")]
 pub use crate::mdx::WordDefinition;
+#[cfg(feature = "std")]
+pub use crate::mdx::RecordLimit;
+pub use crate::format::Salsa20Variant;
+pub use crate::warning::Warning;
+#[cfg(feature = "std")]
+pub use crate::export::{Exporter, Sharding};
+#[cfg(feature = "std")]
+pub use crate::writer::MdxWriter;
+#[cfg(feature = "std")]
+pub use crate::dedup::AliasGroup;
+pub use crate::format::Decoding;
+#[cfg(feature = "std")]
+pub use crate::record::{Record, RecordKind, SharedRecord};
+#[cfg(feature = "scraper")]
+pub use crate::record::{Example, Examples, Pronunciations, Transcription};
+#[cfg(feature = "std")]
+pub use crate::companion::{Companion, CompanionKind, Icon};
+#[cfg(feature = "std")]
+pub use crate::stats::{KeyStats, Script};
+#[cfg(feature = "std")]
+pub use crate::pinyin::ReadingTable;
+#[cfg(feature = "std")]
+pub use crate::search::{GrepHit, Headword, Partial, SearchBudget};
+#[cfg(feature = "std")]
+pub use crate::range::KeyRange;
+#[cfg(feature = "std")]
+pub use crate::block::EntryInfo;
+#[cfg(feature = "std")]
+pub use crate::concurrent::ConcurrentMdx;
+#[cfg(feature = "std")]
+pub use crate::group::{Dedup, DictGroup, GroupDefinition, MatchKind, SearchHit, SearchOptions};
+#[cfg(feature = "std")]
+pub use crate::query::Query;
+#[cfg(feature = "std")]
+pub use crate::untrusted::{parse_untrusted, Limits};
+#[cfg(feature = "rand")]
+pub use crate::random::RandomEntry;
+#[cfg(feature = "std")]
+pub use crate::lint::DanglingReference;
+#[cfg(feature = "std")]
+pub use crate::audio::AudioReference;
+#[cfg(feature = "std")]
+pub use crate::graph::LinkGraph;
+#[cfg(feature = "language")]
+pub use crate::language::Languages;
+#[cfg(feature = "language")]
+pub use whatlang::Lang;
 pub use crate::error::Error;
 pub use crate::error::Result;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
 	use std::borrow::Cow;
+	use std::path::{Path, PathBuf};
 	use crate::MDictBuilder;
+	use crate::transform::{EntryLinks, ImageBase, StripScripts, Transformer};
+
+	/// A temp dir removed on drop, also when an assert fails
+	struct TempDir(PathBuf);
+
+	impl TempDir {
+		fn new(name: &str) -> Self
+		{
+			let dir = std::env::temp_dir().join(format!("mdict-{}-{}", name, std::process::id()));
+			std::fs::create_dir_all(&dir).unwrap();
+			TempDir(dir)
+		}
+	}
+
+	impl std::ops::Deref for TempDir {
+		type Target = Path;
+
+		#[inline]
+		fn deref(&self) -> &Path
+		{
+			&self.0
+		}
+	}
+
+	impl Drop for TempDir {
+		fn drop(&mut self)
+		{
+			let _ = std::fs::remove_dir_all(&self.0);
+		}
+	}
 
-	const MDX_V2: &str = "/home/zl/dicts/漢語大字典/漢語大字典 (2010).mdx";
+	/// `<name>.mdx` titled `name` of `entries`, in a new temp dir
+	fn fixture(name: &str, entries: &[(&str, &str)]) -> (TempDir, PathBuf)
+	{
+		let dir = TempDir::new(name);
+		let mdx = dir.join(format!("{}.mdx", name));
+		let mut writer = crate::MdxWriter::new(name);
+		for (key, definition) in entries {
+			writer.add(*key, definition);
+		}
+		writer.write_file(&mdx).unwrap();
+		(dir, mdx)
+	}
+
+	/// A Chinese dictionary with a font in its mdd
+	fn han_fixture(name: &str) -> (TempDir, PathBuf)
+	{
+		let (dir, mdx) = fixture(name, &[("无", "<b>无</b> 同「無」"), ("無", "<b>無</b>"), ("Wú", "<i>wú</i>")]);
+		let mut writer = crate::MdxWriter::mdd(name);
+		writer.add_record("\\ZhongHuaSongPlane02b-HZ.woff", b"wOFF".to_vec());
+		writer.write_file(mdx.with_extension("mdd")).unwrap();
+		(dir, mdx)
+	}
 
 	#[test]
 	fn lookup()
 	{
-		let mut mdx = MDictBuilder::new(MDX_V2).build().unwrap();
+		let (_dir, path) = han_fixture("lookup");
+		let mut mdx = MDictBuilder::new(&path).build().unwrap();
 		let definition = mdx.lookup("將進酒").unwrap();
 		assert!(definition.is_none());
 		let definition = mdx.lookup("无").unwrap();
@@ -33,7 +235,8 @@ mod tests {
 	#[test]
 	fn cache_lookup()
 	{
-		let mut mdx = MDictBuilder::new(MDX_V2)
+		let (_dir, path) = han_fixture("cache-lookup");
+		let mut mdx = MDictBuilder::new(&path)
 			.cache_definition(true)
 			.cache_resource(true)
 			.build_with_key_maker(|key: &Cow<str>, _| key.to_ascii_lowercase())
@@ -44,7 +247,1600 @@ mod tests {
 		assert!(definition.is_some());
 		let definition = mdx.lookup("無").unwrap();
 		assert!(definition.is_some());
+		// the key maker folds ascii letters only
+		assert!(mdx.lookup("WÚ").unwrap().is_none());
+		assert_eq!(mdx.lookup("Wú").unwrap().unwrap().definition, "<i>wú</i>");
 		let definition = mdx.get_resource("\\ZhongHuaSongPlane02b-HZ.woff").unwrap();
 		assert!(definition.is_some());
 	}
+
+	#[test]
+	fn lookup_record()
+	{
+		let (_dir, path) = han_fixture("lookup-record");
+		let mut mdx = MDictBuilder::new(&path)
+			.cache_definition(true)
+			.build()
+			.unwrap();
+		let record = mdx.lookup_record("無").unwrap().unwrap();
+		assert_eq!(record.key(), "無");
+		assert_eq!(record.raw_key(), "無");
+		assert!(!record.is_cached());
+		assert_eq!(record.size(), record.data().len());
+		assert!(!record.text().unwrap().is_empty());
+		let record = mdx.lookup_record("無").unwrap().unwrap();
+		assert!(record.is_cached());
+		let data = record.data().to_vec();
+		let shared = mdx.lookup_shared("無").unwrap().unwrap();
+		assert!(shared.is_cached());
+		assert_eq!(shared.data(), data);
+		let record = mdx.lookup_record("WÚ").unwrap().unwrap();
+		assert_eq!((record.key(), record.raw_key()), ("wú", "Wú"));
+		let shared = mdx.lookup_shared("wú").unwrap().unwrap();
+		assert_eq!((shared.key(), shared.raw_key()), ("wú", "Wú"));
+	}
+
+	#[test]
+	fn record_limit()
+	{
+		use crate::RecordLimit;
+		let (_dir, path) = fixture("record-limit", &[("long", "0123456789"), ("short", "01")]);
+		// records are stored with a trailing NUL
+		let mut dict = MDictBuilder::new(&path).max_record_size(RecordLimit::Error(8)).build().unwrap();
+		assert!(matches!(dict.lookup("long"), Err(crate::Error::RecordTooLarge(11))));
+		assert_eq!(dict.lookup("short").unwrap().unwrap().definition, "01");
+		let mut dict = MDictBuilder::new(&path).max_record_size(RecordLimit::Truncate(4)).build().unwrap();
+		let record = dict.lookup_record("long").unwrap().unwrap();
+		assert_eq!(record.data(), b"0123\0\0");
+		assert_eq!(record.text().unwrap(), "0123");
+		assert_eq!(dict.lookup("long").unwrap().unwrap().definition, "0123");
+		assert_eq!(dict.lookup("short").unwrap().unwrap().definition, "01");
+	}
+
+	#[test]
+	fn key_stats()
+	{
+		use crate::Script;
+		let (_dir, mdx) = fixture("stats", &[("Apple", "1"), ("apple", "2"), ("дом", "3"), ("中文", "4"),
+			("42", "5"), ("-x", "6")]);
+		let stats = MDictBuilder::new(&mdx).build().unwrap().key_stats();
+		assert_eq!((stats.total, stats.distinct, stats.duplicated), (6, 5, 1));
+		assert_eq!(stats.lengths.into_iter().collect::<Vec<_>>(), [(2, 3), (3, 1), (5, 1)]);
+		assert_eq!(stats.scripts.into_iter().collect::<Vec<_>>(),
+			[(Script::Latin, 2), (Script::Cyrillic, 1), (Script::Han, 1), (Script::Digit, 1)]);
+	}
+
+	#[cfg(feature = "stemming")]
+	#[test]
+	fn stemmed_lookup()
+	{
+		use crate::stem::{Algorithm, Transformation};
+		let (_dir, mdx) = fixture("stemmed", &[("stop", "halt"), ("city", "town"), ("connect", "join")]);
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let mut lookup = |word| dict.lookup_stemmed(word, Algorithm::English).unwrap()
+			.map(|(found, how)| (found.definition, how));
+		assert_eq!(lookup("Stop"), Some(("halt".to_owned(), Transformation::Exact)));
+		assert_eq!(lookup("stopped"),
+			Some(("halt".to_owned(), Transformation::Inflection("past tense", "stop".to_owned()))));
+		assert_eq!(lookup("Cities"),
+			Some(("town".to_owned(), Transformation::Inflection("plural", "city".to_owned()))));
+		assert_eq!(lookup("connections"), Some(("join".to_owned(), Transformation::Stem("connect".to_owned()))));
+		assert_eq!(lookup("xyz"), None);
+	}
+
+	#[test]
+	fn pinyin_search()
+	{
+		let (_dir, mdx) = fixture("pinyin", &[("中文", "Chinese"), ("中", "middle"), ("行", "go"), ("好", "good")]);
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		assert_eq!(dict.search_pinyin("zhong1").count(), 0);
+		let table = crate::ReadingTable::parse("# readings\n中 zhong1\n文 wen2\nU+884C\tkMandarin\txíng háng\n");
+		dict.build_pinyin_index(&table);
+		let search = |query| dict.search_pinyin(query).collect::<Vec<_>>();
+		assert_eq!(search("zhong1wen2"), ["中文"]);
+		assert_eq!(search("Zhōng wén"), ["中文"]);
+		assert_eq!(search("zhongwen"), ["中文"]);
+		assert!(search("zhong2wen2").is_empty());
+		assert_eq!(search("hang"), ["行"]);
+		assert_eq!(search("xing2"), ["行"]);
+		assert!(search("hao3").is_empty());
+	}
+
+	#[cfg(feature = "japanese")]
+	#[test]
+	fn deinflect()
+	{
+		let forms = |word| crate::deinflect::deinflect(word)
+			.into_iter()
+			.map(|d| d.form)
+			.collect::<Vec<_>>();
+		assert!(forms("食べました").contains(&"食べる".to_owned()));
+		assert!(forms("書いていた").contains(&"書く".to_owned()));
+		assert!(forms("高くなかった").contains(&"高い".to_owned()));
+		assert!(forms("勉強しない").contains(&"勉強する".to_owned()));
+	}
+
+	#[test]
+	fn case_fold()
+	{
+		use crate::normalize::{CaseFold, Pipeline};
+		let pipeline = Pipeline::new().then(CaseFold::new());
+		assert_eq!(pipeline.normalize("Straße"), "strasse");
+		assert_eq!(pipeline.normalize("ΟΔΟΣ"), pipeline.normalize("οδος"));
+		assert_eq!(pipeline.normalize("ПРИВЕТ"), "привет");
+		let turkic = Pipeline::new().then(CaseFold::turkic());
+		assert_eq!(turkic.normalize("DİYARBAKIR"), "diyarbakır");
+		let articles = Pipeline::new()
+			.then(crate::normalize::Articles::language("fr").unwrap())
+			.then(CaseFold::new());
+		assert_eq!(articles.normalize("La Défense"), "défense");
+		assert_eq!(articles.normalize("l'Élysée"), articles.normalize("Élysée"));
+		assert_eq!(articles.normalize("Les"), "les");
+		assert_eq!(articles.normalize("Lesotho"), "lesotho");
+		let entities = Pipeline::new().then(crate::normalize::Entities).then(CaseFold::new());
+		assert_eq!(entities.normalize("AT&amp;T"), entities.normalize("at&t"));
+		assert_eq!(entities.normalize("caf&#233; &#x26; &nope;"), "café & &nope;");
+	}
+
+	#[test]
+	fn transform()
+	{
+		let transformer = Transformer::new()
+			.rule(EntryLinks::new("/lookup/"))
+			.rule(StripScripts)
+			.rule(ImageBase::new("/res/"));
+		let html = transformer.transform(
+			"<a href=\"entry://無\">無</a><script>if (a<b) {}</script>\
+			<img src=\"\\img\\a.png\" onclick='x()'><!-- <b> -->");
+		assert_eq!(html, "<a href=\"/lookup/無\">無</a><img src=\"/res/img/a.png\"><!-- <b> -->");
+		let html = crate::transform::highlight(
+			"<a title=\"run\">Run</a> running<script>run()</script>", &["run", "running"]);
+		assert_eq!(html, "<a title=\"run\"><mark>Run</mark></a> <mark>running</mark><script>run()</script>");
+		let html = crate::transform::highlight("a &amp; b&#38;c <i title=\"amp\">Amp</i>", &["amp", "38"]);
+		assert_eq!(html, "a &amp; b&#38;c <i title=\"amp\"><mark>Amp</mark></i>");
+	}
+
+	#[test]
+	fn template()
+	{
+		use crate::template::{Template, Theme};
+		let html = Template::new()
+			.base("https://dict.local/")
+			.theme(Theme::Dark)
+			.js("go()")
+			.render("<b>無</b>", Some("b { color: red }"), None);
+		assert!(html.starts_with("<!DOCTYPE html>\n<html class=\"dark\">"));
+		assert!(html.contains("<base href=\"https://dict.local/\">"));
+		assert!(html.contains("<style>\nb { color: red }\n</style>"));
+		assert!(html.contains("<body>\n<b>無</b>\n<script>\ngo()\n</script>"));
+	}
+
+	#[test]
+	fn markdown()
+	{
+		let markdown = crate::record::to_markdown(
+			"<b>run</b> <i>v.</i><ol><li>move &amp; go</li><li>see <a href=\"entry://ran\">ran</a></li></ol>\
+			<style>b {}</style><p>2*3</p>");
+		assert_eq!(markdown, "**run** *v.*\n\n1. move & go\n2. see [ran](entry://ran)\n\n2\\*3");
+	}
+
+	#[cfg(feature = "scraper")]
+	#[test]
+	fn select()
+	{
+		let html = "<b>run</b> <span class=\"pron\">/rʌn/</span><ol><li>move <span class=\"ex\">run fast</span></li>\
+			<li><span class=\"ex\">run a shop</span></li></ol>";
+		assert_eq!(crate::record::select(html, "span.pron").unwrap(), ["<span class=\"pron\">/rʌn/</span>"]);
+		assert_eq!(crate::record::select(html, "li > .ex").unwrap().len(), 2);
+		assert!(crate::record::select(html, "i").unwrap().is_empty());
+		assert!(matches!(crate::record::select(html, "li >"), Err(crate::Error::InvalidSelector(_))));
+
+		use crate::{Pronunciations, Transcription};
+		let html = "<b>行</b> <span class=\"py\">xíng</span> <span class=\"py\">háng</span> \
+			<span class=\"phon\">/ɕiŋ/</span><span class=\"yue\">hang4</span><span class=\"py\">xíng</span>";
+		assert_eq!(Pronunciations::default().extract(html), [
+			(Transcription::Ipa, "ɕiŋ".to_owned()),
+			(Transcription::Pinyin, "xíng".to_owned()),
+			(Transcription::Pinyin, "háng".to_owned()),
+		]);
+		let cantonese = Pronunciations::empty().selector(Transcription::Jyutping, ".yue").unwrap();
+		assert_eq!(cantonese.extract(html), [(Transcription::Jyutping, "hang4".to_owned())]);
+
+		use crate::{Example, Examples};
+		let html = "<ol><li>to move <span class=\"ex\">She <b>runs</b> fast. <span class=\"tr\">Sie läuft schnell.</span></span></li>\
+			<li><div class=\"example\">Run a shop.<p class=\"eg\">nested</p></div></li></ol>";
+		assert_eq!(crate::record::examples(html), [
+			Example { sentence: "She runs fast.".to_owned(), translation: Some("Sie läuft schnell.".to_owned()) },
+			Example { sentence: "Run a shop. nested".to_owned(), translation: None },
+		]);
+		let examples = Examples::new("li", "b").unwrap();
+		assert_eq!(examples.extract(html)[0].sentence, "to move She fast. Sie läuft schnell.");
+		let html = "<div class=\"ex\">She <b>run</b>s.<br>Er <i>läuft</i>.<div>Next<span class=\"tr\">Sie <b>lauf</b>en.</span></div></div>";
+		assert_eq!(crate::record::examples(html), [
+			Example { sentence: "She runs. Er läuft. Next".to_owned(), translation: Some("Sie laufen.".to_owned()) },
+		]);
+	}
+
+	#[test]
+	fn hexdump()
+	{
+		let dump = crate::inspect::hexdump(b"<b>word</b>\x00\x00 and more");
+		assert_eq!(dump, "00000000: 3c62 3e77 6f72 643c 2f62 3e00 0020 616e  <b>word</b>.. an\n\
+			00000010: 6420 6d6f 7265                           d more\n");
+	}
+
+	#[test]
+	fn error_source()
+	{
+		use std::error::Error as _;
+		let err = crate::Error::from(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "early eof"));
+		assert_eq!(err.to_string(), "Failed to read the dictionary");
+		assert_eq!(err.source().map(|err| err.to_string()).as_deref(), Some("early eof"));
+		assert!(crate::Error::InvalidData.source().is_none());
+	}
+
+	#[test]
+	#[cfg(feature = "encryption")]
+	fn block_key()
+	{
+		use ripemd::{Digest, Ripemd128};
+		let plain = b"block key";
+		let checksum = adler32::RollingAdler32::from_buffer(plain).hash().to_be_bytes();
+		let key = Ripemd128::digest(checksum);
+		let mut block = vec![0x10, 0, 0, 0];
+		block.extend_from_slice(&checksum);
+		let mut prev = 0x36;
+		for (i, &byte) in plain.iter().enumerate() {
+			let t = byte ^ prev ^ (i as u8) ^ key[i % key.len()];
+			prev = t >> 4 | t << 4;
+			block.push(prev);
+		}
+		assert_eq!(crate::raw::decode_block(&block, 0, plain.len()).unwrap(), plain);
+	}
+
+	#[test]
+	fn raw_blocks()
+	{
+		use crate::raw;
+		let (_dir, path) = fixture("raw", &[("one", "1"), ("two", "2")]);
+		let data = std::fs::read(&path).unwrap();
+
+		let len = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+		let checksum = u32::from_le_bytes(data[4 + len..8 + len].try_into().unwrap());
+		let header = raw::Header::parse(&data[4..4 + len], checksum).unwrap();
+		assert_eq!((header.version(), header.title()), (2, "raw"));
+		let mut rest = &data[8 + len..];
+		let section = raw::parse_key_section(rest, &header).unwrap();
+		assert_eq!(section.key_count, 2);
+		rest = &rest[header.key_section_size()..];
+		let info_size = section.info_size as usize;
+		let blocks = raw::parse_key_info(&rest[..info_size], &header).unwrap();
+		let block = &rest[info_size..info_size + blocks[0].compressed_size];
+		let block = raw::decode_block(block, 0, blocks[0].decompressed_size).unwrap();
+		let keys = raw::parse_keys(&block, &header).unwrap();
+		assert_eq!(keys, [(0, "one".to_owned()), (2, "two".to_owned())]);
+	}
+
+	#[test]
+	fn legacy_encodings()
+	{
+		let dir = TempDir::new("encodings");
+		let cases = [
+			(encoding_rs::WINDOWS_1252, "café", "<i>crème</i>"),
+			(encoding_rs::KOI8_R, "дом", "<i>жилище</i>"),
+			(encoding_rs::WINDOWS_1251, "мир", "<i>свет</i>"),
+		];
+		for (encoding, key, definition) in cases {
+			let path = dir.join(format!("{}.mdx", encoding.name()));
+			let mut writer = crate::MdxWriter::new("Words").encoding(encoding);
+			writer.add(key, definition);
+			writer.add("zzz", "last");
+			writer.write_file(&path).unwrap();
+			let mut dict = MDictBuilder::new(&path).build().unwrap();
+			assert!(dict.keys().any(|stored| stored == key));
+			assert_eq!(dict.lookup(key).unwrap().unwrap().definition, definition);
+		}
+
+		let path = dir.join("gbk.mdx");
+		let mut writer = crate::MdxWriter::new("Words").encoding(encoding_rs::GBK);
+		writer.add("中", "中文");
+		let mut record = encoding_rs::GBK.encode("坏").0.into_owned();
+		record.extend_from_slice(&[0x81, b' ', 0]);
+		writer.add_record("坏", record);
+		writer.write_file(&path).unwrap();
+		let mut dict = MDictBuilder::new(&path).build().unwrap();
+		assert_eq!(dict.lookup_text("中", crate::Decoding::Strict).unwrap().unwrap(), "中文");
+		assert_eq!(dict.lookup_text("坏", crate::Decoding::Lossy).unwrap().unwrap(), "坏\u{fffd} ");
+		assert!(matches!(dict.lookup_text("坏", crate::Decoding::Strict),
+			Err(crate::Error::InvalidText("GBK"))));
+
+		// UTF-8 keys, GBK records
+		let mut writer = crate::MdxWriter::new("Words");
+		let mut record = encoding_rs::GBK.encode("中文").0.into_owned();
+		record.push(0);
+		writer.add_record("中", record);
+		writer.write_file(&path).unwrap();
+		let mut dict = MDictBuilder::new(&path).build().unwrap();
+		let definition = dict.lookup_as("中", encoding_rs::GBK).unwrap().unwrap().definition;
+		assert_eq!(definition, "中文");
+		let mut dict = MDictBuilder::new(&path).record_encoding(encoding_rs::GBK).build().unwrap();
+		assert_eq!(dict.lookup("中").unwrap().unwrap().definition, "中文");
+	}
+
+	#[test]
+	fn untrusted_input()
+	{
+		use crate::{parse_untrusted, Limits};
+		let (_dir, path) = fixture("untrusted", &[("apple", "appleappleapple"),
+			("banana", "bananabananabanana"), ("cherry", "cherrycherrycherry")]);
+		let bytes = std::fs::read(&path).unwrap();
+		let mut dict = parse_untrusted(&bytes, &Limits::default()).unwrap();
+		assert_eq!(dict.lookup("Banana").unwrap().unwrap().definition, "bananabananabanana");
+		let limits = Limits { entries: 2, ..Limits::default() };
+		assert!(matches!(parse_untrusted(&bytes, &limits).err(),
+			Some(crate::Error::LimitExceeded("entries"))));
+		let limits = Limits { block_size: 8, ..Limits::default() };
+		assert!(parse_untrusted(&bytes, &limits).is_err());
+		let limits = Limits { total_decoded: 64, ..Limits::default() };
+		assert!(matches!(parse_untrusted(&bytes, &limits).err(),
+			Some(crate::Error::LimitExceeded("total decoded"))));
+		for len in (0..bytes.len()).step_by(3) {
+			assert!(parse_untrusted(&bytes[..len], &Limits::default()).is_err());
+		}
+		for at in (0..bytes.len()).step_by(3) {
+			let mut corrupt = bytes.clone();
+			corrupt[at] ^= 0x5a;
+			if let Ok(mut dict) = parse_untrusted(&corrupt, &Limits::default()) {
+				let _ = dict.lookup("cherry");
+			}
+		}
+	}
+
+	#[test]
+	fn fixture_formats()
+	{
+		use crate::writer::{Compression, Encryption, FormatVersion};
+		let dir = TempDir::new("fixtures");
+		let mut encryptions = vec![Encryption::None];
+		if cfg!(feature = "encryption") {
+			encryptions.extend([Encryption::KeyIndex, Encryption::Blocks]);
+		}
+		let mut compressions = vec![Compression::Stored, Compression::Zlib];
+		if cfg!(feature = "lzo") {
+			compressions.push(Compression::Lzo);
+		}
+		let mut fixture = 0;
+		for version in [FormatVersion::V1, FormatVersion::V2] {
+			for encoding in [encoding_rs::UTF_8, encoding_rs::UTF_16LE, encoding_rs::GBK] {
+				for &compression in &compressions {
+					for encryption in &encryptions {
+						if version == FormatVersion::V1 && *encryption == Encryption::KeyIndex {
+							continue;
+						}
+						fixture += 1;
+						let stem = dir.join(fixture.to_string());
+						let mut writer = crate::MdxWriter::new("Words").encoding(encoding)
+							.version(version).compression(compression).encryption(*encryption);
+						writer.add("中", "<img src=\"a.png\">中文");
+						writer.add("word", &"long ".repeat(20_000));
+						writer.write_file(stem.with_extension("mdx")).unwrap();
+						let mut writer = crate::MdxWriter::mdd("Words")
+							.version(version).compression(compression).encryption(*encryption);
+						writer.add_record("\\a.png", b"PNG".to_vec());
+						writer.write_file(stem.with_extension("mdd")).unwrap();
+
+						let mut dict = MDictBuilder::new(stem.with_extension("mdx")).build().unwrap();
+						assert_eq!(dict.record_block_count(), 2);
+						assert_eq!(dict.lookup("中").unwrap().unwrap().definition,
+							"<img src=\"a.png\">中文");
+						assert_eq!(dict.lookup("word").unwrap().unwrap().definition.len(), 100_000);
+						assert_eq!(dict.get_resource("\\a.png").unwrap().unwrap().as_ref(), b"PNG");
+					}
+				}
+			}
+		}
+		let mut writer = crate::MdxWriter::new("Words")
+			.version(FormatVersion::V1)
+			.encryption(Encryption::KeyIndex);
+		writer.add("word", "text");
+		assert!(matches!(writer.write_file(dir.join("v1.mdx")),
+			Err(crate::Error::UnsupportedByVersion(_))));
+		if !cfg!(feature = "encryption") {
+			let mut writer = crate::MdxWriter::new("Words").encryption(Encryption::Blocks);
+			writer.add("word", "text");
+			assert!(matches!(writer.write_file(dir.join("encrypted.mdx")),
+				Err(crate::Error::DisabledCodec("encryption"))));
+		}
+	}
+
+	#[test]
+	fn static_site()
+	{
+		use crate::site::{Pages, SiteExporter};
+		let (dir, mdx) = fixture("site", &[
+			("apple", "<img src=\"img/a.png\"><a href=\"entry://Banana\">see</a>"),
+			("banana", "@@@LINK=apple"),
+		]);
+		let mut writer = crate::MdxWriter::new("Fruit").encoding(encoding_rs::UTF_16LE);
+		writer.add_record("\\img\\a.png", b"PNG".to_vec());
+		writer.write_file(mdx.with_extension("mdd")).unwrap();
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+
+		let site = dir.join("site");
+		let summary = SiteExporter::new().write(&mut dict, &site).unwrap();
+		assert_eq!((summary.pages, summary.entries, summary.resources), (2, 2, 1));
+		let apple = std::fs::read_to_string(site.join("entries/apple.html")).unwrap();
+		assert!(apple.contains("<img src=\"../res/img/a.png\">"));
+		assert!(apple.contains("<a href=\"banana.html#banana\">see</a>"));
+		assert_eq!(std::fs::read(site.join("res/img/a.png")).unwrap(), b"PNG");
+		assert!(std::fs::read_to_string(site.join("index.html")).unwrap()
+			.contains("<a href=\"entries/banana.html\">banana</a>"));
+
+		let site = dir.join("letters");
+		let summary = SiteExporter::new().pages(Pages::PerLetter).write(&mut dict, &site).unwrap();
+		assert_eq!(summary.pages, 2);
+		let banana = std::fs::read_to_string(site.join("entries/b.html")).unwrap();
+		assert!(banana.contains("<a href=\"a.html#apple\">apple</a>"));
+	}
+
+	#[test]
+	#[cfg(feature = "zip")]
+	fn epub()
+	{
+		use std::io::Read;
+		use crate::epub::EpubExporter;
+		let (dir, mdx) = fixture("epub", &[
+			("Apple", "<p>red<br><img src=\"img/a.png\">&nbsp;<a href=\"entry://banana\">see</a>"),
+			("banana", "yellow</span>"),
+		]);
+		let mut writer = crate::MdxWriter::new("Fruit").encoding(encoding_rs::UTF_16LE);
+		writer.add_record("\\img\\a.png", b"PNG".to_vec());
+		writer.write_file(mdx.with_extension("mdd")).unwrap();
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let mut aliases = crate::AliasTable::new();
+		aliases.insert("apples", "apple");
+		dict.set_aliases(aliases);
+
+		let book = dir.join("fruit.epub");
+		let summary = EpubExporter::new().language("en").dictionary(true)
+			.write(&mut dict, &book).unwrap();
+		assert_eq!((summary.pages, summary.entries, summary.resources), (2, 2, 1));
+		let mut zip = zip::ZipArchive::new(std::fs::File::open(&book).unwrap()).unwrap();
+		let read = |zip: &mut zip::ZipArchive<_>, name: &str| {
+			let mut text = String::new();
+			zip.by_name(name).unwrap().read_to_string(&mut text).unwrap();
+			text
+		};
+		assert_eq!(zip.by_index(0).unwrap().name(), "mimetype");
+		assert_eq!(read(&mut zip, "mimetype"), "application/epub+zip");
+		let apple = read(&mut zip, "OEBPS/entries/a.xhtml");
+		assert!(apple.contains("<h2><dfn>Apple</dfn></h2>\n<div><p>red<br/><img src=\"../res/img/a.png\"/>\u{a0}\
+			<a href=\"b.xhtml#banana\">see</a></p></div>"));
+		assert!(read(&mut zip, "OEBPS/entries/b.xhtml").contains("<div>yellow</div>"));
+		assert_eq!(read(&mut zip, "OEBPS/res/img/a.png"), "PNG");
+		let search = read(&mut zip, "OEBPS/search.xml");
+		assert!(search.contains("<match value=\"apple\">\n<value value=\"Apple\"/>\n<value value=\"apples\"/>"));
+		assert!(read(&mut zip, "OEBPS/content.opf").contains("<dc:type>dictionary</dc:type>"));
+	}
+
+	#[test]
+	fn lint_resources()
+	{
+		let (_dir, mdx) = fixture("lint-resources", &[
+			("apple", "<img src=\"img/apple.png\"><a href=\"sound://uk/apple.mp3\">uk</a>\
+				<a href=\"entry://pear\">pear</a><a href=\"https://example.com\">web</a>"),
+			("pear", "<img src=\"/img/apple.png\">"),
+		]);
+		let mut writer = crate::MdxWriter::mdd("Fruit");
+		writer.add_record("\\img\\apple.png", b"PNG".to_vec());
+		writer.write_file(mdx.with_extension("mdd")).unwrap();
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let dangling = dict.lint_resources().unwrap();
+		assert_eq!(dangling.len(), 1);
+		assert_eq!((&*dangling[0].key, &*dangling[0].reference), ("apple", "sound://uk/apple.mp3"));
+	}
+
+	#[test]
+	fn lint_links()
+	{
+		let (_dir, mdx) = fixture("lint-links", &[
+			("apple", "<a href=\"entry://pear#taste\">pear</a><a href=\"entry://cherry\">cherry</a>"),
+			("pear", "<a href=\"entry://Apple\">apple</a>"),
+			("pears", "@@@LINK=pear"),
+			("plum", "@@@LINK=grape"),
+		]);
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let mut dangling = dict.lint_links().unwrap().into_iter()
+			.map(|dangling| (dangling.key, dangling.reference))
+			.collect::<Vec<_>>();
+		dangling.sort();
+		assert_eq!(dangling, [
+			("apple".to_owned(), "cherry".to_owned()),
+			("plum".to_owned(), "grape".to_owned()),
+		]);
+	}
+
+	#[test]
+	fn link_graph()
+	{
+		let (_dir, mdx) = fixture("link-graph", &[
+			("say \"hi\"", "<a href=\"entry://Salt & pepper#use\">salt</a>"),
+			("salt & pepper", "<a href='entry://say \"hi\"'>hi</a>"),
+			("zest", "@@@LINK=salt & pepper"),
+		]);
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let graph = dict.link_graph().unwrap();
+		let edges = graph.adjacency()
+			.map(|(key, targets)| (key, targets.collect::<Vec<_>>()))
+			.collect::<Vec<_>>();
+		assert_eq!(edges, [
+			("salt & pepper", vec!["say \"hi\""]),
+			("say \"hi\"", vec!["salt & pepper"]),
+			("zest", vec!["salt & pepper"]),
+		]);
+		assert_eq!(graph.targets("zest").collect::<Vec<_>>(), ["salt & pepper"]);
+		assert_eq!(graph.orphans(), ["zest"]);
+		let dot = graph.to_dot();
+		assert!(dot.contains("\t\"say \\\"hi\\\"\" -> \"salt & pepper\";\n"));
+		let graphml = graph.to_graphml();
+		assert!(graphml.contains("<node id=\"say &quot;hi&quot;\"/>"));
+		assert!(graphml.contains("<edge source=\"zest\" target=\"salt &amp; pepper\"/>"));
+		assert!(!graphml.contains("salt & pepper"));
+	}
+
+	#[test]
+	#[cfg(feature = "zip")]
+	fn zip_archive()
+	{
+		use std::io::Write;
+		use zip::write::SimpleFileOptions;
+		use zip::CompressionMethod;
+		let (dir, mdx) = fixture("zip-archive", &[
+			("apple", "<link rel=\"stylesheet\" href=\"zip-archive.css\"><img src=\"apple.png\">red"),
+			("banana", "yellow"),
+		]);
+		let mut writer = crate::MdxWriter::mdd("Fruit");
+		writer.add_record("\\apple.png", b"PNG".to_vec());
+		writer.write_file(mdx.with_extension("mdd")).unwrap();
+		let path = dir.join("fruit.zip");
+		let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+		let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+		let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+		zip.start_file("README.txt", deflated).unwrap();
+		zip.write_all(b"fruit").unwrap();
+		// stored members are read in place, the others inflated
+		zip.start_file("Fruit/zip-archive.mdx", stored).unwrap();
+		zip.write_all(&std::fs::read(&mdx).unwrap()).unwrap();
+		zip.start_file("Fruit/zip-archive.mdd", deflated).unwrap();
+		zip.write_all(&std::fs::read(mdx.with_extension("mdd")).unwrap()).unwrap();
+		zip.start_file("Fruit/zip-archive.css", stored).unwrap();
+		zip.write_all(b"b { color: red }").unwrap();
+		zip.finish().unwrap();
+		std::fs::remove_file(&mdx).unwrap();
+		std::fs::remove_file(mdx.with_extension("mdd")).unwrap();
+
+		let mut dict = MDictBuilder::archive(&path).build().unwrap();
+		assert_eq!(dict.lookup("banana").unwrap().unwrap().definition, "yellow");
+		assert!(dict.lookup("apple").unwrap().unwrap().definition.ends_with("red"));
+		assert!(dict.lookup("cherry").unwrap().is_none());
+		assert_eq!(dict.get_resource("\\apple.png").unwrap().unwrap().as_ref(), b"PNG");
+		assert!(dict.lint_resources().unwrap().is_empty());
+	}
+
+	#[test]
+	fn audio_references()
+	{
+		use crate::AudioReference;
+		let (_dir, mdx) = fixture("audio", &[
+			("apple", "<a href=\"sound://uk/apple.mp3\">uk</a> \
+				<a href=\"sound://us/apple.mp3\">us</a><img src=\"apple.png\"><a href=\"sound://uk/apple.mp3\">again</a>"),
+			("apples", "@@@LINK=apple"),
+			("loop", "@@@LINK=loop"),
+		]);
+		let mut writer = crate::MdxWriter::mdd("Fruit");
+		writer.add_record("\\uk\\apple.mp3", b"ID3".to_vec());
+		writer.write_file(mdx.with_extension("mdd")).unwrap();
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let audio = dict.audio_references("Apple").unwrap();
+		assert_eq!(audio, [
+			AudioReference { reference: "sound://uk/apple.mp3".to_owned(), path: "\\uk\\apple.mp3".to_owned(), found: true },
+			AudioReference { reference: "sound://us/apple.mp3".to_owned(), path: "\\us\\apple.mp3".to_owned(), found: false },
+		]);
+		assert!(dict.resource_shared(&audio[0].path).unwrap().is_some());
+		assert_eq!(dict.audio_references("apples").unwrap(), audio);
+		assert!(dict.audio_references("loop").unwrap().is_empty());
+		assert!(dict.audio_references("pear").unwrap().is_empty());
+	}
+
+	#[test]
+	fn kindle()
+	{
+		use crate::kindle::KindleExporter;
+		let (dir, mdx) = fixture("kindle", &[
+			("Apple", "<b>red</i>"),
+			("apple", "<a href=\"entry://berry\">berry</a>"),
+			("berry", "small"),
+		]);
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let mut aliases = crate::AliasTable::new();
+		aliases.insert("apples", "apple");
+		dict.set_aliases(aliases);
+
+		let out = dir.join("kindle");
+		let summary = KindleExporter::new().output_language("de").write(&mut dict, &out).unwrap();
+		assert_eq!((summary.pages, summary.entries), (2, 3));
+		let page = std::fs::read_to_string(out.join("a.html")).unwrap();
+		assert!(page.contains("<idx:orth value=\"Apple\"><b>Apple</b><idx:infl>\
+			<idx:iform value=\"apple\"/><idx:iform value=\"apples\"/></idx:infl></idx:orth>\n\
+			<div><b>red</b></div>"));
+		assert!(page.contains("<a href=\"b.html#berry\">berry</a>"));
+		let opf = std::fs::read_to_string(out.join("content.opf")).unwrap();
+		assert!(opf.contains("<DictionaryOutLanguage>de</DictionaryOutLanguage>"));
+		assert!(opf.contains("<item id=\"p1\" href=\"b.html\""));
+	}
+
+	#[test]
+	#[cfg(feature = "encryption")]
+	fn salsa20_detection()
+	{
+		use ripemd::{Digest, Ripemd128};
+		let one = "one ".repeat(10_000);
+		let two = "two ".repeat(10_000);
+		let (_dir, mdx) = fixture("salsa", &[("one", &one), ("two", &two)]);
+		let blocks = crate::inspect::read_structure(&mdx).unwrap().record_blocks;
+		assert_eq!(blocks.len(), 2);
+		// Salsa20/8 keyed by block index, which the header doesn't tell
+		let mut data = std::fs::read(&mdx).unwrap();
+		for (index, block) in blocks.iter().enumerate() {
+			let block = &mut data[block.offset as usize..][..block.compressed_size];
+			assert_eq!(block[0] & 0xf0, 0);
+			block[0] |= 0x20;
+			let key: [u8; 16] = Ripemd128::digest(&block[4..8]).into();
+			crate::salsa::apply_keystream(&key, index as u64, 8, &mut block[8..]);
+		}
+		let second = &data[blocks[1].offset as usize..][..blocks[1].compressed_size];
+		let decoded = crate::raw::decode_block(second, 1, blocks[1].decompressed_size).unwrap();
+		assert!(decoded.starts_with(two.as_bytes()));
+		assert!(crate::raw::decode_block(second, 0, blocks[1].decompressed_size).is_err());
+		std::fs::write(&mdx, data).unwrap();
+
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		assert_eq!(dict.lookup("two").unwrap().unwrap().definition, two);
+		assert_eq!(dict.lookup("one").unwrap().unwrap().definition, one);
+	}
+
+	#[test]
+	fn padding_blocks()
+	{
+		use crate::writer::{Compression, FormatVersion};
+		use crate::checksum::adler32;
+		use crate::Warning;
+		let dir = TempDir::new("padding");
+		let mdx = dir.join("a.mdx");
+		let mut writer = crate::MdxWriter::new("A").version(FormatVersion::V1)
+			.compression(Compression::Stored);
+		writer.add("apple", "red");
+		writer.add("pear", "green");
+		writer.write_file(&mdx).unwrap();
+
+		let data = std::fs::read(&mdx).unwrap();
+		let be = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap()) as usize;
+		let keys = 4 + be(0) + 4;
+		assert_eq!(be(keys), 1);
+		let (info_size, blocks_size) = (be(keys + 8), be(keys + 12));
+		let mut info = data[keys + 16..][..info_size].to_vec();
+		let records = &data[keys + 16 + info_size + blocks_size..];
+		// the key block followed by zeros, then an empty key block
+		let mut keys_block = data[keys + 16 + info_size + 8..][..blocks_size - 8].to_vec();
+		keys_block.extend([0; 16]);
+		let mut blocks = vec![0; 4];
+		blocks.extend(adler32(&keys_block).to_be_bytes());
+		blocks.extend(&keys_block);
+		let end = info.len();
+		info[end - 8..end - 4].copy_from_slice(&(blocks.len() as u32).to_be_bytes());
+		info[end - 4..].copy_from_slice(&(keys_block.len() as u32).to_be_bytes());
+		info.extend([0, 0, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0, 0]);
+		blocks.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+		info.extend([0; 8]);
+
+		let mut patched = data[..keys].to_vec();
+		for number in [2, 2, info.len(), blocks.len()] {
+			patched.extend((number as u32).to_be_bytes());
+		}
+		patched.extend(info);
+		patched.extend(blocks);
+		patched.extend(records);
+		std::fs::write(&mdx, patched).unwrap();
+
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let warnings: Vec<_> = dict.warnings().cloned().collect();
+		assert!(warnings.contains(&Warning::Padding { section: "key block infos", len: 8 }));
+		assert!(warnings.contains(&Warning::Padding { section: "key block", len: 16 }));
+		assert!(warnings.contains(&Warning::EmptyKeyBlock(1)));
+		assert_eq!(dict.lookup("apple").unwrap().unwrap().definition, "red");
+		assert_eq!(dict.lookup("pear").unwrap().unwrap().definition, "green");
+	}
+
+	#[test]
+	fn repair_checksums()
+	{
+		use crate::inspect::{check_file, read_structure, BlockKind};
+		use crate::writer::{repair_checksums, ChecksumSite};
+		let (dir, mdx) = fixture("repair", &[("apple", "red")]);
+		let record = read_structure(&mdx).unwrap().record_blocks[0].offset as usize;
+		let mut data = std::fs::read(&mdx).unwrap();
+		let header = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+		data[4 + header] ^= 0xff;
+		data[record + 4] ^= 0xff;
+		std::fs::write(&mdx, data).unwrap();
+		assert!(MDictBuilder::new(&mdx).build().is_err());
+
+		let repaired = dir.join("b.mdx");
+		let repair = repair_checksums(&mdx, &repaired).unwrap();
+		assert_eq!(repair.repaired, [ChecksumSite::Header, ChecksumSite::Block(BlockKind::Record, 0)]);
+		assert!(repair.unchecked.is_empty());
+		assert!(check_file(&repaired).unwrap().is_ok());
+		let mut dict = MDictBuilder::new(&repaired).build().unwrap();
+		assert_eq!(dict.lookup("apple").unwrap().unwrap().definition, "red");
+	}
+
+	#[test]
+	fn invalid_keys()
+	{
+		use crate::{Decoding, Error};
+		let dir = TempDir::new("keys");
+		let mdx = dir.join("a.mdx");
+		let mut writer = crate::MdxWriter::new("A").encoding(encoding_rs::WINDOWS_1252);
+		writer.add("café", "coffee");
+		writer.write_file(&mdx).unwrap();
+		// the key bytes declared as UTF-8 instead
+		let data = std::fs::read(&mdx).unwrap();
+		let len = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+		let text = encoding_rs::UTF_16LE.decode(&data[4..4 + len]).0
+			.replace("windows-1252", "UTF-8");
+		let text: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+		let mut patched = (text.len() as u32).to_be_bytes().to_vec();
+		patched.extend_from_slice(&text);
+		patched.extend_from_slice(&crate::checksum::adler32(&text).to_le_bytes());
+		patched.extend_from_slice(&data[8 + len..]);
+		std::fs::write(&mdx, patched).unwrap();
+
+		let dict = MDictBuilder::new(&mdx).build().unwrap();
+		assert_eq!(dict.invalid_keys().collect::<Vec<_>>(), [(0, 8, "caf\u{fffd}")]);
+		let err = MDictBuilder::new(&mdx).key_decoding(Decoding::Strict).build().err();
+		assert!(matches!(err, Some(Error::InvalidKey { block: 0, offset: 8 })));
+	}
+
+	#[test]
+	fn load_report()
+	{
+		use crate::Warning;
+		let dir = TempDir::new("report");
+		let mdx = dir.join("a.mdx");
+		let mut writer = crate::MdxWriter::new("A").encoding(encoding_rs::UTF_16LE)
+			.attribute("Generator", "hand");
+		writer.add("apple", "red");
+		writer.write_file(&mdx).unwrap();
+		// the encoding left out of the header, UTF-16 is assumed
+		let data = std::fs::read(&mdx).unwrap();
+		let len = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+		let text = encoding_rs::UTF_16LE.decode(&data[4..4 + len]).0
+			.replace("Encoding=\"UTF-16LE\"", "Encoding=\"\"");
+		let text: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+		let mut patched = (text.len() as u32).to_be_bytes().to_vec();
+		patched.extend_from_slice(&text);
+		patched.extend_from_slice(&crate::checksum::adler32(&text).to_le_bytes());
+		patched.extend_from_slice(&data[8 + len..]);
+		std::fs::write(&mdx, patched).unwrap();
+
+		let mut report = MDictBuilder::new(&mdx).build_with_report().unwrap();
+		assert_eq!(report.warnings, [
+			Warning::EncodingFallback("UTF-16LE"),
+			Warning::UnknownAttribute("Generator".into()),
+		]);
+		assert!(report.timings.total() >= report.timings.header + report.timings.key_blocks);
+		assert_eq!(report.mdx.lookup("apple").unwrap().unwrap().definition, "red");
+	}
+
+	#[test]
+	fn record_blocks()
+	{
+		let dir = TempDir::new("blocks");
+		let mdx = dir.join("a.mdx");
+		let mut writer = crate::MdxWriter::new("A");
+		for (key, fill) in [("a", "1"), ("b", "2"), ("c", "3")] {
+			writer.add(key, &fill.repeat(30 * 1024));
+		}
+		writer.add("d", "1");
+		writer.write_file(&mdx).unwrap();
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		assert_eq!(dict.record_block_count(), 2);
+		let block = dict.read_record_block(0).unwrap();
+		let keys: Vec<&str> = block.iter().map(|record| record.key()).collect();
+		assert_eq!(keys, ["a", "b"]);
+		assert_eq!(block[1].text().unwrap(), "2".repeat(30 * 1024));
+		let block = dict.read_record_block(1).unwrap();
+		let records: Vec<(&str, String)> = block.iter()
+			.map(|record| (record.key(), record.text().unwrap().into_owned()))
+			.collect();
+		assert_eq!(records, [("c", "3".repeat(30 * 1024)), ("d", "1".to_owned())]);
+		assert!(dict.read_record_block(2).unwrap().is_empty());
+		#[cfg(feature = "rayon")]
+		{
+			let visited = std::sync::Mutex::new(vec![]);
+			dict.par_visit(|key, record| visited.lock().unwrap().push((key.to_owned(), record.len())))
+				.unwrap();
+			let mut visited = visited.into_inner().unwrap();
+			visited.sort();
+			assert_eq!(visited, [("a".to_owned(), 30 * 1024 + 1), ("b".to_owned(), 30 * 1024 + 1),
+				("c".to_owned(), 30 * 1024 + 1), ("d".to_owned(), 2)]);
+		}
+	}
+
+	#[test]
+	fn tie_order()
+	{
+		let (_dir, mdx) = fixture("ties", &[("b", "3"), ("apple", "2"), ("APPLE", "2"), ("Apple", "1")]);
+		for trust in [false, true] {
+			let dict = MDictBuilder::new(&mdx).trust_sorted(trust).index_cache(false).build().unwrap();
+			let raw: Vec<&str> = dict.headwords().map(|headword| headword.raw).collect();
+			assert_eq!(raw, ["APPLE", "apple", "Apple", "b"]);
+		}
+		let dict = MDictBuilder::new(&mdx).build().unwrap();
+		let range = dict.range("A".."B");
+		assert_eq!((range.len(), range.keys().collect::<Vec<_>>()), (3, vec!["apple"]));
+		assert_eq!(range.find_key("APPLE"), Some("apple"));
+		assert_eq!(range.search_fuzzy("b", 1).chain(range.search_fuzzy("aple", 1)).collect::<Vec<_>>(),
+			[("apple", 1)]);
+		assert_eq!(dict.range("apple"..).search_prefix("b").collect::<Vec<_>>(), ["b"]);
+		assert!(dict.range(.."Apple").is_empty());
+		use std::ops::Bound;
+		assert!(dict.range((Bound::Excluded("apple"), Bound::Excluded("b"))).is_empty());
+	}
+
+	#[test]
+	fn contains_search()
+	{
+		let (_dir, mdx) = fixture("contains", &[("heart", "1"), ("Sweetheart", "2"), ("hearth", "3"),
+			("earth", "4"), ("heat", "5"), ("he", "6")]);
+		let dict = MDictBuilder::new(&mdx).index_cache(false).build().unwrap();
+		let search = |text| dict.search_contains(text).collect::<Vec<_>>();
+		assert_eq!(search("HEART"), ["heart", "hearth", "sweetheart"]);
+		assert_eq!(search("earth"), ["earth", "hearth"]);
+		// shorter than a trigram, every key is scanned
+		assert_eq!(search("ea"), ["earth", "heart", "hearth", "heat", "sweetheart"]);
+		assert!(search("tha").is_empty());
+	}
+
+	#[test]
+	fn search_budget()
+	{
+		use crate::SearchBudget;
+		let (_dir, mdx) = fixture("budget", &[("bat", "1"), ("cat", "2"), ("cot", "3"), ("cut", "4"), ("hat", "5")]);
+		let dict = MDictBuilder::new(&mdx).build().unwrap();
+		let partial = dict.search_fuzzy_within("cat", 1, &SearchBudget::keys(2));
+		assert!(partial.truncated);
+		assert_eq!(partial.items, [("bat", 1), ("cat", 0)]);
+		let full = dict.search_fuzzy_within("cat", 1, &SearchBudget::keys(5));
+		assert!(!full.truncated);
+		assert_eq!(full.items, dict.search_fuzzy("cat", 1).collect::<Vec<_>>());
+		let partial = dict.search_contains_within("at", &SearchBudget::keys(1));
+		assert_eq!((partial.items, partial.truncated), (vec!["bat"], true));
+		let timed = dict.search_contains_within("at", &SearchBudget::time(std::time::Duration::from_secs(60)));
+		assert_eq!((timed.items, timed.truncated), (vec!["bat", "cat", "hat"], false));
+	}
+
+	#[cfg(feature = "icu")]
+	#[test]
+	fn collation_ties()
+	{
+		// the zero width space is ignored by the collator
+		let (_dir, mdx) = fixture("collation", &[("ab", "plain"), ("a\u{200b}b", "spaced"), ("ä", "umlaut"), ("b", "b")]);
+		let mut dict = MDictBuilder::new(&mdx).collation("de").index_cache(false).build().unwrap();
+		assert_eq!(dict.keys().collect::<Vec<_>>(), ["ä", "ab", "a\u{200b}b", "b"]);
+		assert_eq!(dict.lookup("a\u{200b}b").unwrap().unwrap().definition, "spaced");
+		assert_eq!(dict.lookup("AB").unwrap().unwrap().definition, "plain");
+		assert_eq!(dict.lookup("Ä").unwrap().unwrap().definition, "umlaut");
+	}
+
+	#[test]
+	fn natural_order()
+	{
+		let (_dir, mdx) = fixture("natural", &[("H2O", "H2O"), ("10", "10"), ("H10", "H10"),
+			("2", "2"), ("02", "02"), ("b", "b")]);
+		let mut dict = MDictBuilder::new(&mdx).natural_order().index_cache(false).build().unwrap();
+		assert_eq!(dict.keys().collect::<Vec<_>>(), ["02", "2", "10", "b", "h2o", "h10"]);
+		assert_eq!(dict.lookup("H10").unwrap().unwrap().definition, "H10");
+		assert_eq!(dict.range("h"..).keys().collect::<Vec<_>>(), ["h2o", "h10"]);
+	}
+
+	#[test]
+	fn coalesced_reads()
+	{
+		let dir = TempDir::new("coalesce");
+		let mdx = dir.join("a.mdx");
+		// a record block per entry
+		let mut writer = crate::MdxWriter::new("A").compression(crate::writer::Compression::Stored);
+		for n in 0..7 {
+			writer.add(format!("k{}", n), &format!("{}{}", n, "x".repeat(40_000)));
+		}
+		writer.write_file(&mdx).unwrap();
+		let mut dict = MDictBuilder::new(&mdx)
+			.buffer_sizes(crate::BufferSizes { coalesce: 100_000, ..Default::default() })
+			.build()
+			.unwrap();
+		assert_eq!(dict.record_block_count(), 7);
+		let info = dict.entry_info("K3").unwrap();
+		assert_eq!((info.raw, info.block), ("k3", 3));
+		assert_eq!(info.size, dict.lookup_record("k3").unwrap().unwrap().data().len());
+		assert!(dict.entry_infos().map(|info| info.block).eq(0..7));
+		#[cfg(all(feature = "io-uring", target_os = "linux"))]
+		{
+			let mut uring = MDictBuilder::new(&mdx).io_uring(true).build().unwrap();
+			assert!(uring.lookup("K6").unwrap().unwrap().definition.starts_with("6xx"));
+			// reads larger than a chunk are split and submitted together
+			let mut file = crate::uring::UringFile::open(&mdx).unwrap();
+			let mut data = vec![];
+			std::io::Read::read_to_end(&mut file, &mut data).unwrap();
+			assert_eq!(data, std::fs::read(&mdx).unwrap());
+		}
+		let keys = |path: &std::path::Path| std::fs::read_to_string(path).unwrap().lines()
+			.map(|line| line[line.find("\"key\":").unwrap() + 6..].split(',').next().unwrap().to_owned())
+			.collect::<Vec<_>>();
+		crate::Exporter::new().json_lines(&mut dict, dir.join("all.jsonl")).unwrap();
+		assert_eq!(keys(&dir.join("all.jsonl")), ["\"k0\"", "\"k1\"", "\"k2\"", "\"k3\"",
+			"\"k4\"", "\"k5\"", "\"k6\""]);
+		crate::Exporter::new()
+			.shards(2, crate::Sharding::Hash)
+			.json_lines(&mut dict, dir.join("part.jsonl"))
+			.unwrap();
+		let mut parts = [keys(&dir.join("part.jsonl.0")), keys(&dir.join("part.jsonl.1"))].concat();
+		parts.sort();
+		assert_eq!(parts, keys(&dir.join("all.jsonl")));
+		std::fs::write(dir.join("keys.txt"), "# wanted\nK1\n\nk2\nk5\nk9\n").unwrap();
+		crate::Exporter::new()
+			.key_file(dir.join("keys.txt"))
+			.unwrap()
+			.key_range("k2"..)
+			.matching(regex::Regex::new("[0-4]").unwrap())
+			.max_size(50_000)
+			.json_lines(&mut dict, dir.join("some.jsonl"))
+			.unwrap();
+		assert_eq!(keys(&dir.join("some.jsonl")), ["\"k2\""]);
+		// a record over the size limit among ones under it
+		let big = "b".repeat(100);
+		let (sizes, path) = fixture("sizes", &[("big", &big), ("small", "s"), ("tiny", "t")]);
+		let mut dict = MDictBuilder::new(&path).build().unwrap();
+		assert!(dict.entry_info("big").unwrap().size > 50);
+		crate::Exporter::new()
+			.max_size(50)
+			.json_lines(&mut dict, sizes.join("small.jsonl"))
+			.unwrap();
+		assert_eq!(keys(&sizes.join("small.jsonl")), ["\"small\"", "\"tiny\""]);
+	}
+
+	#[test]
+	#[cfg(feature = "parquet")]
+	fn parquet_export()
+	{
+		use parquet::file::reader::{FileReader, SerializedFileReader};
+		use parquet::record::RowAccessor;
+		let (dir, mdx) = fixture("parquet", &[("Apple", "<b>red</b> fruit"), ("pear", "green")]);
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let out = dir.join("a.parquet");
+		crate::export::parquet(&mut dict, std::fs::File::create(&out).unwrap()).unwrap();
+		let reader = SerializedFileReader::new(std::fs::File::open(&out).unwrap()).unwrap();
+		let rows: Vec<_> = reader.get_row_iter(None).unwrap()
+			.map(|row| {
+				let row = row.unwrap();
+				(row.get_string(0).unwrap().clone(), row.get_string(1).unwrap().clone(),
+					row.get_string(2).unwrap().clone(), row.get_long(3).unwrap())
+			})
+			.collect();
+		let row = |key: &str, text: &str, html: &str|
+			(key.to_owned(), text.to_owned(), html.to_owned(), html.len() as i64 + 1);
+		assert_eq!(rows, [row("Apple", "red fruit", "<b>red</b> fruit"), row("pear", "green", "green")]);
+	}
+
+	#[test]
+	fn export_shards()
+	{
+		use crate::{Exporter, Sharding};
+		let keys: Vec<String> = (0..6).map(|n| format!("k{}", n)).collect();
+		let definitions: Vec<String> = (0..6).map(|n| format!("{}{}", n, "x".repeat(40_000))).collect();
+		let entries: Vec<(&str, &str)> = keys.iter().map(String::as_str)
+			.zip(definitions.iter().map(String::as_str))
+			.collect();
+		let (dir, mdx) = fixture("shards", &entries);
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		assert_eq!(dict.record_block_count(), 6);
+		let lines = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap().lines()
+			.map(str::to_owned)
+			.collect::<Vec<_>>();
+		Exporter::new().threads(1).json_lines(&mut dict, dir.join("all.jsonl")).unwrap();
+		let all = lines("all.jsonl");
+		assert_eq!(all.len(), 6);
+
+		for sharding in [Sharding::KeyRange, Sharding::Hash] {
+			Exporter::new().threads(3).shards(3, sharding)
+				.json_lines(&mut dict, dir.join("part.jsonl"))
+				.unwrap();
+			assert!(!dir.join("part.jsonl").exists() && !dir.join("part.jsonl.3").exists());
+			let parts: Vec<_> = (0..3).map(|n| lines(&format!("part.jsonl.{}", n))).collect();
+			if sharding == Sharding::KeyRange {
+				assert_eq!(parts, [&all[0..2], &all[2..4], &all[4..6]]);
+			}
+			let mut joined = parts.concat();
+			joined.sort();
+			assert_eq!(joined, all);
+		}
+	}
+
+	#[test]
+	fn export_resume()
+	{
+		use crate::Exporter;
+		let keys: Vec<String> = (0..6).map(|n| format!("k{}", n)).collect();
+		let definitions: Vec<String> = (0..6).map(|n| format!("{}{}", n, "x".repeat(40_000))).collect();
+		let entries: Vec<(&str, &str)> = keys.iter().map(String::as_str)
+			.zip(definitions.iter().map(String::as_str))
+			.collect();
+		let (dir, mdx) = fixture("resume", &entries);
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let full = dir.join("all.jsonl");
+		Exporter::new().json_lines(&mut dict, &full).unwrap();
+		let full = std::fs::read_to_string(full).unwrap();
+
+		// an export stopped by a corrupt fifth record block
+		let block = &crate::inspect::read_structure(&mdx).unwrap().record_blocks[4];
+		let mut data = std::fs::read(&mdx).unwrap();
+		data[block.offset as usize + 4] ^= 0xff;
+		let bad = dir.join("bad.mdx");
+		std::fs::write(&bad, data).unwrap();
+		let mut bad = MDictBuilder::new(&bad).build().unwrap();
+		let out = dir.join("part.jsonl");
+		let checkpoint = dir.join("part.jsonl.checkpoint");
+		assert!(Exporter::new().json_lines(&mut bad, &out).is_err());
+		// the rows of a block are kept once the next block starts
+		let written: usize = full.lines().take(3).map(|line| line.len() + 1).sum();
+		assert_eq!(std::fs::read_to_string(&checkpoint).unwrap(), format!("3 {}\n", written));
+		// and a row torn after the checkpoint
+		let mut partial = std::fs::OpenOptions::new().append(true).open(&out).unwrap();
+		std::io::Write::write_all(&mut partial, b"{\"key\":\"k4\",\"te").unwrap();
+		drop(partial);
+
+		Exporter::new().resume(true).json_lines(&mut dict, &out).unwrap();
+		assert_eq!(std::fs::read_to_string(&out).unwrap(), full);
+		assert!(!checkpoint.exists());
+	}
+
+	#[test]
+	fn concurrent_lookups()
+	{
+		let dir = TempDir::new("concurrent");
+		let mdx = dir.join("a.mdx");
+		let mut writer = crate::MdxWriter::new("A");
+		for n in 0..50 {
+			writer.add(format!("k{}", n), &format!("<b>{}</b>", n));
+		}
+		writer.add("panic", "<i>rule</i>");
+		writer.write_file(&mdx).unwrap();
+		struct Panic;
+		impl crate::transform::Rule for Panic {
+			fn element(&self, tag: &mut crate::html::Tag) -> crate::transform::Action
+			{
+				assert_ne!(tag.name(), "i");
+				crate::transform::Action::Keep
+			}
+		}
+		let dict = MDictBuilder::new(&mdx).transformer(Transformer::new().rule(Panic)).build().unwrap();
+		// a lookup panicking gives its handles back
+		let single = crate::ConcurrentMdx::new(dict).unwrap();
+		let panicking = std::panic::AssertUnwindSafe(|| single.lookup("panic"));
+		assert!(std::panic::catch_unwind(panicking).is_err());
+		assert_eq!(single.lookup("k7").unwrap().unwrap(), "<b>7</b>");
+		let dict = MDictBuilder::new(&mdx).build().unwrap();
+		let concurrent = crate::ConcurrentMdx::new(dict).unwrap();
+		std::thread::scope(|scope| {
+			for thread in 0..4 {
+				let concurrent = &concurrent;
+				scope.spawn(move || for n in (thread..50).step_by(4) {
+					let definition = concurrent.lookup(&format!("K{}", n)).unwrap().unwrap();
+					assert_eq!(definition, format!("<b>{}</b>", n));
+				});
+			}
+		});
+		assert!(concurrent.lookup_shared("k50").unwrap().is_none());
+		assert_eq!(concurrent.dict().search_prefix("k4").count(), 11);
+		let bytes = std::fs::read(&mdx).unwrap();
+		let untrusted = crate::parse_untrusted(&bytes, &crate::Limits::default()).unwrap();
+		assert!(matches!(crate::ConcurrentMdx::new(untrusted), Err(crate::Error::NoSource)));
+
+		// lookups only get past the barrier when both read at once
+		struct Wait(std::sync::Barrier);
+		impl crate::transform::Rule for Wait {
+			fn element(&self, tag: &mut crate::html::Tag) -> crate::transform::Action
+			{
+				if tag.name() == "i" {
+					self.0.wait();
+				}
+				crate::transform::Action::Keep
+			}
+		}
+		let dict = MDictBuilder::new(&mdx).transformer(Transformer::new().rule(Wait(std::sync::Barrier::new(2)))).build().unwrap();
+		let concurrent = crate::ConcurrentMdx::new(dict).unwrap();
+		std::thread::scope(|scope| {
+			for _ in 0..2 {
+				let concurrent = &concurrent;
+				scope.spawn(move || assert_eq!(concurrent.lookup("panic").unwrap().unwrap(), "<i>rule</i>"));
+			}
+		});
+	}
+
+	#[test]
+	fn warm_cache()
+	{
+		let dir = TempDir::new("warm");
+		let (mdx, cache) = (dir.join("a.mdx"), dir.join("a.cache"));
+		let mut writer = crate::MdxWriter::new("A").compression(crate::writer::Compression::Stored);
+		for n in 0..3 {
+			writer.add(format!("k{}", n), &format!("{}{}", n, "x".repeat(40_000)));
+		}
+		writer.write_file(&mdx).unwrap();
+		let mut dict = MDictBuilder::new(&mdx).cache_definition(true).build().unwrap();
+		assert_eq!(dict.load_record_cache(&cache).unwrap(), 0);
+		dict.lookup("k0").unwrap();
+		dict.lookup("k2").unwrap();
+		dict.lookup("k2").unwrap();
+		// the block looked up most
+		assert_eq!(dict.save_record_cache(&cache, 50_000).unwrap(), 1);
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		assert_eq!(dict.load_record_cache(&cache).unwrap(), 1);
+		assert!(dict.lookup_record("k2").unwrap().unwrap().is_cached());
+		assert!(!dict.lookup_record("k0").unwrap().unwrap().is_cached());
+		// same keys and sizes, other content
+		let identity = dict.identity();
+		let mut writer = crate::MdxWriter::new("A").compression(crate::writer::Compression::Stored);
+		for n in 0..3 {
+			writer.add(format!("k{}", n), &format!("{}{}", n, "y".repeat(40_000)));
+		}
+		writer.write_file(&mdx).unwrap();
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		assert_eq!(dict.identity(), identity);
+		assert_eq!(dict.load_record_cache(&cache).unwrap(), 0);
+		let mut other = crate::MdxWriter::new("B");
+		other.add("k0", "0");
+		other.write_file(dir.join("b.mdx")).unwrap();
+		let mut other = MDictBuilder::new(dir.join("b.mdx")).build().unwrap();
+		assert_eq!(other.load_record_cache(&cache).unwrap(), 0);
+	}
+
+	#[test]
+	fn phonetic()
+	{
+		use crate::phonetic::metaphone;
+		assert_eq!(metaphone("Thumb"), "0M");
+		assert_eq!([metaphone("knight"), metaphone("nite")], ["NT", "NT"]);
+		assert_eq!([metaphone("phone"), metaphone("fone")], ["FN", "FN"]);
+		assert_eq!(metaphone("science"), "SNS");
+		assert_eq!([metaphone("judge"), metaphone("edge")], ["JJ", "EJ"]);
+		let (_dir, mdx) = fixture("phonetic", &[("night", "night"), ("Night", "Night"), ("note", "note"),
+			("knot", "knot"), ("夜", "夜")]);
+		let dict = MDictBuilder::new(&mdx).index_cache(false).build().unwrap();
+		assert_eq!(dict.search_phonetic("nite").collect::<Vec<_>>(), ["knot", "night", "note"]);
+		assert_eq!(dict.search_phonetic("夜").count(), 0);
+	}
+
+	#[cfg(feature = "rand")]
+	#[test]
+	fn random_entry()
+	{
+		let (_dir, mdx) = fixture("random", &[("a", "one"), ("b", "two"), ("c", "three")]);
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let mut rng = rand::rngs::mock::StepRng::new(0, 1 << 62);
+		let mut seen = std::collections::BTreeMap::new();
+		for _ in 0..3 {
+			let entry = dict.random_entry(&mut rng).unwrap();
+			let key = entry.headword().key.to_owned();
+			seen.insert(key, entry.definition().unwrap().unwrap());
+		}
+		assert_eq!(seen.len(), 3);
+		assert_eq!(seen["b"], "two");
+		let entry = dict.random_entry(&mut rng).unwrap();
+		assert_eq!(entry.headword().raw, "a");
+		assert_eq!(entry.record().unwrap().unwrap().text().unwrap(), "one");
+	}
+
+	#[test]
+	fn index_cache()
+	{
+		use crate::index::{read, write, Fingerprint};
+		use crate::format::{BlockEntryInfo, KeyEntry};
+		let dir = TempDir::new("index");
+		let mdx = dir.join("a.mdx");
+		std::fs::write(&mdx, b"dictionary").unwrap();
+		let idx = crate::index::index_path(&mdx);
+		let entries = [
+			KeyEntry { offset: 7, text: "b".to_owned(), raw: Some("B".into()) },
+			KeyEntry { offset: 0, text: "a".to_owned(), raw: None },
+		];
+		let infos = [BlockEntryInfo { compressed_size: 10, decompressed_size: 20 }];
+		let fingerprint = Fingerprint::of(&mdx, 0x1234).unwrap();
+		write(&idx, &fingerprint, &entries, &infos, 99).unwrap();
+		let index = read(&idx, &fingerprint).unwrap();
+		assert_eq!(index.keys, [(0, "a".to_owned()), (7, "B".to_owned())]);
+		assert_eq!(index.records_info[0].decompressed_size, 20);
+		assert_eq!(index.record_block_offset, 99);
+		assert!(read(&idx, &Fingerprint::of(&mdx, 0x4321).unwrap()).is_none());
+	}
+
+	#[test]
+	fn write_split()
+	{
+		use crate::MdxWriter;
+		let dir = TempDir::new("split");
+		let mdx = dir.join("words.mdx");
+		let mut writer = MdxWriter::new("Words").attribute("Description", "a & b");
+		for word in ["Apple", "banana", "Nut", "zebra"] {
+			writer.add(word, &format!("<b>{}</b>", word));
+		}
+		writer.write_file(&mdx).unwrap();
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		assert_eq!(dict.title(), "Words");
+		let nut = dict.lookup("nut").unwrap().unwrap();
+		assert_eq!(nut.definition, "<b>Nut</b>");
+		assert_eq!(nut.kind(), crate::RecordKind::Html);
+		assert_eq!(crate::RecordKind::of_text("1 < 2"), crate::RecordKind::Text);
+		assert_eq!(crate::RecordKind::of_text("@@@LINK=nut"), crate::RecordKind::Link);
+		let reversed = MDictBuilder::new(&mdx)
+			.comparator(|a, b| b.cmp(a))
+			.build()
+			.unwrap();
+		assert_eq!(reversed.keys().collect::<Vec<_>>(), ["zebra", "nut", "banana", "apple"]);
+		assert_eq!(reversed.find_key("Banana"), Some("banana"));
+		let table = crate::AliasTable::parse("# misspellings\nbananna\tbanana\nfig\tfigs\n");
+		assert_eq!(dict.set_aliases(table), 1);
+		assert_eq!(dict.lookup("Bananna").unwrap().unwrap().definition, "<b>banana</b>");
+		assert_eq!(dict.find_key("bananna"), Some("banana"));
+		let mut small = MDictBuilder::new(&mdx)
+			.buffer_sizes(crate::BufferSizes { read: 16, decompress: 3, coalesce: 1 })
+			.trust_sorted(true)
+			.build()
+			.unwrap();
+		assert_eq!(small.lookup("apple").unwrap().unwrap().definition, "<b>Apple</b>");
+
+		let (first, second) = (dir.join("a-m.mdx"), dir.join("n-z.mdx"));
+		crate::writer::split(&mdx, &[("a".."n", &first), ("n".."{", &second)]).unwrap();
+		let mut first = MDictBuilder::new(&first).build().unwrap();
+		let mut second = MDictBuilder::new(&second).build().unwrap();
+		assert_eq!(first.keys().collect::<Vec<_>>(), ["apple", "banana"]);
+		assert_eq!(second.keys().collect::<Vec<_>>(), ["nut", "zebra"]);
+		assert_eq!(second.title(), "Words");
+		assert!(first.lookup("zebra").unwrap().is_none());
+		assert_eq!(second.lookup("Zebra").unwrap().unwrap().definition, "<b>zebra</b>");
+		let hits = dict.grep(&regex::Regex::new("(?i)n[au]").unwrap(), 2).unwrap();
+		assert!(hits.truncated);
+		let keys: Vec<&str> = hits.items.iter().map(|hit| hit.key.as_str()).collect();
+		assert_eq!(keys, ["banana", "banana"]);
+		assert_eq!(&hits.items[1].snippet[hits.items[1].range.clone()], "na");
+		let (_dir, marked) = fixture("grep-markup", &[("run", "<p>She <b>run</b>s.</p><p>Fast</p>")]);
+		let mut marked = MDictBuilder::new(&marked).build().unwrap();
+		let hits = marked.grep(&regex::Regex::new("runs").unwrap(), 10).unwrap();
+		assert_eq!(hits.items.len(), 1);
+		assert_eq!(hits.items[0].snippet, "She runs. Fast");
+		assert!(marked.grep(&regex::Regex::new(r"\.Fast").unwrap(), 10).unwrap().items.is_empty());
+
+		let mut writer = MdxWriter::new("Colors");
+		writer.add("color", "<b>hue</b>");
+		writer.add("colour", "<b>hue</b>");
+		writer.add("hue", "@@@LINK=colour");
+		writer.add("red", "<b>red</b>");
+		writer.write_file(&mdx).unwrap();
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let groups = dict.find_aliases().unwrap();
+		assert_eq!(groups, [crate::AliasGroup {
+			canonical: "color".to_owned(),
+			aliases: vec!["colour".to_owned(), "hue".to_owned()],
+		}]);
+		assert_eq!(dict.synonym_keys("Colour"), ["color"]);
+		assert!(dict.synonym_keys("hue").is_empty());
+		let deduped = dir.join("deduped.mdx");
+		crate::writer::dedup_aliases(&mdx, &deduped).unwrap();
+		let mut dict = MDictBuilder::new(&deduped).build().unwrap();
+		assert_eq!(dict.lookup("hue").unwrap().unwrap().definition, "@@@LINK=color");
+		let learner = dir.join("learner.mdx");
+		let written = crate::writer::subset(&mdx, ["the", "Hue", "red", "color"], 2, &learner).unwrap();
+		assert_eq!(written, 3);
+		let dict = MDictBuilder::new(&learner).build().unwrap();
+		assert_eq!(dict.keys().collect::<Vec<_>>(), ["colour", "hue", "red"]);
+	}
+
+	#[test]
+	fn identical_records()
+	{
+		let dir = TempDir::new("identical");
+		let mdx = dir.join("a.mdx");
+		let mut writer = crate::MdxWriter::new("A").compression(crate::writer::Compression::Stored);
+		writer.add("color", "<b>hue</b>");
+		writer.add("colour", "<b>hux</b>");
+		writer.add("red", "<b>hum</b>");
+		writer.write_file(&mdx).unwrap();
+		// an identical record stored again, as other tools write them
+		let block = &crate::inspect::read_structure(&mdx).unwrap().record_blocks[0];
+		let mut data = std::fs::read(&mdx).unwrap();
+		let start = block.offset as usize + 8;
+		let records = &mut data[start..start + block.decompressed_size];
+		let at = records.windows(3).position(|bytes| bytes == b"hux").unwrap();
+		records[at + 2] = b'e';
+		let checksum = crate::checksum::adler32(records).to_be_bytes();
+		data[start - 4..start].copy_from_slice(&checksum);
+		std::fs::write(&mdx, data).unwrap();
+
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		assert_eq!(dict.lookup("colour").unwrap().unwrap().definition, "<b>hue</b>");
+		assert_eq!(dict.find_aliases().unwrap(), [crate::AliasGroup {
+			canonical: "color".to_owned(),
+			aliases: vec!["colour".to_owned()],
+		}]);
+	}
+
+	#[test]
+	fn invert()
+	{
+		let (dir, mdx) = fixture("en-de", &[
+			("dog", "<i>n.</i> <span class=\"tr\">Hund</span>, <span class=\"tr\">R&#252;de</span>"),
+			("hound", "<span class=\"tr\">Hund</span>"),
+			("hounds", "@@@LINK=hound"),
+		]);
+		let inverted = dir.join("de-en.mdx");
+		let targets = regex::Regex::new(r#"<span class="tr">(.*?)</span>"#).unwrap();
+		assert_eq!(crate::writer::invert(&mdx, &targets, &inverted).unwrap(), 2);
+		let mut dict = MDictBuilder::new(&inverted).build().unwrap();
+		assert_eq!(dict.title(), "en-de (inverted)");
+		assert_eq!(dict.lookup("hund").unwrap().unwrap().definition,
+			"<a href=\"entry://dog\">dog</a><br><a href=\"entry://hound\">hound</a>");
+		assert!(dict.lookup("rüde").unwrap().is_some());
+	}
+
+	#[test]
+	fn group_reload()
+	{
+		use crate::{Dedup, DictGroup, MdxWriter, SearchOptions};
+		let dir = TempDir::new("group");
+		let write = |path: &std::path::Path, words: &[&str]| {
+			let mut writer = MdxWriter::new("Words");
+			for word in words {
+				writer.add(*word, word);
+			}
+			writer.write_file(path).unwrap();
+		};
+		let (first, second) = (dir.join("first.mdx"), dir.join("second.mdx"));
+		write(&first, &["apple"]);
+		write(&second, &["apricot"]);
+		let mut group = DictGroup::new();
+		let load = |path: std::path::PathBuf| move || MDictBuilder::new(&path).build();
+		group.add_loaded(load(first.clone()), 0).unwrap();
+		group.add_loaded(load(second.clone()), 1).unwrap();
+		let options = SearchOptions::default();
+		let keys = |group: &DictGroup<_>| group.search("ap", &options)
+			.into_iter()
+			.map(|hit| hit.key)
+			.collect::<Vec<_>>();
+		assert_eq!(keys(&group), ["apricot", "apple"]);
+		group.set_enabled(1, false);
+		assert_eq!(keys(&group), ["apple"]);
+		write(&first, &["apple", "apply"]);
+		assert!(group.reload(0).unwrap());
+		assert_eq!(keys(&group), ["apple", "apply"]);
+
+		write(&second, &["apple", "apricot"]);
+		assert!(group.reload(1).unwrap());
+		group.set_enabled(1, true);
+		let hits = group.search("apple", &SearchOptions { dedup: Dedup::KeepAll, ..options.clone() });
+		let dicts: Vec<_> = hits.iter().map(|hit| hit.dicts.clone()).collect();
+		assert_eq!(dicts, [[1], [0]]);
+		let merged = group.lookup_all("Apple", Dedup::Merge).unwrap();
+		assert_eq!(merged.len(), 1);
+		assert_eq!(merged[0].definitions, [(1, "apple".to_owned()), (0, "apple".to_owned())]);
+		assert_eq!(group.lookup_all("apple", Dedup::First).unwrap()[0].definitions[0].0, 1);
+		assert_eq!(group.lookup_first("apply").unwrap().unwrap().definitions[0].0, 0);
+		let parallel = group.par_lookup_all("Apple", Dedup::Merge, 4).unwrap();
+		assert_eq!(parallel[0].definitions, merged[0].definitions);
+		let hits = group.search("ap", &SearchOptions { fallback: true, ..options.clone() });
+		assert!(hits.iter().all(|hit| hit.dicts == [1]));
+		let keys = |query: &str| group.search_query(query).unwrap()
+			.into_iter()
+			.map(|hit| hit.key)
+			.collect::<Vec<_>>();
+		assert_eq!(keys("dict:first ap*"), ["apple", "apply"]);
+		assert_eq!(keys("dict:SECOND prefix:apr"), ["apricot"]);
+		assert_eq!(keys("dict:0 apple -prefix"), ["apple"]);
+		assert_eq!(keys("exact:aple fuzzy limit:1"), ["apple"]);
+		assert!(matches!(group.search_query("limit:many"), Err(crate::Error::InvalidQuery(_))));
+
+		let copy = dir.join("copy.mdx");
+		std::fs::copy(&second, &copy).unwrap();
+		let copy = MDictBuilder::new(&copy).build().unwrap();
+		assert_eq!(group.find_duplicate(&copy), Some(1));
+		assert_ne!(copy.identity(), group.get(0).unwrap().identity());
+	}
+
+	#[test]
+	fn companions()
+	{
+		use crate::CompanionKind;
+		let (dir, mdx) = fixture("bundle", &[("apple", "<img src=\"a.png\"><img src=\"b.png\">")]);
+		for (n, name) in [(0, "bundle.mdd"), (1, "bundle.1.mdd")] {
+			let mut writer = crate::MdxWriter::mdd("bundle");
+			writer.add_record(if n == 0 { "\\a.png" } else { "\\b.png" }, vec![n]);
+			writer.write_file(dir.join(name)).unwrap();
+		}
+		std::fs::write(dir.join("bundle.css"), "b { color: red }").unwrap();
+		std::fs::write(dir.join("bundle.ini"), "[dict]").unwrap();
+		std::fs::write(dir.join("other.js"), "go()").unwrap();
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let found: Vec<(CompanionKind, &str)> = dict.companions().iter()
+			.map(|companion| (companion.kind, companion.name.as_str()))
+			.collect();
+		assert_eq!(found, [(CompanionKind::Mdd, "bundle.mdd"), (CompanionKind::Mdd, "bundle.1.mdd"),
+			(CompanionKind::Css, "bundle.css"), (CompanionKind::Ini, "bundle.ini")]);
+		assert_eq!(dict.read_companion(CompanionKind::Css).unwrap().unwrap(), b"b { color: red }");
+		assert!(dict.read_companion(CompanionKind::Js).unwrap().is_none());
+		assert_eq!(dict.get_resource("\\b.png").unwrap().unwrap().as_ref(), [1]);
+	}
+
+	#[test]
+	fn icon_mime()
+	{
+		use crate::companion::image_mime;
+		assert_eq!(image_mime(b"\x89PNG\r\n"), "image/png");
+		assert_eq!(image_mime(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>"), "image/svg+xml");
+		assert_eq!(image_mime(b"\xef\xbb\xbf<?xml version=\"1.0\"?>\n<!-- logo -->\n\
+			<!DOCTYPE svg PUBLIC \"-//W3C//DTD SVG 1.1//EN\" \"svg11.dtd\">\n<svg>"), "image/svg+xml");
+		assert_eq!(image_mime(b"<!DOCTYPE html><html>"), "application/octet-stream");
+		assert_eq!(image_mime(b"<?xml version=\"1.0\"?><feed>"), "application/octet-stream");
+		assert_eq!(image_mime(b"<!-- unterminated"), "application/octet-stream");
+
+		let (dir, mdx) = fixture("icon", &[("apple", "red")]);
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		assert!(dict.icon().unwrap().is_none());
+		std::fs::write(dir.join("icon.png"), b"\x89PNG").unwrap();
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		assert_eq!(dict.icon().unwrap().unwrap().mime, "image/png");
+		let mut writer = crate::MdxWriter::mdd("icon");
+		writer.add_record("\\logo.png", b"GIF89a".to_vec());
+		writer.write_file(dir.join("icon.mdd")).unwrap();
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		let icon = dict.icon().unwrap().unwrap();
+		assert_eq!((icon.mime, icon.data.as_slice()), ("image/gif", &b"GIF89a"[..]));
+	}
+
+	#[cfg(feature = "language")]
+	#[test]
+	fn detect_languages()
+	{
+		use crate::{Lang, Languages};
+		let (_dir, mdx) = fixture("languages", &[
+			("ねこ", "<b>cat</b> a small animal that people often keep in their homes as a pet"),
+			("いぬ", "<b>dog</b> an animal that is kept as a pet or trained to guard buildings"),
+			("さかな", "<b>fish</b> an animal that lives in water and breathes through its gills"),
+			("とり", "@@@LINK=ねこ"),
+		]);
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		assert_eq!(dict.detect_languages(10).unwrap(), Languages { source: Some(Lang::Jpn), target: Some(Lang::Eng) });
+		assert_eq!(dict.detect_languages(0).unwrap(), Languages { source: None, target: None });
+	}
+
+	#[test]
+	fn ranked_search()
+	{
+		use crate::{Dedup, DictGroup, MatchKind, SearchOptions};
+		let (_a, first) = fixture("rank-a", &[("cat", "1"), ("catalog", "2"), ("cart", "3"), ("cab", "4")]);
+		let (_b, second) = fixture("rank-b", &[("cat", "5"), ("category", "6"), ("bat", "7")]);
+		let mut group = DictGroup::new();
+		group.add(MDictBuilder::new(&first).build().unwrap(), 0);
+		group.add(MDictBuilder::new(&second).build().unwrap(), 1);
+		let options = SearchOptions { fuzzy_distance: 1, ..SearchOptions::default() };
+		let ranked = |options: &SearchOptions| group.search("cat", options)
+			.into_iter()
+			.map(|hit| (hit.key, hit.kind, hit.dicts))
+			.collect::<Vec<_>>();
+		assert_eq!(ranked(&options), [
+			("cat".to_owned(), MatchKind::Exact, vec![1, 0]),
+			("category".to_owned(), MatchKind::Prefix, vec![1]),
+			("catalog".to_owned(), MatchKind::Prefix, vec![0]),
+			("bat".to_owned(), MatchKind::Fuzzy(1), vec![1]),
+			("cab".to_owned(), MatchKind::Fuzzy(1), vec![0]),
+			("cart".to_owned(), MatchKind::Fuzzy(1), vec![0]),
+		]);
+		let page = ranked(&SearchOptions { offset: 1, limit: 2, ..options.clone() });
+		assert_eq!(page.iter().map(|(key, _, _)| key.as_str()).collect::<Vec<_>>(), ["category", "catalog"]);
+		let all = ranked(&SearchOptions { dedup: Dedup::KeepAll, prefix: false, fuzzy_distance: 0, ..options });
+		assert_eq!(all, [("cat".to_owned(), MatchKind::Exact, vec![1]), ("cat".to_owned(), MatchKind::Exact, vec![0])]);
+	}
+
+	#[test]
+	fn history()
+	{
+		use crate::history::{FileStore, HistoryStore};
+		let (dir, mdx) = fixture("words", &[("Apple", "red"), ("pear", "green")]);
+		let mut dict = MDictBuilder::new(&mdx).build().unwrap();
+		assert_eq!(dict.id(), "words");
+
+		let path = dir.join("history");
+		let mut store = FileStore::open(&path).unwrap();
+		assert!(dict.lookup_recorded("APPLE", &mut store).unwrap().is_some());
+		assert!(dict.lookup_recorded("plum", &mut store).unwrap().is_none());
+		dict.lookup_recorded("pear", &mut store).unwrap();
+		assert!(dict.set_bookmark("apple", true, &mut store).unwrap());
+		assert!(dict.set_bookmark("Pear", true, &mut store).unwrap());
+		assert!(dict.set_bookmark("pear", false, &mut store).unwrap());
+		assert!(!dict.set_bookmark("plum", true, &mut store).unwrap());
+		drop(store);
+
+		let mut store = FileStore::open(&path).unwrap();
+		let keys: Vec<_> = store.history(Some("words"), 10).unwrap()
+			.into_iter()
+			.map(|entry| entry.key)
+			.collect();
+		assert_eq!(keys, ["pear", "apple"]);
+		assert!(dict.is_bookmarked("Apple", &store).unwrap());
+		assert_eq!(store.bookmarks(None).unwrap(), [("words".to_owned(), "apple".to_owned())]);
+		store.clear_history().unwrap();
+		store.compact().unwrap();
+		let store = FileStore::open(&path).unwrap();
+		assert!(store.history(None, 10).unwrap().is_empty());
+		assert!(store.is_bookmarked("words", "apple").unwrap());
+	}
+
+	#[test]
+	fn query_syntax()
+	{
+		use crate::{Dedup, Query};
+		let query = Query::parse("dict:oxford  prefix:head* -fuzzy dedup:first \"dict:x y\"").unwrap();
+		assert_eq!(query.dicts, ["oxford"]);
+		assert_eq!(query.text, "head dict:x y");
+		assert!(query.options.prefix);
+		assert_eq!((query.options.fuzzy_distance, query.options.dedup), (0, Dedup::First));
+		let query = Query::parse("dict:\"new oxford\" fuzzy:3 offset:10 fallback word").unwrap();
+		assert_eq!((query.dicts[0].as_str(), query.text.as_str()), ("new oxford", "word"));
+		assert_eq!((query.options.fuzzy_distance, query.options.offset), (3, 10));
+		assert!(query.options.fallback);
+	}
+
+	#[cfg(feature = "bytes")]
+	#[test]
+	fn shared_bytes()
+	{
+		let block = std::sync::Arc::new(b"<b>a</b>\0<b>b</b>\0".to_vec());
+		let record = crate::SharedRecord {
+			key: "b".to_owned(),
+			raw_key: None,
+			block: block.clone(),
+			range: 9..18,
+			encoding: encoding_rs::UTF_8,
+			offset: 9,
+			size: 9,
+			cached: true,
+			resource: false,
+		};
+		let bytes = record.into_bytes();
+		assert_eq!(&bytes[..], b"<b>b</b>\0");
+		assert_eq!(bytes.as_ptr(), block[9..].as_ptr());
+	}
+
+	#[cfg(feature = "encryption")]
+	#[test]
+	fn salsa20()
+	{
+		// Salsa20_k0(n) from the Salsa20 specification
+		let key: [u8; 16] = std::array::from_fn(|i| i as u8 + 1);
+		let input: [u8; 16] = std::array::from_fn(|i| i as u8 + 101);
+		assert_eq!(crate::salsa::block(&key, &input, 20), [
+			39, 173, 46, 248, 30, 200, 82, 17, 48, 67, 254, 239, 37, 18, 13, 247,
+			241, 200, 61, 144, 10, 55, 50, 185, 6, 47, 246, 253, 143, 86, 187, 225,
+			134, 85, 110, 246, 161, 163, 43, 235, 231, 94, 171, 51, 145, 214, 112, 29,
+			14, 232, 5, 16, 151, 140, 183, 141, 171, 9, 122, 181, 104, 182, 177, 193,
+		]);
+	}
 }
@@ -0,0 +1,137 @@
+use std::borrow::Cow;
+
+use crate::format::decode_slice_string;
+use crate::html::{Token, Tokenizer};
+use crate::mdx::{KeyMaker, MDict};
+use crate::parser::{find_entry, for_each_record};
+use crate::Result;
+
+#[derive(Debug)]
+pub struct DanglingReference {
+	/// key of the entry containing the reference
+	pub key: String,
+	/// the reference as written in the definition
+	pub reference: String,
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Report `src`, `href` and `sound://` references that exist neither in
+	/// the mdd files nor beside the mdx file or in its archive
+	pub fn lint_resources(&mut self) -> Result<Vec<DanglingReference>>
+	{
+		let encoding = self.mdx.encoding;
+		let resources = &self.resources;
+		let key_maker = &self.key_maker;
+		let dir = &self.dir;
+		#[cfg(feature = "zip")]
+		let archive = &self.archive;
+		let mut dangling = vec![];
+		for_each_record(&mut self.mdx, |entry, data| {
+			let definition = decode_slice_string(data, encoding)?.0;
+			for reference in references(&definition) {
+				if let Some(path) = resource_path(&reference) {
+					let key = key_maker.make(&Cow::Borrowed(&path), true);
+					let found = resources.iter().any(|mdx| find_entry(mdx, &key).is_some())
+						|| dir.join(path.trim_start_matches('\\').replace('\\', "/")).is_file();
+					#[cfg(feature = "zip")]
+					let found = found || archive.as_ref().is_some_and(|archive| archive.contains(&path));
+					if !found {
+						dangling.push(DanglingReference {
+							key: entry.text.clone(),
+							reference,
+						});
+					}
+				}
+			}
+			Ok(())
+		})?;
+		Ok(dangling)
+	}
+
+	/// Report `entry://` links and `@@@LINK=` redirects whose target is not
+	/// a key of the dictionary
+	pub fn lint_links(&mut self) -> Result<Vec<DanglingReference>>
+	{
+		let encoding = self.mdx.encoding;
+		let key_maker = &self.key_maker;
+		let mut targets = vec![];
+		for_each_record(&mut self.mdx, |entry, data| {
+			let definition = decode_slice_string(data, encoding)?.0;
+			for target in link_targets(&definition) {
+				let key = key_maker.make(&Cow::Borrowed(&target), false);
+				targets.push((entry.text.clone(), target, key));
+			}
+			Ok(())
+		})?;
+		let dangling = targets.into_iter()
+			.filter(|(_, _, key)| find_entry(&self.mdx, key).is_none())
+			.map(|(key, reference, _)| DanglingReference { key, reference })
+			.collect();
+		Ok(dangling)
+	}
+}
+
+/// Headwords referenced by `entry://` links or a `@@@LINK=` redirect
+pub(crate) fn link_targets(definition: &str) -> Vec<String>
+{
+	if let Some(target) = definition.strip_prefix("@@@LINK=") {
+		return vec![target.trim_end_matches(['\r', '\n', '\0']).to_owned()];
+	}
+	references(definition)
+		.into_iter()
+		.filter_map(|reference| {
+			let target = reference.strip_prefix("entry://")?;
+			let target = target.split('#').next()?;
+			if target.is_empty() {
+				None
+			} else {
+				Some(target.to_owned())
+			}
+		})
+		.collect()
+}
+
+/// Values of all `src` and `href` attributes
+pub(crate) fn references(html: &str) -> Vec<String>
+{
+	let mut references = vec![];
+	for token in Tokenizer::new(html) {
+		if let Token::Tag(tag) = token {
+			for (name, value) in tag.attrs() {
+				if let Some(value) = value {
+					if name.eq_ignore_ascii_case("src") || name.eq_ignore_ascii_case("href") {
+						references.push(value.to_owned());
+					}
+				}
+			}
+		}
+	}
+	references
+}
+
+/// Map a reference to the `\path\file` form used as mdd key, `None` for
+/// references that don't point into the resources
+pub(crate) fn resource_path(reference: &str) -> Option<String>
+{
+	let path = if let Some(path) = reference.strip_prefix("sound://") {
+		path
+	} else if let Some(path) = reference.strip_prefix("file://") {
+		path
+	} else if reference.contains("://") || reference.starts_with('#')
+		|| reference.starts_with("data:") || reference.starts_with("javascript:")
+		|| reference.starts_with("mailto:") {
+		return None;
+	} else {
+		reference
+	};
+	let path = path.split(['#', '?']).next()?;
+	if path.is_empty() {
+		return None;
+	}
+	let path = path.replace('/', "\\");
+	if path.starts_with('\\') {
+		Some(path)
+	} else {
+		Some(format!("\\{}", path))
+	}
+}
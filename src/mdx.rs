@@ -1,13 +1,105 @@
 use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use encoding_rs::{Encoding, UTF_16LE};
-use crate::parser::{decode_slice_string, load, lookup_record};
+#[cfg(feature = "zip")]
+use crate::archive::Archive;
+use crate::collation::{natural_cmp, Comparator, KeyOrder};
+use crate::companion::{Companion, CompanionKind};
+use crate::format::{BlockEntryInfo, Decoding, KeyEntry, Salsa20Variant, DECOMPRESS_CHUNK};
+use crate::index::IndexMode;
+use crate::normalize::fold_case;
+use crate::parser::{load, lookup_record, lookup_shared, Scratch};
+use crate::phonetic::PhoneticIndex;
+use crate::pinyin::PinyinIndex;
+use crate::record::{Record, RecordKind, SharedRecord};
+use crate::transform::Transformer;
+use crate::trigram::TrigramIndex;
+use crate::warning::Warning;
 use crate::{Error, Result};
 
-pub type Reader = BufReader<File>;
+// a dictionary file and its path on disk, none for archive members
+type Opened = Option<(Reader, Option<PathBuf>)>;
+
+pub(crate) enum Reader {
+	File(BufReader<File>),
+	Memory(Cursor<Vec<u8>>),
+	#[cfg(all(feature = "io-uring", target_os = "linux"))]
+	Uring(Box<crate::uring::UringFile>),
+	/// `len` bytes from `start` of the file, e.g. a stored archive member
+	#[cfg(feature = "zip")]
+	Slice {
+		reader: BufReader<File>,
+		start: u64,
+		len: u64,
+		pos: u64,
+	},
+}
+
+impl Reader {
+	#[inline]
+	pub(crate) fn open(path: &Path) -> Result<Self>
+	{
+		Self::with_capacity(BufferSizes::default().read, path)
+	}
+
+	#[inline]
+	pub(crate) fn with_capacity(capacity: usize, path: &Path) -> Result<Self>
+	{
+		Ok(Reader::File(BufReader::with_capacity(capacity, File::open(path)?)))
+	}
+}
+
+impl Read for Reader {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+	{
+		match self {
+			Reader::File(reader) => reader.read(buf),
+			Reader::Memory(cursor) => cursor.read(buf),
+			#[cfg(all(feature = "io-uring", target_os = "linux"))]
+			Reader::Uring(file) => file.read(buf),
+			#[cfg(feature = "zip")]
+			Reader::Slice { reader, len, pos, .. } => {
+				let left = len.saturating_sub(*pos);
+				let max = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+				let read = reader.read(&mut buf[..max])?;
+				*pos += read as u64;
+				Ok(read)
+			}
+		}
+	}
+}
+
+impl Seek for Reader {
+	fn seek(&mut self, from: SeekFrom) -> std::io::Result<u64>
+	{
+		match self {
+			Reader::File(reader) => reader.seek(from),
+			Reader::Memory(cursor) => cursor.seek(from),
+			#[cfg(all(feature = "io-uring", target_os = "linux"))]
+			Reader::Uring(file) => file.seek(from),
+			#[cfg(feature = "zip")]
+			Reader::Slice { reader, start, len, pos } => {
+				let target = match from {
+					SeekFrom::Start(offset) => Some(offset),
+					SeekFrom::End(delta) => len.checked_add_signed(delta),
+					SeekFrom::Current(delta) => pos.checked_add_signed(delta),
+				};
+				let target = target.ok_or_else(|| std::io::Error::new(
+					std::io::ErrorKind::InvalidInput, "invalid seek to a negative position"))?;
+				reader.seek(SeekFrom::Start(*start + target))?;
+				*pos = target;
+				Ok(target)
+			}
+		}
+	}
+}
 
 pub trait KeyMaker {
 	fn make(&self, key: &Cow<str>, resource: bool) -> String;
@@ -25,36 +117,121 @@ pub struct MDict<M: KeyMaker> {
 	pub(crate) mdx: Mdx,
 	pub(crate) resources: Vec<Mdx>,
 	pub(crate) key_maker: M,
+	pub(crate) transformer: Option<Transformer>,
+	pub(crate) dir: PathBuf,
+	#[cfg(feature = "zip")]
+	pub(crate) archive: Option<Archive>,
+	pub(crate) companions: Vec<Companion>,
+	pub(crate) pinyin: Option<PinyinIndex>,
+	pub(crate) trigrams: OnceLock<TrigramIndex>,
+	pub(crate) phonetic: OnceLock<PhoneticIndex>,
+	// see `identity()`
+	pub(crate) identity: OnceLock<u64>,
+	// alias key to headword key, see `set_aliases()`
+	pub(crate) aliases: HashMap<String, String>,
 }
 
 pub struct Mdx {
 	pub(crate) encoding: &'static Encoding,
 	pub(crate) title: String,
+	// adler32 of the header text
+	pub(crate) header_checksum: u32,
 	#[allow(unused)]
 	pub(crate) encrypted: u8,
 	pub(crate) key_entries: Vec<KeyEntry>,
+	pub(crate) key_order: KeyOrder,
+	// distinct record offsets in ascending order, to find where records end
+	pub(crate) record_offsets: Vec<usize>,
 	pub(crate) records_info: Vec<BlockEntryInfo>,
 	pub(crate) reader: Reader,
+	// the file on disk, none for archive members
+	pub(crate) source: Option<PathBuf>,
 	pub(crate) record_block_offset: u64,
-	pub(crate) record_cache: Option<HashMap<usize, Vec<u8>>>,
+	pub(crate) record_cache: Option<HashMap<u64, Arc<Vec<u8>>>>,
+	// lookups of each block while caching, by the same offset, so the
+	// hottest are saved
+	pub(crate) block_hits: HashMap<u64, u64>,
+	pub(crate) record_limit: Option<RecordLimit>,
+	pub(crate) resource: bool,
+	// pinned once detected
+	pub(crate) salsa20: Salsa20Variant,
+	pub(crate) scratch: Scratch,
+	pub(crate) warnings: Vec<Warning>,
+	pub(crate) timings: LoadTimings,
+	// key indices by record offset, built by the first `read_record_block()`
+	pub(crate) offset_order: Option<Vec<usize>>,
 }
 
-#[derive(Debug)]
-pub(crate) struct KeyEntry {
-	pub(crate) offset: usize,
-	pub(crate) text: String,
+/// Time spent in each step of loading a file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadTimings {
+	pub header: Duration,
+	/// key block header and key block index
+	pub key_info: Duration,
+	/// decoding the key blocks, or the keys of a cached index
+	pub key_blocks: Duration,
+	pub sort: Duration,
+	/// record block index and record offsets
+	pub record_index: Duration,
 }
 
-#[derive(Debug)]
-pub(crate) struct BlockEntryInfo {
-	pub(crate) compressed_size: usize,
-	pub(crate) decompressed_size: usize,
+impl LoadTimings {
+	#[inline]
+	pub fn total(&self) -> Duration
+	{
+		self.header + self.key_info + self.key_blocks + self.sort + self.record_index
+	}
+}
+
+pub(crate) struct LoadOptions {
+	pub(crate) cache: bool,
+	pub(crate) record_limit: Option<RecordLimit>,
+	pub(crate) key_order: KeyOrder,
+	pub(crate) salsa20: Salsa20Variant,
+	pub(crate) index: IndexMode,
+	pub(crate) buffers: BufferSizes,
+	// keys are in the order of `key_order` as stored
+	pub(crate) trust_sorted: bool,
+	// decodes records instead of the header encoding
+	pub(crate) record_encoding: Option<&'static Encoding>,
+	pub(crate) key_decoding: Decoding,
+}
+
+/// Sizes of the buffers used while reading a file
+#[derive(Debug, Clone, Copy)]
+pub struct BufferSizes {
+	/// capacity of the buffered file reader
+	pub read: usize,
+	/// bytes decompressed from a zlib block per read
+	pub decompress: usize,
+	/// largest single read of adjacent record blocks when visiting many
+	/// records, e.g. exporting
+	pub coalesce: usize,
+}
+
+impl Default for BufferSizes {
+	#[inline]
+	fn default() -> Self
+	{
+		BufferSizes { read: 8 * 1024, decompress: DECOMPRESS_CHUNK, coalesce: 1024 * 1024 }
+	}
+}
+
+/// What to do with records larger than the given size in bytes
+#[derive(Debug, Clone, Copy)]
+pub enum RecordLimit {
+	/// fail with `Error::RecordTooLarge`
+	Error(usize),
+	/// return only the leading bytes of the record
+	Truncate(usize),
 }
 
 #[derive(Debug)]
 pub(crate) struct RecordOffset {
-	pub(crate) buf_offset: usize,
+	pub(crate) index: usize,
+	pub(crate) buf_offset: u64,
 	pub(crate) block_offset: usize,
+	pub(crate) record_end: usize,
 	pub(crate) record_size: usize,
 	pub(crate) decomp_size: usize,
 }
@@ -65,40 +242,182 @@ pub struct WordDefinition<'a> {
 	pub definition: String,
 }
 
+impl WordDefinition<'_> {
+	#[inline]
+	pub fn kind(&self) -> RecordKind
+	{
+		RecordKind::of_text(&self.definition)
+	}
+}
+
 impl<M: KeyMaker> MDict<M> {
 	pub fn lookup<'a>(&mut self, word: &'a str) -> Result<Option<WordDefinition<'a>>>
 	{
-		let encoding = self.mdx.encoding;
-		let key = self.key_maker.make(&Cow::Borrowed(word), false);
-		if let Some(slice) = lookup_record(&mut self.mdx, &key)? {
-			let definition = decode_slice_string(&slice, encoding)?.0.to_string();
+		let key = self.resolve_key(word);
+		if let Some(record) = lookup_record(&mut self.mdx, &key)? {
+			let definition = record.text()?;
+			let definition = match &self.transformer {
+				Some(transformer) => transformer.transform(&definition),
+				None => definition.into_owned(),
+			};
 			Ok(Some(WordDefinition { key: word, definition }))
 		} else {
 			Ok(None)
 		}
 	}
 
-	pub fn get_resource(&mut self, path: &str) -> Result<Option<Cow<[u8]>>>
+	/// `lookup()` decoding the record with `encoding` instead of the
+	/// dictionary one
+	pub fn lookup_as<'a>(&mut self, word: &'a str, encoding: &'static Encoding)
+		-> Result<Option<WordDefinition<'a>>>
+	{
+		let Some(record) = self.lookup_record(word)? else {
+			return Ok(None);
+		};
+		let definition = record.with_encoding(encoding).text()?.into_owned();
+		let definition = match &self.transformer {
+			Some(transformer) => transformer.transform(&definition),
+			None => definition,
+		};
+		Ok(Some(WordDefinition { key: word, definition }))
+	}
+
+	/// The record text decoded with the header encoding, without the
+	/// transformer
+	pub fn lookup_text(&mut self, word: &str, decoding: Decoding) -> Result<Option<String>>
+	{
+		match self.lookup_record(word)? {
+			Some(record) => Ok(Some(record.text_with(decoding)?.into_owned())),
+			None => Ok(None),
+		}
+	}
+
+	#[inline]
+	pub fn lookup_record(&mut self, word: &str) -> Result<Option<Record<'_>>>
+	{
+		let key = self.resolve_key(word);
+		lookup_record(&mut self.mdx, &key)
+	}
+
+	/// Like `lookup_record`, sharing the decoded block instead of
+	/// borrowing the dictionary
+	#[inline]
+	pub fn lookup_shared(&mut self, word: &str) -> Result<Option<SharedRecord>>
+	{
+		let key = self.resolve_key(word);
+		lookup_shared(&mut self.mdx, &key)
+	}
+
+	#[inline]
+	pub fn get_resource(&mut self, path: &str) -> Result<Option<Cow<'_, [u8]>>>
+	{
+		Ok(self.resource_record(path)?.map(Record::into_data))
+	}
+
+	pub fn resource_record(&mut self, path: &str) -> Result<Option<Record<'_>>>
+	{
+		let key = self.key_maker.make(&Cow::Borrowed(path), true);
+		for mdx in &mut self.resources {
+			if let Some(record) = lookup_record(mdx, &key)? {
+				return Ok(Some(record));
+			}
+		}
+		Ok(None)
+	}
+
+	pub fn resource_shared(&mut self, path: &str) -> Result<Option<SharedRecord>>
 	{
 		let key = self.key_maker.make(&Cow::Borrowed(path), true);
 		for mdx in &mut self.resources {
-			if let Some(slice) = lookup_record(mdx, &key)? {
-				return Ok(Some(slice));
+			if let Some(record) = lookup_shared(mdx, &key)? {
+				return Ok(Some(record));
 			}
 		}
 		Ok(None)
 	}
 
+	/// The definition record as `Bytes` sharing the decoded block
+	#[cfg(feature = "bytes")]
+	#[inline]
+	pub fn lookup_bytes(&mut self, word: &str) -> Result<Option<bytes::Bytes>>
+	{
+		Ok(self.lookup_shared(word)?.map(SharedRecord::into_bytes))
+	}
+
+	/// The resource as `Bytes` sharing the decoded block
+	#[cfg(feature = "bytes")]
+	#[inline]
+	pub fn resource_bytes(&mut self, path: &str) -> Result<Option<bytes::Bytes>>
+	{
+		Ok(self.resource_shared(path)?.map(SharedRecord::into_bytes))
+	}
+
 	pub fn title(&self) -> &str
 	{
 		&self.mdx.title
 	}
+
+	/// Oddities skipped while loading the dictionary and its mdd files
+	pub fn warnings(&self) -> impl Iterator<Item = &Warning>
+	{
+		self.mdx.warnings.iter()
+			.chain(self.resources.iter().flat_map(|mdx| &mdx.warnings))
+	}
+
+	/// Block, offset in the decoded block and text with replacement
+	/// characters of the keys with invalid bytes, in the mdx file then in
+	/// the mdd files
+	pub fn invalid_keys(&self) -> impl Iterator<Item = (usize, usize, &str)>
+	{
+		self.warnings().filter_map(|warning| match warning {
+			Warning::InvalidKey { block, offset, key } => Some((*block, *offset, key.as_str())),
+			_ => None,
+		})
+	}
+
+	/// Where the time went while loading the mdx file
+	#[inline]
+	pub fn load_timings(&self) -> &LoadTimings
+	{
+		&self.mdx.timings
+	}
+}
+
+/// A loaded dictionary and what was skipped or assumed while loading it
+pub struct LoadReport<M: KeyMaker> {
+	pub mdx: MDict<M>,
+	pub warnings: Vec<Warning>,
+	pub timings: LoadTimings,
+}
+
+impl<M: KeyMaker> LoadReport<M> {
+	pub fn new(mdx: MDict<M>) -> Self
+	{
+		let warnings = mdx.warnings().cloned().collect();
+		let timings = *mdx.load_timings();
+		LoadReport { mdx, warnings, timings }
+	}
 }
 
 pub struct MDictBuilder {
 	path: PathBuf,
+	#[cfg(feature = "zip")]
+	archive: bool,
 	cache_definition: bool,
 	cache_resource: bool,
+	record_limit: Option<RecordLimit>,
+	salsa20: Salsa20Variant,
+	index: IndexMode,
+	buffers: BufferSizes,
+	trust_sorted: bool,
+	comparator: Option<Comparator>,
+	record_encoding: Option<&'static Encoding>,
+	key_decoding: Decoding,
+	#[cfg(feature = "icu")]
+	collation: Option<String>,
+	transformer: Option<Transformer>,
+	#[cfg(all(feature = "io-uring", target_os = "linux"))]
+	io_uring: bool,
 }
 
 impl MDictBuilder {
@@ -106,11 +425,37 @@ impl MDictBuilder {
 	{
 		MDictBuilder {
 			path: path.into(),
+			#[cfg(feature = "zip")]
+			archive: false,
 			cache_definition: false,
 			cache_resource: false,
+			record_limit: None,
+			salsa20: Salsa20Variant::Detect,
+			index: IndexMode::Off,
+			buffers: BufferSizes::default(),
+			trust_sorted: false,
+			comparator: None,
+			record_encoding: None,
+			key_decoding: Decoding::Lossy,
+			#[cfg(feature = "icu")]
+			collation: None,
+			transformer: None,
+			#[cfg(all(feature = "io-uring", target_os = "linux"))]
+			io_uring: false,
 		}
 	}
 
+	/// Read the dictionary and its mdd files from the zip archive at
+	/// `path` without extracting it
+	#[cfg(feature = "zip")]
+	#[inline]
+	pub fn archive(path: impl Into<PathBuf>) -> Self
+	{
+		let mut builder = Self::new(path);
+		builder.archive = true;
+		builder
+	}
+
 	#[inline]
 	pub fn cache_definition(mut self, cache: bool) -> Self
 	{
@@ -124,76 +469,267 @@ impl MDictBuilder {
 		self
 	}
 	#[inline]
+	pub fn max_record_size(mut self, limit: RecordLimit) -> Self
+	{
+		self.record_limit = Some(limit);
+		self
+	}
+	/// Keep the keys of each file in a `.idx` file next to it, so later
+	/// loads skip decoding the key blocks
+	#[inline]
+	pub fn index_cache(mut self, enabled: bool) -> Self
+	{
+		self.index = if enabled { IndexMode::Use } else { IndexMode::Off };
+		self
+	}
+	/// Write the `.idx` files again instead of reading them
+	#[inline]
+	pub fn rebuild_index(mut self) -> Self
+	{
+		self.index = IndexMode::Rebuild;
+		self
+	}
+	/// Larger buffers suit spinning disks, smaller ones many dictionaries
+	/// open on a small device
+	#[inline]
+	pub fn buffer_sizes(mut self, buffers: BufferSizes) -> Self
+	{
+		self.buffers = buffers;
+		self
+	}
+	/// Skip sorting the keys after loading, for files known to store them
+	/// in the order lookups use. Lookups miss keys when they are not
+	#[inline]
+	pub fn trust_sorted(mut self, trust: bool) -> Self
+	{
+		self.trust_sorted = trust;
+		self
+	}
+	/// Order keys by `compare` instead of byte-wise, both when sorting
+	/// them and when searching them, so the two always agree. It must be
+	/// consistent with the `KeyMaker`, keys equal after it compare equal
+	#[inline]
+	pub fn comparator(mut self,
+		compare: impl Fn(&str, &str) -> Ordering + Send + Sync + 'static) -> Self
+	{
+		self.comparator = Some(Arc::new(compare));
+		self
+	}
+	/// Order keys byte-wise but numbers in them by value, so `2` comes
+	/// before `10`, for model numbers and chemical names
+	#[inline]
+	pub fn natural_order(mut self) -> Self
+	{
+		self.comparator = Some(Arc::new(natural_cmp));
+		self
+	}
+	/// Decode mdx records with `encoding` while keys keep the one the
+	/// header names, for files declaring the wrong one for their records
+	#[inline]
+	pub fn record_encoding(mut self, encoding: &'static Encoding) -> Self
+	{
+		self.record_encoding = Some(encoding);
+		self
+	}
+	/// How keys with bytes invalid in their encoding are read: with
+	/// replacement characters and a [`Warning::InvalidKey`] each, or
+	/// failing the load with [`Error::InvalidKey`]. Keys read from the
+	/// index file are not checked, `Strict` always reads the key blocks
+	#[inline]
+	pub fn key_decoding(mut self, decoding: Decoding) -> Self
+	{
+		self.key_decoding = decoding;
+		self
+	}
+	/// Salsa20 parameters of encrypted blocks, detected by default
+	#[inline]
+	pub fn salsa20(mut self, variant: Salsa20Variant) -> Self
+	{
+		self.salsa20 = variant;
+		self
+	}
+	/// Order keys by the collation of `locale` instead of byte-wise
+	#[cfg(feature = "icu")]
+	#[inline]
+	pub fn collation(mut self, locale: impl Into<String>) -> Self
+	{
+		self.collation = Some(locale.into());
+		self
+	}
+	#[inline]
+	pub fn transformer(mut self, transformer: Transformer) -> Self
+	{
+		self.transformer = Some(transformer);
+		self
+	}
+	/// Read record blocks through io_uring instead of read syscalls, for
+	/// servers doing many lookups. Keys are still loaded the usual way,
+	/// files in archives are read as before. Fails to build when the
+	/// kernel doesn't allow io_uring
+	#[cfg(all(feature = "io-uring", target_os = "linux"))]
+	#[inline]
+	pub fn io_uring(mut self, io_uring: bool) -> Self
+	{
+		self.io_uring = io_uring;
+		self
+	}
+	#[inline]
 	pub fn build(self) -> Result<MDict<impl KeyMaker>>
 	{
-		self.build_with_key_maker(|key: &Cow<str>, _resource: bool| key.to_ascii_lowercase())
+		self.build_with_key_maker(|key: &Cow<str>, _resource: bool| fold_case(key))
+	}
+	/// Build the dictionary along with the warnings of its files
+	#[inline]
+	pub fn build_with_report(self) -> Result<LoadReport<impl KeyMaker>>
+	{
+		Ok(LoadReport::new(self.build()?))
 	}
 	pub fn build_with_key_maker<M: KeyMaker>(self, key_maker: M)
 		-> Result<MDict<M>>
 	{
 		let path = self.path;
-		let f = File::open(&path)?;
-		let reader = BufReader::new(f);
 		let cwd = path.parent()
 			.ok_or_else(|| Error::InvalidPath(path.clone()))?
 			.canonicalize()?;
-		let mdx = load(
+		#[cfg(feature = "icu")]
+		let key_order = match (self.comparator, &self.collation) {
+			(Some(compare), _) => KeyOrder::Custom(compare),
+			(None, Some(locale)) => KeyOrder::icu(locale)?,
+			(None, None) => KeyOrder::Bytes,
+		};
+		#[cfg(not(feature = "icu"))]
+		let key_order = match self.comparator {
+			Some(compare) => KeyOrder::Custom(compare),
+			None => KeyOrder::Bytes,
+		};
+		let mut options = LoadOptions {
+			cache: self.cache_definition,
+			record_limit: self.record_limit,
+			key_order,
+			salsa20: self.salsa20,
+			index: self.index,
+			buffers: self.buffers,
+			trust_sorted: self.trust_sorted,
+			record_encoding: self.record_encoding,
+			key_decoding: self.key_decoding,
+		};
+		#[cfg(feature = "zip")]
+		let mut archive = if self.archive {
+			Some(Archive::open(&path)?)
+		} else {
+			None
+		};
+		#[cfg(feature = "zip")]
+		let mdx_name = match &archive {
+			Some(archive) => Some(archive.mdx_name().ok_or_else(|| Error::NoMdx(path.clone()))?),
+			None => None,
+		};
+		#[cfg(not(feature = "zip"))]
+		let mdx_name: Option<String> = None;
+		let buffers = self.buffers;
+		let mut open = |name: &str| -> Result<Opened> {
+			#[cfg(feature = "zip")]
+			if let Some(archive) = &mut archive {
+				return Ok(archive.reader(name, buffers.read)?.map(|reader| (reader, None)));
+			}
+			let path = cwd.join(name);
+			if path.exists() {
+				Ok(Some((Reader::with_capacity(buffers.read, &path)?, Some(path))))
+			} else {
+				Ok(None)
+			}
+		};
+		let filename = match mdx_name {
+			Some(name) => name,
+			None => path.file_name()
+				.ok_or_else(|| Error::InvalidPath(path.clone()))?
+				.to_str()
+				.ok_or_else(|| Error::InvalidPath(path.clone()))?
+				.to_owned(),
+		};
+		let (reader, source) = open(&filename)?.ok_or_else(|| Error::NoMdx(path.clone()))?;
+		#[allow(unused_mut)]
+		let mut mdx = load(
 			reader,
+			source.as_deref(),
 			UTF_16LE,
-			self.cache_definition,
+			&options,
 			&key_maker,
 			false)?;
-		let filename = path.file_stem()
-			.ok_or_else(|| Error::InvalidPath(path.clone()))?
-			.to_str()
+		let stem = Path::new(&filename).file_stem()
+			.and_then(|stem| stem.to_str())
 			.ok_or_else(|| Error::InvalidPath(path.clone()))?;
-		let resources = load_resources(
-			&cwd,
-			filename,
-			self.cache_resource,
-			&key_maker)?;
+		options.cache = self.cache_resource;
+		options.record_encoding = None;
+		#[allow(unused_mut)]
+		let mut resources = load_resources(
+			stem,
+			&options,
+			&key_maker,
+			&mut open)?;
+		#[cfg(all(feature = "io-uring", target_os = "linux"))]
+		if self.io_uring {
+			for mdx in std::iter::once(&mut mdx).chain(&mut resources) {
+				crate::uring::use_uring(mdx)?;
+			}
+		}
+		let exists = |name: &str| {
+			#[cfg(feature = "zip")]
+			if let Some(archive) = &archive {
+				return archive.contains(name);
+			}
+			cwd.join(name).is_file()
+		};
+		let mut companions: Vec<Companion> = (0..resources.len())
+			.map(|i| Companion { kind: CompanionKind::Mdd, name: mdd_name(stem, i) })
+			.collect();
+		for (kind, extension) in CompanionKind::SIBLINGS {
+			let name = format!("{}.{}", stem, extension);
+			if exists(&name) {
+				companions.push(Companion { kind, name });
+			}
+		}
 		Ok(MDict {
 			mdx,
 			resources,
 			key_maker,
+			transformer: self.transformer,
+			dir: cwd,
+			#[cfg(feature = "zip")]
+			archive,
+			companions,
+			pinyin: None,
+			trigrams: OnceLock::new(),
+			phonetic: OnceLock::new(),
+			identity: OnceLock::new(),
+			aliases: HashMap::new(),
 		})
 	}
 }
 
-fn load_resources(cwd: &PathBuf, name: &str, cache_resources: bool,
-	key_maker: &dyn KeyMaker) -> Result<Vec<Mdx>>
+/// `<name>.mdd` then `<name>.<n>.mdd` from 1
+#[inline]
+fn mdd_name(name: &str, n: usize) -> String
+{
+	if n == 0 {
+		format!("{}.mdd", name)
+	} else {
+		format!("{}.{}.mdd", name, n)
+	}
+}
+
+fn load_resources(name: &str, options: &LoadOptions, key_maker: &dyn KeyMaker,
+	open: &mut dyn FnMut(&str) -> Result<Opened>) -> Result<Vec<Mdx>>
 {
 	let mut resources = vec![];
-	// <filename>.mdd first
-	let path = cwd.join(format!("{}.mdd", name));
-	if !path.exists() {
-		return Ok(resources);
-	}
-	let f = File::open(&path)?;
-	let reader = BufReader::new(f);
-	resources.push(load(
-		reader,
-		UTF_16LE,
-		cache_resources,
-		key_maker,
-		true)?);
-
-	// filename.n.mdd then
-	let mut i = 1;
-	loop {
-		let path = cwd.join(format!("{}.{}.mdd", name, i));
-		if !path.exists() {
-			break;
-		}
-		let f = File::open(&path)?;
-		let reader = BufReader::new(f);
+	while let Some((reader, source)) = open(&mdd_name(name, resources.len()))? {
 		resources.push(load(
 			reader,
+			source.as_deref(),
 			UTF_16LE,
-			cache_resources,
+			options,
 			key_maker,
 			true)?);
-		i += 1;
 	}
 	Ok(resources)
 }
@@ -0,0 +1,176 @@
+//! Reusable key normalizers, chained into a `KeyMaker` by `Pipeline`
+
+use std::borrow::Cow;
+
+use crate::html::decode_entities;
+use crate::mdx::KeyMaker;
+
+pub trait Normalizer: Send + Sync {
+	fn normalize<'a>(&self, text: Cow<'a, str>) -> Cow<'a, str>;
+}
+
+/// Normalizers applied in order, used as `KeyMaker` for both words and
+/// resource paths
+#[derive(Default)]
+pub struct Pipeline {
+	steps: Vec<Box<dyn Normalizer>>,
+}
+
+impl Pipeline {
+	#[inline]
+	pub fn new() -> Self
+	{
+		Pipeline { steps: vec![] }
+	}
+
+	#[inline]
+	pub fn then(mut self, normalizer: impl Normalizer + 'static) -> Self
+	{
+		self.steps.push(Box::new(normalizer));
+		self
+	}
+
+	pub fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str>
+	{
+		let mut text = Cow::Borrowed(text);
+		for step in &self.steps {
+			text = step.normalize(text);
+		}
+		text
+	}
+}
+
+impl KeyMaker for Pipeline {
+	#[inline]
+	fn make(&self, key: &Cow<str>, _resource: bool) -> String
+	{
+		self.normalize(key).into_owned()
+	}
+}
+
+/// Full unicode case folding, e.g. `ß` => `ss`, `ς` => `σ`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CaseFold {
+	turkic: bool,
+}
+
+impl CaseFold {
+	#[inline]
+	pub fn new() -> Self
+	{
+		CaseFold { turkic: false }
+	}
+
+	/// Fold `I` to `ı` and `İ` to `i` as in Turkish and Azerbaijani
+	#[inline]
+	pub fn turkic() -> Self
+	{
+		CaseFold { turkic: true }
+	}
+}
+
+impl Normalizer for CaseFold {
+	fn normalize<'a>(&self, text: Cow<'a, str>) -> Cow<'a, str>
+	{
+		if self.turkic {
+			Cow::Owned(fold_case_turkic(&text))
+		} else if text.chars().all(|ch| ch.is_ascii() && !ch.is_ascii_uppercase()) {
+			text
+		} else {
+			Cow::Owned(fold_case(&text))
+		}
+	}
+}
+
+/// Drops a leading article, e.g. `The Beatles` => `Beatles`, so titles
+/// are found with or without it. A text that is only an article is kept
+#[derive(Debug, Clone, Default)]
+pub struct Articles {
+	articles: Vec<String>,
+}
+
+impl Articles {
+	/// Strip the given articles, compared case-insensitively. One ending
+	/// in an apostrophe, e.g. `l'`, needs no space after it
+	pub fn new<S: AsRef<str>>(articles: impl IntoIterator<Item = S>) -> Self
+	{
+		let articles = articles.into_iter()
+			.map(|article| article.as_ref().to_lowercase())
+			.filter(|article| !article.is_empty())
+			.collect();
+		Articles { articles }
+	}
+
+	/// The articles of a language by its ISO 639-1 code, None when not
+	/// known
+	pub fn language(code: &str) -> Option<Self>
+	{
+		let articles: &[&str] = match code.to_ascii_lowercase().as_str() {
+			"en" => &["the", "a", "an"],
+			"fr" => &["le", "la", "les", "l'", "l’", "un", "une", "des"],
+			"de" => &["der", "die", "das", "den", "dem", "des", "ein", "eine", "einen", "einem",
+				"einer", "eines"],
+			"es" => &["el", "la", "los", "las", "un", "una", "unos", "unas"],
+			"it" => &["il", "lo", "la", "i", "gli", "le", "l'", "l’", "un", "uno", "una", "un'"],
+			"pt" => &["o", "a", "os", "as", "um", "uma", "uns", "umas"],
+			"nl" => &["de", "het", "een", "'t"],
+			_ => return None,
+		};
+		Some(Self::new(articles))
+	}
+}
+
+impl Normalizer for Articles {
+	fn normalize<'a>(&self, text: Cow<'a, str>) -> Cow<'a, str>
+	{
+		let trimmed = text.trim_start();
+		let rest = self.articles.iter().find_map(|article| {
+			let head = trimmed.get(..article.len())?;
+			if head.to_lowercase() != *article {
+				return None;
+			}
+			let rest = &trimmed[article.len()..];
+			(article.ends_with(['\'', '’']) || rest.starts_with(char::is_whitespace))
+				.then(|| rest.trim_start())
+		});
+		match rest {
+			Some(rest) if !rest.is_empty() => Cow::Owned(rest.to_owned()),
+			_ => text,
+		}
+	}
+}
+
+/// Decodes HTML character references, e.g. `AT&amp;T` => `AT&T`, for
+/// files whose converter escaped their keys
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Entities;
+
+impl Normalizer for Entities {
+	fn normalize<'a>(&self, text: Cow<'a, str>) -> Cow<'a, str>
+	{
+		match decode_entities(&text) {
+			Cow::Owned(decoded) => Cow::Owned(decoded),
+			Cow::Borrowed(_) => text,
+		}
+	}
+}
+
+/// Full unicode case folding
+#[inline]
+pub fn fold_case(text: &str) -> String
+{
+	caseless::default_case_fold_str(text)
+}
+
+/// Case folding with the Turkic mappings for dotted and dotless i
+pub fn fold_case_turkic(text: &str) -> String
+{
+	let text: String = text.chars()
+		.map(|ch| match ch {
+			'I' => 'ı',
+			'İ' => 'i',
+			_ => ch,
+		})
+		.collect();
+	fold_case(&text)
+}
@@ -1,72 +1,37 @@
 use std::borrow::Cow;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::io::{BufReader, Read, Seek, SeekFrom};
-use adler32::RollingAdler32;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::{ControlFlow, Range};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use byteorder::{BE, ByteOrder, LE, ReadBytesExt};
-use compress::zlib;
-use encoding_rs::{Encoding, UTF_16LE, UTF_8};
-use regex::Regex;
-use ripemd::{Digest, Ripemd128, Ripemd128Core};
-use salsa20::Salsa20;
-use salsa20::cipher::{KeyIvInit, StreamCipher};
-use salsa20::cipher::crypto_common::Output;
+use encoding_rs::{Encoding, UTF_16LE};
 
 use crate::{Error, mdx::Mdx, Result};
-use crate::mdx::{BlockEntryInfo, KeyEntry, KeyMaker, Reader, RecordOffset};
-
-#[derive(Debug)]
-struct KeyBlockHeader {
-	// block_num: usize,
-	// entry_num: usize,
-	// decompressed_size: usize,
-	block_info_size: usize,
-	key_block_size: usize,
-}
-
-#[derive(Debug)]
-enum Version {
-	V1,
-	V2,
-}
+use crate::format::{block_header, decode_block_into, decompress_verified, inflate, key_entry,
+	parse_header, parse_key_block_header, parse_key_block_infos, parse_key_entries, to_usize,
+	BlockEntryInfo, BlockHeader, Decoding, Header, KeyBlockHeader, KeyEntry, DECOMPRESS_CHUNK,
+	Salsa20Variant, Version};
+use crate::mdx::{BufferSizes, KeyMaker, LoadOptions, LoadTimings, Reader, RecordLimit, RecordOffset};
+use crate::checksum::adler32;
+use crate::index::{Fingerprint, IndexMode, index_path, read as read_index, write as write_index};
+use crate::record::{Record, SharedRecord};
+use crate::warning::Warning;
+use crate::inspect::BlockKind;
+use crate::writer::ChecksumSite;
 
 impl Version {
 	#[inline]
-	fn read_number(&self, reader: &mut Reader) -> Result<usize>
+	fn read_number(&self, reader: &mut Reader) -> Result<u64>
 	{
 		let number = match self {
-			Version::V1 => reader.read_u32::<BE>()? as usize,
-			Version::V2 => reader.read_u64::<BE>()? as usize,
+			Version::V1 => u64::from(reader.read_u32::<BE>()?),
+			Version::V2 => reader.read_u64::<BE>()?,
 		};
 		Ok(number)
 	}
-	#[inline]
-	#[allow(unused)]
-	fn byte_number(&self, data: &[u8]) -> (usize, usize)
-	{
-		match self {
-			Version::V1 => (BE::read_u32(data) as usize, 4),
-			Version::V2 => (BE::read_u64(data) as usize, 8),
-		}
-	}
-}
-
-fn read_keys(s: &str) -> HashMap<String, String>
-{
-	let re = Regex::new(r#"(\w+)="((.|\r\n|[\r\n])*?)""#).unwrap();
-	let mut attrs = HashMap::new();
-	for cap in re.captures_iter(s) {
-		attrs.insert(cap[1].to_string(), cap[2].to_string());
-	}
-	attrs
-}
-
-#[derive(Debug)]
-struct Header {
-	version: Version,
-	encrypted: u8,
-	encoding: &'static Encoding,
-	title: String,
 }
 
 #[inline]
@@ -78,463 +43,957 @@ fn read_buf(reader: &mut impl Read, len: usize) -> Result<Vec<u8>>
 }
 
 #[inline]
-fn check_adler32(data: &[u8], checksum: u32) -> Result<()>
+fn read_into(reader: &mut impl Read, len: usize, buf: &mut Vec<u8>) -> Result<()>
 {
-	if RollingAdler32::from_buffer(data).hash() != checksum {
-		return Err(Error::InvalidCheckSum("header"));
-	}
+	buf.clear();
+	buf.resize(len, 0);
+	reader.read_exact(buf)?;
 	Ok(())
 }
 
-fn read_header(reader: &mut Reader, default_encoding: &'static Encoding) -> Result<Header>
+fn read_header(reader: &mut Reader, default_encoding: &'static Encoding,
+	warnings: &mut Vec<Warning>) -> Result<Header>
 {
 	let bytes = reader.read_u32::<BE>()?;
 	let info_buf = read_buf(reader, bytes as usize)?;
 	let checksum = reader.read_u32::<LE>()?;
-	check_adler32(&info_buf, checksum)?;
-
-	let info = UTF_16LE.decode(&info_buf).0;
-	let attrs = read_keys(&info);
-
-	let version_str = attrs
-		.get("GeneratedByEngineVersion")
-		.ok_or(Error::NoVersion)?
-		.trim();
-	let version = version_str[0..1]
-		.parse::<u8>()
-		.or(Err(Error::InvalidVersion(version_str.to_owned())))?;
-
-
-	let title = attrs
-		.get("Title")
-		.ok_or(Error::NoTitle)?
-		.trim()
-		.to_owned();
-
-	let version = match version {
-		1 => Version::V1,
-		2 => Version::V2,
-		3 |
-		_ => return Err(Error::UnsupportedVersion(version)),
-	};
-
-	let encrypted = attrs
-		.get("Encrypted")
-		.and_then(|x| match x == "Yes" {
-			true => Some(1_u8),
-			false => x.as_str().parse().ok(),
-		})
-		.unwrap_or(0);
-
-	let encoding = if let Some(encoding) = attrs.get("Encoding") {
-		if encoding.is_empty() {
-			default_encoding
-		} else {
-			Encoding::for_label(encoding.as_bytes())
-				.ok_or(Error::InvalidEncoding(encoding.clone()))?
-		}
-	} else {
-		default_encoding
-	};
-	Ok(Header {
-		version,
-		encrypted,
-		encoding,
-		title,
-	})
-}
-
-fn read_key_block_header_v1(reader: &mut Reader) -> Result<KeyBlockHeader>
-{
-	let buf = read_buf(reader, 16)?;
-	// let block_num = BE::read_u32(&buf[0..4]);
-	// let entry_num = BE::read_u32(&buf[4..8]);
-	let block_info_size = BE::read_u32(&buf[8..12]);
-	let key_block_size = BE::read_u32(&buf[12..16]);
-
-	Ok(KeyBlockHeader {
-		// block_num: block_num as usize,
-		// entry_num: entry_num as usize,
-		// decompressed_size: block_info_size as usize,
-		block_info_size: block_info_size as usize,
-		key_block_size: key_block_size as usize,
-	})
+	parse_header(&info_buf, checksum, default_encoding, warnings)
 }
 
-fn read_key_block_header_v2(reader: &mut Reader) -> Result<KeyBlockHeader>
+fn read_key_block_header(reader: &mut Reader, version: &Version) -> Result<KeyBlockHeader>
 {
-	let buf = read_buf(reader, 40)?;
-	let checksum = reader.read_u32::<BE>()?;
-	check_adler32(&buf, checksum)?;
-
-	// let block_num = BE::read_u64(&buf[0..8]);
-	// let entry_num = BE::read_u64(&buf[8..16]);
-	// let decompressed_size = BE::read_u64(&buf[16..24]);
-	let block_info_size = BE::read_u64(&buf[24..32]);
-	let key_block_size = BE::read_u64(&buf[32..40]);
-
-	Ok(KeyBlockHeader {
-		// block_num: block_num as usize,
-		// entry_num: entry_num as usize,
-		// decompressed_size: decompressed_size as usize,
-		block_info_size: block_info_size as usize,
-		key_block_size: key_block_size as usize,
-	})
+	let buf = read_buf(reader, KeyBlockHeader::size(version))?;
+	parse_key_block_header(&buf, version)
 }
 
-fn fast_decrypt(encrypted: &[u8], key: &[u8]) -> Vec<u8>
+fn read_key_block_infos(reader: &mut Reader, size: usize, header: &Header,
+	warnings: &mut Vec<Warning>) -> Result<Vec<BlockEntryInfo>>
 {
-	let mut buf = Vec::from(encrypted);
-	let mut prev = 0x36;
-	for i in 0..buf.len() {
-		let mut t = buf[i] >> 4 | buf[i] << 4;
-		t = t ^ prev ^ (i as u8) ^ key[i % key.len()];
-		prev = buf[i];
-		buf[i] = t;
-	}
-	buf
+	let buf = read_buf(reader, size)?;
+	parse_key_block_infos(buf, header, u64::MAX, warnings)
 }
 
-fn read_key_block_infos(reader: &mut Reader, size: usize, header: &Header) -> Result<Vec<BlockEntryInfo>>
-{
-	let buf = read_buf(reader, size)?;
-	//decrypt
-	let key_block_info = match header.version {
-		Version::V1 => buf,
-		Version::V2 => {
-			if buf[0..4] != [2, 0, 0, 0] {
-				return Err(Error::InvalidData);
-			}
-			let checksum = BE::read_u32(&buf[4..8]);
-			let mut info = vec![];
-			if header.encrypted == 2 {
-				let mut v = Vec::from(&buf[4..8]);
-				let value: u32 = 0x3695;
-				v.extend_from_slice(&value.to_le_bytes());
-				let mut md = Ripemd128::default();
-				md.update(v);
-				let key = md.finalize();
-				let decrypted = fast_decrypt(&buf[8..], key.as_slice());
-				zlib::Decoder::new(BufReader::new(decrypted.as_slice()))
-					.read_to_end(&mut info)?;
-			} else {
-				zlib::Decoder::new(&buf[8..])
-					.read_to_end(&mut info)?;
-			}
-			check_adler32(&info, checksum)?;
-			info
-		}
-	};
-	let key_blocks = decode_key_blocks(&key_block_info, header)?;
-	Ok(key_blocks)
+/// Buffers reused from block to block, so lookup loops don't allocate for
+/// every record
+pub(crate) struct Scratch {
+	// the block as stored in the file
+	raw: Vec<u8>,
+	// the decrypted block
+	plain: Vec<u8>,
+	// the decoded block
+	block: Vec<u8>,
+	// bytes decompressed per read
+	chunk: usize,
+	// largest read of adjacent blocks, see `BufferSizes::coalesce`
+	coalesce: usize,
+	// file position and index of the first and last block read into `raw`
+	// by `decode_run()`
+	run: Option<(u64, usize, usize)>,
 }
 
-fn decode_key_blocks(data: &[u8], header: &Header)
-	-> Result<Vec<BlockEntryInfo>>
-{
+impl Default for Scratch {
 	#[inline]
-	fn read_size(data: &[u8], header: &Header) -> (usize, usize)
+	fn default() -> Self
 	{
-		match header.version {
-			Version::V1 => (BE::read_u32(&data[0..4]) as usize, 4),
-			Version::V2 => (BE::read_u64(&data[0..8]) as usize, 8),
-		}
+		Scratch::new(&BufferSizes::default())
 	}
+}
+
+impl Scratch {
 	#[inline]
-	fn read_num_bytes(data: &[u8], header: &Header) -> (usize, usize)
+	pub(crate) fn new(buffers: &BufferSizes) -> Self
 	{
-		match header.version {
-			Version::V1 => (data[0] as usize, 1),
-			Version::V2 => (BE::read_u16(&data[0..2]) as usize, 2)
+		Scratch {
+			raw: vec![],
+			plain: vec![],
+			block: vec![],
+			chunk: buffers.decompress.max(1),
+			coalesce: buffers.coalesce,
+			run: None,
 		}
 	}
-	#[inline]
-	fn text_bytes(header: &Header, bytes: usize) -> usize
+
+
+	/// Read the block at `position` and decode it into `block`
+	#[allow(clippy::too_many_arguments)]
+	fn decode(&mut self, reader: &mut Reader, position: u64, compressed_size: usize,
+		decompressed_size: usize, limit: Option<usize>, salsa: &mut Salsa20Variant,
+		index: usize) -> Result<()>
 	{
-		let text_size = match header.version {
-			Version::V1 => bytes,
-			Version::V2 => bytes + 1,
-		};
-		if header.encoding == UTF_16LE {
-			text_size * 2
-		} else {
-			text_size
-		}
+		self.run = None;
+		reader.seek(SeekFrom::Start(position))?;
+		read_into(reader, compressed_size, &mut self.raw)?;
+		decode_block_into(&self.raw, compressed_size, decompressed_size, limit, salsa, index,
+			self.chunk, &mut self.plain, &mut self.block)
 	}
-	#[inline]
-	#[allow(unused)]
-	fn extract_text(data: &[u8], header: &Header, bytes: usize) -> (String, usize)
+
+	/// Decode block `blocks[at]` into `block`, reading it along with the
+	/// blocks after it in `blocks`, adjacent in the file, in one read of
+	/// up to `coalesce` bytes unless the last read already holds it
+	fn decode_run(&mut self, reader: &mut Reader, blocks: &[usize], at: usize,
+		spans: &[BlockSpan], salsa: &mut Salsa20Variant) -> Result<()>
 	{
-		let text_size = match header.version {
-			Version::V1 => bytes,
-			Version::V2 => bytes + 1,
-		};
-		let bytes = if header.encoding == UTF_16LE {
-			text_size * 2
-		} else {
-			text_size
+		let index = blocks[at];
+		let (start, _, _) = match self.run {
+			Some(run @ (_, first, last)) if (first..=last).contains(&index) => run,
+			_ => {
+				let first = &spans[index];
+				let mut last = index;
+				let mut len = first.compressed_size;
+				for next in &blocks[at + 1..] {
+					let span = &spans[*next];
+					if *next != last + 1 || len + span.compressed_size > self.coalesce {
+						break;
+					}
+					len += span.compressed_size;
+					last = *next;
+				}
+				self.run = None;
+				reader.seek(SeekFrom::Start(first.position))?;
+				read_into(reader, len, &mut self.raw)?;
+				*self.run.insert((first.position, index, last))
+			}
 		};
-		let text = header.encoding
-			.decode(&data[..text_size])
-			.0
-			.trim_matches(char::from(0))
-			.to_string();
-		(text, bytes)
+		let span = &spans[index];
+		let offset = (span.position - start) as usize;
+		decode_block_into(&self.raw[offset..offset + span.compressed_size], span.compressed_size,
+			span.decompressed_size, None, salsa, index, self.chunk, &mut self.plain, &mut self.block)
 	}
+}
 
-	let mut key_block_info_list = vec![];
-	let mut slice = data;
-	while !slice.is_empty() {
-		let (_num_entries, delta) = read_size(slice, header);
-		slice = &slice[delta..];
-		let (bytes, delta) = read_num_bytes(slice, header);
-		slice = &slice[delta..];
-		let delta = text_bytes(header, bytes);
-		slice = &slice[delta..];
-		let (bytes, delta) = read_num_bytes(slice, header);
-		slice = &slice[delta..];
-		let delta = text_bytes(header, bytes);
-		slice = &slice[delta..];
-		let (compressed_size, delta) = read_size(slice, header);
-		slice = &slice[delta..];
-		let (decompressed_size, delta) = read_size(slice, header);
-		slice = &slice[delta..];
-		key_block_info_list.push(BlockEntryInfo {
-			compressed_size,
-			decompressed_size,
-		});
-	}
-	Ok(key_block_info_list)
+/// Where a record block is in the file and in the decoded records
+struct BlockSpan {
+	position: u64,
+	compressed_size: usize,
+	decompressed_size: usize,
+	// offset of its first record
+	start: usize,
 }
 
-fn decode_block(slice: &[u8], compressed_size: usize, decompressed_size: usize) -> Result<Vec<u8>>
+/// The spans of the record blocks in file order
+fn block_spans(records_info: &[BlockEntryInfo], record_block_offset: u64) -> Vec<BlockSpan>
 {
-	#[inline]
-	fn make_key(data: &[u8]) -> Output<Ripemd128Core>
-	{
-		let mut md = Ripemd128::default();
-		md.update(&data[4..8]);
-		md.finalize()
-	}
-
-	let enc = LE::read_u32(&slice[0..4]);
-	let checksum_bytes = &slice[4..8];
-	let checksum = BE::read_u32(checksum_bytes);
-	let encryption_method = (enc >> 4) & 0xf;
-	// let encryption_size = (enc >> 8) & 0xff;
-	let compress_method = enc & 0xf;
-
-	let encrypted = &slice[8..compressed_size];
-	let compressed: Vec<u8> = match encryption_method {
-		0 => Vec::from(encrypted),
-		1 => fast_decrypt(encrypted, make_key(checksum_bytes).as_slice()),
-		2 => {
-			let mut decrypt = Vec::from(encrypted);
-			let mut cipher = Salsa20::new(make_key(checksum_bytes).as_slice().into(), &[0; 8].into());
-			cipher.apply_keystream(&mut decrypt);
-			decrypt
-		}
-		_ => return Err(Error::InvalidEncryptMethod(encryption_method)),
-	};
-
-	let decompressed = match compress_method {
-		0 => compressed,
-		1 => {
-			let mut decompressed = vec![0; decompressed_size];
-			let (result, err) = rust_lzo::LZOContext::decompress_to_slice(&compressed, &mut decompressed);
-			if err != rust_lzo::LZOError::OK {
-				return Err(Error::InvalidData);
-			}
-			Vec::from(result)
-		},
-		2 => {
-			let mut v = vec![];
-			zlib::Decoder::new(&compressed[..]).read_to_end(&mut v)
-				.or(Err(Error::InvalidData))?;
-			v
-		}
-		_ => return Err(Error::InvalidCompressMethod(compress_method)),
-	};
-
-	check_adler32(&decompressed, checksum)?;
-	Ok(decompressed)
+	let (mut position, mut start) = (record_block_offset, 0usize);
+	records_info.iter()
+		.map(|info| {
+			let span = BlockSpan {
+				position,
+				compressed_size: info.compressed_size,
+				decompressed_size: info.decompressed_size,
+				start,
+			};
+			position += info.compressed_size as u64;
+			start = start.saturating_add(info.decompressed_size);
+			span
+		})
+		.collect()
 }
 
+#[allow(clippy::too_many_arguments)]
 fn read_key_entries(reader: &mut Reader, size: usize, header: &Header,
-	entry_infos: Vec<BlockEntryInfo>, key_maker: &dyn KeyMaker, resource: bool)
-	-> Result<Vec<KeyEntry>>
+	entry_infos: Vec<BlockEntryInfo>, key_maker: &dyn KeyMaker,
+	resource: bool, salsa: &mut Salsa20Variant, chunk: usize, decoding: Decoding,
+	warnings: &mut Vec<Warning>) -> Result<Vec<KeyEntry>>
 {
-	let data = read_buf(reader, size)?;
-
+	// one block is held at a time, the region may be hundreds of MB
+	let start = reader.stream_position()?;
+	let mut remaining = size;
 	let mut entries = vec![];
-	let mut slice = data.as_slice();
-	for info in entry_infos {
-		let decompressed = decode_block(
-			slice, info.compressed_size, info.decompressed_size)?;
-		slice = &slice[info.compressed_size..];
-
-		let mut entries_slice = decompressed.as_slice();
-		while !entries_slice.is_empty() {
-			let (offset, delta) = match header.version {
-				Version::V1 => (BE::read_u32(entries_slice) as usize, 4),
-				Version::V2 => (BE::read_u64(entries_slice) as usize, 8),
-			};
-			entries_slice = &entries_slice[delta..];
-			let (text, idx) = decode_slice_string(entries_slice, header.encoding)?;
-			let text = key_maker.make(&text, resource);
-			entries.push(KeyEntry { offset, text });
-			entries_slice = &entries_slice[idx..];
+	let (mut block, mut plain, mut decompressed) = (vec![], vec![], vec![]);
+	for (index, info) in entry_infos.iter().enumerate() {
+		remaining = remaining.checked_sub(info.compressed_size).ok_or(Error::InvalidData)?;
+		if info.decompressed_size == 0 {
+			warnings.push(Warning::EmptyKeyBlock(index));
+			reader.seek(SeekFrom::Current(info.compressed_size as i64))?;
+			continue;
 		}
+		read_into(reader, info.compressed_size, &mut block)?;
+		decode_block_into(&block, info.compressed_size, info.decompressed_size, None, salsa, index,
+			chunk, &mut plain, &mut decompressed)?;
+		parse_key_entries(&decompressed, header, &|raw| key_maker.make(&Cow::Borrowed(raw), resource),
+			index, decoding, &mut entries, warnings)?;
 	}
-	entries.sort_by(|a, b| a.text.cmp(&b.text));
+	reader.seek(SeekFrom::Start(start + size as u64))?;
 
 	Ok(entries)
 }
 
+/// Counts and sizes leading the record block index
+struct RecordsHeader {
+	num_entries: u64,
+	record_info_size: u64,
+	record_data_size: u64,
+}
+
 fn read_record_blocks(reader: &mut Reader, header: &Header)
-	-> Result<Vec<BlockEntryInfo>>
+	-> Result<(RecordsHeader, Vec<BlockEntryInfo>)>
 {
 	let version = &header.version;
 	let num_records = version.read_number(reader)?;
-	let _num_entries = version.read_number(reader)?;
-	let _record_info_size = version.read_number(reader)?;
-	let _record_data_size = version.read_number(reader)?;
+	let num_entries = version.read_number(reader)?;
+	let record_info_size = version.read_number(reader)?;
+	let record_data_size = version.read_number(reader)?;
 	let mut records = vec![];
 	for _i in 0..num_records {
-		let compressed_size = version.read_number(reader)?;
-		let decompressed_size = version.read_number(reader)?;
+		let compressed_size = to_usize(version.read_number(reader)?)?;
+		let decompressed_size = to_usize(version.read_number(reader)?)?;
 		records.push(BlockEntryInfo { compressed_size, decompressed_size })
 	}
-	Ok(records)
+	let records_header = RecordsHeader {
+		num_entries,
+		record_info_size,
+		record_data_size,
+	};
+	Ok((records_header, records))
 }
 
-pub(crate) fn load(mut reader: Reader, default_encoding: &'static Encoding,
-	cache: bool, key_maker: &dyn KeyMaker, resource: bool) -> Result<Mdx>
+/// Load the file read by `reader`, `source` is its path on disk if any,
+/// where the key index is kept
+pub(crate) fn load(mut reader: Reader, source: Option<&Path>, default_encoding: &'static Encoding,
+	options: &LoadOptions, key_maker: &dyn KeyMaker, resource: bool) -> Result<Mdx>
 {
-	let header = read_header(&mut reader, default_encoding)?;
-	let key_block_header = match &header.version {
-		Version::V1 => read_key_block_header_v1(&mut reader)?,
-		Version::V2 => read_key_block_header_v2(&mut reader)?,
+	let mut warnings = vec![];
+	let mut timings = LoadTimings::default();
+	let mut started = Instant::now();
+	let mut lap = |time: &mut Duration| {
+		let now = Instant::now();
+		*time = now - started;
+		started = now;
+	};
+	let header = read_header(&mut reader, default_encoding, &mut warnings)?;
+	lap(&mut timings.header);
+	if resource {
+		// mdd files don't name their encoding, keys are always UTF-16
+		warnings.retain(|warning| !matches!(warning, Warning::EncodingFallback(_)));
+	}
+	let index = match source {
+		Some(source) if options.index != IndexMode::Off =>
+			Some((index_path(source), Fingerprint::of(source, header.checksum)?)),
+		_ => None,
+	};
+	let indexed = match &index {
+		Some((path, fingerprint))
+			if options.index == IndexMode::Use && options.key_decoding == Decoding::Lossy =>
+			read_index(path, fingerprint),
+		_ => None,
 	};
-	let key_block_infos = read_key_block_infos(
-		&mut reader,
-		key_block_header.block_info_size,
-		&header)?;
-
-	let key_entries = read_key_entries(
-		&mut reader,
-		key_block_header.key_block_size,
-		&header,
-		key_block_infos,
-		key_maker,
-		resource)?;
 
-	let records_info = read_record_blocks(
-		&mut reader,
-		&header)?;
+	let mut salsa20 = options.salsa20;
+	let (key_entries, records_info, record_block_offset) = if let Some(indexed) = indexed {
+		let mut key_entries: Vec<KeyEntry> = indexed.keys.into_iter()
+			.map(|(offset, raw)| key_entry(&|raw| key_maker.make(&Cow::Borrowed(raw), resource), offset, &raw))
+			.collect();
+		lap(&mut timings.key_blocks);
+		sort_keys(&mut key_entries, options);
+		lap(&mut timings.sort);
+		(key_entries, indexed.records_info, indexed.record_block_offset)
+	} else {
+		let key_block_header = read_key_block_header(&mut reader, &header.version)?;
+		let key_block_infos = read_key_block_infos(
+			&mut reader,
+			to_usize(key_block_header.block_info_size)?,
+			&header,
+			&mut warnings)?;
+		lap(&mut timings.key_info);
+
+		let mut key_entries = read_key_entries(
+			&mut reader,
+			to_usize(key_block_header.key_block_size)?,
+			&header,
+			key_block_infos,
+			key_maker,
+			resource,
+			&mut salsa20,
+			options.buffers.decompress,
+			options.key_decoding,
+			&mut warnings)?;
+		lap(&mut timings.key_blocks);
+		sort_keys(&mut key_entries, options);
+		lap(&mut timings.sort);
+
+		let (_, records_info) = read_record_blocks(
+			&mut reader,
+			&header)?;
+
+		let record_block_offset = reader.stream_position()?;
+		if let Some((path, fingerprint)) = &index {
+			// without the index the next load is only slower
+			let _ = write_index(path, fingerprint, &key_entries, &records_info, record_block_offset);
+		}
+		(key_entries, records_info, record_block_offset)
+	};
 
-	let record_block_offset = reader.stream_position()?;
+	let mut record_offsets: Vec<usize> = key_entries.iter()
+		.map(|entry| entry.offset)
+		.collect();
+	record_offsets.sort_unstable();
+	record_offsets.dedup();
+	lap(&mut timings.record_index);
 
 	Ok(Mdx {
-		encoding: header.encoding,
+		encoding: options.record_encoding.unwrap_or(header.encoding),
 		title: header.title,
+		header_checksum: header.checksum,
 		encrypted: header.encrypted,
 		key_entries,
+		key_order: options.key_order.clone(),
+		record_offsets,
 		records_info,
 		reader,
+		source: source.map(Path::to_path_buf),
 		record_block_offset,
-		record_cache: if cache { Some(HashMap::new()) } else { None },
+		record_cache: if options.cache { Some(HashMap::new()) } else { None },
+		block_hits: HashMap::new(),
+		record_limit: options.record_limit,
+		resource,
+		salsa20,
+		scratch: Scratch::new(&options.buffers),
+		warnings,
+		timings,
+		offset_order: None,
+	})
+}
+
+/// Sort by key, entries with equal keys by record offset then stored
+/// headword, so the order doesn't depend on the run or the platform
+fn sort_keys(key_entries: &mut [KeyEntry], options: &LoadOptions)
+{
+	let order = &options.key_order;
+	let ties = |a: &KeyEntry, b: &KeyEntry| a.offset.cmp(&b.offset).then_with(|| a.raw().cmp(b.raw()));
+	if options.trust_sorted {
+		for run in key_entries.chunk_by_mut(|a, b| order.compare(&a.text, &b.text).is_eq()) {
+			run.sort_by(ties);
+		}
+	} else {
+		key_entries.sort_by(|a, b| order.compare(&a.text, &b.text).then_with(|| ties(a, b)));
+	}
+}
+
+/// Where the key and record blocks of a file are, read without decoding
+/// any of them
+pub(crate) struct Layout {
+	// header attributes by name
+	pub(crate) attributes: Vec<(String, String)>,
+	pub(crate) key_block_num: u64,
+	pub(crate) key_entry_num: u64,
+	pub(crate) key_info_decompressed_size: u64,
+	pub(crate) key_info_size: u64,
+	pub(crate) key_blocks_size: u64,
+	pub(crate) key_blocks_offset: u64,
+	pub(crate) key_blocks: Vec<BlockEntryInfo>,
+	pub(crate) record_entry_num: u64,
+	pub(crate) record_info_size: u64,
+	pub(crate) record_data_size: u64,
+	pub(crate) record_blocks_offset: u64,
+	pub(crate) record_blocks: Vec<BlockEntryInfo>,
+}
+
+pub(crate) fn read_layout(reader: &mut Reader, default_encoding: &'static Encoding) -> Result<Layout>
+{
+	reader.seek(SeekFrom::Start(0))?;
+	let header = read_header(reader, default_encoding, &mut vec![])?;
+	let key_block_header = read_key_block_header(reader, &header.version)?;
+	let key_blocks = read_key_block_infos(
+		reader,
+		to_usize(key_block_header.block_info_size)?,
+		&header,
+		&mut vec![])?;
+	let key_blocks_offset = reader.stream_position()?;
+	reader.seek(SeekFrom::Start(key_blocks_offset + key_block_header.key_block_size))?;
+	let (records_header, record_blocks) = read_record_blocks(reader, &header)?;
+	let record_blocks_offset = reader.stream_position()?;
+	let mut attributes: Vec<(String, String)> = header.attrs.into_iter().collect();
+	attributes.sort();
+	Ok(Layout {
+		attributes,
+		key_block_num: key_block_header.block_num,
+		key_entry_num: key_block_header.entry_num,
+		key_info_decompressed_size: key_block_header.decompressed_size,
+		key_info_size: key_block_header.block_info_size,
+		key_blocks_size: key_block_header.key_block_size,
+		key_blocks_offset,
+		key_blocks,
+		record_entry_num: records_header.num_entries,
+		record_info_size: records_header.record_info_size,
+		record_data_size: records_header.record_data_size,
+		record_blocks_offset,
+		record_blocks,
 	})
 }
 
-fn record_offset(records_info: &Vec<BlockEntryInfo>, entry: &KeyEntry) -> Option<RecordOffset> {
-	let mut block_offset = 0;
+/// A checksum stored in the file and the one of the data it covers
+pub(crate) struct StoredChecksum {
+	pub(crate) site: ChecksumSite,
+	/// position in the file
+	pub(crate) offset: u64,
+	pub(crate) big_endian: bool,
+	pub(crate) stored: u32,
+	/// None when the data can't be decoded, or the checksum is also the
+	/// key of encrypted data
+	pub(crate) actual: Option<u32>,
+}
+
+/// Every checksum of the file and the one its data has, read without
+/// trusting any of them
+pub(crate) fn stored_checksums(reader: &mut Reader) -> Result<Vec<StoredChecksum>>
+{
+	let mut checksums = vec![];
+	reader.seek(SeekFrom::Start(0))?;
+	let bytes = reader.read_u32::<BE>()?;
+	let info_buf = read_buf(reader, bytes as usize)?;
+	let actual = adler32(&info_buf);
+	checksums.push(StoredChecksum {
+		site: ChecksumSite::Header,
+		offset: 4 + u64::from(bytes),
+		big_endian: false,
+		stored: reader.read_u32::<LE>()?,
+		actual: Some(actual),
+	});
+	let header = parse_header(&info_buf, actual, UTF_16LE, &mut vec![])?;
+	let v2 = matches!(header.version, Version::V2);
+
+	let offset = reader.stream_position()?;
+	let mut buf = read_buf(reader, KeyBlockHeader::size(&header.version))?;
+	if v2 {
+		let actual = adler32(&buf[..40]);
+		checksums.push(StoredChecksum {
+			site: ChecksumSite::KeySection,
+			offset: offset + 40,
+			big_endian: true,
+			stored: BE::read_u32(&buf[40..44]),
+			actual: Some(actual),
+		});
+		BE::write_u32(&mut buf[40..44], actual);
+	}
+	let key_block_header = parse_key_block_header(&buf, &header.version)?;
+
+	let offset = reader.stream_position()?;
+	let mut buf = read_buf(reader, to_usize(key_block_header.block_info_size)?)?;
+	if v2 && buf.len() >= 8 {
+		let actual = if header.encrypted == 2 {
+			None
+		} else {
+			let mut info = vec![];
+			inflate(&buf[8..], None, DECOMPRESS_CHUNK, &mut info).ok().map(|_| adler32(&info))
+		};
+		checksums.push(StoredChecksum {
+			site: ChecksumSite::KeyIndex,
+			offset: offset + 4,
+			big_endian: true,
+			stored: BE::read_u32(&buf[4..8]),
+			actual,
+		});
+		if let Some(actual) = actual {
+			BE::write_u32(&mut buf[4..8], actual);
+		}
+	}
+	let key_blocks = parse_key_block_infos(buf, &header, u64::MAX, &mut vec![])?;
+	let key_blocks_offset = reader.stream_position()?;
+	block_checksums(reader, BlockKind::Key, key_blocks_offset, &key_blocks, &mut checksums)?;
+	reader.seek(SeekFrom::Start(key_blocks_offset + key_block_header.key_block_size))?;
+	let (_, record_blocks) = read_record_blocks(reader, &header)?;
+	let offset = reader.stream_position()?;
+	block_checksums(reader, BlockKind::Record, offset, &record_blocks, &mut checksums)?;
+	Ok(checksums)
+}
+
+fn block_checksums(reader: &mut Reader, kind: BlockKind, mut offset: u64,
+	infos: &[BlockEntryInfo], checksums: &mut Vec<StoredChecksum>) -> Result<()>
+{
+	let mut plain = vec![];
+	let mut out = vec![];
+	for (index, info) in infos.iter().enumerate() {
+		reader.seek(SeekFrom::Start(offset))?;
+		let raw = read_buf(reader, info.compressed_size)?;
+		let header = block_header(&raw)?;
+		let actual = if header.encryption != 0 {
+			None
+		} else {
+			out.clear();
+			decompress_verified(&raw, info.compressed_size, info.decompressed_size, None,
+				&mut Salsa20Variant::Detect, index, BufferSizes::default().decompress, &mut plain,
+				&mut out)
+				.ok()
+				.filter(|_| out.len() == info.decompressed_size)
+				.map(|_| adler32(&out))
+		};
+		checksums.push(StoredChecksum {
+			site: ChecksumSite::Block(kind, index),
+			offset: offset + 4,
+			big_endian: true,
+			stored: header.checksum,
+			actual,
+		});
+		offset += info.compressed_size as u64;
+	}
+	Ok(())
+}
+
+/// Checksum stored in the block and the one of its decoded content
+pub(crate) struct BlockCheck {
+	pub(crate) header: Option<BlockHeader>,
+	pub(crate) actual_checksum: Option<u32>,
+	pub(crate) error: Option<Error>,
+}
+
+pub(crate) fn check_block(reader: &mut Reader, offset: u64, index: usize,
+	info: &BlockEntryInfo) -> BlockCheck
+{
+	let mut check = BlockCheck { header: None, actual_checksum: None, error: None };
+	let mut data = vec![];
+	let result = reader.seek(SeekFrom::Start(offset))
+		.map_err(Error::from)
+		.and_then(|_| read_buf(reader, info.compressed_size))
+		.and_then(|raw| {
+			check.header = Some(block_header(&raw)?);
+			decompress_verified(&raw, info.compressed_size, info.decompressed_size, None,
+				&mut Salsa20Variant::Detect, index, BufferSizes::default().decompress, &mut vec![],
+				&mut data)
+		});
+	match result {
+		Ok(_) => {
+			check.actual_checksum = Some(adler32(&data));
+			if data.len() != info.decompressed_size {
+				check.error = Some(Error::BlockSize(data.len()));
+			}
+		}
+		Err(err) => check.error = Some(err),
+	}
+	check
+}
+
+pub(crate) fn record_offset(mdx: &Mdx, entry: &KeyEntry) -> Option<RecordOffset> {
+	let offsets = &mdx.record_offsets;
+	let next = offsets.partition_point(|offset| *offset <= entry.offset);
+	let mut block_offset = 0usize;
 	let mut buf_offset = 0;
-	for info in records_info {
-		if entry.offset < block_offset + info.decompressed_size {
+	for (index, info) in mdx.records_info.iter().enumerate() {
+		let block_end = block_offset.saturating_add(info.decompressed_size);
+		if entry.offset < block_end {
+			let record_end = offsets.get(next).map_or(block_end, |end| (*end).min(block_end));
 			return Some(RecordOffset {
+				index,
 				buf_offset,
 				block_offset: entry.offset - block_offset,
+				record_end: record_end - block_offset,
 				record_size: info.compressed_size,
 				decomp_size: info.decompressed_size,
 			});
 		} else {
-			block_offset += info.decompressed_size;
-			buf_offset += info.compressed_size;
+			block_offset = block_end;
+			buf_offset += info.compressed_size as u64;
 		}
 	}
 	None
 }
 
-fn find_definition(mdx: &mut Mdx, offset: RecordOffset) -> Result<Cow<[u8]>>
+// decode the record into the scratch block when it is over the size
+// limit, returns whether it was
+#[inline]
+fn decode_limited(mdx: &mut Mdx, offset: &RecordOffset) -> Result<bool>
+{
+	let Mdx { reader, scratch, salsa20, record_limit, record_block_offset, resource, .. } = mdx;
+	decode_limited_with(reader, scratch, salsa20, *record_limit,
+		*record_block_offset + offset.buf_offset, *resource, offset)
+}
+
+// `decode_limited()` reading the block at `position` with `reader`
+fn decode_limited_with(reader: &mut Reader, scratch: &mut Scratch, salsa20: &mut Salsa20Variant,
+	record_limit: Option<RecordLimit>, position: u64, resource: bool, offset: &RecordOffset)
+	-> Result<bool>
 {
-	#[inline]
-	fn read_record(reader: &mut Reader, record_block_offset: u64,
-		offset: RecordOffset) -> Result<Vec<u8>>
-	{
-		reader.seek(SeekFrom::Start(record_block_offset + offset.buf_offset as u64))?;
-		let data = read_buf(reader, offset.record_size)?;
-		decode_block(&data, offset.record_size, offset.decomp_size)
+	let size = offset.record_end - offset.block_offset;
+	let limit = match record_limit {
+		Some(RecordLimit::Error(limit)) if size > limit =>
+			return Err(Error::RecordTooLarge(size)),
+		Some(RecordLimit::Truncate(limit)) if size > limit => limit,
+		_ => return Ok(false),
+	};
+	let end = offset.block_offset + limit;
+	scratch.decode(reader, position, offset.record_size, offset.decomp_size, Some(end),
+		salsa20, offset.index)?;
+	let data = &mut scratch.block;
+	data.drain(..offset.block_offset);
+	if !resource {
+		// keep the text terminated, aligned for utf-16
+		data.truncate(data.len() & !1);
+		data.extend_from_slice(&[0, 0]);
+	}
+	Ok(true)
+}
+
+// the record data and whether its block came from the cache
+fn find_definition(mdx: &mut Mdx, offset: RecordOffset) -> Result<(Cow<'_, [u8]>, bool)>
+{
+	if decode_limited(mdx, &offset)? {
+		return Ok((Cow::Borrowed(&mdx.scratch.block), false));
 	}
-	let block_offset = offset.block_offset;
-	if let Some(cache) = &mut mdx.record_cache {
-		let data = match cache.entry(offset.buf_offset) {
-			Entry::Occupied(o) => o.into_mut(),
+	let range = offset.block_offset..offset.record_end;
+	let position = mdx.record_block_offset + offset.buf_offset;
+	let Mdx { reader, scratch, record_cache, block_hits, salsa20, .. } = mdx;
+	if let Some(cache) = record_cache {
+		*block_hits.entry(offset.buf_offset).or_default() += 1;
+		let (data, cached) = match cache.entry(offset.buf_offset) {
+			Entry::Occupied(o) => (o.into_mut(), true),
 			Entry::Vacant(v) => {
-				let reader = &mut mdx.reader;
-				let decompressed = read_record(reader, mdx.record_block_offset, offset)?;
-				v.insert(decompressed)
+				scratch.decode(reader, position, offset.record_size, offset.decomp_size, None,
+					salsa20, offset.index)?;
+				(v.insert(Arc::new(std::mem::take(&mut scratch.block))), false)
 			}
 		};
-		Ok(Cow::Borrowed(&data[block_offset..]))
+		Ok((Cow::Borrowed(&data[range]), cached))
 	} else {
-		let reader = &mut mdx.reader;
-		let mut data = read_record(reader, mdx.record_block_offset, offset)?;
-		if block_offset != 0 {
-			data = Vec::from(&data[block_offset..]);
+		scratch.decode(reader, position, offset.record_size, offset.decomp_size, None,
+			salsa20, offset.index)?;
+		Ok((Cow::Borrowed(&scratch.block[range]), false))
+	}
+}
+
+// the block holding the record, the record range in it and whether the
+// block came from the cache
+fn find_shared(mdx: &mut Mdx, offset: RecordOffset) -> Result<(Arc<Vec<u8>>, Range<usize>, bool)>
+{
+	if decode_limited(mdx, &offset)? {
+		let data = std::mem::take(&mut mdx.scratch.block);
+		let len = data.len();
+		return Ok((Arc::new(data), 0..len, false));
+	}
+	let range = offset.block_offset..offset.record_end;
+	let position = mdx.record_block_offset + offset.buf_offset;
+	let Mdx { reader, scratch, record_cache, block_hits, salsa20, .. } = mdx;
+	if record_cache.is_some() {
+		*block_hits.entry(offset.buf_offset).or_default() += 1;
+	}
+	if let Some(block) = record_cache.as_ref().and_then(|cache| cache.get(&offset.buf_offset)) {
+		return Ok((block.clone(), range, true));
+	}
+	scratch.decode(reader, position, offset.record_size, offset.decomp_size, None,
+		salsa20, offset.index)?;
+	let block = Arc::new(std::mem::take(&mut scratch.block));
+	if let Some(cache) = record_cache {
+		cache.insert(offset.buf_offset, block.clone());
+	}
+	Ok((block, range, false))
+}
+
+#[inline]
+fn find_index(mdx: &Mdx, key: &str) -> Option<usize>
+{
+	mdx.key_entries
+		.binary_search_by(|entry| mdx.key_order.compare(&entry.text, key))
+		.ok()
+}
+
+#[inline]
+pub(crate) fn find_entry<'a>(mdx: &'a Mdx, key: &str) -> Option<&'a KeyEntry>
+{
+	Some(&mdx.key_entries[find_index(mdx, key)?])
+}
+
+#[inline]
+pub(crate) fn lookup_record<'a>(mdx: &'a mut Mdx, key: &str) -> Result<Option<Record<'a>>>
+{
+	match find_index(mdx, key) {
+		Some(index) => entry_record(mdx, index),
+		None => Ok(None),
+	}
+}
+
+/// The record of the key entry at `index`
+pub(crate) fn entry_record(mdx: &mut Mdx, index: usize) -> Result<Option<Record<'_>>>
+{
+	if let Some(entry) = mdx.key_entries.get(index) {
+		if let Some(offset) = record_offset(mdx, entry) {
+			let key = entry.text.clone();
+			let raw_key = entry.raw.clone();
+			let entry_offset = entry.offset;
+			let size = offset.record_end - offset.block_offset;
+			let encoding = mdx.encoding;
+			let resource = mdx.resource;
+			let (data, cached) = find_definition(mdx, offset)?;
+			return Ok(Some(Record {
+				key,
+				raw_key,
+				data,
+				encoding,
+				offset: entry_offset,
+				size,
+				cached,
+				resource,
+			}));
 		}
-		Ok(Cow::Owned(data))
 	}
+	Ok(None)
 }
 
-pub(crate) fn lookup_record<'a>(mdx: &'a mut Mdx, key: &str) -> Result<Option<Cow<'a, [u8]>>>
+pub(crate) fn lookup_shared(mdx: &mut Mdx, key: &str) -> Result<Option<SharedRecord>>
 {
-	if let Ok(idx) = mdx.key_entries.binary_search_by(|entry| entry.text.as_str().cmp(key)) {
-		let entry = &mdx.key_entries[idx];
-		if let Some(offset) = record_offset(&mdx.records_info, entry) {
-			let slice = find_definition(mdx, offset)?;
-			return Ok(Some(slice));
+	if let Some(entry) = find_entry(mdx, key) {
+		if let Some(offset) = record_offset(mdx, entry) {
+			let key = entry.text.clone();
+			let raw_key = entry.raw.clone();
+			let entry_offset = entry.offset;
+			let size = offset.record_end - offset.block_offset;
+			let encoding = mdx.encoding;
+			let resource = mdx.resource;
+			let (block, range, cached) = find_shared(mdx, offset)?;
+			return Ok(Some(SharedRecord {
+				key,
+				raw_key,
+				block,
+				range,
+				encoding,
+				offset: entry_offset,
+				size,
+				cached,
+				resource,
+			}));
 		}
 	}
 	Ok(None)
 }
 
-pub(crate) fn decode_slice_string<'a>(slice: &'a [u8],
-	encoding: &'static Encoding) -> Result<(Cow<'a, str>, usize)>
+/// Visit every record in file order, each record block is decoded once
+pub(crate) fn for_each_record(mdx: &mut Mdx,
+	mut f: impl FnMut(&KeyEntry, &[u8]) -> Result<()>) -> Result<()>
 {
-	let (idx, delta) = if encoding == UTF_16LE {
-		let mut found = None;
-		for i in (0..slice.len()).step_by(2) {
-			if slice[i] == 0 && slice[i + 1] == 0 {
-				found = Some(i);
-				break;
-			}
+	for_each_record_until(mdx, |entry, data| f(entry, data).map(ControlFlow::Continue))
+}
+
+/// `for_each_record()` stopping when `f` breaks
+pub(crate) fn for_each_record_until(mdx: &mut Mdx,
+	mut f: impl FnMut(&KeyEntry, &[u8]) -> Result<ControlFlow<()>>) -> Result<()>
+{
+	let Mdx { key_entries, records_info, record_offsets, reader, record_block_offset, scratch,
+		salsa20, .. } = mdx;
+	let mut order: Vec<&KeyEntry> = key_entries.iter().collect();
+	order.sort_by_key(|entry| entry.offset);
+	visit_records(reader, scratch, salsa20, records_info, record_offsets, *record_block_offset,
+		&order, |_, entry, data| f(entry, data))
+}
+
+/// `for_each_record()` of the entries `keep` selects, reading only the
+/// blocks holding them
+pub(crate) fn for_each_record_where(mdx: &mut Mdx, keep: impl Fn(&KeyEntry) -> bool,
+	mut f: impl FnMut(&KeyEntry, &[u8]) -> Result<()>) -> Result<()>
+{
+	let Mdx { key_entries, records_info, record_offsets, reader, record_block_offset, scratch,
+		salsa20, .. } = mdx;
+	let mut order: Vec<&KeyEntry> = key_entries.iter().filter(|entry| keep(entry)).collect();
+	order.sort_by_key(|entry| entry.offset);
+	visit_records(reader, scratch, salsa20, records_info, record_offsets, *record_block_offset,
+		&order, |_, entry, data| f(entry, data).map(ControlFlow::Continue))
+}
+
+/// Call `f` with the block index and the record of each of `order`, sorted
+/// by offset, reading only the blocks holding them, until `f` breaks.
+/// Adjacent blocks are read together, see `BufferSizes::coalesce`
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn visit_records(reader: &mut Reader, scratch: &mut Scratch,
+	salsa20: &mut Salsa20Variant, records_info: &[BlockEntryInfo], record_offsets: &[usize],
+	record_block_offset: u64, order: &[&KeyEntry],
+	mut f: impl FnMut(usize, &KeyEntry, &[u8]) -> Result<ControlFlow<()>>) -> Result<()>
+{
+	let spans = block_spans(records_info, record_block_offset);
+	let block_of = |entry: &KeyEntry| spans
+		.partition_point(|span| span.start.saturating_add(span.decompressed_size) <= entry.offset);
+	let mut blocks: Vec<usize> = order.iter().map(|entry| block_of(entry)).collect();
+	blocks.dedup();
+	let mut at = None;
+	let (mut block_index, mut block_start, mut block_end) = (0, 0, 0);
+	for entry in order {
+		if entry.offset >= block_end {
+			let next = at.map_or(0, |at| at + 1);
+			let Some(index) = blocks.get(next).copied().filter(|index| *index < spans.len()) else {
+				return Ok(());
+			};
+			scratch.decode_run(reader, &blocks, next, &spans, salsa20)?;
+			at = Some(next);
+			block_index = index;
+			block_start = spans[index].start;
+			block_end = block_start.saturating_add(spans[index].decompressed_size);
 		}
-		if let Some(idx) = found {
-			(idx, 2)
-		} else {
-			return Err(Error::InvalidData);
+		let next = record_offsets.partition_point(|offset| *offset <= entry.offset);
+		let end = record_offsets.get(next)
+			.map_or(block_end, |offset| (*offset).min(block_end));
+		let record = &scratch.block[entry.offset - block_start..end - block_start];
+		if f(block_index, entry, record)?.is_break() {
+			break;
+		}
+	}
+	Ok(())
+}
+
+/// Reads the records of entries in any order, decoding a block only when
+/// the entry read before was in another one. Most files store records in
+/// key order, so reading in key order decodes each block once
+#[derive(Default)]
+pub(crate) struct RecordReader {
+	scratch: Scratch,
+	// index of the block in `scratch`
+	block: Option<usize>,
+}
+
+impl RecordReader {
+	/// The record of key entry `index`, empty without one
+	pub(crate) fn read(&mut self, mdx: &mut Mdx, index: usize) -> Result<&[u8]>
+	{
+		let Some(offset) = mdx.key_entries.get(index).and_then(|entry| record_offset(mdx, entry))
+		else {
+			return Ok(&[]);
+		};
+		if self.block != Some(offset.index) {
+			self.block = None;
+			self.scratch.decode(&mut mdx.reader, mdx.record_block_offset + offset.buf_offset,
+				offset.record_size, offset.decomp_size, None, &mut mdx.salsa20, offset.index)?;
+			self.block = Some(offset.index);
 		}
-	} else if encoding == UTF_8 {
-		let idx = slice
-			.iter()
-			.position(|b| *b == 0)
-			.ok_or(Error::InvalidData)?;
-		(idx, 1)
+		Ok(&self.scratch.block[offset.block_offset..offset.record_end])
+	}
+}
+
+/// The records stored in record block `index`, in file order
+pub(crate) fn read_record_block(mdx: &mut Mdx, index: usize) -> Result<Vec<Record<'static>>>
+{
+	let (encoding, resource) = (mdx.encoding, mdx.resource);
+	let Mdx { key_entries, records_info, record_offsets, reader, record_block_offset, scratch,
+		salsa20, offset_order, .. } = mdx;
+	let Some(info) = records_info.get(index) else {
+		return Ok(vec![]);
+	};
+	let (start, position) = records_info[..index].iter()
+		.fold((0usize, *record_block_offset), |(start, position), info|
+			(start + info.decompressed_size, position + info.compressed_size as u64));
+	let end = start + info.decompressed_size;
+	let order = offset_order.get_or_insert_with(|| by_offset(key_entries));
+	scratch.decode(reader, position, info.compressed_size, info.decompressed_size, None,
+		salsa20, index)?;
+	let records = block_entries(order, key_entries, start, end).iter()
+		.map(|idx| {
+			let entry = &key_entries[*idx];
+			let record_end = record_end(record_offsets, entry.offset, end);
+			Record {
+				key: entry.text.clone(),
+				raw_key: entry.raw.clone(),
+				data: Cow::Owned(scratch.block[entry.offset - start..record_end - start].to_vec()),
+				encoding,
+				offset: entry.offset,
+				size: record_end - entry.offset,
+				cached: false,
+				resource,
+			}
+		})
+		.collect();
+	Ok(records)
+}
+
+/// Decode the record blocks on the rayon thread pool, calling `f` with
+/// the stored key and record of every entry. Blocks are read a few at a
+/// time, in one read, ahead of the threads decoding them
+#[cfg(feature = "rayon")]
+pub(crate) fn par_visit_records(mdx: &mut Mdx, f: &(dyn Fn(&str, &[u8]) + Sync)) -> Result<()>
+{
+	use rayon::prelude::*;
+
+	let Mdx { key_entries, records_info, record_offsets, reader, record_block_offset, salsa20,
+		offset_order, scratch, .. } = mdx;
+	let order = offset_order.get_or_insert_with(|| by_offset(key_entries));
+	let spans: Vec<_> = block_spans(records_info, *record_block_offset).into_iter()
+		.enumerate()
+		.collect();
+	let (salsa20, chunk) = (*salsa20, scratch.chunk);
+	for batch in spans.chunks(rayon::current_num_threads() * 2) {
+		let (Some((_, first)), Some((_, last))) = (batch.first(), batch.last()) else {
+			continue;
+		};
+		reader.seek(SeekFrom::Start(first.position))?;
+		let len = (last.position - first.position) as usize + last.compressed_size;
+		let raw = read_buf(reader, len)?;
+		batch.par_iter().try_for_each(|(index, span)| {
+			let (mut plain, mut block, mut salsa20) = (vec![], vec![], salsa20);
+			let offset = (span.position - first.position) as usize;
+			decode_block_into(&raw[offset..offset + span.compressed_size], span.compressed_size,
+				span.decompressed_size, None, &mut salsa20, *index, chunk, &mut plain, &mut block)?;
+			let (start, end) = (span.start, span.start + span.decompressed_size);
+			for idx in block_entries(order, key_entries, start, end) {
+				let entry = &key_entries[*idx];
+				let record_end = record_end(record_offsets, entry.offset, end);
+				f(entry.raw(), &block[entry.offset - start..record_end - start]);
+			}
+			Ok::<_, Error>(())
+		})?;
+	}
+	Ok(())
+}
+
+/// `lookup_shared()` reading with `reader`, `scratch` and `salsa20`
+/// instead of those of `mdx`, without its record cache, so handles
+/// reopening the file can share `mdx`
+pub(crate) fn read_shared(mdx: &Mdx, key: &str, reader: &mut Reader, scratch: &mut Scratch,
+	salsa20: &mut Salsa20Variant) -> Result<Option<SharedRecord>>
+{
+	let Some(entry) = find_entry(mdx, key) else {
+		return Ok(None);
+	};
+	let Some(offset) = record_offset(mdx, entry) else {
+		return Ok(None);
+	};
+	let position = mdx.record_block_offset + offset.buf_offset;
+	let (block, range) = if decode_limited_with(reader, scratch, salsa20, mdx.record_limit,
+		position, mdx.resource, &offset)?
+	{
+		let data = std::mem::take(&mut scratch.block);
+		let len = data.len();
+		(Arc::new(data), 0..len)
 	} else {
-		return Err(Error::InvalidEncoding(encoding.name().to_owned()));
+		scratch.decode(reader, position, offset.record_size, offset.decomp_size, None, salsa20,
+			offset.index)?;
+		(Arc::new(std::mem::take(&mut scratch.block)), offset.block_offset..offset.record_end)
 	};
+	Ok(Some(SharedRecord {
+		key: entry.text.clone(),
+		raw_key: entry.raw.clone(),
+		block,
+		range,
+		encoding: mdx.encoding,
+		offset: entry.offset,
+		size: offset.record_end - offset.block_offset,
+		cached: false,
+		resource: mdx.resource,
+	}))
+}
+
+/// Indexes of the key entries whose record starts at `offset`
+pub(crate) fn entries_at(mdx: &mut Mdx, offset: usize) -> Vec<usize>
+{
+	let Mdx { key_entries, offset_order, .. } = mdx;
+	let order = offset_order.get_or_insert_with(|| by_offset(key_entries));
+	block_entries(order, key_entries, offset, offset + 1).to_vec()
+}
+
+/// Indices of the keys by record offset
+fn by_offset(key_entries: &[KeyEntry]) -> Vec<usize>
+{
+	let mut order: Vec<usize> = (0..key_entries.len()).collect();
+	order.sort_by_key(|idx| key_entries[*idx].offset);
+	order
+}
 
-	let text = encoding.decode(&slice[..idx]).0;
-	Ok((text, idx + delta))
+/// The part of `order` with records in the decoded range `start..end`
+#[inline]
+fn block_entries<'a>(order: &'a [usize], key_entries: &[KeyEntry], start: usize, end: usize)
+	-> &'a [usize]
+{
+	let first = order.partition_point(|idx| key_entries[*idx].offset < start);
+	let last = order.partition_point(|idx| key_entries[*idx].offset < end);
+	&order[first..last]
 }
+
+/// Where the record at `offset` ends, records never cross `block_end`
+#[inline]
+pub(crate) fn record_end(record_offsets: &[usize], offset: usize, block_end: usize) -> usize
+{
+	let next = record_offsets.partition_point(|start| *start <= offset);
+	record_offsets.get(next).map_or(block_end, |start| (*start).min(block_end))
+}
+
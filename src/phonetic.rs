@@ -0,0 +1,133 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::format::KeyEntry;
+use crate::mdx::{KeyMaker, MDict};
+use crate::search::dedup_keys;
+
+/// Key indices by the Metaphone code of their latin letters
+#[derive(Debug, Default)]
+pub(crate) struct PhoneticIndex {
+	postings: HashMap<String, Vec<u32>>,
+}
+
+impl PhoneticIndex {
+	pub(crate) fn build(entries: &[KeyEntry]) -> Self
+	{
+		let mut postings: HashMap<String, Vec<u32>> = HashMap::new();
+		for (idx, entry) in entries.iter().enumerate() {
+			let code = metaphone(&entry.text);
+			if !code.is_empty() {
+				postings.entry(code).or_default().push(idx as u32);
+			}
+		}
+		PhoneticIndex { postings }
+	}
+}
+
+#[inline]
+fn is_vowel(letter: Option<&u8>) -> bool
+{
+	matches!(letter, Some(b'A' | b'E' | b'I' | b'O' | b'U'))
+}
+
+/// Metaphone code of the ascii letters of `text`, others are skipped,
+/// e.g. `night` and `nite` are both `NT`
+pub(crate) fn metaphone(text: &str) -> String
+{
+	let mut word: Vec<u8> = text.bytes()
+		.filter(u8::is_ascii_alphabetic)
+		.map(|ch| ch.to_ascii_uppercase())
+		.collect();
+	match word.as_slice() {
+		[b'A', b'E', ..] | [b'G' | b'K' | b'P', b'N', ..] | [b'W', b'R', ..] => {
+			word.remove(0);
+		}
+		[b'W', b'H', ..] => {
+			word.remove(1);
+		}
+		[b'X', ..] => word[0] = b'S',
+		_ => {}
+	}
+	let at = |idx: usize| word.get(idx);
+	let mut code = String::new();
+	for (idx, &ch) in word.iter().enumerate() {
+		let prev = idx.checked_sub(1).and_then(at);
+		let next = at(idx + 1);
+		if prev == Some(&ch) && ch != b'C' {
+			continue;
+		}
+		match ch {
+			b'A' | b'E' | b'I' | b'O' | b'U' => if idx == 0 {
+				code.push(ch as char);
+			},
+			b'B' => if !(prev == Some(&b'M') && next.is_none()) {
+				code.push('B');
+			},
+			b'C' => match next {
+				Some(b'I') if at(idx + 2) == Some(&b'A') => code.push('X'),
+				Some(b'H') if prev == Some(&b'S') => code.push('K'),
+				Some(b'H') => code.push('X'),
+				Some(b'I' | b'E' | b'Y') if prev == Some(&b'S') => {}
+				Some(b'I' | b'E' | b'Y') => code.push('S'),
+				_ => code.push('K'),
+			},
+			b'D' => match (next, at(idx + 2)) {
+				(Some(b'G'), Some(b'E' | b'Y' | b'I')) => code.push('J'),
+				_ => code.push('T'),
+			},
+			b'G' => match (next, at(idx + 2)) {
+				// the J of DGE, DGI and DGY is the D's
+				(Some(b'E' | b'Y' | b'I'), _) if prev == Some(&b'D') => {}
+				(Some(b'H'), after) if after.is_some() && !is_vowel(after) => {}
+				(Some(b'N'), None) => {}
+				(Some(b'N'), Some(b'E')) if word[idx + 2..] == *b"ED" => {}
+				(Some(b'I' | b'E' | b'Y'), _) => code.push('J'),
+				_ => code.push('K'),
+			},
+			b'H' => if is_vowel(next) && !matches!(prev, Some(b'C' | b'G' | b'P' | b'S' | b'T')) {
+				code.push('H');
+			},
+			b'K' => if prev != Some(&b'C') {
+				code.push('K');
+			},
+			b'P' => code.push(if next == Some(&b'H') { 'F' } else { 'P' }),
+			b'Q' => code.push('K'),
+			b'S' => match (next, at(idx + 2)) {
+				(Some(b'H'), _) | (Some(b'I'), Some(b'O' | b'A')) => code.push('X'),
+				_ => code.push('S'),
+			},
+			b'T' => match (next, at(idx + 2)) {
+				(Some(b'I'), Some(b'O' | b'A')) => code.push('X'),
+				(Some(b'H'), _) => code.push('0'),
+				(Some(b'C'), Some(b'H')) => {}
+				_ => code.push('T'),
+			},
+			b'V' => code.push('F'),
+			b'W' | b'Y' => if is_vowel(next) {
+				code.push(ch as char);
+			},
+			b'X' => code.push_str("KS"),
+			b'Z' => code.push('S'),
+			_ => code.push(ch as char),
+		}
+	}
+	code
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Keys of latin letters sounding like `word` in English by their
+	/// Metaphone code, e.g. `nite` finds `night`. The index is built on
+	/// first use
+	pub fn search_phonetic(&self, word: &str) -> impl Iterator<Item=&str>
+	{
+		let word = self.key_maker.make(&Cow::Borrowed(word), false);
+		let entries = &self.mdx.key_entries;
+		let indices = self.phonetic
+			.get_or_init(|| PhoneticIndex::build(entries))
+			.postings
+			.get(&metaphone(&word))
+			.map_or(&[][..], Vec::as_slice);
+		dedup_keys(indices.iter().map(|idx| entries[*idx as usize].text.as_str()))
+	}
+}
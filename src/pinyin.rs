@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+
+use crate::mdx::{KeyMaker, MDict};
+use crate::search::dedup_keys;
+
+// headwords with more reading combinations are indexed by the first ones only
+const MAX_READINGS: usize = 32;
+
+/// Pinyin readings of Han characters, in numbered tone form like `zhong1`
+#[derive(Debug, Default)]
+pub struct ReadingTable {
+	readings: HashMap<char, Vec<String>>,
+}
+
+impl ReadingTable {
+	#[inline]
+	pub fn new() -> Self
+	{
+		ReadingTable { readings: HashMap::new() }
+	}
+
+	/// Add a reading, numbered (`zhong1`) or with tone marks (`zhōng`)
+	pub fn insert(&mut self, ch: char, reading: &str)
+	{
+		let reading = normalize_syllable(reading);
+		if reading.is_empty() {
+			return;
+		}
+		let readings = self.readings.entry(ch).or_default();
+		if !readings.contains(&reading) {
+			readings.push(reading);
+		}
+	}
+
+	/// Parse lines of `<char> <reading>...` or unihan style
+	/// `U+4E2D\tkMandarin\tzhōng`, separated by spaces, tabs or commas
+	pub fn parse(text: &str) -> Self
+	{
+		let mut table = ReadingTable::new();
+		for line in text.lines() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let mut fields = line
+				.split(|c: char| c.is_whitespace() || c == ',')
+				.filter(|field| !field.is_empty());
+			let ch = match fields.next() {
+				Some(field) => match field.strip_prefix("U+") {
+					Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+					None => field.chars().next(),
+				},
+				None => None,
+			};
+			if let Some(ch) = ch {
+				for reading in fields.filter(|field| !field.starts_with('k')) {
+					table.insert(ch, reading);
+				}
+			}
+		}
+		table
+	}
+
+	#[inline]
+	pub fn readings(&self, ch: char) -> Option<&[String]>
+	{
+		self.readings.get(&ch).map(Vec::as_slice)
+	}
+}
+
+/// Headwords by reading, with and without tone numbers
+#[derive(Debug, Default)]
+pub(crate) struct PinyinIndex {
+	toned: HashMap<String, Vec<usize>>,
+	toneless: HashMap<String, Vec<usize>>,
+}
+
+#[inline]
+fn split_mark(ch: char) -> (char, u8)
+{
+	match ch {
+		'ā' => ('a', 1), 'á' => ('a', 2), 'ǎ' => ('a', 3), 'à' => ('a', 4),
+		'ē' => ('e', 1), 'é' => ('e', 2), 'ě' => ('e', 3), 'è' => ('e', 4),
+		'ī' => ('i', 1), 'í' => ('i', 2), 'ǐ' => ('i', 3), 'ì' => ('i', 4),
+		'ō' => ('o', 1), 'ó' => ('o', 2), 'ǒ' => ('o', 3), 'ò' => ('o', 4),
+		'ū' => ('u', 1), 'ú' => ('u', 2), 'ǔ' => ('u', 3), 'ù' => ('u', 4),
+		'ǖ' => ('v', 1), 'ǘ' => ('v', 2), 'ǚ' => ('v', 3), 'ǜ' => ('v', 4),
+		'ü' => ('v', 0),
+		_ => (ch, 0),
+	}
+}
+
+/// Lowercase, `ü` as `v`, tone mark turned into a trailing tone number
+fn normalize_syllable(syllable: &str) -> String
+{
+	let mut text = String::with_capacity(syllable.len());
+	let mut tone = None;
+	for ch in syllable.chars().flat_map(char::to_lowercase) {
+		let (base, mark) = split_mark(ch);
+		if mark != 0 {
+			tone = Some(mark);
+		}
+		text.push(base);
+	}
+	let mut text = text.replace("u:", "v");
+	if let Some(tone) = tone {
+		text.push(char::from(b'0' + tone));
+	}
+	text
+}
+
+#[inline]
+fn strip_tones(reading: &str) -> String
+{
+	reading.chars().filter(|c| !c.is_ascii_digit()).collect()
+}
+
+impl PinyinIndex {
+	fn build(keys: impl Iterator<Item=(usize, impl AsRef<str>)>, table: &ReadingTable) -> Self
+	{
+		let mut index = PinyinIndex::default();
+		'keys: for (idx, key) in keys {
+			let mut readings = vec![String::new()];
+			for ch in key.as_ref().chars() {
+				if ch.is_whitespace() {
+					continue;
+				}
+				let char_readings = match table.readings(ch) {
+					Some(r) => r,
+					None => continue 'keys,
+				};
+				let mut next = Vec::with_capacity(readings.len() * char_readings.len());
+				'combine: for prefix in &readings {
+					for reading in char_readings {
+						if next.len() == MAX_READINGS {
+							break 'combine;
+						}
+						let reading = if reading.ends_with(|c: char| c.is_ascii_digit()) {
+							reading.clone()
+						} else {
+							format!("{}5", reading)
+						};
+						next.push(format!("{}{}", prefix, reading));
+					}
+				}
+				readings = next;
+			}
+			if readings.len() == 1 && readings[0].is_empty() {
+				continue;
+			}
+			for reading in readings {
+				let toneless = strip_tones(&reading);
+				push_unique(index.toned.entry(reading).or_default(), idx);
+				push_unique(index.toneless.entry(toneless).or_default(), idx);
+			}
+		}
+		index
+	}
+
+	fn search(&self, query: &str) -> &[usize]
+	{
+		let mut tones_known = true;
+		let query: String = query
+			.split(|c: char| c.is_whitespace() || c == '\'' || c == '-')
+			.map(|syllable| {
+				// tone marks of unseparated syllables can't be placed
+				let marks = syllable.chars()
+					.filter(|ch| split_mark(*ch).1 != 0)
+					.count();
+				if marks > 1 {
+					tones_known = false;
+				}
+				normalize_syllable(syllable)
+			})
+			.collect();
+		let found = if tones_known && query.contains(|c: char| c.is_ascii_digit()) {
+			self.toned.get(&query)
+		} else {
+			self.toneless.get(&query)
+		};
+		found.map_or(&[], Vec::as_slice)
+	}
+}
+
+#[inline]
+fn push_unique(indices: &mut Vec<usize>, idx: usize)
+{
+	if indices.last() != Some(&idx) {
+		indices.push(idx);
+	}
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Index every key whose characters all have readings in `table`
+	pub fn build_pinyin_index(&mut self, table: &ReadingTable)
+	{
+		let keys = self.mdx.key_entries.iter()
+			.enumerate()
+			.map(|(idx, entry)| (idx, entry.text.as_str()));
+		self.pinyin = Some(PinyinIndex::build(keys, table));
+	}
+
+	/// Keys read as `query`, e.g. `zhong1wen2`, `zhōng wén` or `zhongwen`
+	/// ignoring tones, empty without `build_pinyin_index()`
+	pub fn search_pinyin(&self, query: &str) -> impl Iterator<Item=&str>
+	{
+		let indices = self.pinyin.as_ref().map_or(&[][..], |index| index.search(query));
+		let keys = indices.iter()
+			.map(|idx| self.mdx.key_entries[*idx].text.as_str());
+		dedup_keys(keys)
+	}
+}
@@ -0,0 +1,113 @@
+//! Search queries as users type them, e.g. `dict:oxford head* -fuzzy`
+
+use crate::group::{Dedup, SearchOptions};
+use crate::{Error, Result};
+
+/// A parsed query: words separated by spaces, `"quoted text"` kept whole.
+/// Words naming an option set it, the others make up the text searched:
+///
+/// - `dict:<name>` search the dictionaries whose index is `<name>` or
+///   whose title or file name contains it, all enabled ones when absent
+/// - `prefix:<text>` or `<text>*` match keys starting with the text,
+///   `-prefix` and `exact:<text>` only keys equal to it
+/// - `fuzzy` or `fuzzy:<n>` also match keys within 2 or `<n>` edits,
+///   `-fuzzy` none
+/// - `limit:<n>`, `offset:<n>` the page of hits
+/// - `dedup:all|first|merge` keys found in several dictionaries, see
+///   [`Dedup`]
+/// - `fallback`, `-fallback` stop at the first dictionary with a hit
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+	pub text: String,
+	pub dicts: Vec<String>,
+	pub options: SearchOptions,
+}
+
+// edits allowed by `fuzzy` without a number
+const FUZZY_DISTANCE: usize = 2;
+
+impl Query {
+	pub fn parse(input: &str) -> Result<Self>
+	{
+		let mut query = Query::default();
+		let mut text = vec![];
+		for word in words(input) {
+			let (name, value) = match word.split_once(':') {
+				Some((name, value)) if !word.starts_with('"') => (name, Some(value)),
+				_ => (word.as_str(), None),
+			};
+			let options = &mut query.options;
+			match (name, value) {
+				("dict", Some(value)) => query.dicts.push(unquote(value).to_owned()),
+				("prefix", Some(value)) => {
+					options.prefix = true;
+					text.push(unquote(value).trim_end_matches('*').to_owned());
+				}
+				("exact", Some(value)) => {
+					options.prefix = false;
+					options.fuzzy_distance = 0;
+					text.push(unquote(value).to_owned());
+				}
+				("-prefix", None) => options.prefix = false,
+				("fuzzy", None) => options.fuzzy_distance = FUZZY_DISTANCE,
+				("fuzzy", Some(value)) => options.fuzzy_distance = number(&word, value)?,
+				("-fuzzy", None) => options.fuzzy_distance = 0,
+				("limit", Some(value)) => options.limit = number(&word, value)?,
+				("offset", Some(value)) => options.offset = number(&word, value)?,
+				("dedup", Some(value)) => options.dedup = match value {
+					"all" => Dedup::KeepAll,
+					"first" => Dedup::First,
+					"merge" => Dedup::Merge,
+					_ => return Err(Error::InvalidQuery(word.clone())),
+				},
+				("fallback", None) => options.fallback = true,
+				("-fallback", None) => options.fallback = false,
+				_ => match unquote(&word).strip_suffix('*') {
+					Some(prefix) if !word.starts_with('"') => {
+						options.prefix = true;
+						text.push(prefix.to_owned());
+					}
+					_ => text.push(unquote(&word).to_owned()),
+				},
+			}
+		}
+		query.text = text.join(" ");
+		Ok(query)
+	}
+}
+
+/// Words of `input`, a quoted part is one word with its quotes
+fn words(input: &str) -> Vec<String>
+{
+	let mut words = vec![];
+	let mut word = String::new();
+	let mut quoted = false;
+	for ch in input.chars() {
+		match ch {
+			'"' => {
+				quoted = !quoted;
+				word.push(ch);
+			}
+			ch if ch.is_whitespace() && !quoted => if !word.is_empty() {
+				words.push(std::mem::take(&mut word));
+			},
+			ch => word.push(ch),
+		}
+	}
+	if !word.is_empty() {
+		words.push(word);
+	}
+	words
+}
+
+#[inline]
+fn unquote(text: &str) -> &str
+{
+	text.strip_prefix('"').map_or(text, |text| text.strip_suffix('"').unwrap_or(text))
+}
+
+#[inline]
+fn number(word: &str, value: &str) -> Result<usize>
+{
+	value.parse().map_err(|_| Error::InvalidQuery(word.to_owned()))
+}
@@ -0,0 +1,58 @@
+use rand::Rng;
+
+use crate::mdx::{KeyMaker, MDict};
+use crate::parser::entry_record;
+use crate::record::Record;
+use crate::search::Headword;
+use crate::Result;
+
+/// An entry picked by [`MDict::random_entry()`], its record is only read
+/// and decompressed when asked for
+pub struct RandomEntry<'a, M: KeyMaker> {
+	dict: &'a mut MDict<M>,
+	index: usize,
+}
+
+impl<'a, M: KeyMaker> RandomEntry<'a, M> {
+	#[inline]
+	pub fn headword(&self) -> Headword<'_>
+	{
+		let entry = &self.dict.mdx.key_entries[self.index];
+		Headword { raw: entry.raw(), key: &entry.text }
+	}
+
+	#[inline]
+	pub fn record(self) -> Result<Option<Record<'a>>>
+	{
+		entry_record(&mut self.dict.mdx, self.index)
+	}
+
+	/// The record text passed through the transformer, as `lookup()`
+	/// gives it
+	pub fn definition(self) -> Result<Option<String>>
+	{
+		let RandomEntry { dict, index } = self;
+		let Some(record) = entry_record(&mut dict.mdx, index)? else {
+			return Ok(None);
+		};
+		let definition = record.text()?;
+		Ok(Some(match &dict.transformer {
+			Some(transformer) => transformer.transform(&definition),
+			None => definition.into_owned(),
+		}))
+	}
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// An entry picked uniformly among all of the key index, none when the
+	/// dictionary is empty. Seed `rng` with the date for a word of the day
+	pub fn random_entry(&mut self, rng: &mut impl Rng) -> Option<RandomEntry<'_, M>>
+	{
+		let count = self.mdx.key_entries.len();
+		if count == 0 {
+			return None;
+		}
+		let index = rng.gen_range(0..count);
+		Some(RandomEntry { dict: self, index })
+	}
+}
@@ -0,0 +1,117 @@
+use std::borrow::Cow;
+use std::ops::{Bound, Range, RangeBounds};
+
+use crate::collation::KeyOrder;
+use crate::format::KeyEntry;
+use crate::mdx::{KeyMaker, MDict};
+use crate::search::{dedup_keys, edit_distance, Headword};
+
+/// The keys of a dictionary within a range, see [`MDict::range()`]
+pub struct KeyRange<'a, M: KeyMaker> {
+	dict: &'a MDict<M>,
+	entries: &'a [KeyEntry],
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// The keys from the start to the end of `range` in key order, both
+	/// normalized first, e.g. `"a".."b"` for a letter tab. Searches of the
+	/// view only see its keys
+	#[inline]
+	pub fn range<'b>(&self, range: impl RangeBounds<&'b str>) -> KeyRange<'_, M>
+	{
+		KeyRange { dict: self, entries: &self.mdx.key_entries[self.range_indices(range)] }
+	}
+
+	/// Indices of the key entries `range()` holds
+	pub(crate) fn range_indices<'b>(&self, range: impl RangeBounds<&'b str>) -> Range<usize>
+	{
+		let entries = &self.mdx.key_entries;
+		let order = &self.mdx.key_order;
+		// first entry past the keys before `key`, or also past `key` itself
+		let point = |key: &str, past: bool| {
+			let key = self.key_maker.make(&Cow::Borrowed(key), false);
+			entries.partition_point(|entry| {
+				let ordering = order.compare(&entry.text, &key);
+				ordering.is_lt() || past && ordering.is_eq()
+			})
+		};
+		let start = match range.start_bound() {
+			Bound::Included(key) => point(key, false),
+			Bound::Excluded(key) => point(key, true),
+			Bound::Unbounded => 0,
+		};
+		let end = match range.end_bound() {
+			Bound::Included(key) => point(key, true),
+			Bound::Excluded(key) => point(key, false),
+			Bound::Unbounded => entries.len(),
+		};
+		start..end.max(start)
+	}
+}
+
+impl<'a, M: KeyMaker> KeyRange<'a, M> {
+	/// Entries in the range, keys normalized alike counted each time
+	#[inline]
+	pub fn len(&self) -> usize
+	{
+		self.entries.len()
+	}
+
+	#[inline]
+	pub fn is_empty(&self) -> bool
+	{
+		self.entries.is_empty()
+	}
+
+	/// Keys in key order, equal keys once
+	#[inline]
+	pub fn keys(&self) -> impl Iterator<Item=&'a str>
+	{
+		dedup_keys(self.entries.iter().map(|entry| entry.text.as_str()))
+	}
+
+	#[inline]
+	pub fn headwords(&self) -> impl Iterator<Item=Headword<'a>>
+	{
+		self.entries.iter().map(|entry| Headword { raw: entry.raw(), key: &entry.text })
+	}
+
+	/// The key `word` is found by when it is in the range
+	pub fn find_key(&self, word: &str) -> Option<&'a str>
+	{
+		let key = self.dict.resolve_key(word);
+		let order = &self.dict.mdx.key_order;
+		let idx = self.entries.binary_search_by(|entry| order.compare(&entry.text, &key)).ok()?;
+		Some(&self.entries[idx].text)
+	}
+
+	/// Keys of the range starting with `prefix`, in key order
+	pub fn search_prefix(&self, prefix: &str) -> impl Iterator<Item=&'a str>
+	{
+		let prefix = self.dict.key_maker.make(&Cow::Borrowed(prefix), false);
+		let entries = self.entries;
+		// matches are contiguous in byte order
+		let (start, contiguous) = match self.dict.mdx.key_order {
+			KeyOrder::Bytes => (entries.partition_point(|entry| entry.text < prefix), true),
+			#[allow(unreachable_patterns)]
+			_ => (0, false),
+		};
+		let end_prefix = prefix.clone();
+		let keys = entries[start..].iter()
+			.map(|entry| entry.text.as_str())
+			.take_while(move |key| !contiguous || key.starts_with(end_prefix.as_str()))
+			.filter(move |key| key.starts_with(prefix.as_str()));
+		dedup_keys(keys)
+	}
+
+	/// Keys of the range within `max_distance` edits of `word`, with their
+	/// distance
+	pub fn search_fuzzy(&self, word: &str, max_distance: usize)
+		-> impl Iterator<Item=(&'a str, usize)>
+	{
+		let word = self.dict.key_maker.make(&Cow::Borrowed(word), false);
+		let word: Vec<char> = word.chars().collect();
+		self.keys().filter_map(move |key| edit_distance(&word, key, max_distance)
+			.map(|distance| (key, distance)))
+	}
+}
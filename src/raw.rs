@@ -0,0 +1,145 @@
+//! Building blocks of the file format for tools decoding dictionaries
+//! their own way, e.g. converters and format research. Offsets and sizes
+//! of every section are given by [`crate::inspect::read_structure`]
+
+use alloc::borrow::Cow;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use encoding_rs::{Encoding, UTF_16LE};
+
+use crate::format::{self, Decoding, KeyBlockHeader, KeyEntry, Salsa20Variant, Version, DECOMPRESS_CHUNK};
+use crate::{Error, Result};
+
+/// The header of a file, decoded from its UTF-16 text
+#[derive(Debug)]
+pub struct Header(format::Header);
+
+impl Header {
+	/// Decode the header text, `checksum` is the adler32 stored after it
+	pub fn parse(text: &[u8], checksum: u32) -> Result<Self>
+	{
+		Ok(Header(format::parse_header(text, checksum, UTF_16LE, &mut vec![])?))
+	}
+
+	/// Major version of the engine that wrote the file, 1 or 2
+	#[inline]
+	pub fn version(&self) -> u8
+	{
+		match self.0.version {
+			Version::V1 => 1,
+			Version::V2 => 2,
+		}
+	}
+
+	/// Encoding of the keys and of mdx records
+	#[inline]
+	pub fn encoding(&self) -> &'static Encoding
+	{
+		self.0.encoding
+	}
+
+	/// The `Encrypted` attribute, 2 when the key block index is encrypted
+	#[inline]
+	pub fn encrypted(&self) -> u8
+	{
+		self.0.encrypted
+	}
+
+	#[inline]
+	pub fn title(&self) -> &str
+	{
+		&self.0.title
+	}
+
+	#[inline]
+	pub fn attribute(&self, name: &str) -> Option<&str>
+	{
+		self.0.attrs.get(name).map(String::as_str)
+	}
+
+	/// Bytes of the key section header following the header
+	#[inline]
+	pub fn key_section_size(&self) -> usize
+	{
+		KeyBlockHeader::size(&self.0.version)
+	}
+}
+
+/// Counts and sizes leading the key section
+#[derive(Debug, Clone, Copy)]
+pub struct KeySection {
+	pub block_count: u64,
+	pub key_count: u64,
+	pub info_decompressed_size: u64,
+	/// bytes of the key block index
+	pub info_size: u64,
+	/// bytes of all key blocks
+	pub blocks_size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockSize {
+	pub compressed_size: usize,
+	pub decompressed_size: usize,
+}
+
+/// Decode the `header.key_section_size()` bytes after the header
+pub fn parse_key_section(data: &[u8], header: &Header) -> Result<KeySection>
+{
+	let section = format::parse_key_block_header(data, &header.0.version)?;
+	Ok(KeySection {
+		block_count: section.block_num,
+		key_count: section.entry_num,
+		info_decompressed_size: section.decompressed_size,
+		info_size: section.block_info_size,
+		blocks_size: section.key_block_size,
+	})
+}
+
+/// Sizes of the key blocks from the key block index, decrypting and
+/// decompressing it first
+pub fn parse_key_info(data: &[u8], header: &Header) -> Result<Vec<BlockSize>>
+{
+	let infos = format::parse_key_block_infos(data.to_vec(), &header.0, u64::MAX, &mut vec![])?;
+	Ok(infos.into_iter()
+		.map(|info| BlockSize {
+			compressed_size: info.compressed_size,
+			decompressed_size: info.decompressed_size,
+		})
+		.collect())
+}
+
+/// Decrypt and decompress a key or record block, verifying its checksum.
+/// `index` is its position in its section, the nonce of some Salsa20
+/// variants
+pub fn decode_block(data: &[u8], index: usize, decompressed_size: usize) -> Result<Vec<u8>>
+{
+	let mut out = vec![];
+	let verified = format::decompress_verified(data, data.len(), decompressed_size, None,
+		&mut Salsa20Variant::Detect, index, DECOMPRESS_CHUNK, &mut vec![], &mut out)?;
+	if !verified {
+		return Err(Error::InvalidCheckSum("block"));
+	}
+	Ok(out)
+}
+
+/// Record offsets and keys of a decoded key block
+pub fn parse_keys(block: &[u8], header: &Header) -> Result<Vec<(usize, String)>>
+{
+	let mut entries = vec![];
+	format::parse_key_entries(block, &header.0, &|key: &str| key.to_string(), 0, Decoding::Lossy,
+		&mut entries, &mut vec![])?;
+	Ok(entries.into_iter()
+		.map(|KeyEntry { offset, text, .. }| (offset, text))
+		.collect())
+}
+
+/// The NUL terminated string at the start of `data` and the bytes it
+/// takes with its terminator
+#[inline]
+pub fn decode_slice_string<'a>(data: &'a [u8], encoding: &'static Encoding)
+	-> Result<(Cow<'a, str>, usize)>
+{
+	format::decode_slice_string(data, encoding)
+}
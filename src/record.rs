@@ -0,0 +1,617 @@
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::Arc;
+use encoding_rs::Encoding;
+
+use crate::format::{decode_slice_string, decode_slice_string_with};
+pub use crate::format::Decoding;
+use crate::html::{decode_entities, Tag, Token, Tokenizer};
+use crate::Result;
+#[cfg(feature = "scraper")]
+use crate::Error;
+
+/// What a record holds, so renderers can pick how to show it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+	Html,
+	Text,
+	/// an `@@@LINK=` redirect to another headword
+	Link,
+	/// resource data of an mdd file
+	Binary,
+}
+
+impl RecordKind {
+	/// Sniff the kind of an mdx record from its text
+	pub fn of_text(text: &str) -> Self
+	{
+		let text = text.trim_start();
+		if text.starts_with("@@@LINK=") {
+			return RecordKind::Link;
+		}
+		let html = Tokenizer::new(text)
+			.any(|token| matches!(token, Token::Tag(_) | Token::Other(_)));
+		if html { RecordKind::Html } else { RecordKind::Text }
+	}
+}
+
+#[inline]
+fn kind(data: &[u8], encoding: &'static Encoding, resource: bool) -> RecordKind
+{
+	if resource {
+		return RecordKind::Binary;
+	}
+	match decode_slice_string(data, encoding) {
+		Ok((text, _)) => RecordKind::of_text(&text),
+		Err(_) => RecordKind::Binary,
+	}
+}
+
+/// A record found by key, with where it was found
+#[derive(Debug)]
+pub struct Record<'a> {
+	pub(crate) key: String,
+	pub(crate) raw_key: Option<Box<str>>,
+	pub(crate) data: Cow<'a, [u8]>,
+	pub(crate) encoding: &'static Encoding,
+	pub(crate) offset: usize,
+	pub(crate) size: usize,
+	pub(crate) cached: bool,
+	// from an mdd file
+	pub(crate) resource: bool,
+}
+
+impl<'a> Record<'a> {
+	/// The matched key, after normalization by the `KeyMaker`
+	#[inline]
+	pub fn key(&self) -> &str
+	{
+		&self.key
+	}
+
+	/// The headword as stored in the dictionary
+	#[inline]
+	pub fn raw_key(&self) -> &str
+	{
+		self.raw_key.as_deref().unwrap_or(&self.key)
+	}
+
+	#[inline]
+	pub fn data(&self) -> &[u8]
+	{
+		&self.data
+	}
+
+	#[inline]
+	pub fn into_data(self) -> Cow<'a, [u8]>
+	{
+		self.data
+	}
+
+	/// Decode the record text with the dictionary encoding
+	#[inline]
+	pub fn text(&self) -> Result<Cow<'_, str>>
+	{
+		Ok(decode_slice_string(&self.data, self.encoding)?.0)
+	}
+
+	#[inline]
+	pub fn text_with(&self, decoding: Decoding) -> Result<Cow<'_, str>>
+	{
+		Ok(decode_slice_string_with(&self.data, self.encoding, decoding)?.0)
+	}
+
+	/// Decode the text with `encoding` instead of the dictionary one
+	#[inline]
+	pub fn with_encoding(mut self, encoding: &'static Encoding) -> Self
+	{
+		self.encoding = encoding;
+		self
+	}
+
+	#[inline]
+	pub fn kind(&self) -> RecordKind
+	{
+		kind(&self.data, self.encoding, self.resource)
+	}
+
+	/// Offset of the record in the decompressed record data
+	#[inline]
+	pub fn offset(&self) -> usize
+	{
+		self.offset
+	}
+
+	/// Record size in bytes, `data()` is shorter when truncated
+	#[inline]
+	pub fn size(&self) -> usize
+	{
+		self.size
+	}
+
+	/// Whether the record block was already in the cache
+	#[inline]
+	pub fn is_cached(&self) -> bool
+	{
+		self.cached
+	}
+}
+
+/// A record holding on to its decoded block, cheap to clone and to keep
+/// across lookups
+#[derive(Debug, Clone)]
+pub struct SharedRecord {
+	pub(crate) key: String,
+	pub(crate) raw_key: Option<Box<str>>,
+	pub(crate) block: Arc<Vec<u8>>,
+	pub(crate) range: Range<usize>,
+	pub(crate) encoding: &'static Encoding,
+	pub(crate) offset: usize,
+	pub(crate) size: usize,
+	pub(crate) cached: bool,
+	pub(crate) resource: bool,
+}
+
+impl SharedRecord {
+	/// The matched key, after normalization by the `KeyMaker`
+	#[inline]
+	pub fn key(&self) -> &str
+	{
+		&self.key
+	}
+
+	/// The headword as stored in the dictionary
+	#[inline]
+	pub fn raw_key(&self) -> &str
+	{
+		self.raw_key.as_deref().unwrap_or(&self.key)
+	}
+
+	#[inline]
+	pub fn data(&self) -> &[u8]
+	{
+		&self.block[self.range.clone()]
+	}
+
+	/// The decoded block and where the record is in it
+	#[inline]
+	pub fn into_parts(self) -> (Arc<Vec<u8>>, Range<usize>)
+	{
+		(self.block, self.range)
+	}
+
+	/// Decode the record text with the dictionary encoding
+	#[inline]
+	pub fn text(&self) -> Result<Cow<'_, str>>
+	{
+		Ok(decode_slice_string(self.data(), self.encoding)?.0)
+	}
+
+	#[inline]
+	pub fn text_with(&self, decoding: Decoding) -> Result<Cow<'_, str>>
+	{
+		Ok(decode_slice_string_with(self.data(), self.encoding, decoding)?.0)
+	}
+
+	/// Decode the text with `encoding` instead of the dictionary one
+	#[inline]
+	pub fn with_encoding(mut self, encoding: &'static Encoding) -> Self
+	{
+		self.encoding = encoding;
+		self
+	}
+
+	#[inline]
+	pub fn kind(&self) -> RecordKind
+	{
+		kind(self.data(), self.encoding, self.resource)
+	}
+
+	/// Offset of the record in the decompressed record data
+	#[inline]
+	pub fn offset(&self) -> usize
+	{
+		self.offset
+	}
+
+	/// Record size in bytes, `data()` is shorter when truncated
+	#[inline]
+	pub fn size(&self) -> usize
+	{
+		self.size
+	}
+
+	/// Whether the record block was already in the cache
+	#[inline]
+	pub fn is_cached(&self) -> bool
+	{
+		self.cached
+	}
+
+	/// The record data backed by the decoded block, without copying
+	#[cfg(feature = "bytes")]
+	pub fn into_bytes(self) -> bytes::Bytes
+	{
+		bytes::Bytes::from_owner(Block(self.block)).slice(self.range)
+	}
+}
+
+// lets `Bytes` own the shared block
+#[cfg(feature = "bytes")]
+struct Block(Arc<Vec<u8>>);
+
+#[cfg(feature = "bytes")]
+impl AsRef<[u8]> for Block {
+	#[inline]
+	fn as_ref(&self) -> &[u8]
+	{
+		&self.0
+	}
+}
+
+#[cfg(feature = "bytes")]
+impl From<SharedRecord> for bytes::Bytes {
+	#[inline]
+	fn from(record: SharedRecord) -> Self
+	{
+		record.into_bytes()
+	}
+}
+
+/// Markdown writer keeping track of pending whitespace and open lists
+#[derive(Default)]
+struct Markdown {
+	text: String,
+	space: bool,
+	// `None` for unordered lists, the next number otherwise
+	lists: Vec<Option<usize>>,
+	links: Vec<Option<String>>,
+	skip: usize,
+}
+
+impl Markdown {
+	#[inline]
+	fn at_line_start(&self) -> bool
+	{
+		self.text.is_empty() || self.text.ends_with('\n')
+	}
+
+	/// Start a new line, or a paragraph after an empty line
+	fn block(&mut self, paragraph: bool)
+	{
+		self.space = false;
+		if self.text.is_empty() {
+			return;
+		}
+		let newlines = self.text.len() - self.text.trim_end_matches('\n').len();
+		let wanted = if paragraph { 2 } else { 1 };
+		for _ in newlines..wanted {
+			self.text.push('\n');
+		}
+	}
+
+	fn inline(&mut self, markup: &str, opening: bool)
+	{
+		if opening && self.space && !self.at_line_start() {
+			self.text.push(' ');
+			self.space = false;
+		}
+		self.text.push_str(markup);
+	}
+
+	fn push_text(&mut self, text: &str)
+	{
+		let text = decode_entities(text);
+		for (idx, word) in text.split(|c: char| c.is_whitespace()).enumerate() {
+			if idx > 0 {
+				self.space = true;
+			}
+			if word.is_empty() {
+				continue;
+			}
+			if self.space && !self.at_line_start() {
+				self.text.push(' ');
+			}
+			self.space = false;
+			for ch in word.chars() {
+				if matches!(ch, '\\' | '*' | '_' | '`' | '[' | ']') {
+					self.text.push('\\');
+				}
+				self.text.push(ch);
+			}
+		}
+	}
+
+	fn tag(&mut self, tag: &Tag)
+	{
+		let opening = !tag.is_end();
+		match tag.name() {
+			"script" | "style" => if opening {
+				self.skip += 1;
+			} else {
+				self.skip = self.skip.saturating_sub(1);
+			},
+			"b" | "strong" => self.inline("**", opening),
+			"i" | "em" => self.inline("*", opening),
+			"code" => self.inline("`", opening),
+			"br" => {
+				self.text.push('\n');
+				self.space = false;
+			}
+			"hr" => {
+				self.block(true);
+				self.text.push_str("---");
+				self.block(true);
+			}
+			"p" | "blockquote" | "table" => self.block(true),
+			"div" | "tr" | "dt" | "dd" | "section" => self.block(false),
+			name @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+				self.block(true);
+				if opening {
+					let level = usize::from(name.as_bytes()[1] - b'0');
+					self.text.push_str(&"#".repeat(level));
+					self.text.push(' ');
+				}
+			}
+			"ul" | "ol" => {
+				let top = if opening {
+					self.lists.push(if tag.name() == "ol" { Some(1) } else { None });
+					self.lists.len() == 1
+				} else {
+					self.lists.pop();
+					self.lists.is_empty()
+				};
+				self.block(top);
+			}
+			"li" if opening => {
+				self.block(false);
+				let depth = self.lists.len().max(1) - 1;
+				self.text.push_str(&"  ".repeat(depth));
+				match self.lists.last_mut() {
+					Some(Some(number)) => {
+						self.text.push_str(&format!("{}. ", number));
+						*number += 1;
+					}
+					_ => self.text.push_str("- "),
+				}
+			}
+			"a" => if opening {
+				let href = tag.attr("href").map(|href| decode_entities(href).into_owned());
+				if href.is_some() {
+					self.inline("[", true);
+				}
+				self.links.push(href);
+			} else if let Some(Some(href)) = self.links.pop() {
+				self.text.push_str(&format!("]({})", href.replace(' ', "%20")));
+			},
+			"img" => if let Some(src) = tag.attr("src") {
+				let alt = tag.attr("alt").map(decode_entities).unwrap_or_default();
+				self.inline(&format!("![{}]({})", alt, src.replace(' ', "%20")), true);
+			},
+			_ => {}
+		}
+	}
+}
+
+/// Convert definition html to markdown, keeping emphasis, headings,
+/// lists, links and images and dropping other markup
+pub fn to_markdown(html: &str) -> String
+{
+	let mut markdown = Markdown::default();
+	for token in Tokenizer::new(html) {
+		match token {
+			Token::Text(text) => if markdown.skip == 0 {
+				markdown.push_text(text);
+			},
+			Token::Tag(tag) => markdown.tag(&tag),
+			Token::Other(_) => {}
+		}
+	}
+	markdown.text.trim_end().to_owned()
+}
+
+/// Outer html of the elements of the definition `html` matching the CSS
+/// selector `selector`, in document order, e.g. `span.pron` for just the
+/// pronunciation of a verbose entry
+#[cfg(feature = "scraper")]
+pub fn select(html: &str, selector: &str) -> Result<Vec<String>>
+{
+	let selector = scraper::Selector::parse(selector)
+		.map_err(|err| Error::InvalidSelector(err.to_string()))?;
+	Ok(scraper::Html::parse_fragment(html)
+		.select(&selector)
+		.map(|element| element.html())
+		.collect())
+}
+
+/// Transcription system of a pronunciation
+#[cfg(feature = "scraper")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transcription {
+	Ipa,
+	Pinyin,
+	Jyutping,
+}
+
+/// Selectors of the pronunciations in definitions by transcription
+/// system, with defaults for the markup of popular dictionaries
+#[cfg(feature = "scraper")]
+#[derive(Debug, Clone)]
+pub struct Pronunciations {
+	selectors: Vec<(Transcription, scraper::Selector)>,
+}
+
+#[cfg(feature = "scraper")]
+const PRONUNCIATION_SELECTORS: [(Transcription, &str); 3] = [
+	(Transcription::Ipa, ".phon, .pron, .PRON, .ipa, .IPA, .phonetic, .pr"),
+	(Transcription::Pinyin, ".pinyin, .py"),
+	(Transcription::Jyutping, ".jyutping, .jp"),
+];
+
+#[cfg(feature = "scraper")]
+impl Default for Pronunciations {
+	fn default() -> Self
+	{
+		let selectors = PRONUNCIATION_SELECTORS.iter()
+			.map(|(system, selector)| (*system, scraper::Selector::parse(selector)
+				.expect("default pronunciation selectors are valid")))
+			.collect();
+		Pronunciations { selectors }
+	}
+}
+
+#[cfg(feature = "scraper")]
+impl Pronunciations {
+	/// Selectors of none
+	#[inline]
+	pub fn empty() -> Self
+	{
+		Pronunciations { selectors: vec![] }
+	}
+
+	/// Also take the text of the elements matching `selector` as
+	/// pronunciations in `system`
+	pub fn selector(mut self, system: Transcription, selector: &str) -> Result<Self>
+	{
+		let selector = scraper::Selector::parse(selector)
+			.map_err(|err| Error::InvalidSelector(err.to_string()))?;
+		self.selectors.push((system, selector));
+		Ok(self)
+	}
+
+	/// The pronunciations of the definition `html`, without the slashes or
+	/// brackets around them, each once
+	pub fn extract(&self, html: &str) -> Vec<(Transcription, String)>
+	{
+		let document = scraper::Html::parse_fragment(html);
+		let mut found: Vec<(Transcription, String)> = vec![];
+		for (system, selector) in &self.selectors {
+			for element in document.select(selector) {
+				let text = element.text().collect::<String>();
+				let text = collapse_whitespace(text.trim_matches(|ch: char| "/[]".contains(ch)));
+				if !text.is_empty() && !found.iter().any(|(other, seen)| other == system && *seen == text) {
+					found.push((*system, text));
+				}
+			}
+		}
+		found
+	}
+}
+
+/// An example sentence of a definition
+#[cfg(feature = "scraper")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Example {
+	pub sentence: String,
+	/// when marked up within the example
+	pub translation: Option<String>,
+}
+
+/// Selectors of the example sentences in definitions and of their
+/// translations within them, with defaults for the markup of popular
+/// dictionaries
+#[cfg(feature = "scraper")]
+#[derive(Debug, Clone)]
+pub struct Examples {
+	example: scraper::Selector,
+	translation: scraper::Selector,
+}
+
+#[cfg(feature = "scraper")]
+impl Default for Examples {
+	fn default() -> Self
+	{
+		let parse = |selector| scraper::Selector::parse(selector)
+			.expect("default example selectors are valid");
+		Examples {
+			example: parse(".example, .EXAMPLE, .ex, .eg, .exa, .x, .sentence"),
+			translation: parse(".translation, .trans, .tran, .tr, .xtr, .chn"),
+		}
+	}
+}
+
+#[cfg(feature = "scraper")]
+impl Examples {
+	/// Examples are the elements matching `example`, their translations
+	/// the first elements within matching `translation`
+	pub fn new(example: &str, translation: &str) -> Result<Self>
+	{
+		let parse = |selector| scraper::Selector::parse(selector)
+			.map_err(|err| Error::InvalidSelector(err.to_string()));
+		Ok(Examples { example: parse(example)?, translation: parse(translation)? })
+	}
+
+	/// The examples of the definition `html` in document order, those
+	/// within others are part of them
+	pub fn extract(&self, html: &str) -> Vec<Example>
+	{
+		let document = scraper::Html::parse_fragment(html);
+		document.select(&self.example)
+			.filter(|element| !element.ancestors()
+				.filter_map(scraper::ElementRef::wrap)
+				.any(|ancestor| self.example.matches(&ancestor)))
+			.filter_map(|element| {
+				let translation = element.select(&self.translation).next();
+				let sentence = rendered_text(element, translation);
+				if sentence.is_empty() {
+					return None;
+				}
+				let translation = translation
+					.map(|translation| rendered_text(translation, None))
+					.filter(|translation| !translation.is_empty());
+				Some(Example { sentence, translation })
+			})
+			.collect()
+	}
+}
+
+/// The example sentences of the definition `html` by the default
+/// selectors of [`Examples`]
+#[cfg(feature = "scraper")]
+#[inline]
+pub fn examples(html: &str) -> Vec<Example>
+{
+	Examples::default().extract(html)
+}
+
+/// The text within `element` outside `skip` as rendered: inline tags join
+/// their neighbours, block-level elements and line breaks separate them
+#[cfg(feature = "scraper")]
+fn rendered_text(element: scraper::ElementRef, skip: Option<scraper::ElementRef>) -> String
+{
+	let mut text = String::new();
+	let mut block = None;
+	for node in element.descendants() {
+		if skip.is_some_and(|skip| node.ancestors().any(|ancestor| ancestor.id() == skip.id())) {
+			continue;
+		}
+		if let Some(tag) = node.value().as_element() {
+			if tag.name() == "br" {
+				text.push(' ');
+			}
+			continue;
+		}
+		let Some(part) = node.value().as_text() else {
+			continue;
+		};
+		// the innermost block below `element` the text is laid out in
+		let within = node.ancestors()
+			.take_while(|ancestor| ancestor.id() != element.id())
+			.find(|ancestor| ancestor.value().as_element()
+				.is_some_and(|tag| crate::html::BLOCK_ELEMENTS.contains(&tag.name())))
+			.map(|ancestor| ancestor.id());
+		if within != block {
+			text.push(' ');
+			block = within;
+		}
+		text.push_str(part);
+	}
+	collapse_whitespace(&text)
+}
+
+#[cfg(feature = "scraper")]
+#[inline]
+fn collapse_whitespace(text: &str) -> String
+{
+	text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
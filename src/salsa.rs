@@ -0,0 +1,58 @@
+//! Salsa20 with the 128-bit keys of encrypted blocks, which the `salsa20`
+//! crate doesn't take
+
+use byteorder::{ByteOrder, LE};
+
+// "expand 16-byte k"
+const TAU: [u32; 4] = [0x6170_7865, 0x3120_646e, 0x7962_2d36, 0x6b20_6574];
+
+#[inline]
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize)
+{
+	x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+	x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+	x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+	x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+}
+
+/// Keystream block of `input`, the nonce followed by the block counter
+pub(crate) fn block(key: &[u8; 16], input: &[u8; 16], rounds: usize) -> [u8; 64]
+{
+	let mut state = [0; 16];
+	for i in 0..4 {
+		state[i * 5] = TAU[i];
+		state[1 + i] = LE::read_u32(&key[i * 4..]);
+		state[11 + i] = state[1 + i];
+		state[6 + i] = LE::read_u32(&input[i * 4..]);
+	}
+	let mut x = state;
+	for _ in 0..rounds / 2 {
+		quarter_round(&mut x, 0, 4, 8, 12);
+		quarter_round(&mut x, 5, 9, 13, 1);
+		quarter_round(&mut x, 10, 14, 2, 6);
+		quarter_round(&mut x, 15, 3, 7, 11);
+		quarter_round(&mut x, 0, 1, 2, 3);
+		quarter_round(&mut x, 5, 6, 7, 4);
+		quarter_round(&mut x, 10, 11, 8, 9);
+		quarter_round(&mut x, 15, 12, 13, 14);
+	}
+	let mut out = [0; 64];
+	for i in 0..16 {
+		LE::write_u32(&mut out[i * 4..], x[i].wrapping_add(state[i]));
+	}
+	out
+}
+
+/// Xor `data` with the keystream of `key` and `nonce`
+pub(crate) fn apply_keystream(key: &[u8; 16], nonce: u64, rounds: usize, data: &mut [u8])
+{
+	let mut input = [0; 16];
+	LE::write_u64(&mut input[..8], nonce);
+	for (counter, chunk) in data.chunks_mut(64).enumerate() {
+		LE::write_u64(&mut input[8..], counter as u64);
+		for (byte, key) in chunk.iter_mut().zip(block(key, &input, rounds)) {
+			*byte ^= key;
+		}
+	}
+}
+
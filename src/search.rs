@@ -0,0 +1,290 @@
+use std::borrow::Cow;
+use std::ops::{ControlFlow, Range};
+use std::time::{Duration, Instant};
+use regex::Regex;
+
+use crate::collation::KeyOrder;
+use crate::format::decode_slice_string;
+use crate::html::text_content;
+use crate::mdx::{KeyMaker, MDict};
+use crate::parser::{find_entry, for_each_record_until};
+
+/// Levenshtein distance of `a` and `b` if it's not over `max`
+pub(crate) fn edit_distance(a: &[char], b: &str, max: usize) -> Option<usize>
+{
+	let b: Vec<char> = b.chars().collect();
+	if a.len().abs_diff(b.len()) > max {
+		return None;
+	}
+	let mut prev: Vec<usize> = (0..=b.len()).collect();
+	let mut row = vec![0; b.len() + 1];
+	for (i, ca) in a.iter().enumerate() {
+		row[0] = i + 1;
+		let mut row_min = row[0];
+		for (j, cb) in b.iter().enumerate() {
+			let cost = usize::from(ca != cb);
+			row[j + 1] = (prev[j] + cost)
+				.min(prev[j + 1] + 1)
+				.min(row[j] + 1);
+			row_min = row_min.min(row[j + 1]);
+		}
+		if row_min > max {
+			return None;
+		}
+		std::mem::swap(&mut prev, &mut row);
+	}
+	let distance = prev[b.len()];
+	if distance <= max {
+		Some(distance)
+	} else {
+		None
+	}
+}
+
+// keys scanned between clock reads
+const CLOCK_INTERVAL: usize = 256;
+// characters of definition text kept on each side of a grep match
+const SNIPPET_CONTEXT: usize = 40;
+
+/// Limits on the work done by a search, unlimited by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchBudget {
+	pub time: Option<Duration>,
+	/// number of keys examined
+	pub keys: Option<usize>,
+}
+
+impl SearchBudget {
+	#[inline]
+	pub fn time(time: Duration) -> Self
+	{
+		SearchBudget { time: Some(time), keys: None }
+	}
+
+	#[inline]
+	pub fn keys(keys: usize) -> Self
+	{
+		SearchBudget { time: None, keys: Some(keys) }
+	}
+}
+
+/// A headword as stored in the dictionary and its normalized key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Headword<'a> {
+	pub raw: &'a str,
+	pub key: &'a str,
+}
+
+/// Results of a budgeted search
+#[derive(Debug, Clone)]
+pub struct Partial<T> {
+	pub items: Vec<T>,
+	/// the budget ran out before every key was examined
+	pub truncated: bool,
+}
+
+pub(crate) struct Meter {
+	deadline: Option<Instant>,
+	keys_left: Option<usize>,
+	ticks: usize,
+}
+
+impl Meter {
+	pub(crate) fn new(budget: &SearchBudget) -> Self
+	{
+		Meter {
+			deadline: budget.time.map(|time| Instant::now() + time),
+			keys_left: budget.keys,
+			ticks: 0,
+		}
+	}
+
+	/// Account for one examined key, false once the budget is spent
+	pub(crate) fn tick(&mut self) -> bool
+	{
+		if let Some(left) = &mut self.keys_left {
+			if *left == 0 {
+				return false;
+			}
+			*left -= 1;
+		}
+		self.ticks += 1;
+		match self.deadline {
+			Some(deadline) if self.ticks.is_multiple_of(CLOCK_INTERVAL) => Instant::now() < deadline,
+			_ => true,
+		}
+	}
+
+	/// Collect `items` until the budget is spent, `examine` is called
+	/// once per key examined
+	pub(crate) fn collect<I, T>(mut self, candidates: I, mut examine: impl FnMut(I::Item) -> Option<T>)
+		-> Partial<T>
+		where I: Iterator
+	{
+		let mut items = vec![];
+		for candidate in candidates {
+			if !self.tick() {
+				return Partial { items, truncated: true };
+			}
+			if let Some(item) = examine(candidate) {
+				items.push(item);
+			}
+		}
+		Partial { items, truncated: false }
+	}
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// The stored key `word` is normalized to or is an alias of, if it
+	/// exists
+	#[inline]
+	pub fn find_key(&self, word: &str) -> Option<&str>
+	{
+		let key = self.resolve_key(word);
+		find_entry(&self.mdx, &key).map(|entry| entry.text.as_str())
+	}
+
+	/// All keys in key order, equal keys once
+	#[inline]
+	pub fn keys(&self) -> impl Iterator<Item=&str>
+	{
+		dedup_keys(self.mdx.key_entries.iter().map(|entry| entry.text.as_str()))
+	}
+
+	/// Every entry as its stored headword and normalized key, in key
+	/// order, keys normalized alike are repeated, by record offset then
+	/// headword. The order is the same on every run and platform
+	pub fn headwords(&self) -> impl Iterator<Item=Headword<'_>>
+	{
+		self.mdx.key_entries.iter().map(|entry| Headword { raw: entry.raw(), key: &entry.text })
+	}
+
+	/// The stored headwords normalized to the same key as `word`
+	pub fn raw_keys(&self, word: &str) -> impl Iterator<Item=&str>
+	{
+		let key = self.key_maker.make(&Cow::Borrowed(word), false);
+		let entries = &self.mdx.key_entries;
+		let order = &self.mdx.key_order;
+		let start = entries.partition_point(|entry| order.compare(&entry.text, &key).is_lt());
+		entries[start..].iter()
+			.take_while(move |entry| order.compare(&entry.text, &key).is_eq())
+			.map(|entry| entry.raw())
+	}
+
+	/// Keys starting with `prefix`, in key order
+	pub fn search_prefix(&self, prefix: &str) -> impl Iterator<Item=&str>
+	{
+		let prefix = self.key_maker.make(&Cow::Borrowed(prefix), false);
+		let entries = &self.mdx.key_entries;
+		// matches are contiguous in byte order
+		let (start, contiguous) = match self.mdx.key_order {
+			KeyOrder::Bytes => (entries.partition_point(|entry| entry.text < prefix), true),
+			#[allow(unreachable_patterns)]
+			_ => (0, false),
+		};
+		let end_prefix = prefix.clone();
+		let keys = entries[start..].iter()
+			.map(|entry| entry.text.as_str())
+			.take_while(move |key| !contiguous || key.starts_with(end_prefix.as_str()))
+			.filter(move |key| key.starts_with(prefix.as_str()));
+		dedup_keys(keys)
+	}
+
+	/// `html` with `query` and the key it normalizes to marked, see
+	/// [`highlight`](crate::transform::highlight)
+	pub fn highlight(&self, html: &str, query: &str) -> String
+	{
+		let key = self.key_maker.make(&Cow::Borrowed(query), false);
+		crate::transform::highlight(html, &[query, &key])
+	}
+
+	/// Keys within `max_distance` edits of `word`, with their distance
+	pub fn search_fuzzy(&self, word: &str, max_distance: usize)
+		-> impl Iterator<Item=(&str, usize)>
+	{
+		let word = self.key_maker.make(&Cow::Borrowed(word), false);
+		let word: Vec<char> = word.chars().collect();
+		let mut prev = None;
+		self.mdx.key_entries.iter()
+			.filter(move |entry| {
+				let first = prev != Some(entry.text.as_str());
+				prev = Some(entry.text.as_str());
+				first
+			})
+			.filter_map(move |entry| edit_distance(&word, &entry.text, max_distance)
+				.map(|distance| (entry.text.as_str(), distance)))
+	}
+
+	/// `search_fuzzy()` stopping when `budget` is spent
+	pub fn search_fuzzy_within(&self, word: &str, max_distance: usize, budget: &SearchBudget)
+		-> Partial<(&str, usize)>
+	{
+		let word = self.key_maker.make(&Cow::Borrowed(word), false);
+		let word: Vec<char> = word.chars().collect();
+		let keys = dedup_keys(self.mdx.key_entries.iter().map(|entry| entry.text.as_str()));
+		Meter::new(budget).collect(keys, |key| edit_distance(&word, key, max_distance)
+			.map(|distance| (key, distance)))
+	}
+}
+
+/// A match of `MDict::grep()`
+#[derive(Debug, Clone)]
+pub struct GrepHit {
+	/// the headword as stored
+	pub key: String,
+	/// definition text around the match, without markup
+	pub snippet: String,
+	/// where the match is in `snippet`
+	pub range: Range<usize>,
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Search the text of every definition for `pattern`, decoding each
+	/// record block once, up to `limit` matches. `truncated` tells the
+	/// limit was reached
+	pub fn grep(&mut self, pattern: &Regex, limit: usize) -> crate::Result<Partial<GrepHit>>
+	{
+		let encoding = self.mdx.encoding;
+		let mut items = vec![];
+		let mut truncated = false;
+		for_each_record_until(&mut self.mdx, |entry, data| {
+			let definition = decode_slice_string(data, encoding)?.0;
+			let text = text_content(&definition);
+			for found in pattern.find_iter(&text) {
+				if items.len() == limit {
+					truncated = true;
+					return Ok(ControlFlow::Break(()));
+				}
+				let (snippet, range) = snippet(&text, found.range());
+				items.push(GrepHit { key: entry.raw().to_owned(), snippet, range });
+			}
+			Ok(ControlFlow::Continue(()))
+		})?;
+		Ok(Partial { items, truncated })
+	}
+}
+
+/// `SNIPPET_CONTEXT` characters of `text` on each side of `range`, and where
+/// `range` is in the snippet
+fn snippet(text: &str, range: Range<usize>) -> (String, Range<usize>)
+{
+	let start = text[..range.start].char_indices()
+		.rev()
+		.nth(SNIPPET_CONTEXT - 1)
+		.map_or(0, |(index, _)| index);
+	let end = text[range.end..].char_indices()
+		.nth(SNIPPET_CONTEXT)
+		.map_or(text.len(), |(index, _)| range.end + index);
+	(text[start..end].to_owned(), range.start - start..range.end - start)
+}
+
+/// Skip repeated keys, equal keys are next to each other
+pub(crate) fn dedup_keys<'a>(keys: impl Iterator<Item=&'a str>) -> impl Iterator<Item=&'a str>
+{
+	let mut prev = None;
+	keys.filter(move |key| {
+		let first = prev != Some(*key);
+		prev = Some(*key);
+		first
+	})
+}
@@ -0,0 +1,456 @@
+//! Static website export, one html page per entry or per first letter
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::companion::CompanionKind;
+use crate::format::decode_slice_string;
+use crate::html::{decode_entities, Token, Tokenizer};
+use crate::lint::resource_path;
+use crate::mdx::{KeyMaker, MDict};
+use crate::parser::RecordReader;
+use crate::template::escape_attr;
+use crate::Result;
+
+pub(crate) struct Article {
+	/// the headword as stored
+	pub(crate) headword: String,
+	pub(crate) html: String,
+}
+
+/// The definitions of a dictionary a key at a time, in key order, with
+/// redirects turned into links to their target. Only those of one key are
+/// held at a time
+pub(crate) struct Articles {
+	// key entry indices by key, then record offset
+	order: Vec<usize>,
+	at: usize,
+	records: RecordReader,
+}
+
+impl Articles {
+	pub(crate) fn new<M: KeyMaker>(dict: &MDict<M>) -> Self
+	{
+		let entries = &dict.mdx.key_entries;
+		let mut order: Vec<usize> = (0..entries.len()).collect();
+		order.sort_by(|a, b| {
+			let (a, b) = (&entries[*a], &entries[*b]);
+			a.text.cmp(&b.text).then(a.offset.cmp(&b.offset))
+		});
+		Articles { order, at: 0, records: RecordReader::default() }
+	}
+
+	/// The next key and its entries
+	pub(crate) fn next<M: KeyMaker>(&mut self, dict: &mut MDict<M>)
+		-> Result<Option<(String, Vec<Article>)>>
+	{
+		let Some(first) = self.order.get(self.at) else {
+			return Ok(None);
+		};
+		let key = dict.mdx.key_entries[*first].text.clone();
+		let mut articles = vec![];
+		while let Some(&index) = self.order.get(self.at) {
+			let entry = &dict.mdx.key_entries[index];
+			if entry.text != key {
+				break;
+			}
+			let headword = entry.raw().to_owned();
+			let data = self.records.read(&mut dict.mdx, index)?;
+			let definition = decode_slice_string(data, dict.mdx.encoding)?.0;
+			let html = match definition.strip_prefix("@@@LINK=") {
+				Some(target) => {
+					let target = target.trim_end_matches(['\r', '\n', '\0']);
+					format!("<a href=\"entry://{}\">{}</a>", escape_attr(target), escape_text(target))
+				}
+				None => definition.into_owned(),
+			};
+			articles.push(Article { headword, html });
+			self.at += 1;
+		}
+		Ok(Some((key, articles)))
+	}
+}
+
+/// The words leading to each key: the key, its headwords as stored and
+/// the aliases set on the dictionary
+pub(crate) fn forms<M: KeyMaker>(dict: &MDict<M>) -> BTreeMap<String, BTreeSet<String>>
+{
+	let mut forms: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+	for entry in &dict.mdx.key_entries {
+		let forms = forms.entry(entry.text.clone()).or_default();
+		forms.insert(entry.text.clone());
+		forms.insert(entry.raw().to_owned());
+	}
+	for (alias, key) in &dict.aliases {
+		if let Some(forms) = forms.get_mut(key) {
+			forms.insert(alias.clone());
+		}
+	}
+	forms
+}
+
+#[inline]
+pub(crate) fn escape_text(text: &str) -> String
+{
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// `html` with `entry://` links given by `entry_href` and resource
+/// references by `resource_href`, the mdd paths referenced are added to
+/// `resources`
+pub(crate) fn rewrite(html: &str, entry_href: &dyn Fn(&str) -> Option<String>,
+	resource_href: &dyn Fn(&str) -> String, resources: &mut BTreeSet<String>) -> String
+{
+	let mut out = String::with_capacity(html.len());
+	for token in Tokenizer::new(html) {
+		match token {
+			Token::Tag(mut tag) => {
+				for name in ["href", "src"] {
+					let Some(value) = tag.attr(name) else {
+						continue;
+					};
+					let href = if let Some(target) = value.strip_prefix("entry://") {
+						let (word, fragment) = target.split_once('#').unwrap_or((target, ""));
+						entry_href(word).map(|href| if fragment.is_empty() {
+							href
+						} else {
+							format!("{}#{}", href.split('#').next().unwrap_or(&href), fragment)
+						})
+					} else {
+						resource_path(value).map(|path| {
+							let href = resource_href(&path);
+							resources.insert(path);
+							href
+						})
+					};
+					if let Some(href) = href {
+						tag.set_attr(name, href);
+					}
+				}
+				let _ = write!(out, "{}", tag);
+			}
+			Token::Text(text) | Token::Other(text) => out.push_str(text),
+		}
+	}
+	out
+}
+
+// elements without content or end tag
+const VOID: [&str; 13] = ["area", "base", "br", "col", "embed", "hr", "img", "input", "link",
+	"meta", "source", "track", "wbr"];
+
+/// Well formed XHTML of an html fragment: void and unclosed elements are
+/// closed, stray end tags, comments and odd attribute names dropped and
+/// character references unknown to XML decoded
+pub(crate) fn to_xhtml(html: &str) -> String
+{
+	let mut out = String::with_capacity(html.len());
+	let mut open: Vec<String> = vec![];
+	for token in Tokenizer::new(html) {
+		match token {
+			Token::Tag(tag) if xml_name(tag.name()) => {
+				let name = tag.name();
+				if tag.is_end() {
+					if let Some(idx) = open.iter().rposition(|open| open == name) {
+						for name in open.drain(idx..).rev() {
+							let _ = write!(out, "</{}>", name);
+						}
+					}
+					continue;
+				}
+				let _ = write!(out, "<{}", name);
+				let mut seen = vec![];
+				for (attr, value) in tag.attrs() {
+					let attr = attr.to_ascii_lowercase();
+					if !xml_name(&attr) || seen.contains(&attr) {
+						continue;
+					}
+					let value = decode_entities(value.unwrap_or(attr.as_str())).into_owned();
+					let _ = write!(out, " {}=\"{}\"", attr, escape_attr(&value));
+					seen.push(attr);
+				}
+				if tag.is_self_closing() || VOID.contains(&name) {
+					out.push_str("/>");
+				} else {
+					out.push('>');
+					open.push(name.to_owned());
+				}
+			}
+			Token::Text(text) => out.push_str(&escape_text(&decode_entities(text))),
+			Token::Tag(_) | Token::Other(_) => {}
+		}
+	}
+	for name in open.into_iter().rev() {
+		let _ = write!(out, "</{}>", name);
+	}
+	out
+}
+
+/// A name fine for XML without namespace declarations
+fn xml_name(name: &str) -> bool
+{
+	name.starts_with(|ch: char| ch.is_ascii_alphabetic())
+		&& name.chars().all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.'))
+		|| name == "xml:lang"
+}
+
+/// Relative path of an mdd resource, without `..` or absolute parts
+pub(crate) fn resource_file(path: &str) -> PathBuf
+{
+	path.split(['\\', '/'])
+		.filter(|part| !part.is_empty() && *part != "." && *part != "..")
+		.collect()
+}
+
+/// The resource path as a url relative to the resource directory
+#[inline]
+pub(crate) fn resource_url(path: &str) -> String
+{
+	let file = resource_file(path);
+	let parts: Vec<String> = file.iter()
+		.map(|part| percent_encode(&part.to_string_lossy()))
+		.collect();
+	parts.join("/")
+}
+
+/// Write the resources referenced into `dir`, returns those missing from
+/// the mdd files
+pub(crate) fn extract_resources<M: KeyMaker>(dict: &mut MDict<M>, resources: &BTreeSet<String>,
+	dir: &Path) -> Result<Vec<String>>
+{
+	let mut missing = vec![];
+	for path in resources {
+		match dict.get_resource(path)? {
+			Some(data) => {
+				let file = dir.join(resource_file(path));
+				if let Some(parent) = file.parent() {
+					fs::create_dir_all(parent)?;
+				}
+				fs::write(file, data)?;
+			}
+			None => missing.push(path.clone()),
+		}
+	}
+	Ok(missing)
+}
+
+pub(crate) fn media_type(path: &str) -> &'static str
+{
+	let extension = path.rsplit_once('.')
+		.map(|(_, extension)| extension.to_ascii_lowercase())
+		.unwrap_or_default();
+	match extension.as_str() {
+		"png" => "image/png",
+		"jpg" | "jpeg" => "image/jpeg",
+		"gif" => "image/gif",
+		"svg" => "image/svg+xml",
+		"webp" => "image/webp",
+		"css" => "text/css",
+		"js" => "application/javascript",
+		"mp3" => "audio/mpeg",
+		"ogg" => "audio/ogg",
+		"wav" => "audio/wav",
+		"ttf" => "font/ttf",
+		"otf" => "font/otf",
+		"woff" => "font/woff",
+		"woff2" => "font/woff2",
+		_ => "application/octet-stream",
+	}
+}
+
+/// Form of `key` safe as file name, url and anchor, `~XX` for other
+/// bytes
+pub(crate) fn slug(key: &str) -> String
+{
+	if key.is_empty() {
+		return "_".to_owned();
+	}
+	escape_bytes(key, '~')
+}
+
+#[inline]
+fn percent_encode(text: &str) -> String
+{
+	escape_bytes(text, '%')
+}
+
+fn escape_bytes(text: &str, marker: char) -> String
+{
+	let mut escaped = String::with_capacity(text.len());
+	for ch in text.chars() {
+		if ch.is_alphanumeric() || matches!(ch, '-' | '_' | '.') && !escaped.is_empty() {
+			escaped.push(ch);
+		} else {
+			let mut buf = [0; 4];
+			for byte in ch.encode_utf8(&mut buf).bytes() {
+				let _ = write!(escaped, "{}{:02X}", marker, byte);
+			}
+		}
+	}
+	escaped
+}
+
+/// How entries are spread over pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pages {
+	PerEntry,
+	/// one page for all keys starting with the same character
+	PerLetter,
+}
+
+/// What a site export wrote
+#[derive(Debug, Clone, Default)]
+pub struct SiteSummary {
+	pub pages: usize,
+	pub entries: usize,
+	pub resources: usize,
+	/// resources referenced but not found in the mdd files
+	pub missing: Vec<String>,
+}
+
+/// Writes a dictionary as a browsable static site: `index.html`, the
+/// pages in `entries/` and the resources in `res/`
+pub struct SiteExporter {
+	pages: Pages,
+}
+
+impl Default for SiteExporter {
+	#[inline]
+	fn default() -> Self
+	{
+		SiteExporter { pages: Pages::PerEntry }
+	}
+}
+
+impl SiteExporter {
+	#[inline]
+	pub fn new() -> Self
+	{
+		Self::default()
+	}
+
+	#[inline]
+	pub fn pages(mut self, pages: Pages) -> Self
+	{
+		self.pages = pages;
+		self
+	}
+
+	/// The page and anchor of a key, relative to `entries/`
+	fn page(&self, key: &str) -> (String, String)
+	{
+		let anchor = slug(key);
+		match self.pages {
+			Pages::PerEntry => (format!("{}.html", anchor), anchor),
+			Pages::PerLetter => {
+				let letter: String = key.chars().next().into_iter().collect();
+				(format!("{}.html", slug(&letter)), anchor)
+			}
+		}
+	}
+
+	pub fn write<M: KeyMaker>(&self, dict: &mut MDict<M>, dir: impl AsRef<Path>)
+		-> Result<SiteSummary>
+	{
+		let dir = dir.as_ref();
+		let entries_dir = dir.join("entries");
+		fs::create_dir_all(&entries_dir)?;
+		let css = match dict.read_companion(CompanionKind::Css)? {
+			Some(css) => {
+				fs::write(dir.join("style.css"), css)?;
+				"<link rel=\"stylesheet\" href=\"../style.css\">\n"
+			}
+			None => "",
+		};
+		let title = escape_text(dict.title());
+		let mut index = format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+			<title>{}</title>\n</head>\n<body>\n<h1>{}</h1>\n<ul>\n", title, title);
+
+		// keys of a page are adjacent in key order, so pages are written
+		// one at a time
+		let mut summary = SiteSummary::default();
+		let mut page: Option<(String, BufWriter<File>)> = None;
+		let mut articles = Articles::new(dict);
+		let mut resources = BTreeSet::new();
+		let resource_href = |path: &str| format!("../res/{}", resource_url(path));
+		while let Some((key, articles)) = articles.next(dict)? {
+			let (name, anchor) = self.page(&key);
+			let (_, html) = match page.take() {
+				Some((open, html)) if open == name => page.insert((open, html)),
+				last => {
+					if let Some((_, html)) = last {
+						end_page(html)?;
+					}
+					let mut html = BufWriter::new(File::create(entries_dir.join(&name))?);
+					write!(html, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+						<title>{}</title>\n{}</head>\n<body>\n\
+						<p><a href=\"../index.html\">{}</a></p>\n", title, css, title)?;
+					if self.pages == Pages::PerLetter {
+						let letter = name.trim_end_matches(".html");
+						let _ = writeln!(index, "<li><a href=\"entries/{}\">{}</a></li>",
+							escape_attr(&name), escape_text(&unslug(letter)));
+					}
+					summary.pages += 1;
+					page.insert((name, html))
+				}
+			};
+			if self.pages == Pages::PerEntry {
+				let (page, _) = self.page(&key);
+				let _ = writeln!(index, "<li><a href=\"entries/{}\">{}</a></li>",
+					escape_attr(&page), escape_text(&key));
+			}
+			let entry_href = |word: &str| {
+				let key = dict.find_key(word)?;
+				let (page, anchor) = self.page(key);
+				Some(format!("{}#{}", page, anchor))
+			};
+			writeln!(html, "<section id=\"{}\">", escape_attr(&anchor))?;
+			for article in &articles {
+				writeln!(html, "<h2>{}</h2>\n{}", escape_text(&article.headword),
+					rewrite(&article.html, &entry_href, &resource_href, &mut resources))?;
+			}
+			html.write_all(b"</section>\n")?;
+			summary.entries += articles.len();
+		}
+		if let Some((_, html)) = page {
+			end_page(html)?;
+		}
+		index.push_str("</ul>\n</body>\n</html>\n");
+		fs::write(dir.join("index.html"), index)?;
+
+		summary.missing = extract_resources(dict, &resources, &dir.join("res"))?;
+		summary.resources = resources.len() - summary.missing.len();
+		Ok(summary)
+	}
+}
+
+#[inline]
+fn end_page(mut html: BufWriter<File>) -> Result<()>
+{
+	html.write_all(b"</body>\n</html>\n")?;
+	html.flush()?;
+	Ok(())
+}
+
+/// The text a slug was made from
+pub(crate) fn unslug(slug: &str) -> String
+{
+	let mut bytes = vec![];
+	let mut rest = slug.as_bytes();
+	while let Some((&byte, tail)) = rest.split_first() {
+		match (byte, tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok())) {
+			(b'~', Some(hex)) if u8::from_str_radix(hex, 16).is_ok() => {
+				bytes.push(u8::from_str_radix(hex, 16).unwrap_or(0));
+				rest = &tail[2..];
+			}
+			_ => {
+				bytes.push(byte);
+				rest = tail;
+			}
+		}
+	}
+	String::from_utf8_lossy(&bytes).into_owned()
+}
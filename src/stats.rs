@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+
+use crate::mdx::{KeyMaker, MDict};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Script {
+	Latin,
+	Greek,
+	Cyrillic,
+	Armenian,
+	Hebrew,
+	Arabic,
+	Devanagari,
+	Thai,
+	Hangul,
+	Hiragana,
+	Katakana,
+	Han,
+	Digit,
+	Other,
+}
+
+impl Script {
+	pub fn of(ch: char) -> Script
+	{
+		match ch as u32 {
+			0x30..=0x39 => Script::Digit,
+			0x41..=0x5a | 0x61..=0x7a | 0xc0..=0x24f | 0x1e00..=0x1eff => Script::Latin,
+			0x370..=0x3ff | 0x1f00..=0x1fff => Script::Greek,
+			0x400..=0x52f => Script::Cyrillic,
+			0x530..=0x58f => Script::Armenian,
+			0x590..=0x5ff => Script::Hebrew,
+			0x600..=0x6ff | 0x750..=0x77f => Script::Arabic,
+			0x900..=0x97f => Script::Devanagari,
+			0xe00..=0xe7f => Script::Thai,
+			0x1100..=0x11ff | 0x3130..=0x318f | 0xac00..=0xd7af => Script::Hangul,
+			0x3040..=0x309f => Script::Hiragana,
+			0x30a0..=0x30ff | 0x31f0..=0x31ff => Script::Katakana,
+			0x3400..=0x4dbf | 0x4e00..=0x9fff | 0xf900..=0xfaff | 0x20000..=0x3134f => Script::Han,
+			_ => Script::Other,
+		}
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct KeyStats {
+	pub total: usize,
+	/// number of distinct keys
+	pub distinct: usize,
+	/// distinct keys occurring more than once
+	pub duplicated: usize,
+	/// key length in chars => number of keys
+	pub lengths: BTreeMap<usize, usize>,
+	/// script of the first letter or digit => number of keys
+	pub scripts: BTreeMap<Script, usize>,
+}
+
+impl<M: KeyMaker> MDict<M> {
+	pub fn key_stats(&self) -> KeyStats
+	{
+		let mut stats = KeyStats::default();
+		let mut prev: Option<&str> = None;
+		let mut counted = false;
+		for entry in &self.mdx.key_entries {
+			let text = entry.text.as_str();
+			stats.total += 1;
+			if prev == Some(text) {
+				if !counted {
+					stats.duplicated += 1;
+					counted = true;
+				}
+				continue;
+			}
+			prev = Some(text);
+			counted = false;
+			stats.distinct += 1;
+			*stats.lengths.entry(text.chars().count()).or_default() += 1;
+			let script = text.chars()
+				.find(|ch| ch.is_alphanumeric())
+				.map_or(Script::Other, Script::of);
+			*stats.scripts.entry(script).or_default() += 1;
+		}
+		stats
+	}
+}
@@ -0,0 +1,86 @@
+use rust_stemmers::Stemmer;
+
+use crate::mdx::{KeyMaker, MDict, WordDefinition};
+use crate::Result;
+
+pub use rust_stemmers::Algorithm;
+
+/// How the looked up word was changed to find a definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transformation {
+	Exact,
+	/// an inflection rule, e.g. "plural", with the form that matched
+	Inflection(&'static str, String),
+	/// the stem produced by the stemmer
+	Stem(String),
+}
+
+// (rule, suffix, replacement)
+const ENGLISH_INFLECTIONS: [(&str, &str, &str); 14] = [
+	("plural", "ies", "y"),
+	("plural", "es", ""),
+	("plural", "s", ""),
+	("past tense", "ied", "y"),
+	("past tense", "ed", ""),
+	("past tense", "ed", "e"),
+	("progressive", "ing", ""),
+	("progressive", "ing", "e"),
+	("comparative", "ier", "y"),
+	("comparative", "er", ""),
+	("comparative", "er", "e"),
+	("superlative", "iest", "y"),
+	("superlative", "est", ""),
+	("superlative", "est", "e"),
+];
+
+fn english_inflections(word: &str) -> Vec<(&'static str, String)>
+{
+	let mut candidates = vec![];
+	for (rule, suffix, replacement) in ENGLISH_INFLECTIONS {
+		if let Some(base) = word.strip_suffix(suffix) {
+			if base.is_empty() {
+				continue;
+			}
+			candidates.push((rule, format!("{}{}", base, replacement)));
+			// stopped => stop, bigger => big
+			if replacement.is_empty() && matches!(suffix, "ed" | "ing" | "er" | "est") {
+				let mut chars = base.chars().rev();
+				if let (Some(a), Some(b)) = (chars.next(), chars.next()) {
+					if a == b && !"aeiou".contains(a) {
+						candidates.push((rule, base[..base.len() - a.len_utf8()].to_owned()));
+					}
+				}
+			}
+		}
+	}
+	candidates
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Lookup `word`, falling back to its inflected base forms (English
+	/// only) and then to its stem
+	pub fn lookup_stemmed<'a>(&mut self, word: &'a str, algorithm: Algorithm)
+		-> Result<Option<(WordDefinition<'a>, Transformation)>>
+	{
+		if let Some(definition) = self.lookup(word)? {
+			return Ok(Some((definition, Transformation::Exact)));
+		}
+		let lower = word.to_lowercase();
+		if matches!(algorithm, Algorithm::English) {
+			for (rule, form) in english_inflections(&lower) {
+				if let Some(found) = self.lookup(&form)? {
+					let definition = WordDefinition { key: word, definition: found.definition };
+					return Ok(Some((definition, Transformation::Inflection(rule, form))));
+				}
+			}
+		}
+		let stem = Stemmer::create(algorithm).stem(&lower).into_owned();
+		if stem != lower {
+			if let Some(found) = self.lookup(&stem)? {
+				let definition = WordDefinition { key: word, definition: found.definition };
+				return Ok(Some((definition, Transformation::Stem(stem))));
+			}
+		}
+		Ok(None)
+	}
+}
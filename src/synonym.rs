@@ -0,0 +1,112 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::mdx::{KeyMaker, MDict};
+use crate::parser::{entries_at, find_entry};
+use crate::Result;
+
+/// Words leading to the headword to look up instead, e.g. inflected
+/// forms and common misspellings
+#[derive(Debug, Clone, Default)]
+pub struct AliasTable {
+	aliases: Vec<(String, String)>,
+}
+
+impl AliasTable {
+	#[inline]
+	pub fn new() -> Self
+	{
+		Self::default()
+	}
+
+	/// One `word<TAB>headword` pair per line, empty lines and lines
+	/// starting with `#` are skipped
+	pub fn parse(text: &str) -> Self
+	{
+		let aliases = text.lines()
+			.filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+			.filter_map(|line| line.split_once('\t'))
+			.map(|(word, headword)| (word.trim().to_owned(), headword.trim().to_owned()))
+			.collect();
+		AliasTable { aliases }
+	}
+
+	#[inline]
+	pub fn load(path: impl AsRef<Path>) -> Result<Self>
+	{
+		Ok(Self::parse(&fs::read_to_string(path)?))
+	}
+
+	#[inline]
+	pub fn insert(&mut self, word: impl Into<String>, headword: impl Into<String>)
+	{
+		self.aliases.push((word.into(), headword.into()));
+	}
+
+	#[inline]
+	pub fn len(&self) -> usize
+	{
+		self.aliases.len()
+	}
+
+	#[inline]
+	pub fn is_empty(&self) -> bool
+	{
+		self.aliases.is_empty()
+	}
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Consult `table` when a lookup finds nothing, replacing any table
+	/// set before. Aliases of missing headwords are dropped, returns how
+	/// many are kept
+	pub fn set_aliases(&mut self, table: AliasTable) -> usize
+	{
+		let mut aliases = HashMap::new();
+		for (word, headword) in table.aliases {
+			let headword = self.key_maker.make(&Cow::Owned(headword), false);
+			if find_entry(&self.mdx, &headword).is_some() {
+				let word = self.key_maker.make(&Cow::Owned(word), false);
+				aliases.entry(word).or_insert(headword);
+			}
+		}
+		self.aliases = aliases;
+		self.aliases.len()
+	}
+
+	/// The key to look `word` up by, its alias target when it is missing
+	pub(crate) fn resolve_key(&self, word: &str) -> String
+	{
+		let key = self.key_maker.make(&Cow::Borrowed(word), false);
+		if self.aliases.is_empty() || find_entry(&self.mdx, &key).is_some() {
+			return key;
+		}
+		self.aliases.get(&key).cloned().unwrap_or(key)
+	}
+
+	/// Headwords stored under other keys with the same record as `word`,
+	/// the way mdx files keep variant spellings, in key order
+	pub fn synonym_keys(&mut self, word: &str) -> Vec<&str>
+	{
+		let key = self.resolve_key(word);
+		let entries = &self.mdx.key_entries;
+		let order = &self.mdx.key_order;
+		let start = entries.partition_point(|entry| order.compare(&entry.text, &key).is_lt());
+		let end = start + entries[start..].iter()
+			.take_while(|entry| order.compare(&entry.text, &key).is_eq())
+			.count();
+		let offsets: Vec<usize> = entries[start..end].iter().map(|entry| entry.offset).collect();
+		let mut synonyms: Vec<usize> = offsets.into_iter()
+			.flat_map(|offset| entries_at(&mut self.mdx, offset))
+			.filter(|idx| !(start..end).contains(idx))
+			.collect();
+		synonyms.sort_unstable();
+		let mut keys: Vec<&str> = synonyms.into_iter()
+			.map(|idx| self.mdx.key_entries[idx].raw())
+			.collect();
+		keys.dedup();
+		keys
+	}
+}
@@ -0,0 +1,157 @@
+//! Wrap definitions into complete HTML documents for webviews
+
+use std::fmt::Write;
+
+use crate::companion::CompanionKind;
+use crate::mdx::{KeyMaker, MDict};
+use crate::Result;
+
+const DEFAULT_VIEWPORT: &str = "width=device-width, initial-scale=1";
+
+/// Class set on the `<html>` element
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Theme {
+	#[default]
+	Light,
+	Dark,
+	/// follow `prefers-color-scheme`
+	Auto,
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+	base: Option<String>,
+	viewport: Option<String>,
+	theme: Theme,
+	classes: Vec<String>,
+	css: Vec<String>,
+	js: Vec<String>,
+}
+
+impl Default for Template {
+	fn default() -> Self
+	{
+		Template {
+			base: None,
+			viewport: Some(DEFAULT_VIEWPORT.to_owned()),
+			theme: Theme::Light,
+			classes: vec![],
+			css: vec![],
+			js: vec![],
+		}
+	}
+}
+
+#[inline]
+pub(crate) fn escape_attr(text: &str) -> String
+{
+	text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;")
+}
+
+impl Template {
+	#[inline]
+	pub fn new() -> Self
+	{
+		Self::default()
+	}
+
+	/// `<base href>` resolving relative resource urls
+	#[inline]
+	pub fn base(mut self, href: impl Into<String>) -> Self
+	{
+		self.base = Some(href.into());
+		self
+	}
+
+	/// Content of the viewport meta, `None` to leave it out
+	#[inline]
+	pub fn viewport(mut self, content: Option<String>) -> Self
+	{
+		self.viewport = content;
+		self
+	}
+
+	#[inline]
+	pub fn theme(mut self, theme: Theme) -> Self
+	{
+		self.theme = theme;
+		self
+	}
+
+	/// Extra class of the `<html>` element
+	#[inline]
+	pub fn class(mut self, class: impl Into<String>) -> Self
+	{
+		self.classes.push(class.into());
+		self
+	}
+
+	/// Stylesheet inlined after the dictionary's
+	#[inline]
+	pub fn css(mut self, css: impl Into<String>) -> Self
+	{
+		self.css.push(css.into());
+		self
+	}
+
+	/// Script inlined after the dictionary's
+	#[inline]
+	pub fn js(mut self, js: impl Into<String>) -> Self
+	{
+		self.js.push(js.into());
+		self
+	}
+
+	/// The document with `definition` as body, `css` and `js` are inlined
+	/// before the template's own
+	pub fn render(&self, definition: &str, css: Option<&str>, js: Option<&str>) -> String
+	{
+		let mut classes = self.classes.clone();
+		match self.theme {
+			Theme::Light => classes.push("light".to_owned()),
+			Theme::Dark => classes.push("dark".to_owned()),
+			Theme::Auto => {}
+		}
+		let mut html = String::with_capacity(definition.len() + 256);
+		html.push_str("<!DOCTYPE html>\n");
+		if classes.is_empty() {
+			html.push_str("<html>\n<head>\n");
+		} else {
+			let _ = writeln!(html, "<html class=\"{}\">\n<head>", escape_attr(&classes.join(" ")));
+		}
+		html.push_str("<meta charset=\"utf-8\">\n");
+		if let Some(viewport) = &self.viewport {
+			let _ = writeln!(html, "<meta name=\"viewport\" content=\"{}\">", escape_attr(viewport));
+		}
+		if let Some(base) = &self.base {
+			let _ = writeln!(html, "<base href=\"{}\">", escape_attr(base));
+		}
+		if self.theme == Theme::Auto {
+			html.push_str("<script>document.documentElement.classList.add(\
+				matchMedia('(prefers-color-scheme: dark)').matches ? 'dark' : 'light')</script>\n");
+		}
+		for css in css.into_iter().chain(self.css.iter().map(String::as_str)) {
+			let _ = writeln!(html, "<style>\n{}\n</style>", css.replace("</style", "<\\/style"));
+		}
+		html.push_str("</head>\n<body>\n");
+		html.push_str(definition);
+		html.push('\n');
+		for js in js.into_iter().chain(self.js.iter().map(String::as_str)) {
+			let _ = writeln!(html, "<script>\n{}\n</script>", js.replace("</script", "<\\/script"));
+		}
+		html.push_str("</body>\n</html>\n");
+		html
+	}
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Wrap `definition` by `template` with the dictionary's css and js files
+	pub fn render_document(&mut self, definition: &str, template: &Template) -> Result<String>
+	{
+		let css = self.read_companion(CompanionKind::Css)?
+			.map(|css| String::from_utf8_lossy(&css).into_owned());
+		let js = self.read_companion(CompanionKind::Js)?
+			.map(|js| String::from_utf8_lossy(&js).into_owned());
+		Ok(template.render(definition, css.as_deref(), js.as_deref()))
+	}
+}
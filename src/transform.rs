@@ -0,0 +1,243 @@
+//! Rewrite rules applied to definition html.
+//!
+//! The html is processed tag by tag in a single pass, without building a
+//! document tree, so the cost stays proportional to the article size.
+
+use std::fmt::{self, Write};
+use regex::Regex;
+
+use crate::html::{Token, Tokenizer};
+
+pub use crate::html::Tag;
+
+pub enum Action {
+	Keep,
+	/// drop the tag itself, its content is kept
+	Remove,
+	/// drop the element and everything inside it
+	RemoveWithContent,
+}
+
+pub trait Rule: Send + Sync {
+	fn element(&self, _tag: &mut Tag) -> Action
+	{
+		Action::Keep
+	}
+
+	/// content written before the definition
+	fn prepend(&self, _out: &mut dyn Write) -> fmt::Result
+	{
+		Ok(())
+	}
+}
+
+#[derive(Default)]
+pub struct Transformer {
+	rules: Vec<Box<dyn Rule>>,
+}
+
+impl Transformer {
+	#[inline]
+	pub fn new() -> Self
+	{
+		Transformer { rules: vec![] }
+	}
+
+	#[inline]
+	pub fn rule(mut self, rule: impl Rule + 'static) -> Self
+	{
+		self.rules.push(Box::new(rule));
+		self
+	}
+
+	pub fn transform(&self, html: &str) -> String
+	{
+		let mut out = String::with_capacity(html.len());
+		// writing into a String never fails
+		let _ = self.transform_to(html, &mut out);
+		out
+	}
+
+	pub fn transform_to(&self, html: &str, out: &mut dyn Write) -> fmt::Result
+	{
+		for rule in &self.rules {
+			rule.prepend(out)?;
+		}
+		// name and nesting depth of the element being removed
+		let mut skipping: Option<(String, usize)> = None;
+		for token in Tokenizer::new(html) {
+			match token {
+				Token::Tag(mut tag) => {
+					if let Some((name, depth)) = &mut skipping {
+						if tag.name() == name && !tag.is_self_closing() {
+							if !tag.is_end() {
+								*depth += 1;
+							} else if *depth == 0 {
+								skipping = None;
+							} else {
+								*depth -= 1;
+							}
+						}
+						continue;
+					}
+					match self.apply(&mut tag) {
+						Action::Keep => write!(out, "{}", tag)?,
+						Action::Remove => {}
+						Action::RemoveWithContent => if !tag.is_end() && !tag.is_self_closing() {
+							skipping = Some((tag.name().to_owned(), 0));
+						}
+					}
+				}
+				Token::Text(text) | Token::Other(text) => if skipping.is_none() {
+					out.write_str(text)?;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn apply(&self, tag: &mut Tag) -> Action
+	{
+		for rule in &self.rules {
+			match rule.element(tag) {
+				Action::Keep => {}
+				action => return action,
+			}
+		}
+		Action::Keep
+	}
+}
+
+/// Replace `entry://` links with `prefix` followed by the target word
+pub struct EntryLinks {
+	prefix: String,
+}
+
+impl EntryLinks {
+	#[inline]
+	pub fn new(prefix: impl Into<String>) -> Self
+	{
+		EntryLinks { prefix: prefix.into() }
+	}
+}
+
+impl Rule for EntryLinks {
+	fn element(&self, tag: &mut Tag) -> Action
+	{
+		if tag.name() == "a" {
+			if let Some(target) = tag.attr("href")
+				.and_then(|href| href.strip_prefix("entry://")) {
+				let href = format!("{}{}", self.prefix, target);
+				tag.set_attr("href", href);
+			}
+		}
+		Action::Keep
+	}
+}
+
+pub enum InjectCss {
+	Link(String),
+	Inline(String),
+}
+
+impl Rule for InjectCss {
+	fn prepend(&self, out: &mut dyn Write) -> fmt::Result
+	{
+		match self {
+			InjectCss::Link(href) =>
+				write!(out, "<link rel=\"stylesheet\" type=\"text/css\" href=\"{}\" />", href),
+			InjectCss::Inline(css) => write!(out, "<style>{}</style>", css),
+		}
+	}
+}
+
+/// Remove `<script>` elements and inline event handlers
+pub struct StripScripts;
+
+impl Rule for StripScripts {
+	fn element(&self, tag: &mut Tag) -> Action
+	{
+		if tag.name() == "script" {
+			return Action::RemoveWithContent;
+		}
+		tag.retain_attrs(|name| !name.get(..2)
+			.is_some_and(|p| p.eq_ignore_ascii_case("on")));
+		Action::Keep
+	}
+}
+
+/// Resolve relative `<img src>` paths against `base`
+pub struct ImageBase {
+	base: String,
+}
+
+impl ImageBase {
+	#[inline]
+	pub fn new(base: impl Into<String>) -> Self
+	{
+		ImageBase { base: base.into() }
+	}
+}
+
+impl Rule for ImageBase {
+	fn element(&self, tag: &mut Tag) -> Action
+	{
+		if tag.name() == "img" {
+			if let Some(src) = tag.attr("src") {
+				if !src.contains("://") && !src.starts_with("data:") {
+					let src = src
+						.trim_start_matches(['/', '\\'])
+						.replace('\\', "/");
+					let src = format!("{}{}", self.base, src);
+					tag.set_attr("src", src);
+				}
+			}
+		}
+		Action::Keep
+	}
+}
+
+/// Wrap the occurrences of `terms` in the text of `html` in `<mark>`,
+/// ignoring case. Tags, comments, character references and script or style
+/// content are kept as is
+pub fn highlight(html: &str, terms: &[&str]) -> String
+{
+	let mut terms: Vec<&str> = terms.iter().copied().filter(|term| !term.is_empty()).collect();
+	if terms.is_empty() {
+		return html.to_owned();
+	}
+	// longer terms first, so a term wins over its prefixes
+	terms.sort_by_key(|term| std::cmp::Reverse(term.len()));
+	let terms: Vec<String> = terms.into_iter().map(regex::escape).collect();
+	// references come first, so no term matches inside one
+	let pattern = Regex::new(&format!("&#?[0-9A-Za-z]+;|(?i:({}))", terms.join("|")))
+		.expect("escaped terms form a valid pattern");
+
+	let mut out = String::with_capacity(html.len());
+	let mut raw_text = false;
+	for token in Tokenizer::new(html) {
+		match token {
+			Token::Text(text) if !raw_text => {
+				let mut last = 0;
+				for found in pattern.captures_iter(text) {
+					let Some(found) = found.get(1) else {
+						continue;
+					};
+					out.push_str(&text[last..found.start()]);
+					out.push_str("<mark>");
+					out.push_str(found.as_str());
+					out.push_str("</mark>");
+					last = found.end();
+				}
+				out.push_str(&text[last..]);
+			}
+			Token::Text(text) | Token::Other(text) => out.push_str(text),
+			Token::Tag(tag) => {
+				raw_text = matches!(tag.name(), "script" | "style")
+					&& !tag.is_end() && !tag.is_self_closing();
+				let _ = write!(out, "{}", tag);
+			}
+		}
+	}
+	out
+}
@@ -0,0 +1,106 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::format::KeyEntry;
+use crate::mdx::{KeyMaker, MDict};
+use crate::search::{dedup_keys, Meter, Partial, SearchBudget};
+
+type Trigram = [char; 3];
+
+/// Key indices by the character trigrams they contain
+#[derive(Debug, Default)]
+pub(crate) struct TrigramIndex {
+	postings: HashMap<Trigram, Vec<u32>>,
+}
+
+#[inline]
+fn trigrams(text: &str) -> impl Iterator<Item=Trigram> + '_
+{
+	let chars: Vec<char> = text.chars().collect();
+	(0..chars.len().saturating_sub(2)).map(move |i| [chars[i], chars[i + 1], chars[i + 2]])
+}
+
+impl TrigramIndex {
+	pub(crate) fn build(entries: &[KeyEntry]) -> Self
+	{
+		let mut postings: HashMap<Trigram, Vec<u32>> = HashMap::new();
+		for (idx, entry) in entries.iter().enumerate() {
+			let idx = idx as u32;
+			for trigram in trigrams(&entry.text) {
+				let list = postings.entry(trigram).or_default();
+				if list.last() != Some(&idx) {
+					list.push(idx);
+				}
+			}
+		}
+		for list in postings.values_mut() {
+			list.shrink_to_fit();
+		}
+		TrigramIndex { postings }
+	}
+
+	/// Indices of keys that contain every trigram of `text`, in key order
+	fn candidates(&self, text: &str) -> Vec<u32>
+	{
+		let mut lists = vec![];
+		for trigram in trigrams(text) {
+			match self.postings.get(&trigram) {
+				Some(list) => lists.push(list),
+				None => return vec![],
+			}
+		}
+		lists.sort_by_key(|list| list.len());
+		let mut lists = lists.into_iter();
+		let mut result = match lists.next() {
+			Some(list) => list.clone(),
+			None => return vec![],
+		};
+		for list in lists {
+			result.retain(|idx| list.binary_search(idx).is_ok());
+			if result.is_empty() {
+				break;
+			}
+		}
+		result
+	}
+}
+
+impl<M: KeyMaker> MDict<M> {
+	/// Normalized `text` and the indices of keys that may contain it
+	fn contains_candidates(&self, text: &str) -> (String, Box<dyn Iterator<Item=usize> + '_>)
+	{
+		let text = self.key_maker.make(&Cow::Borrowed(text), false);
+		let entries = &self.mdx.key_entries;
+		let candidates: Box<dyn Iterator<Item=usize>> = if text.chars().count() < 3 {
+			Box::new(0..entries.len())
+		} else {
+			let candidates = self.trigrams
+				.get_or_init(|| TrigramIndex::build(entries))
+				.candidates(&text);
+			Box::new(candidates.into_iter().map(|idx| idx as usize))
+		};
+		(text, candidates)
+	}
+
+	/// Keys containing `text`, the trigram index is built on first use
+	pub fn search_contains(&self, text: &str) -> impl Iterator<Item=&str>
+	{
+		let (text, candidates) = self.contains_candidates(text);
+		let keys = candidates
+			.map(|idx| self.mdx.key_entries[idx].text.as_str())
+			.filter(move |key| key.contains(text.as_str()));
+		dedup_keys(keys)
+	}
+
+	/// `search_contains()` stopping when `budget` is spent
+	pub fn search_contains_within(&self, text: &str, budget: &SearchBudget) -> Partial<&str>
+	{
+		let (text, candidates) = self.contains_candidates(text);
+		let keys = dedup_keys(candidates.map(|idx| self.mdx.key_entries[idx].text.as_str()));
+		Meter::new(budget).collect(keys, |key| if key.contains(text.as_str()) {
+			Some(key)
+		} else {
+			None
+		})
+	}
+}
@@ -0,0 +1,183 @@
+//! Loading dictionaries from input that can't be trusted, e.g. files
+//! uploaded to a service
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use byteorder::{ByteOrder, BE, LE};
+use encoding_rs::UTF_16LE;
+
+use crate::collation::KeyOrder;
+use crate::format::{decode_untrusted, parse_header, parse_key_block_header, parse_key_block_infos,
+	parse_key_entries, to_usize, Decoding, KeyBlockHeader, Salsa20Variant, Version};
+use crate::index::IndexMode;
+use crate::mdx::{BufferSizes, KeyMaker, LoadOptions, MDict, Reader};
+use crate::normalize::fold_case;
+use crate::parser::load;
+use crate::{Error, Result};
+
+/// Bounds on what loading an untrusted file may allocate, exceeding one
+/// fails with `Error::LimitExceeded`
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+	/// bytes of the header text
+	pub header: usize,
+	/// key blocks, and record blocks
+	pub blocks: usize,
+	/// decoded bytes of a block or of the key block index
+	pub block_size: usize,
+	/// key entries
+	pub entries: usize,
+	/// decoded bytes of all blocks and the key block index together
+	pub total_decoded: usize,
+}
+
+impl Default for Limits {
+	#[inline]
+	fn default() -> Self
+	{
+		Limits {
+			header: 64 * 1024,
+			blocks: 64 * 1024,
+			block_size: 8 * 1024 * 1024,
+			entries: 4 * 1024 * 1024,
+			total_decoded: 512 * 1024 * 1024,
+		}
+	}
+}
+
+/// Load the mdx file `bytes` checking every size and offset it stores
+/// against the input and `limits` before using it. Every block is decoded
+/// once up front, refused when it decodes past its stated size, so
+/// lookups afterwards stay within the limits too
+pub fn parse_untrusted(bytes: &[u8], limits: &Limits) -> Result<MDict<impl KeyMaker>>
+{
+	let key_maker = |key: &Cow<str>, _resource: bool| fold_case(key);
+	let salsa20 = check(bytes, limits, &key_maker)?;
+	let options = LoadOptions {
+		cache: false,
+		record_limit: None,
+		key_order: KeyOrder::Bytes,
+		salsa20,
+		index: IndexMode::Off,
+		buffers: BufferSizes::default(),
+		trust_sorted: false,
+		record_encoding: None,
+		key_decoding: Decoding::Lossy,
+	};
+	let reader = Reader::Memory(Cursor::new(bytes.to_vec()));
+	let mdx = load(reader, None, UTF_16LE, &options, &key_maker, false)?;
+	Ok(MDict {
+		mdx,
+		resources: vec![],
+		key_maker,
+		transformer: None,
+		dir: PathBuf::new(),
+		#[cfg(feature = "zip")]
+		archive: None,
+		companions: vec![],
+		pinyin: None,
+		trigrams: OnceLock::new(),
+		phonetic: OnceLock::new(),
+		identity: OnceLock::new(),
+		aliases: HashMap::new(),
+	})
+}
+
+/// Walk every section of the file, returns the Salsa20 variant its
+/// encrypted blocks use
+fn check(bytes: &[u8], limits: &Limits, key_maker: &dyn KeyMaker) -> Result<Salsa20Variant>
+{
+	let mut at = 0;
+	let size = BE::read_u32(take(bytes, &mut at, 4)?) as usize;
+	if size > limits.header {
+		return Err(Error::LimitExceeded("header"));
+	}
+	let text = take(bytes, &mut at, size)?;
+	let checksum = LE::read_u32(take(bytes, &mut at, 4)?);
+	let header = parse_header(text, checksum, UTF_16LE, &mut vec![])?;
+	let version = &header.version;
+
+	let section = take(bytes, &mut at, KeyBlockHeader::size(version))?;
+	let section = parse_key_block_header(section, version)?;
+	within(section.block_num, limits.blocks, "blocks")?;
+	within(section.entry_num, limits.entries, "entries")?;
+	let info = take(bytes, &mut at, to_usize(section.block_info_size)?)?;
+	let key_blocks = parse_key_block_infos(info.to_vec(), &header, limits.block_size as u64,
+		&mut vec![])?;
+	within(key_blocks.len() as u64, limits.blocks, "blocks")?;
+	// every size is checked before any block is decoded
+	let mut decoded = section.decompressed_size;
+	for info in &key_blocks {
+		within(info.decompressed_size as u64, limits.block_size, "block size")?;
+		decoded = decoded.saturating_add(info.decompressed_size as u64);
+	}
+	within(decoded, limits.total_decoded, "total decoded")?;
+	let end = at.checked_add(to_usize(section.key_block_size)?)
+		.filter(|end| *end <= bytes.len())
+		.ok_or(Error::InvalidData)?;
+
+	let mut records_at = end;
+	let mut number = || -> Result<u64> {
+		let width = if matches!(version, Version::V1) { 4 } else { 8 };
+		Ok(version.byte_number(take(bytes, &mut records_at, width)?)?.0 as u64)
+	};
+	let count = number()?;
+	within(count, limits.blocks, "blocks")?;
+	let _entries = number()?;
+	let _info_size = number()?;
+	let _data_size = number()?;
+	let mut record_blocks = Vec::with_capacity(count as usize);
+	for _ in 0..count {
+		let compressed_size = to_usize(number()?)?;
+		let decompressed_size = to_usize(number()?)?;
+		within(decompressed_size as u64, limits.block_size, "block size")?;
+		decoded = decoded.saturating_add(decompressed_size as u64);
+		within(decoded, limits.total_decoded, "total decoded")?;
+		record_blocks.push((compressed_size, decompressed_size));
+	}
+
+	let mut salsa20 = Salsa20Variant::Detect;
+	let mut block = vec![];
+	let mut keys = vec![];
+	let mut entries = 0;
+	for (index, info) in key_blocks.iter().enumerate() {
+		let data = take(&bytes[..end], &mut at, info.compressed_size)?;
+		if info.decompressed_size == 0 {
+			continue;
+		}
+		decode_untrusted(data, info.decompressed_size, &mut salsa20, index, &mut block)?;
+		parse_key_entries(&block, &header, &|raw| key_maker.make(&Cow::Borrowed(raw), false), index,
+			Decoding::Lossy, &mut keys, &mut vec![])?;
+		entries += keys.len();
+		keys.clear();
+		within(entries as u64, limits.entries, "entries")?;
+	}
+	for (index, (compressed_size, decompressed_size)) in record_blocks.into_iter().enumerate() {
+		let data = take(bytes, &mut records_at, compressed_size)?;
+		decode_untrusted(data, decompressed_size, &mut salsa20, index, &mut block)?;
+	}
+	Ok(salsa20)
+}
+
+/// `len` bytes at `at`, moving past them
+#[inline]
+fn take<'a>(bytes: &'a [u8], at: &mut usize, len: usize) -> Result<&'a [u8]>
+{
+	let slice = at.checked_add(len)
+		.and_then(|end| bytes.get(*at..end))
+		.ok_or(Error::InvalidData)?;
+	*at += len;
+	Ok(slice)
+}
+
+#[inline]
+fn within(value: u64, limit: usize, name: &'static str) -> Result<()>
+{
+	if value > limit as u64 {
+		return Err(Error::LimitExceeded(name));
+	}
+	Ok(())
+}
@@ -0,0 +1,163 @@
+//! Record block reads through io_uring on Linux, see
+//! [`MDictBuilder::io_uring`](crate::MDictBuilder::io_uring)
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+use io_uring::{opcode, types, IoUring};
+
+use crate::mdx::{Mdx, Reader};
+use crate::Result;
+
+// submission queue size, the most reads submitted together
+const ENTRIES: u32 = 8;
+// bytes per read, larger reads, e.g. of adjacent blocks, are split in
+// reads of this size submitted together
+const CHUNK: usize = 128 * 1024;
+
+/// A file read at its position through a ring instead of read syscalls
+pub(crate) struct UringFile {
+	file: File,
+	ring: IoUring,
+	// the kernel reads into this, never into the caller's buffer, so no
+	// read in flight can outlive the memory it writes to
+	buf: Vec<u8>,
+	// tells the completions of the last `read()` from stale ones
+	generation: u64,
+	// the ring failed with reads in flight, `buf` is leaked on drop
+	broken: bool,
+	pos: u64,
+	len: u64,
+}
+
+impl UringFile {
+	pub(crate) fn open(path: &Path) -> Result<Self>
+	{
+		let file = File::open(path)?;
+		let len = file.metadata()?.len();
+		Ok(UringFile {
+			file,
+			ring: IoUring::new(ENTRIES)?,
+			buf: vec![],
+			generation: 0,
+			broken: false,
+			pos: 0,
+			len,
+		})
+	}
+
+	/// Submit the reads of `chunks` chunks of `want` bytes into `buf` and
+	/// wait for all of them, their results by chunk
+	fn read_chunks(&mut self, want: usize, chunks: usize) -> io::Result<[i32; ENTRIES as usize]>
+	{
+		self.generation += 1;
+		let fd = types::Fd(self.file.as_raw_fd());
+		for idx in 0..chunks {
+			let start = idx * CHUNK;
+			let len = CHUNK.min(want - start) as u32;
+			let entry = opcode::Read::new(fd, self.buf[start..].as_mut_ptr(), len)
+				.offset(self.pos + start as u64)
+				.build()
+				.user_data(self.generation << 8 | idx as u64);
+			// SAFETY: `buf` is neither touched nor freed until every read
+			// submitted completed, it is leaked when that can't be known
+			if unsafe { self.ring.submission().push(&entry) }.is_err() {
+				self.broken = true;
+				return Err(io::Error::other("io_uring submission queue is full"));
+			}
+		}
+		let mut results = [None; ENTRIES as usize];
+		let mut pending = chunks;
+		while pending > 0 {
+			match self.ring.submit_and_wait(pending) {
+				Ok(_) => {}
+				Err(err) if matches!(err.kind(), io::ErrorKind::Interrupted
+					| io::ErrorKind::WouldBlock) => {}
+				Err(err) => {
+					self.broken = true;
+					return Err(err);
+				}
+			}
+			for entry in self.ring.completion() {
+				let idx = (entry.user_data() & 0xff) as usize;
+				if entry.user_data() >> 8 != self.generation || idx >= chunks {
+					continue;
+				}
+				if results[idx].replace(entry.result()).is_none() {
+					pending -= 1;
+				}
+			}
+		}
+		Ok(results.map(|result| result.unwrap_or(0)))
+	}
+}
+
+impl Read for UringFile {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+	{
+		if self.broken {
+			return Err(io::Error::other("io_uring failed with reads in flight"));
+		}
+		let want = buf.len().min(CHUNK * ENTRIES as usize);
+		if want == 0 {
+			return Ok(0);
+		}
+		if self.buf.len() < want {
+			self.buf.resize(want, 0);
+		}
+		let chunks = want.div_ceil(CHUNK);
+		let results = self.read_chunks(want, chunks)?;
+		// the chunks read in full, up to the first short or failed read
+		let mut read = 0;
+		for (idx, result) in results[..chunks].iter().enumerate() {
+			if *result < 0 {
+				if read == 0 {
+					return Err(io::Error::from_raw_os_error(-result));
+				}
+				break;
+			}
+			read += *result as usize;
+			if *result as usize != CHUNK.min(want - idx * CHUNK) {
+				break;
+			}
+		}
+		buf[..read].copy_from_slice(&self.buf[..read]);
+		self.pos += read as u64;
+		Ok(read)
+	}
+}
+
+impl Drop for UringFile {
+	fn drop(&mut self)
+	{
+		if self.broken {
+			std::mem::forget(std::mem::take(&mut self.buf));
+		}
+	}
+}
+
+impl Seek for UringFile {
+	fn seek(&mut self, from: SeekFrom) -> io::Result<u64>
+	{
+		let target = match from {
+			SeekFrom::Start(offset) => Some(offset),
+			SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+			SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+		};
+		self.pos = target.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+			"invalid seek to a negative position"))?;
+		Ok(self.pos)
+	}
+}
+
+/// Read the record blocks of `mdx` through io_uring from now on, when it
+/// was read from a file
+pub(crate) fn use_uring(mdx: &mut Mdx) -> Result<()>
+{
+	if let (Reader::File(_), Some(source)) = (&mdx.reader, &mdx.source) {
+		mdx.reader = Reader::Uring(Box::new(UringFile::open(source)?));
+	}
+	Ok(())
+}
@@ -0,0 +1,137 @@
+//! Record cache saved to a file and loaded back at startup, so the first
+//! lookups after a restart find their blocks decoded
+//!
+//! The format is little endian throughout:
+//!
+//! ```text
+//! magic "MDICTRCC", format version u32
+//! dictionary identity u64, block count u32
+//! per block: offset among the record blocks u64, length u64, adler32 u32,
+//!     decoded block
+//! ```
+//!
+//! Loading checks each block against the checksum the dictionary stores
+//! for it, so a file rewritten with the same keys doesn't get stale blocks
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+
+use crate::checksum::adler32;
+use crate::format::{block_header, to_usize};
+use crate::mdx::{KeyMaker, MDict};
+use crate::Result;
+
+const MAGIC: &[u8; 8] = b"MDICTRCC";
+const VERSION: u32 = 1;
+
+// offset among the record blocks and decoded block
+type Blocks = Vec<(u64, Arc<Vec<u8>>)>;
+
+impl<M: KeyMaker> MDict<M> {
+	/// Write the record blocks cached by lookups to `path`, those looked
+	/// up most first, up to `max_bytes` of them, returning how many were
+	/// written. Needs
+	/// [`cache_definition`](crate::MDictBuilder::cache_definition)
+	pub fn save_record_cache(&self, path: impl AsRef<Path>, max_bytes: usize) -> Result<usize>
+	{
+		let mut blocks: Vec<(&u64, &Arc<Vec<u8>>)> = self.mdx.record_cache.iter()
+			.flatten()
+			.collect();
+		let hits = |offset: &u64| self.mdx.block_hits.get(offset).copied().unwrap_or(0);
+		blocks.sort_unstable_by_key(|(offset, _)| (Reverse(hits(offset)), **offset));
+		let mut total = 0usize;
+		blocks.retain(|(_, block)| match total.checked_add(block.len()) {
+			Some(sum) if sum <= max_bytes => {
+				total = sum;
+				true
+			}
+			_ => false,
+		});
+
+		let path = path.as_ref();
+		let mut temp = OsString::from(path.as_os_str());
+		temp.push(".tmp");
+		let temp = PathBuf::from(temp);
+		let mut writer = BufWriter::new(File::create(&temp)?);
+		writer.write_all(MAGIC)?;
+		writer.write_u32::<LE>(VERSION)?;
+		writer.write_u64::<LE>(self.identity())?;
+		writer.write_u32::<LE>(blocks.len() as u32)?;
+		for (offset, block) in &blocks {
+			writer.write_u64::<LE>(**offset)?;
+			writer.write_u64::<LE>(block.len() as u64)?;
+			writer.write_u32::<LE>(adler32(block))?;
+			writer.write_all(block)?;
+		}
+		writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+		fs::rename(&temp, path)?;
+		Ok(blocks.len())
+	}
+
+	/// Fill the record cache from a file written by `save_record_cache()`,
+	/// turning caching on, and return how many blocks it held. A missing
+	/// file, one of another dictionary or a corrupt one loads nothing
+	pub fn load_record_cache(&mut self, path: impl AsRef<Path>) -> Result<usize>
+	{
+		let file = match File::open(path) {
+			Ok(file) => file,
+			Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+			Err(err) => return Err(err.into()),
+		};
+		let Some(blocks) = self.read_record_cache(&mut BufReader::new(file)).ok().flatten() else {
+			return Ok(0);
+		};
+		let count = blocks.len();
+		self.mdx.record_cache.get_or_insert_with(HashMap::new).extend(blocks);
+		Ok(count)
+	}
+
+	fn read_record_cache(&mut self, reader: &mut impl Read) -> Result<Option<Blocks>>
+	{
+		let mut magic = [0; 8];
+		reader.read_exact(&mut magic)?;
+		if &magic != MAGIC || reader.read_u32::<LE>()? != VERSION
+			|| reader.read_u64::<LE>()? != self.identity() {
+			return Ok(None);
+		}
+		// decoded size of the block at each offset
+		let mut sizes = HashMap::new();
+		let mut offset = 0;
+		for info in &self.mdx.records_info {
+			sizes.insert(offset, info.decompressed_size);
+			offset += info.compressed_size as u64;
+		}
+		let mut blocks = vec![];
+		for _ in 0..reader.read_u32::<LE>()? {
+			let offset = reader.read_u64::<LE>()?;
+			let len = to_usize(reader.read_u64::<LE>()?)?;
+			let checksum = reader.read_u32::<LE>()?;
+			if sizes.get(&offset) != Some(&len) {
+				return Ok(None);
+			}
+			let mut block = vec![0; len];
+			reader.read_exact(&mut block)?;
+			if adler32(&block) != checksum || self.stored_checksum(offset)? != checksum {
+				return Ok(None);
+			}
+			blocks.push((offset, Arc::new(block)));
+		}
+		Ok(Some(blocks))
+	}
+
+	/// Checksum in the header of the record block at `offset`
+	fn stored_checksum(&mut self, offset: u64) -> Result<u32>
+	{
+		let mut header = [0; 8];
+		let reader = &mut self.mdx.reader;
+		reader.seek(SeekFrom::Start(self.mdx.record_block_offset + offset))?;
+		reader.read_exact(&mut header)?;
+		Ok(block_header(&header)?.checksum)
+	}
+}
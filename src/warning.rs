@@ -0,0 +1,42 @@
+//! Non-fatal oddities found while loading a dictionary
+
+use alloc::string::String;
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+	/// key block without any entry, skipped
+	EmptyKeyBlock(usize),
+	/// trailing zero bytes in `section`, ignored
+	Padding {
+		section: &'static str,
+		len: usize,
+	},
+	/// header attribute MDict doesn't write
+	UnknownAttribute(String),
+	/// no `Encoding` in the header, the named default was used
+	EncodingFallback(&'static str),
+	/// key with bytes invalid in the encoding, at `offset` of the decoded
+	/// key block `block`, kept with replacement characters
+	InvalidKey {
+		block: usize,
+		offset: usize,
+		key: String,
+	},
+}
+
+impl fmt::Display for Warning {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+	{
+		match self {
+			Warning::EmptyKeyBlock(index) => write!(f, "skipped empty key block {}", index),
+			Warning::Padding { section, len } =>
+				write!(f, "ignored {} bytes of padding after the {}", len, section),
+			Warning::UnknownAttribute(name) => write!(f, "unknown header attribute {}", name),
+			Warning::EncodingFallback(encoding) =>
+				write!(f, "no encoding in the header, assumed {}", encoding),
+			Warning::InvalidKey { block, offset, key } =>
+				write!(f, "invalid key {:?} at {:#x} of key block {}", key, offset, block),
+		}
+	}
+}
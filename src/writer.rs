@@ -0,0 +1,647 @@
+//! Writing mdx and mdd files, version 2 unencrypted by default, with blocks
+//! compressed by zlib when the flate2 feature is enabled and stored as is
+//! otherwise. Other versions, compressions and encryptions make small
+//! fixtures for tests without shipping real dictionaries
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use byteorder::{BE, LE, WriteBytesExt};
+use encoding_rs::{Encoding, UTF_16LE, UTF_8};
+use regex::Regex;
+
+use crate::checksum::adler32;
+use crate::format::decode_slice_string;
+use crate::html::text_content;
+use crate::mdx::{MDictBuilder, Reader};
+use crate::normalize::fold_case;
+use crate::inspect::BlockKind;
+use crate::parser::{find_entry, for_each_record, read_layout, stored_checksums};
+use crate::site::escape_text;
+use crate::template::escape_attr;
+use crate::{Error, Result};
+
+// decoded size a block is closed at
+const KEY_BLOCK_SIZE: usize = 32 * 1024;
+const RECORD_BLOCK_SIZE: usize = 64 * 1024;
+/// Attributes the writer sets itself
+const WRITER_ATTRIBUTES: [&str; 4] = [
+	"GeneratedByEngineVersion", "RequiredEngineVersion", "Encrypted", "Encoding",
+];
+
+/// Layout of the file written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FormatVersion {
+	/// 1.2, 32-bit sizes and a plain key block index
+	V1,
+	/// 2.0, 64-bit sizes and a zlib compressed key block index
+	#[default]
+	V2,
+}
+
+/// How key and record blocks are compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+	Stored,
+	/// LZO1X, written as literal runs only
+	Lzo,
+	/// deflate with the flate2 feature, stored deflate blocks without
+	Zlib,
+}
+
+impl Default for Compression {
+	#[inline]
+	fn default() -> Self
+	{
+		if cfg!(feature = "flate2") { Compression::Zlib } else { Compression::Stored }
+	}
+}
+
+/// What is enciphered, needs the encryption feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encryption {
+	#[default]
+	None,
+	/// the key block index, `Encrypted="2"` in the header, version 2 only
+	KeyIndex,
+	/// every key and record block, with the fast cipher
+	Blocks,
+}
+
+/// Collects entries and writes them as an mdx file, identical records are
+/// stored once
+#[derive(Clone)]
+pub struct MdxWriter {
+	encoding: &'static Encoding,
+	version: FormatVersion,
+	compression: Compression,
+	encryption: Encryption,
+	// name and value as written in the header
+	attributes: Vec<(String, String)>,
+	// key and record, encoded and terminated
+	entries: Vec<(String, Vec<u8>)>,
+}
+
+impl MdxWriter {
+	pub fn new(title: &str) -> Self
+	{
+		MdxWriter {
+			encoding: UTF_8,
+			version: FormatVersion::default(),
+			compression: Compression::default(),
+			encryption: Encryption::default(),
+			attributes: vec![("Title".to_owned(), escape_attr(title))],
+			entries: vec![],
+		}
+	}
+
+	/// Writer of an mdd file: keys are UTF-16, resources are added with
+	/// `add_record()` as they are, e.g. `\img\a.png`
+	#[inline]
+	pub fn mdd(title: &str) -> Self
+	{
+		Self::new(title).encoding(UTF_16LE)
+	}
+
+	/// Encoding of keys and definitions, UTF-8 by default
+	#[inline]
+	pub fn encoding(mut self, encoding: &'static Encoding) -> Self
+	{
+		self.encoding = encoding;
+		self
+	}
+
+	#[inline]
+	pub fn version(mut self, version: FormatVersion) -> Self
+	{
+		self.version = version;
+		self
+	}
+
+	#[inline]
+	pub fn compression(mut self, compression: Compression) -> Self
+	{
+		self.compression = compression;
+		self
+	}
+
+	#[inline]
+	pub fn encryption(mut self, encryption: Encryption) -> Self
+	{
+		self.encryption = encryption;
+		self
+	}
+
+	/// Header attribute such as `Description` or `StyleSheet`
+	#[inline]
+	pub fn attribute(mut self, name: &str, value: &str) -> Self
+	{
+		self.raw_attribute(name, escape_attr(value));
+		self
+	}
+
+	fn raw_attribute(&mut self, name: &str, value: String)
+	{
+		if WRITER_ATTRIBUTES.contains(&name) {
+			return;
+		}
+		match self.attributes.iter_mut().find(|(known, _)| known == name) {
+			Some(attribute) => attribute.1 = value,
+			None => self.attributes.push((name.to_owned(), value)),
+		}
+	}
+
+	/// Add an entry with its definition
+	pub fn add(&mut self, key: impl Into<String>, definition: &str)
+	{
+		let mut record = encode(definition, self.encoding);
+		record.extend_from_slice(terminator(self.encoding));
+		self.entries.push((key.into(), record));
+	}
+
+	/// Add an entry with its record as stored, in the encoding of the
+	/// writer and terminated
+	#[inline]
+	pub fn add_record(&mut self, key: impl Into<String>, record: Vec<u8>)
+	{
+		self.entries.push((key.into(), record));
+	}
+
+	pub fn write_file(self, path: impl AsRef<Path>) -> Result<()>
+	{
+		let mut writer = BufWriter::new(File::create(path)?);
+		self.write(&mut writer)?;
+		writer.flush()?;
+		Ok(())
+	}
+
+	pub fn write(mut self, writer: &mut impl Write) -> Result<()>
+	{
+		if self.version == FormatVersion::V1 && self.encryption == Encryption::KeyIndex {
+			return Err(Error::UnsupportedByVersion("key index encryption"));
+		}
+		self.entries.sort_by_cached_key(|(key, _)| (fold_case(key), key.clone()));
+		let (encoding, version) = (self.encoding, self.version);
+		let entry_num = self.entries.len() as u64;
+
+		// records, never split between blocks
+		let mut record_blocks = vec![];
+		let mut block = vec![];
+		let mut offsets = Vec::with_capacity(self.entries.len());
+		let mut stored: HashMap<&[u8], u64> = HashMap::new();
+		let mut offset = 0u64;
+		for (_, record) in &self.entries {
+			if let Some(offset) = stored.get(record.as_slice()) {
+				offsets.push(*offset);
+				continue;
+			}
+			if !block.is_empty() && block.len() + record.len() > RECORD_BLOCK_SIZE {
+				record_blocks.push(self.encode_block(&block)?);
+				block.clear();
+			}
+			stored.insert(record, offset);
+			offsets.push(offset);
+			offset += record.len() as u64;
+			block.extend_from_slice(record);
+		}
+		if !block.is_empty() {
+			record_blocks.push(self.encode_block(&block)?);
+		}
+
+		// keys and the first and last key of each block
+		let mut key_blocks = vec![];
+		let mut info = vec![];
+		let mut block = vec![];
+		let mut first = 0;
+		for (index, ((key, _), offset)) in self.entries.iter().zip(offsets).enumerate() {
+			write_number(&mut block, version, offset)?;
+			block.extend_from_slice(&encode(key, encoding));
+			block.extend_from_slice(terminator(encoding));
+			if block.len() >= KEY_BLOCK_SIZE || index + 1 == self.entries.len() {
+				let keys = &self.entries[first..=index];
+				write_number(&mut info, version, keys.len() as u64)?;
+				for (key, _) in [&keys[0], &keys[keys.len() - 1]] {
+					let text = encode(key, encoding);
+					let len = text.len() / terminator(encoding).len();
+					match version {
+						FormatVersion::V1 => info.push(u8::try_from(len)
+							.map_err(|_| Error::TooLargeForVersion(len as u64))?),
+						FormatVersion::V2 => info.write_u16::<BE>(len as u16)?,
+					}
+					info.extend_from_slice(&text);
+					if version == FormatVersion::V2 {
+						info.extend_from_slice(terminator(encoding));
+					}
+				}
+				let (encoded, _) = self.encode_block(&block)?;
+				write_number(&mut info, version, encoded.len() as u64)?;
+				write_number(&mut info, version, block.len() as u64)?;
+				key_blocks.push(encoded);
+				block.clear();
+				first = index + 1;
+			}
+		}
+		let key_info = match version {
+			FormatVersion::V1 => info.clone(),
+			FormatVersion::V2 => {
+				let mut key_info = vec![2, 0, 0, 0];
+				key_info.write_u32::<BE>(adler32(&info))?;
+				key_info.extend_from_slice(&zlib(&info)?);
+				if self.encryption == Encryption::KeyIndex {
+					let mut seed = key_info[4..8].to_vec();
+					seed.extend_from_slice(&0x3695u32.to_le_bytes());
+					encrypt(&mut key_info[8..], &seed)?;
+				}
+				key_info
+			}
+		};
+
+		self.write_header(writer)?;
+		let mut key_header = vec![];
+		write_number(&mut key_header, version, key_blocks.len() as u64)?;
+		write_number(&mut key_header, version, entry_num)?;
+		if version == FormatVersion::V2 {
+			key_header.write_u64::<BE>(info.len() as u64)?;
+		}
+		write_number(&mut key_header, version, key_info.len() as u64)?;
+		write_number(&mut key_header, version,
+			key_blocks.iter().map(|block| block.len() as u64).sum())?;
+		if version == FormatVersion::V2 {
+			let checksum = adler32(&key_header);
+			key_header.write_u32::<BE>(checksum)?;
+		}
+		writer.write_all(&key_header)?;
+		writer.write_all(&key_info)?;
+		for block in &key_blocks {
+			writer.write_all(block)?;
+		}
+
+		let mut records_header = vec![];
+		write_number(&mut records_header, version, record_blocks.len() as u64)?;
+		write_number(&mut records_header, version, entry_num)?;
+		let width = if version == FormatVersion::V1 { 8 } else { 16 };
+		write_number(&mut records_header, version, record_blocks.len() as u64 * width)?;
+		write_number(&mut records_header, version,
+			record_blocks.iter().map(|(block, _)| block.len() as u64).sum())?;
+		for (block, size) in &record_blocks {
+			write_number(&mut records_header, version, block.len() as u64)?;
+			write_number(&mut records_header, version, *size as u64)?;
+		}
+		writer.write_all(&records_header)?;
+		for (block, _) in &record_blocks {
+			writer.write_all(block)?;
+		}
+		Ok(())
+	}
+
+	/// The block with its flags and checksum, and its decoded size
+	fn encode_block(&self, data: &[u8]) -> Result<(Vec<u8>, usize)>
+	{
+		let (method, compressed) = match self.compression {
+			Compression::Stored => (0, data.to_vec()),
+			Compression::Lzo => (1, lzo(data)),
+			Compression::Zlib => (2, zlib(data)?),
+		};
+		let encryption = if self.encryption == Encryption::Blocks { 1 } else { 0 };
+		let checksum = adler32(data).to_be_bytes();
+		let mut block = Vec::with_capacity(compressed.len() + 8);
+		block.write_u32::<LE>(method | encryption << 4)?;
+		block.extend_from_slice(&checksum);
+		block.extend_from_slice(&compressed);
+		if encryption != 0 {
+			encrypt(&mut block[8..], &checksum)?;
+		}
+		Ok((block, data.len()))
+	}
+
+	fn write_header(&self, writer: &mut impl Write) -> Result<()>
+	{
+		let version = match self.version {
+			FormatVersion::V1 => "1.2",
+			FormatVersion::V2 => "2.0",
+		};
+		let encrypted = match self.encryption {
+			Encryption::KeyIndex => "2",
+			_ => "No",
+		};
+		let mut header = format!(
+			"<Dictionary GeneratedByEngineVersion=\"{v}\" RequiredEngineVersion=\"{v}\" \
+			Encrypted=\"{}\" Encoding=\"{}\"", encrypted, self.encoding.name(), v = version);
+		for (name, value) in &self.attributes {
+			header.push_str(&format!(" {}=\"{}\"", name, value));
+		}
+		header.push_str("/>\r\n\0");
+		let header = encode(&header, UTF_16LE);
+		writer.write_u32::<BE>(header.len() as u32)?;
+		writer.write_all(&header)?;
+		writer.write_u32::<LE>(adler32(&header))?;
+		Ok(())
+	}
+}
+
+#[inline]
+fn encode(text: &str, encoding: &'static Encoding) -> Vec<u8>
+{
+	if encoding == UTF_16LE {
+		text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+	} else {
+		encoding.encode(text).0.into_owned()
+	}
+}
+
+#[inline]
+fn terminator(encoding: &'static Encoding) -> &'static [u8]
+{
+	if encoding == UTF_16LE { &[0, 0] } else { &[0] }
+}
+
+#[inline]
+fn write_number(out: &mut Vec<u8>, version: FormatVersion, number: u64) -> Result<()>
+{
+	match version {
+		FormatVersion::V1 => out.write_u32::<BE>(u32::try_from(number)
+			.map_err(|_| Error::TooLargeForVersion(number))?)?,
+		FormatVersion::V2 => out.write_u64::<BE>(number)?,
+	}
+	Ok(())
+}
+
+/// LZO1X stream of a single literal run and the end marker
+fn lzo(data: &[u8]) -> Vec<u8>
+{
+	let mut stream = Vec::with_capacity(data.len() + 8);
+	match data.len() {
+		0 => {}
+		len @ 1..=238 => stream.push(len as u8 + 17),
+		len => {
+			// a zero byte, then 255 per further zero byte plus the last byte
+			let rest = len - 18;
+			let zeros = (rest - 1) / 255;
+			stream.push(0);
+			stream.resize(stream.len() + zeros, 0);
+			stream.push((rest - zeros * 255) as u8);
+		}
+	}
+	stream.extend_from_slice(data);
+	stream.extend_from_slice(&[0x11, 0, 0]);
+	stream
+}
+
+/// Encipher `data` the way the parser's `fast_decrypt` deciphers it, the
+/// key being the RIPEMD-128 digest of `seed`
+#[cfg(feature = "encryption")]
+fn encrypt(data: &mut [u8], seed: &[u8]) -> Result<()>
+{
+	use ripemd::{Digest, Ripemd128};
+	let key = Ripemd128::digest(seed);
+	let mut prev = 0x36;
+	for (i, byte) in data.iter_mut().enumerate() {
+		*byte = (*byte ^ prev ^ (i as u8) ^ key[i % key.len()]).rotate_left(4);
+		prev = *byte;
+	}
+	Ok(())
+}
+
+#[cfg(not(feature = "encryption"))]
+#[inline]
+fn encrypt(_data: &mut [u8], _seed: &[u8]) -> Result<()>
+{
+	Err(Error::DisabledCodec("encryption"))
+}
+
+#[cfg(feature = "flate2")]
+fn zlib(data: &[u8]) -> Result<Vec<u8>>
+{
+	let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+	encoder.write_all(data)?;
+	Ok(encoder.finish()?)
+}
+
+/// zlib stream of stored deflate blocks
+#[cfg(not(feature = "flate2"))]
+fn zlib(data: &[u8]) -> Result<Vec<u8>>
+{
+	let mut stream = vec![0x78, 0x01];
+	let mut chunks = data.chunks(0xffff).peekable();
+	if data.is_empty() {
+		stream.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+	}
+	while let Some(chunk) = chunks.next() {
+		stream.push(u8::from(chunks.peek().is_none()));
+		stream.write_u16::<LE>(chunk.len() as u16)?;
+		stream.write_u16::<LE>(!(chunk.len() as u16))?;
+		stream.extend_from_slice(chunk);
+	}
+	stream.write_u32::<BE>(adler32(data))?;
+	Ok(stream)
+}
+
+/// Write the entries of the mdx file `input` with keys in each range to
+/// the file paired with it, keeping the header attributes of `input`.
+/// Keys are compared case folded
+pub fn split<'a, R: RangeBounds<&'a str>>(input: impl AsRef<Path>,
+	ranges: &[(R, impl AsRef<Path>)]) -> Result<()>
+{
+	let input = input.as_ref();
+	let mut dict = MDictBuilder::new(input).build()?;
+	let mut writers = vec![copy_header(input, dict.mdx.encoding)?; ranges.len()];
+	let bounds: Vec<(Bound<String>, Bound<String>)> = ranges.iter()
+		.map(|(range, _)| (
+			range.start_bound().map(|key| fold_case(key)),
+			range.end_bound().map(|key| fold_case(key))))
+		.collect();
+	for_each_record(&mut dict.mdx, |entry, data| {
+		for (writer, bounds) in writers.iter_mut().zip(&bounds) {
+			if bounds.contains(&entry.text) {
+				writer.add_record(entry.raw(), data.to_vec());
+			}
+		}
+		Ok(())
+	})?;
+	for (writer, (_, path)) in writers.into_iter().zip(ranges) {
+		writer.write_file(path)?;
+	}
+	Ok(())
+}
+
+/// Write the entries of the mdx file `input` for the first `top` words
+/// of the frequency list `words`, most frequent first, that it has to
+/// `output`, along with the entries their `@@@LINK=` redirects lead to so
+/// those still resolve. Keys are compared case folded. Returns the number
+/// of entries written
+pub fn subset<S: AsRef<str>>(input: impl AsRef<Path>, words: impl IntoIterator<Item = S>,
+	top: usize, output: impl AsRef<Path>) -> Result<usize>
+{
+	let input = input.as_ref();
+	let mut dict = MDictBuilder::new(input).build()?;
+	let mut keys = HashSet::new();
+	for word in words {
+		if keys.len() >= top {
+			break;
+		}
+		let key = fold_case(word.as_ref());
+		if find_entry(&dict.mdx, &key).is_some() {
+			keys.insert(key);
+		}
+	}
+	let encoding = dict.mdx.encoding;
+	let mut redirects: HashMap<String, Vec<String>> = HashMap::new();
+	for_each_record(&mut dict.mdx, |entry, data| {
+		if let Some(target) = decode_slice_string(data, encoding)?.0.strip_prefix("@@@LINK=") {
+			let target = fold_case(target.trim_end_matches(['\r', '\n', '\0']));
+			redirects.entry(entry.text.clone()).or_default().push(target);
+		}
+		Ok(())
+	})?;
+	let mut pending: Vec<String> = keys.iter().cloned().collect();
+	while let Some(key) = pending.pop() {
+		for target in redirects.get(&key).into_iter().flatten() {
+			if keys.insert(target.clone()) {
+				pending.push(target.clone());
+			}
+		}
+	}
+	let mut writer = copy_header(input, encoding)?;
+	let mut written = 0;
+	for_each_record(&mut dict.mdx, |entry, data| {
+		if keys.contains(&entry.text) {
+			writer.add_record(entry.raw(), data.to_vec());
+			written += 1;
+		}
+		Ok(())
+	})?;
+	writer.write_file(output)?;
+	Ok(written)
+}
+
+/// Experimental: write the reverse of the bilingual mdx file `input` to
+/// `output`. Each translation `targets` matches in a definition, the text
+/// of its first group or else of the whole match, becomes a headword
+/// linking to the headwords it translates. Redirects are skipped. Returns
+/// the number of headwords written
+pub fn invert(input: impl AsRef<Path>, targets: &Regex, output: impl AsRef<Path>)
+	-> Result<usize>
+{
+	let mut dict = MDictBuilder::new(input.as_ref()).build()?;
+	let encoding = dict.mdx.encoding;
+	let mut inverted: BTreeMap<String, Vec<String>> = BTreeMap::new();
+	for_each_record(&mut dict.mdx, |entry, data| {
+		let definition = decode_slice_string(data, encoding)?.0;
+		if definition.starts_with("@@@LINK=") {
+			return Ok(());
+		}
+		for captures in targets.captures_iter(&definition) {
+			let Some(found) = captures.get(1).or_else(|| captures.get(0)) else {
+				continue;
+			};
+			let target = text_content(found.as_str()).split_whitespace().collect::<Vec<_>>().join(" ");
+			if target.is_empty() {
+				continue;
+			}
+			let sources = inverted.entry(target).or_default();
+			if !sources.iter().any(|source| source == entry.raw()) {
+				sources.push(entry.raw().to_owned());
+			}
+		}
+		Ok(())
+	})?;
+	let mut writer = MdxWriter::new(&format!("{} (inverted)", dict.title()));
+	for (target, sources) in &inverted {
+		let links: Vec<String> = sources.iter()
+			.map(|source| format!("<a href=\"entry://{}\">{}</a>", escape_attr(source), escape_text(source)))
+			.collect();
+		writer.add(target.as_str(), &links.join("<br>"));
+	}
+	writer.write_file(output)?;
+	Ok(inverted.len())
+}
+
+/// Write `input` to `output` with the aliases [`MDict::find_aliases`]
+/// reports replaced by `@@@LINK=` records to their canonical headword
+///
+/// [`MDict::find_aliases`]: crate::MDict::find_aliases
+pub fn dedup_aliases(input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<()>
+{
+	let input = input.as_ref();
+	let mut dict = MDictBuilder::new(input).build()?;
+	let mut writer = copy_header(input, dict.mdx.encoding)?;
+	let canonical: HashMap<(usize, String), String> = dict.alias_groups()?
+		.into_iter()
+		.flat_map(|group| {
+			let canonical = group.canonical;
+			group.aliases.into_iter().map(move |alias| (alias, canonical.clone()))
+		})
+		.collect();
+	for_each_record(&mut dict.mdx, |entry, data| {
+		match canonical.get(&(entry.offset, entry.raw().to_owned())) {
+			Some(target) => writer.add(entry.raw(), &format!("@@@LINK={}", target)),
+			None => writer.add_record(entry.raw(), data.to_vec()),
+		}
+		Ok(())
+	})?;
+	writer.write_file(output)
+}
+
+/// Where a checksum is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumSite {
+	Header,
+	/// the counts and sizes leading the key section
+	KeySection,
+	/// the key block index
+	KeyIndex,
+	/// a key or record block by index
+	Block(BlockKind, usize),
+}
+
+/// What a checksum repair did
+#[derive(Debug, Clone, Default)]
+pub struct ChecksumRepair {
+	/// checksums rewritten
+	pub repaired: Vec<ChecksumSite>,
+	/// checksums left as they are: those of data failing to decode and of
+	/// encrypted data, whose key derives from them
+	pub unchecked: Vec<ChecksumSite>,
+}
+
+/// Copy the mdx or mdd file `input` to `output` with every checksum not
+/// matching the data it covers rewritten, for files whose data is intact
+/// but whose checksums were written wrong
+pub fn repair_checksums(input: impl AsRef<Path>, output: impl AsRef<Path>)
+	-> Result<ChecksumRepair>
+{
+	let checksums = stored_checksums(&mut Reader::open(input.as_ref())?)?;
+	fs::copy(input, &output)?;
+	let mut file = OpenOptions::new().write(true).open(output)?;
+	let mut repair = ChecksumRepair::default();
+	for checksum in checksums {
+		match checksum.actual {
+			Some(actual) if actual != checksum.stored => {
+				file.seek(SeekFrom::Start(checksum.offset))?;
+				if checksum.big_endian {
+					file.write_u32::<BE>(actual)?;
+				} else {
+					file.write_u32::<LE>(actual)?;
+				}
+				repair.repaired.push(checksum.site);
+			}
+			Some(_) => {}
+			None => repair.unchecked.push(checksum.site),
+		}
+	}
+	file.flush()?;
+	Ok(repair)
+}
+
+/// Writer with the header attributes of the mdx file `input`
+fn copy_header(input: &Path, encoding: &'static Encoding) -> Result<MdxWriter>
+{
+	let layout = read_layout(&mut Reader::open(input)?, UTF_16LE)?;
+	let mut writer = MdxWriter::new("").encoding(encoding);
+	for (name, value) in layout.attributes {
+		writer.raw_attribute(&name, value);
+	}
+	Ok(writer)
+}